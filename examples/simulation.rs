@@ -434,7 +434,9 @@ fn main() {
         .map(|_| Transaction::new(args.flag_tx_size))
         .collect();
     let new_honey_badger = |netinfo: NetworkInfo<NodeId>| {
-        let dyn_hb = DynamicHoneyBadger::builder().build(netinfo);
+        let dyn_hb = DynamicHoneyBadger::builder()
+            .build(netinfo)
+            .expect("instantiate DynamicHoneyBadger");
         QueueingHoneyBadger::builder(dyn_hb)
             .batch_size(args.flag_b)
             .build_with_transactions(txs.clone())
@@ -0,0 +1,152 @@
+extern crate bincode;
+extern crate docopt;
+extern crate env_logger;
+extern crate hbbft;
+extern crate rand;
+#[macro_use]
+extern crate rand_derive;
+extern crate serde;
+#[macro_use(Deserialize, Serialize)]
+extern crate serde_derive;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use docopt::Docopt;
+use rand::Rng;
+use serde::Serialize;
+
+use hbbft::messaging::{DistAlgorithm, NetworkInfo, Step, Target};
+use hbbft::mvba_subset::MvbaSubset;
+use hbbft::subset::{Subset, SubsetOutput};
+
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+const USAGE: &str = "
+Subset benchmark example
+
+Compares the `Subset` and `MvbaSubset` algorithms: For each network size, every node proposes a
+value, messages are delivered in random order, and the number and total size of the messages,
+as well as the running time, are reported.
+
+Usage:
+  subset_benchmark [options]
+  subset_benchmark (--help | -h )
+  subset_benchmark --version
+
+Options:
+  -h, --help                    Show this message.
+  --version                     Show the version of hbbft.
+  -n <sizes>, --nodes <sizes>   Comma-separated network sizes [default: 4,7,10,16,25]
+  --value-size <size>           The size of a proposed value, in bytes [default: 100]
+";
+
+#[derive(Deserialize)]
+struct Args {
+    flag_nodes: String,
+    flag_value_size: usize,
+}
+
+/// A node identifier. In the benchmark, nodes are simply numbered.
+#[derive(Serialize, Deserialize, Eq, PartialEq, Ord, PartialOrd, Hash, Debug, Clone, Copy, Rand)]
+pub struct NodeId(pub usize);
+
+/// The measurements of a single run.
+struct Stats {
+    messages: usize,
+    bytes: usize,
+    duration: Duration,
+}
+
+/// Runs one instance of the algorithm created by `new_algo` in each node, with each node proposing
+/// a random value, until all of them have terminated or no messages are left.
+fn run<D, F>(size: usize, value_size: usize, new_algo: F) -> Stats
+where
+    D: DistAlgorithm<NodeId = NodeId, Input = Vec<u8>, Output = SubsetOutput<NodeId>>,
+    D::Message: Serialize + Clone,
+    F: Fn(Arc<NetworkInfo<NodeId>>) -> D,
+{
+    let mut rng = rand::thread_rng();
+    let netinfos = NetworkInfo::generate_map((0..size).map(NodeId), &mut rng)
+        .expect("generate network infos");
+    let mut nodes: BTreeMap<NodeId, D> = netinfos
+        .into_iter()
+        .map(|(id, netinfo)| (id, new_algo(Arc::new(netinfo))))
+        .collect();
+    let ids: Vec<NodeId> = nodes.keys().cloned().collect();
+    let mut queue: Vec<(NodeId, NodeId, D::Message)> = Vec::new();
+    let mut stats = Stats {
+        messages: 0,
+        bytes: 0,
+        duration: Duration::default(),
+    };
+
+    let start = Instant::now();
+    {
+        let mut dispatch = |sender: NodeId, step: Step<D>, queue: &mut Vec<_>| {
+            for msg in step.messages {
+                let size = bincode::serialized_size(&msg.message).expect("message size") as usize;
+                let recipients: Vec<NodeId> = match msg.target {
+                    Target::All => ids.iter().filter(|id| **id != sender).cloned().collect(),
+                    Target::Node(id) => vec![id],
+                };
+                stats.messages += recipients.len();
+                stats.bytes += recipients.len() * size;
+                for recipient in recipients {
+                    queue.push((sender, recipient, msg.message.clone()));
+                }
+            }
+        };
+        for (id, node) in &mut nodes {
+            let value: Vec<u8> = rng.gen_iter().take(value_size).collect();
+            let step = node.handle_input(value).expect("handle input");
+            dispatch(*id, step, &mut queue);
+        }
+        while !nodes.values().all(DistAlgorithm::terminated) {
+            if queue.is_empty() {
+                eprintln!("No messages left, but not all nodes have terminated.");
+                break;
+            }
+            let index = rng.gen_range(0, queue.len());
+            let (sender, recipient, msg) = queue.swap_remove(index);
+            let node = nodes.get_mut(&recipient).expect("recipient exists");
+            let step = node.handle_message(&sender, msg).expect("handle message");
+            dispatch(recipient, step, &mut queue);
+        }
+    }
+    stats.duration = start.elapsed();
+    stats
+}
+
+fn print_stats(name: &str, size: usize, stats: &Stats) {
+    let millis = stats.duration.as_secs() * 1000 + u64::from(stats.duration.subsec_millis());
+    println!(
+        "{:>5} {:>12} {:>10} {:>14} {:>10}",
+        size, name, stats.messages, stats.bytes, millis
+    );
+}
+
+fn main() {
+    env_logger::init();
+    let args: Args = Docopt::new(USAGE)
+        .and_then(|d| d.version(Some(VERSION.to_string())).deserialize())
+        .unwrap_or_else(|e| e.exit());
+    let sizes: Vec<usize> = args
+        .flag_nodes
+        .split(',')
+        .map(|s| s.trim().parse().expect("network size"))
+        .collect();
+
+    println!(
+        "{:>5} {:>12} {:>10} {:>14} {:>10}",
+        "Nodes", "Algorithm", "Messages", "Bytes", "Time (ms)"
+    );
+    for size in sizes {
+        let new_subset = |netinfo| Subset::new(netinfo, 0).expect("new Subset instance");
+        let stats = run(size, args.flag_value_size, new_subset);
+        print_stats("Subset", size, &stats);
+        let new_mvba = |netinfo| MvbaSubset::new(netinfo, 0).expect("new MvbaSubset instance");
+        let stats = run(size, args.flag_value_size, new_mvba);
+        print_stats("MvbaSubset", size, &stats);
+    }
+}
@@ -0,0 +1,305 @@
+//! # Local coin agreement
+//!
+//! A randomized binary agreement algorithm that doesn't need threshold keys: This is Ben-Or's
+//! Byzantine agreement, in which every node flips its own, local coin. Unlike with
+//! `BinaryAgreement`, agreement does not depend on the coin values, but it only tolerates _f_
+//! faulty nodes if _N > 5 f_, and the expected number of rounds can grow exponentially with _N_.
+//!
+//! In each round, every validator multicasts a `Report` with its current estimate. Once it has
+//! received _N - f_ of them, it multicasts a `Proposal` with the value that more than
+//! _(N + f) / 2_ of them agree on, if any. Since any two such majorities have a correct node in
+//! common, no two correct nodes propose different values. Once it has received _N - f_ proposals:
+//!
+//! * If more than _(N + f) / 2_ of them propose `v`, it decides `v` and multicasts `Term(v)`.
+//! * Otherwise, if at least _f + 1_ of them propose `v`, its next estimate is `v`.
+//! * Otherwise, its next estimate is the parity of its signature of the round's nonce.
+//!
+//! If a correct node decides `v`, more than _(N - f) / 2_ correct nodes have proposed `v`, so every
+//! correct node receives at least _f + 1_ of those proposals, starts the next round with
+//! estimate `v` and decides `v`, too. A `Term(r, v)` from a node that decided in round `r` counts
+//! as its report and proposal of `v` in every round after `r`, and _f + 1_ of them are enough to
+//! decide.
+//!
+//! Reports and proposals are only accepted for the current round and the next `MAX_FUTURE_ROUNDS`
+//! rounds; earlier rounds' messages are discarded when a new round starts.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use super::{Error, Result};
+use messaging::{self, DistAlgorithm, NetworkInfo, Target};
+use traits::NodeIdT;
+
+pub type Step<N> = messaging::Step<Agreement<N>>;
+
+/// The number of rounds beyond the current one for which reports and proposals are accepted.
+const MAX_FUTURE_ROUNDS: u32 = 3;
+
+/// A message of the local coin agreement.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AgreementMessage {
+    /// The sender's estimate in the given round.
+    Report(u32, bool),
+    /// The value that more than _(N + f) / 2_ of the reports the sender received in the given round
+    /// agree on, if any.
+    Proposal(u32, Option<bool>),
+    /// The sender has decided on the given value in the given round.
+    Term(u32, bool),
+}
+
+/// The reports and proposals received in one round, by sender.
+#[derive(Debug)]
+struct Round<N> {
+    reports: BTreeMap<N, bool>,
+    proposals: BTreeMap<N, Option<bool>>,
+}
+
+impl<N: NodeIdT> Round<N> {
+    fn new() -> Self {
+        Round {
+            reports: BTreeMap::new(),
+            proposals: BTreeMap::new(),
+        }
+    }
+}
+
+/// A binary agreement instance using local coins.
+#[derive(Debug)]
+pub struct Agreement<N> {
+    /// Shared network information.
+    netinfo: Arc<NetworkInfo<N>>,
+    /// The maximum number of faulty nodes. Must be less than a fifth of the number of nodes.
+    max_faulty: usize,
+    /// The nonce that distinguishes this instance's coins from other instances'.
+    nonce: Vec<u8>,
+    /// The current round.
+    round: u32,
+    /// Our estimate in the current round, if we already have one.
+    estimate: Option<bool>,
+    /// Whether we have already evaluated the current round's reports.
+    proposed: bool,
+    /// The messages received for the current and later rounds.
+    rounds: BTreeMap<u32, Round<N>>,
+    /// The rounds and values of the received `Term` messages, by sender.
+    terms: BTreeMap<N, (u32, bool)>,
+    /// The decided value, once we have decided.
+    decision: Option<bool>,
+}
+
+impl<N: NodeIdT> DistAlgorithm for Agreement<N> {
+    type NodeId = N;
+    type Input = bool;
+    type Output = bool;
+    type Message = AgreementMessage;
+    type Error = Error;
+
+    fn handle_input(&mut self, input: bool) -> Result<Step<N>> {
+        if !self.accepts_input() {
+            return Err(Error::MultipleInputs);
+        }
+        self.estimate = Some(input);
+        let mut step = self.send(AgreementMessage::Report(0, input));
+        step.extend(self.try_progress());
+        Ok(step)
+    }
+
+    fn handle_message(&mut self, sender_id: &N, msg: AgreementMessage) -> Result<Step<N>> {
+        if self.decision.is_some() {
+            return Ok(Step::default());
+        }
+        // Only the first report and proposal of each sender in each round count.
+        match msg {
+            AgreementMessage::Report(round, _) | AgreementMessage::Proposal(round, _)
+                if round < self.round =>
+            {
+                return Ok(Step::default()); // Obsolete.
+            }
+            AgreementMessage::Report(round, _) | AgreementMessage::Proposal(round, _)
+                if round > self.round.saturating_add(MAX_FUTURE_ROUNDS) =>
+            {
+                return Ok(Step::default()); // Too far in the future.
+            }
+            AgreementMessage::Report(round, v) => {
+                self.round_mut(round)
+                    .reports
+                    .entry(sender_id.clone())
+                    .or_insert(v);
+            }
+            AgreementMessage::Proposal(round, opt_v) => {
+                self.round_mut(round)
+                    .proposals
+                    .entry(sender_id.clone())
+                    .or_insert(opt_v);
+            }
+            AgreementMessage::Term(round, v) => {
+                self.terms.entry(sender_id.clone()).or_insert((round, v));
+            }
+        }
+        Ok(self.try_progress())
+    }
+
+    fn terminated(&self) -> bool {
+        self.decision.is_some()
+    }
+
+    fn our_id(&self) -> &N {
+        self.netinfo.our_id()
+    }
+}
+
+impl<N: NodeIdT> Agreement<N> {
+    /// Creates a new instance that tolerates `max_faulty` faulty nodes. The `nonce` must be unique
+    /// to this instance.
+    pub fn new(netinfo: Arc<NetworkInfo<N>>, max_faulty: usize, nonce: Vec<u8>) -> Self {
+        Agreement {
+            netinfo,
+            max_faulty,
+            nonce,
+            round: 0,
+            estimate: None,
+            proposed: false,
+            rounds: BTreeMap::new(),
+            terms: BTreeMap::new(),
+            decision: None,
+        }
+    }
+
+    /// Returns `true` if the instance has not received any input yet.
+    pub fn accepts_input(&self) -> bool {
+        self.round == 0 && self.estimate.is_none() && self.decision.is_none()
+    }
+
+    /// Proposes, moves on to the next round or decides, as long as there are enough messages.
+    fn try_progress(&mut self) -> Step<N> {
+        let mut step = Step::default();
+        let num_nodes = self.netinfo.num_nodes();
+        let quorum = num_nodes - self.max_faulty;
+        while self.decision.is_none() {
+            if let Some(v) = self.term_value() {
+                step.extend(self.decide(v));
+            } else if self.estimate.is_none() {
+                break; // We need our own input first.
+            } else if !self.proposed {
+                let (count, counts) = self
+                    .count(|round| round.reports.iter().map(|(id, v)| (id, Some(*v))).collect());
+                if count < quorum {
+                    break;
+                }
+                self.proposed = true;
+                let majority = (num_nodes + self.max_faulty) / 2;
+                let opt_v = [false, true]
+                    .iter()
+                    .cloned()
+                    .find(|v| counts[*v as usize] > majority);
+                let round = self.round;
+                step.extend(self.send(AgreementMessage::Proposal(round, opt_v)));
+            } else {
+                let (count, counts) =
+                    self.count(|round| round.proposals.iter().map(|(id, v)| (id, *v)).collect());
+                if count < quorum {
+                    break;
+                }
+                let v = counts[1] > counts[0];
+                if counts[v as usize] > (num_nodes + self.max_faulty) / 2 {
+                    step.extend(self.decide(v));
+                } else if counts[v as usize] > self.max_faulty {
+                    step.extend(self.next_round(v));
+                } else {
+                    let coin = self.coin();
+                    step.extend(self.next_round(coin));
+                }
+            }
+        }
+        step
+    }
+
+    /// Returns the number of senders of the current round's reports or proposals, and the numbers
+    /// of those with values `false` and `true`, respectively. The `Term` messages from earlier
+    /// rounds count as both.
+    fn count<'a, F>(&'a self, f: F) -> (usize, [usize; 2])
+    where
+        F: Fn(&'a Round<N>) -> BTreeMap<&'a N, Option<bool>>,
+    {
+        let mut values: BTreeMap<&N, Option<bool>> = self
+            .terms
+            .iter()
+            .filter(|&(_, &(round, _))| round < self.round)
+            .map(|(id, &(_, v))| (id, Some(v)))
+            .collect();
+        if let Some(round) = self.rounds.get(&self.round) {
+            values.extend(f(round));
+        }
+        let mut counts = [0, 0];
+        for v in values.values().filter_map(|opt_v| *opt_v) {
+            counts[v as usize] += 1;
+        }
+        (values.len(), counts)
+    }
+
+    /// Returns the value of more than _f_ `Term` messages, if any. At least one of them must be
+    /// from a correct node that has decided.
+    fn term_value(&self) -> Option<bool> {
+        [false, true].iter().cloned().find(|v| {
+            self.terms.values().filter(|&&(_, term)| term == *v).count() > self.max_faulty
+        })
+    }
+
+    /// Moves on to the next round with the given estimate.
+    fn next_round(&mut self, estimate: bool) -> Step<N> {
+        self.round += 1;
+        self.estimate = Some(estimate);
+        self.proposed = false;
+        self.rounds = self.rounds.split_off(&self.round);
+        debug!(
+            "{:?} Local coin agreement round {}, estimate {}",
+            self.netinfo.our_id(),
+            self.round,
+            estimate
+        );
+        let round = self.round;
+        self.send(AgreementMessage::Report(round, estimate))
+    }
+
+    /// Decides on a value and multicasts a `Term` message with that value.
+    fn decide(&mut self, v: bool) -> Step<N> {
+        self.decision = Some(v);
+        self.rounds.clear();
+        let mut step = Step::default();
+        step.output.push_back(v);
+        if self.netinfo.is_validator() {
+            let msg = AgreementMessage::Term(self.round, v);
+            step.messages.push_back(Target::All.message(msg));
+        }
+        step
+    }
+
+    /// Records our own report or proposal and multicasts it. Does nothing if we are an observer.
+    fn send(&mut self, msg: AgreementMessage) -> Step<N> {
+        if !self.netinfo.is_validator() {
+            return Step::default();
+        }
+        let our_id = self.netinfo.our_id().clone();
+        match msg {
+            AgreementMessage::Report(round, v) => {
+                self.round_mut(round).reports.insert(our_id, v);
+            }
+            AgreementMessage::Proposal(round, opt_v) => {
+                self.round_mut(round).proposals.insert(our_id, opt_v);
+            }
+            AgreementMessage::Term(..) => unreachable!("Term is sent on decision"),
+        }
+        Target::All.message(msg).into()
+    }
+
+    /// Returns our local coin for the current round: the parity of our signature of the nonce.
+    fn coin(&self) -> bool {
+        let mut doc = self.nonce.clone();
+        doc.extend(format!(":{}", self.round).bytes());
+        self.netinfo.secret_key().sign(doc).parity()
+    }
+
+    /// Returns the messages received in the given round, creating the entry if necessary.
+    fn round_mut(&mut self, round: u32) -> &mut Round<N> {
+        self.rounds.entry(round).or_insert_with(Round::new)
+    }
+}
@@ -0,0 +1,740 @@
+//! # Asynchronous Key Generation
+//!
+//! An algorithm for dealerless distributed key generation that, unlike `SyncKeyGen`, does not
+//! require the nodes to handle the messages in the same order: it can be used to bootstrap a
+//! network's initial threshold keys without a trusted dealer and without any consensus algorithm
+//! already running.
+//!
+//! When the algorithm completes, every validator outputs the same public key set and its own secret
+//! key share, suitable for threshold signatures and encryption. The secret master key is not known
+//! by anyone. The algorithm succeeds if up to _f_ nodes are faulty, where _f_ is the `max_faulty`
+//! parameter, and the number of nodes _N_ must be at least _5 f + 1_. The keys' `threshold` _t_,
+//! i.e. the number of shares minus one needed to sign or decrypt, is independent of _f_: It can be
+//! any value with _f <= t_ and _t + 2 f < N_. In particular, keys for a Honey Badger network with
+//! _t = (N - 1) / 3_ can be generated.
+//!
+//! ## How it works
+//!
+//! Every validator acts as a _dealer_ of a random contribution to the secret master key, which it
+//! shares using asynchronous verifiable secret sharing (AVSS):
+//!
+//! * The dealer creates a random symmetric bivariate polynomial `f` of degree _t_ and multicasts a
+//! `Part` message with a commitment to `f`, and for each node `i`, the row `f(i, _)`, encrypted to
+//! that node.
+//! * A node that receives its valid row multicasts an `Echo` with the commitment and, for each node
+//! `j`, the value of its row at `j`, encrypted to `j`. Since `f` is symmetric, that is a value of
+//! `j`'s own row, which it can verify against the commitment.
+//! * Once a node has received _max(⌈(N + f + 1) / 2⌉, t + f + 1)_ `Echo`s or _f + 1_ `Ready`s for
+//! the same commitment, it multicasts a `Ready` message with the commitment and values for each
+//! node. If it didn't receive the `Part`, it interpolates its row from the _t + 1_ values in the
+//! `Echo`s or `Ready`s. The `Echo` quorum guarantees that at most one commitment gets `Ready`s from
+//! correct nodes, and that at least _t + 1_ correct nodes know their rows, so every correct node can
+//! eventually interpolate its own. Since the quorum must be reachable without the _f_ faulty nodes,
+//! _t + 2 f < N_ is required.
+//! * Once a node has received _2 f + 1_ `Ready`s, every correct node will eventually do so, too.
+//! The sharing is complete, and the node's share of the dealer's secret is its row's value at 0.
+//!
+//! Each node's first `Echo` and first `Ready` about each dealer count, so that faulty nodes can't
+//! make us track arbitrarily many commitments.
+//!
+//! Since a faulty dealer's sharing may never complete, the nodes then agree on the set of dealers
+//! to use, like in `Subset`: For each dealer there is a binary agreement instance, with input
+//! `true` once the dealer's sharing is complete. After _N - f_ instances decided `true`, the
+//! remaining ones get input `false`. The nodes wait for all the accepted dealers' sharings to
+//! complete, and sum up the shares and commitments to obtain the keys.
+//!
+//! As there are no threshold keys yet, `BinaryAgreement` can't be used: Its agreement depends on
+//! the coin being common to all nodes. Instead, the instances run Ben-Or's agreement algorithm, in
+//! which every node flips its own coin, using its regular secret key. That is what requires
+//! _N > 5 f_, and it means agreement can take many rounds in large networks, so this is best
+//! suited for small ones.
+//!
+//! ## Usage
+//!
+//! Each node needs a regular (non-threshold) key pair, and all validators' public keys must be
+//! known to every node. After creating an `AsyncKeyGen` instance, each validator inputs `()` to
+//! start dealing. The output is the `PublicKeySet` and, for validators, the `SecretKeyShare`,
+//! which can be used to create a new `NetworkInfo` with `NetworkInfo::new`.
+
+mod agreement;
+
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Debug, Formatter};
+use std::sync::Arc;
+
+use bincode;
+use crypto::{
+    error::Error as CryptoError,
+    poly::{BivarCommitment, BivarPoly, Commitment, Poly},
+    serde_impl::field_vec::FieldWrap,
+    Ciphertext, PublicKey, PublicKeySet, SecretKey, SecretKeyShare,
+};
+use pairing::bls12_381::{Fr, G1Affine};
+use pairing::{CurveAffine, Field};
+use rand::{self, Rng};
+use tiny_keccak::sha3_256;
+
+use fault_log::{AckMessageFault, Fault, FaultKind};
+use messaging::{self, DistAlgorithm, NetworkInfo, Target};
+use traits::NodeIdT;
+use util::SubRng;
+
+use self::agreement::Agreement;
+pub use self::agreement::AgreementMessage;
+
+/// An asynchronous key generation error.
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Error creating AsyncKeyGen: {}", _0)]
+    Creation(CryptoError),
+    #[fail(display = "Error generating keys: {}", _0)]
+    Generation(CryptoError),
+    #[fail(display = "Threshold out of range for the number of faulty nodes")]
+    InvalidThreshold,
+    #[fail(display = "Too many faulty nodes for the number of nodes")]
+    InvalidMaxFaulty,
+    #[fail(display = "Multiple inputs")]
+    MultipleInputs,
+    #[fail(display = "Unknown dealer")]
+    UnknownDealer,
+    #[fail(display = "Unknown sender")]
+    UnknownSender,
+}
+
+/// An asynchronous key generation result.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// A SHA-3 hash of a commitment.
+type Digest = [u8; 32];
+
+/// A message sent during asynchronous key generation.
+#[derive(Serialize, Deserialize, Clone)]
+pub enum Message {
+    /// A dealer's commitment to a symmetric bivariate polynomial, and for each node, an encrypted
+    /// row.
+    Part(BivarCommitment, Vec<Ciphertext>),
+    /// A confirmation that the sender received a valid row of the given dealer's polynomial. For
+    /// each node, it contains one encrypted value of that row.
+    Echo(u64, BivarCommitment, Vec<Ciphertext>),
+    /// A confirmation that every correct node will be able to obtain its row of the given dealer's
+    /// polynomial. For each node, it contains one encrypted value of the sender's row.
+    Ready(u64, BivarCommitment, Vec<Ciphertext>),
+    /// A message of the agreement instance that decides whether to use the given dealer's
+    /// polynomial.
+    Agreement(u64, AgreementMessage),
+}
+
+impl Debug for Message {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match *self {
+            Message::Part(ref commit, ref rows) => f
+                .debug_tuple("Part")
+                .field(&format!("<degree {}>", commit.degree()))
+                .field(&format!("<{} rows>", rows.len()))
+                .finish(),
+            Message::Echo(idx, ref commit, ref values) => f
+                .debug_tuple("Echo")
+                .field(&idx)
+                .field(&format!("<degree {}>", commit.degree()))
+                .field(&format!("<{} values>", values.len()))
+                .finish(),
+            Message::Ready(idx, ref commit, ref values) => f
+                .debug_tuple("Ready")
+                .field(&idx)
+                .field(&format!("<degree {}>", commit.degree()))
+                .field(&format!("<{} values>", values.len()))
+                .finish(),
+            Message::Agreement(idx, ref msg) => {
+                f.debug_tuple("Agreement").field(&idx).field(msg).finish()
+            }
+        }
+    }
+}
+
+/// The information about one commitment that was received for a dealer.
+#[derive(Debug)]
+struct CommitState {
+    /// The commitment to the dealer's polynomial.
+    commit: BivarCommitment,
+    /// Our row of the polynomial, if we received it or have enough values to interpolate it.
+    row: Option<Poly>,
+    /// The verified values of our row, by sender index + 1.
+    values: BTreeMap<u64, Fr>,
+    /// The senders of `Echo` messages with this commitment.
+    echos: BTreeSet<u64>,
+    /// The senders of `Ready` messages with this commitment.
+    readys: BTreeSet<u64>,
+}
+
+impl CommitState {
+    /// Creates a new commitment state.
+    fn new(commit: BivarCommitment) -> CommitState {
+        CommitState {
+            commit,
+            row: None,
+            values: BTreeMap::new(),
+            echos: BTreeSet::new(),
+            readys: BTreeSet::new(),
+        }
+    }
+
+    /// If we don't know our row yet but have at least `threshold + 1` values, interpolates it.
+    fn interpolate_row(&mut self, threshold: usize) -> Result<()> {
+        if self.row.is_none() && self.values.len() > threshold {
+            let row = Poly::interpolate(self.values.iter().take(threshold + 1))
+                .map_err(Error::Generation)?;
+            self.row = Some(row);
+        }
+        Ok(())
+    }
+}
+
+/// The information needed to track a single dealer's secret sharing process.
+#[derive(Debug, Default)]
+struct DealerState {
+    /// Whether we have received the dealer's `Part` message.
+    part_received: bool,
+    /// Whether we have sent a `Ready` message for this dealer.
+    ready_sent: bool,
+    /// The senders of `Echo` messages about this dealer, with any commitment.
+    echo_senders: BTreeSet<u64>,
+    /// The senders of `Ready` messages about this dealer, with any commitment.
+    ready_senders: BTreeSet<u64>,
+    /// The commitments we received in messages about this dealer, by hash. Since only one `Echo`
+    /// and one `Ready` from each sender count, there are at most _2 N + 1_ of them.
+    commits: BTreeMap<Digest, CommitState>,
+    /// Once the sharing is complete, the commitment to the dealer's secret polynomial and our
+    /// share of it, if we are a validator.
+    complete: Option<(Commitment, Option<Fr>)>,
+}
+
+/// An asynchronous algorithm for dealerless distributed key generation.
+pub struct AsyncKeyGen<N> {
+    /// Network information with a placeholder key set, used by the agreement instances.
+    netinfo: Arc<NetworkInfo<N>>,
+    /// Our node index, if we are a validator.
+    our_idx: Option<u64>,
+    /// The degree of the generated polynomial.
+    threshold: usize,
+    /// The maximum number of faulty nodes.
+    max_faulty: usize,
+    /// A random number generator, used to create our polynomial and to encrypt.
+    rng: Box<dyn Rng + Send + Sync>,
+    /// Whether we have already started dealing.
+    had_input: bool,
+    /// The secret sharing state of each dealer, by node index.
+    dealers: BTreeMap<u64, DealerState>,
+    /// The agreement instance for each dealer, by node index.
+    agreements: BTreeMap<u64, Agreement<N>>,
+    /// The decisions of the agreement instances.
+    decisions: BTreeMap<u64, bool>,
+    /// Whether we have already output the keys.
+    terminated: bool,
+}
+
+impl<N: Debug> Debug for AsyncKeyGen<N> {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("AsyncKeyGen")
+            .field("netinfo", &self.netinfo)
+            .field("our_idx", &self.our_idx)
+            .field("threshold", &self.threshold)
+            .field("max_faulty", &self.max_faulty)
+            .field("rng", &"<RNG>")
+            .field("had_input", &self.had_input)
+            .field("dealers", &self.dealers)
+            .field("agreements", &self.agreements)
+            .field("decisions", &self.decisions)
+            .field("terminated", &self.terminated)
+            .finish()
+    }
+}
+
+pub type Step<N> = messaging::Step<AsyncKeyGen<N>>;
+
+impl<N: NodeIdT> DistAlgorithm for AsyncKeyGen<N> {
+    type NodeId = N;
+    type Input = ();
+    type Output = (PublicKeySet, Option<SecretKeyShare>);
+    type Message = Message;
+    type Error = Error;
+
+    fn handle_input(&mut self, _input: ()) -> Result<Step<N>> {
+        if self.had_input {
+            return Err(Error::MultipleInputs);
+        }
+        self.had_input = true;
+        if !self.netinfo.is_validator() {
+            return Ok(Step::default()); // Observers don't deal.
+        }
+        let our_part = BivarPoly::random(self.threshold, &mut self.rng).map_err(Error::Creation)?;
+        let commit = our_part.commitment();
+        let rows = {
+            let rng = &mut self.rng;
+            let encrypt = |(i, pk): (usize, &PublicKey)| {
+                let row = our_part.row(i + 1).map_err(Error::Creation)?;
+                let bytes = bincode::serialize(&row).expect("failed to serialize row");
+                Ok(pk.encrypt_with_rng(rng, &bytes))
+            };
+            self.netinfo
+                .public_key_map()
+                .values()
+                .enumerate()
+                .map(encrypt)
+                .collect::<Result<Vec<_>>>()?
+        };
+        let part_msg = Message::Part(commit.clone(), rows.clone());
+        let mut step: Step<N> = Target::All.message(part_msg).into();
+        let our_id = &self.netinfo.our_id().clone();
+        step.extend(self.handle_part(our_id, commit, &rows)?);
+        Ok(step)
+    }
+
+    fn handle_message(&mut self, sender_id: &N, message: Message) -> Result<Step<N>> {
+        if !self.netinfo.is_node_validator(sender_id) {
+            return Err(Error::UnknownSender);
+        }
+        match message {
+            Message::Part(commit, rows) => self.handle_part(sender_id, commit, &rows),
+            Message::Echo(dealer_idx, commit, values) => {
+                self.handle_echo_or_ready(sender_id, dealer_idx, commit, &values, false)
+            }
+            Message::Ready(dealer_idx, commit, values) => {
+                self.handle_echo_or_ready(sender_id, dealer_idx, commit, &values, true)
+            }
+            Message::Agreement(dealer_idx, msg) => {
+                self.process_agreement(dealer_idx, |agr| agr.handle_message(sender_id, msg))
+            }
+        }
+    }
+
+    fn terminated(&self) -> bool {
+        self.terminated
+    }
+
+    fn our_id(&self) -> &N {
+        self.netinfo.our_id()
+    }
+}
+
+impl<N: NodeIdT> AsyncKeyGen<N> {
+    /// Creates a new `AsyncKeyGen` instance for the node `our_id` with the given secret key.
+    /// `pub_keys` must contain the public keys of all validators, and `threshold` is the degree of
+    /// the generated polynomial: _t + 1_ shares will be needed to sign or decrypt. The algorithm
+    /// tolerates up to `max_faulty` faulty nodes.
+    ///
+    /// Returns `InvalidMaxFaulty` unless `5 * max_faulty < N`, and `InvalidThreshold` unless
+    /// `max_faulty <= threshold` and `threshold + 2 * max_faulty < N`.
+    ///
+    /// If `our_id` is not in `pub_keys`, the instance is an observer: it will output the public
+    /// key set, but no secret key share.
+    pub fn new<R: rand::Rng>(
+        rng: &mut R,
+        our_id: N,
+        sec_key: SecretKey,
+        pub_keys: BTreeMap<N, PublicKey>,
+        threshold: usize,
+        max_faulty: usize,
+    ) -> Result<Self> {
+        let num_nodes = pub_keys.len();
+        if 5 * max_faulty >= num_nodes {
+            return Err(Error::InvalidMaxFaulty);
+        }
+        if threshold < max_faulty || threshold + 2 * max_faulty >= num_nodes {
+            return Err(Error::InvalidThreshold);
+        }
+        let our_idx = pub_keys
+            .keys()
+            .position(|id| *id == our_id)
+            .map(|idx| idx as u64);
+        // There are no threshold keys yet: The agreement instances' local coins don't use them.
+        let pk_set = Poly::zero().map_err(Error::Creation)?.commitment().into();
+        let netinfo = Arc::new(NetworkInfo::new(
+            our_id,
+            SecretKeyShare::default(),
+            pk_set,
+            sec_key,
+            pub_keys,
+        ));
+        let mut agreements = BTreeMap::new();
+        for idx in 0..netinfo.num_nodes() {
+            let nonce = Vec::from(format!(
+                "Nonce for AsyncKeyGen agreement {:?}:{}",
+                netinfo.invocation_id().as_ref(),
+                idx
+            ));
+            let agreement = Agreement::new(netinfo.clone(), max_faulty, nonce);
+            agreements.insert(idx as u64, agreement);
+        }
+        Ok(AsyncKeyGen {
+            netinfo,
+            our_idx,
+            threshold,
+            max_faulty,
+            rng: rng.sub_rng(),
+            had_input: false,
+            dealers: BTreeMap::new(),
+            agreements,
+            decisions: BTreeMap::new(),
+            terminated: false,
+        })
+    }
+
+    /// Handles a `Part` message. If our row is valid, multicasts an `Echo`.
+    fn handle_part(
+        &mut self,
+        sender_id: &N,
+        commit: BivarCommitment,
+        rows: &[Ciphertext],
+    ) -> Result<Step<N>> {
+        let dealer_idx = self.node_index(sender_id)?;
+        {
+            let dealer = self
+                .dealers
+                .entry(dealer_idx)
+                .or_insert_with(DealerState::default);
+            if dealer.part_received {
+                debug!("Received multiple parts from node {:?}.", sender_id);
+                return Ok(Step::default());
+            }
+            dealer.part_received = true;
+        }
+        if commit.degree() != self.threshold || rows.len() != self.netinfo.num_nodes() {
+            return Ok(Fault::new(sender_id.clone(), FaultKind::InvalidPartMessage).into());
+        }
+        let our_idx = match self.our_idx {
+            Some(our_idx) => our_idx,
+            None => return Ok(Step::default()), // We are only an observer. We don't echo.
+        };
+        let opt_row = self
+            .netinfo
+            .secret_key()
+            .decrypt(&rows[our_idx as usize])
+            .and_then(|ser_row| bincode::deserialize::<Poly>(&ser_row).ok());
+        let row = match opt_row {
+            Some(ref row) if row.commitment() == commit.row(our_idx + 1) => row.clone(),
+            _ => {
+                error!("Invalid part from node {:?}.", sender_id);
+                return Ok(Fault::new(sender_id.clone(), FaultKind::InvalidPartMessage).into());
+            }
+        };
+        // The row is valid: now encrypt one value for each node.
+        let values = self.encrypt_values(&row);
+        let digest = digest(&commit);
+        self.commit_state(dealer_idx, digest, &commit).row = Some(row);
+        let echo_msg = Message::Echo(dealer_idx, commit.clone(), values.clone());
+        let mut step: Step<N> = Target::All.message(echo_msg).into();
+        let our_id = &self.netinfo.our_id().clone();
+        step.extend(self.handle_echo_or_ready(our_id, dealer_idx, commit, &values, false)?);
+        Ok(step)
+    }
+
+    /// Handles an `Echo` or `Ready` message: Verifies and records the value, and checks whether
+    /// we can send `Ready` or the sharing is complete.
+    fn handle_echo_or_ready(
+        &mut self,
+        sender_id: &N,
+        dealer_idx: u64,
+        commit: BivarCommitment,
+        values: &[Ciphertext],
+        is_ready: bool,
+    ) -> Result<Step<N>> {
+        let sender_idx = self.node_index(sender_id)?;
+        let fault = |fault| -> Result<Step<N>> {
+            Ok(Fault::new(sender_id.clone(), FaultKind::AckMessage(fault)).into())
+        };
+        if dealer_idx as usize >= self.netinfo.num_nodes() {
+            return fault(AckMessageFault::SenderExist);
+        }
+        if values.len() != self.netinfo.num_nodes() {
+            return fault(AckMessageFault::NodeCount);
+        }
+        if commit.degree() != self.threshold {
+            return fault(AckMessageFault::ValueInvalid);
+        }
+        {
+            let dealer = self
+                .dealers
+                .entry(dealer_idx)
+                .or_insert_with(DealerState::default);
+            let senders = if is_ready {
+                &dealer.ready_senders
+            } else {
+                &dealer.echo_senders
+            };
+            if senders.contains(&sender_idx) {
+                return fault(AckMessageFault::DuplicateAck);
+            }
+        }
+        let opt_val = match self.our_idx {
+            None => None, // We are only an observer. Nothing to decrypt for us.
+            Some(our_idx) => match self.decrypt_value(our_idx, sender_idx, &commit, values) {
+                Ok(val) => Some(val),
+                Err(ack_fault) => {
+                    debug!("Invalid value from node {:?}: {}", sender_id, ack_fault);
+                    return fault(ack_fault);
+                }
+            },
+        };
+        let digest = digest(&commit);
+        {
+            let dealer = self
+                .dealers
+                .get_mut(&dealer_idx)
+                .expect("dealer state exists");
+            if is_ready {
+                dealer.ready_senders.insert(sender_idx);
+            } else {
+                dealer.echo_senders.insert(sender_idx);
+            }
+            let state = dealer
+                .commits
+                .entry(digest)
+                .or_insert_with(|| CommitState::new(commit.clone()));
+            if is_ready {
+                state.readys.insert(sender_idx);
+            } else {
+                state.echos.insert(sender_idx);
+            }
+            if let Some(val) = opt_val {
+                state.values.insert(sender_idx + 1, val);
+            }
+        }
+        let mut step = self.try_send_ready(dealer_idx, digest)?;
+        step.extend(self.try_complete(dealer_idx, digest)?);
+        Ok(step)
+    }
+
+    /// Multicasts a `Ready` message if we haven't done so yet for this dealer, and have received
+    /// enough `Echo` or `Ready` messages for the given commitment.
+    fn try_send_ready(&mut self, dealer_idx: u64, digest: Digest) -> Result<Step<N>> {
+        if !self.netinfo.is_validator() {
+            return Ok(Step::default());
+        }
+        let threshold = self.threshold;
+        let max_faulty = self.max_faulty;
+        let num_nodes = self.netinfo.num_nodes();
+        let echo_quorum = cmp::max((num_nodes + max_faulty + 2) / 2, threshold + max_faulty + 1);
+        let (commit, row) = {
+            let dealer = self
+                .dealers
+                .get_mut(&dealer_idx)
+                .expect("dealer state exists");
+            if dealer.ready_sent {
+                return Ok(Step::default());
+            }
+            let state = dealer
+                .commits
+                .get_mut(&digest)
+                .expect("commit state exists");
+            if state.echos.len() < echo_quorum && state.readys.len() <= max_faulty {
+                return Ok(Step::default());
+            }
+            state.interpolate_row(threshold)?;
+            match state.row {
+                Some(ref row) => (state.commit.clone(), row.clone()),
+                None => return Ok(Step::default()), // Not enough values yet.
+            }
+        };
+        self.dealers
+            .get_mut(&dealer_idx)
+            .expect("dealer state exists")
+            .ready_sent = true;
+        let values = self.encrypt_values(&row);
+        let ready_msg = Message::Ready(dealer_idx, commit.clone(), values.clone());
+        let mut step: Step<N> = Target::All.message(ready_msg).into();
+        let our_id = &self.netinfo.our_id().clone();
+        step.extend(self.handle_echo_or_ready(our_id, dealer_idx, commit, &values, true)?);
+        Ok(step)
+    }
+
+    /// Checks whether the dealer's sharing is complete, i.e. there are _2 f + 1_ `Ready` messages
+    /// for the given commitment and we know our row. If so, inputs `true` to its agreement
+    /// instance.
+    fn try_complete(&mut self, dealer_idx: u64, digest: Digest) -> Result<Step<N>> {
+        let threshold = self.threshold;
+        let max_faulty = self.max_faulty;
+        let is_validator = self.netinfo.is_validator();
+        {
+            let dealer = self
+                .dealers
+                .get_mut(&dealer_idx)
+                .expect("dealer state exists");
+            if dealer.complete.is_some() {
+                return Ok(Step::default());
+            }
+            let state = dealer
+                .commits
+                .get_mut(&digest)
+                .expect("commit state exists");
+            if state.readys.len() <= 2 * max_faulty {
+                return Ok(Step::default());
+            }
+            state.interpolate_row(threshold)?;
+            let opt_val = match state.row {
+                Some(ref row) => Some(row.evaluate(0)),
+                None if is_validator => return Ok(Step::default()), // Not enough values yet.
+                None => None,
+            };
+            dealer.complete = Some((state.commit.row(0), opt_val));
+        }
+        debug!(
+            "{:?} Sharing of dealer {} complete.",
+            self.netinfo.our_id(),
+            dealer_idx
+        );
+        let accepts_input = self
+            .agreements
+            .get(&dealer_idx)
+            .map_or(false, Agreement::accepts_input);
+        if accepts_input {
+            self.process_agreement(dealer_idx, |agr| agr.handle_input(true))
+        } else {
+            self.try_output()
+        }
+    }
+
+    /// Passes an input or message to the given dealer's agreement instance, and handles its
+    /// decision.
+    fn process_agreement<F>(&mut self, dealer_idx: u64, f: F) -> Result<Step<N>>
+    where
+        F: FnOnce(&mut Agreement<N>) -> Result<agreement::Step<N>>,
+    {
+        let mut step = Step::default();
+        let accepted = {
+            let agreement = self
+                .agreements
+                .get_mut(&dealer_idx)
+                .ok_or(Error::UnknownDealer)?;
+            if agreement.terminated() {
+                return Ok(step);
+            }
+            let to_msg = |agr_msg| Message::Agreement(dealer_idx, agr_msg);
+            match step.extend_with(f(agreement)?, to_msg).into_iter().next() {
+                Some(accepted) => accepted,
+                None => return Ok(step),
+            }
+        };
+        self.decisions.insert(dealer_idx, accepted);
+        let count_true = self
+            .decisions
+            .values()
+            .filter(|accepted| **accepted)
+            .count();
+        if accepted && count_true == self.netinfo.num_nodes() - self.max_faulty {
+            // Enough dealers have been accepted: provide input `false` to the remaining instances.
+            for (idx, agreement) in &mut self.agreements {
+                if agreement.accepts_input() {
+                    let idx = *idx;
+                    let to_msg = |agr_msg| Message::Agreement(idx, agr_msg);
+                    let agr_step = agreement.handle_input(false)?;
+                    for output in step.extend_with(agr_step, to_msg) {
+                        self.decisions.insert(idx, output);
+                    }
+                }
+            }
+        }
+        step.extend(self.try_output()?);
+        Ok(step)
+    }
+
+    /// Outputs the keys, if all agreement instances have decided, and the sharings of all accepted
+    /// dealers are complete.
+    fn try_output(&mut self) -> Result<Step<N>> {
+        if self.terminated || self.decisions.len() < self.netinfo.num_nodes() {
+            return Ok(Step::default());
+        }
+        let mut pk_commit = Poly::zero().map_err(Error::Generation)?.commitment();
+        let mut opt_sk_val = self.our_idx.map(|_| Fr::zero());
+        for (dealer_idx, _) in self.decisions.iter().filter(|&(_, accepted)| *accepted) {
+            let complete = self
+                .dealers
+                .get(dealer_idx)
+                .and_then(|d| d.complete.as_ref());
+            let (commit, opt_val) = match complete {
+                Some(&(ref commit, ref opt_val)) => (commit, opt_val),
+                None => return Ok(Step::default()), // Waiting for the sharing to complete.
+            };
+            pk_commit += commit.clone();
+            if let (Some(sk_val), Some(val)) = (opt_sk_val.as_mut(), opt_val.as_ref()) {
+                sk_val.add_assign(val);
+            }
+        }
+        let opt_sk = if let Some(mut fr) = opt_sk_val {
+            let sk = SecretKeyShare::from_mut_ptr(&mut fr as *mut Fr).map_err(Error::Generation)?;
+            Some(sk)
+        } else {
+            None
+        };
+        self.terminated = true;
+        debug!("{:?} Key generation complete.", self.netinfo.our_id());
+        Ok(Step::default().with_output((pk_commit.into(), opt_sk)))
+    }
+
+    /// Returns the state of the given commitment for the given dealer, creating it if necessary.
+    fn commit_state(
+        &mut self,
+        dealer_idx: u64,
+        digest: Digest,
+        commit: &BivarCommitment,
+    ) -> &mut CommitState {
+        self.dealers
+            .entry(dealer_idx)
+            .or_insert_with(DealerState::default)
+            .commits
+            .entry(digest)
+            .or_insert_with(|| CommitState::new(commit.clone()))
+    }
+
+    /// Returns the values of `row` for each node, each encrypted to that node.
+    fn encrypt_values(&mut self, row: &Poly) -> Vec<Ciphertext> {
+        let rng = &mut self.rng;
+        let encrypt = |(idx, pk): (usize, &PublicKey)| {
+            let wrap = FieldWrap::new(row.evaluate(idx + 1));
+            let ser_val = bincode::serialize(&wrap).expect("failed to serialize value");
+            pk.encrypt_with_rng(rng, ser_val)
+        };
+        self.netinfo
+            .public_key_map()
+            .values()
+            .enumerate()
+            .map(encrypt)
+            .collect()
+    }
+
+    /// Decrypts the value addressed to us, and verifies it against the commitment.
+    fn decrypt_value(
+        &self,
+        our_idx: u64,
+        sender_idx: u64,
+        commit: &BivarCommitment,
+        values: &[Ciphertext],
+    ) -> ::std::result::Result<Fr, AckMessageFault> {
+        let ser_val = self
+            .netinfo
+            .secret_key()
+            .decrypt(&values[our_idx as usize])
+            .ok_or(AckMessageFault::ValueDecryption)?;
+        let val = bincode::deserialize::<FieldWrap<Fr, Fr>>(&ser_val)
+            .map_err(|_| AckMessageFault::ValueDeserialization)?
+            .into_inner();
+        if commit.evaluate(our_idx + 1, sender_idx + 1) != G1Affine::one().mul(val) {
+            return Err(AckMessageFault::ValueInvalid);
+        }
+        Ok(val)
+    }
+
+    /// Returns the index of the given validator.
+    fn node_index(&self, node_id: &N) -> Result<u64> {
+        let idx = self
+            .netinfo
+            .node_index(node_id)
+            .ok_or(Error::UnknownSender)?;
+        Ok(idx as u64)
+    }
+}
+
+/// Returns the hash of the commitment.
+fn digest(commit: &BivarCommitment) -> Digest {
+    sha3_256(&bincode::serialize(commit).expect("failed to serialize commitment"))
+}
@@ -4,7 +4,7 @@ use std::sync::Arc;
 use super::bool_multimap::BoolMultimap;
 use super::bool_set::BoolSet;
 use super::sbv_broadcast::{self, SbvBroadcast};
-use super::{Error, Message, MessageContent, Nonce, Result, Step};
+use super::{CoinSchedule, Error, Message, MessageContent, Nonce, Result, Step};
 use coin::{self, Coin, CoinMessage};
 use messaging::{DistAlgorithm, NetworkInfo, Target};
 use traits::NodeIdT;
@@ -17,6 +17,8 @@ enum CoinState<N> {
     Decided(bool),
     /// The coin value is not known yet.
     InProgress(Coin<N, Nonce>),
+    /// The value of a coin that is shared with other instances is not known yet.
+    Shared,
 }
 
 impl<N> CoinState<N> {
@@ -24,7 +26,7 @@ impl<N> CoinState<N> {
     fn value(&self) -> Option<bool> {
         match self {
             CoinState::Decided(value) => Some(*value),
-            CoinState::InProgress(_) => None,
+            CoinState::InProgress(_) | CoinState::Shared => None,
         }
     }
 }
@@ -68,6 +70,10 @@ pub struct BinaryAgreement<N> {
     conf_values: Option<BoolSet>,
     /// The state of this epoch's coin.
     coin_state: CoinState<N>,
+    /// The schedule that determines in which epochs a coin is flipped.
+    coin_schedule: CoinSchedule,
+    /// Whether the coin values are provided by the caller instead of our own `Coin` instances.
+    shared_coin: bool,
 }
 
 impl<N: NodeIdT> DistAlgorithm for BinaryAgreement<N> {
@@ -125,9 +131,58 @@ impl<N: NodeIdT> BinaryAgreement<N> {
             incoming_queue: BTreeMap::new(),
             conf_values: None,
             coin_state: CoinState::Decided(true),
+            coin_schedule: CoinSchedule::default(),
+            shared_coin: false,
         })
     }
 
+    /// Sets the schedule that determines in which epochs a threshold signature coin is flipped.
+    /// This must be called before any input or message is handled.
+    pub fn with_coin_schedule(mut self, coin_schedule: CoinSchedule) -> Self {
+        self.coin_schedule = coin_schedule;
+        self.coin_state = self.coin_state();
+        self
+    }
+
+    /// Makes the instance use a coin that is shared with other instances: Instead of flipping its
+    /// own coin, it waits until `handle_shared_coin` is called with the coin's value. This must be
+    /// called before any input or message is handled.
+    pub(crate) fn with_shared_coin(mut self) -> Self {
+        self.shared_coin = true;
+        self.coin_state = self.coin_state();
+        self
+    }
+
+    /// Returns the current epoch if the instance uses a shared coin and needs its value to
+    /// continue, i.e. if the `Conf` round of this epoch has finished.
+    pub(crate) fn awaiting_shared_coin(&self) -> Option<u32> {
+        match self.coin_state {
+            CoinState::Shared
+                if self.decision.is_none()
+                    && self.conf_values.is_some()
+                    && self.conf_weight() >= self.netinfo.correct_weight() =>
+            {
+                Some(self.epoch)
+            }
+            _ => None,
+        }
+    }
+
+    /// Handles the value of the shared coin for the given epoch. Does nothing unless the instance
+    /// is currently waiting for that coin.
+    pub(crate) fn handle_shared_coin(&mut self, epoch: u32, value: bool) -> Result<Step<N>> {
+        if self.awaiting_shared_coin() != Some(epoch) {
+            return Ok(Step::default());
+        }
+        self.coin_state = value.into();
+        self.try_update_epoch()
+    }
+
+    /// Returns the current epoch.
+    pub(crate) fn epoch(&self) -> u32 {
+        self.epoch
+    }
+
     /// Sets the input value for Binary Agreement.
     fn handle_input(&mut self, input: bool) -> Result<Step<N>> {
         if self.epoch != 0 || self.estimated.is_some() {
@@ -180,13 +235,13 @@ impl<N: NodeIdT> BinaryAgreement<N> {
             return Ok(step); // The `Conf` round has already started.
         }
         if let Some(aux_vals) = output.into_iter().next() {
-            // Execute the Coin schedule `false, true, get_coin(), false, true, get_coin(), ...`
+            // Execute the Coin schedule, e.g. `true, false, get_coin(), true, false, get_coin(), ...`
             match self.coin_state {
                 CoinState::Decided(_) => {
                     self.conf_values = Some(aux_vals);
                     step.extend(self.try_update_epoch()?)
                 }
-                CoinState::InProgress(_) => {
+                CoinState::InProgress(_) | CoinState::Shared => {
                     // Start the `Conf` message round.
                     step.extend(self.send_conf(aux_vals)?)
                 }
@@ -210,7 +265,7 @@ impl<N: NodeIdT> BinaryAgreement<N> {
         // Check for the expedite termination condition.
         if self.decision.is_some() {
             Ok(Step::default())
-        } else if self.received_term[b].len() > self.netinfo.num_faulty() {
+        } else if self.netinfo.weight_of(&self.received_term[b]) > self.netinfo.faulty_weight() {
             Ok(self.decide(b))
         } else {
             // Otherwise handle the `Term` as a `BVal`, `Aux` and `Conf`.
@@ -227,6 +282,7 @@ impl<N: NodeIdT> BinaryAgreement<N> {
     fn handle_coin(&mut self, sender_id: &N, msg: CoinMessage) -> Result<Step<N>> {
         let coin_step = match self.coin_state {
             CoinState::Decided(_) => return Ok(Step::default()), // Coin value is already decided.
+            CoinState::Shared => return Ok(Step::default()), // Provided by the caller.
             CoinState::InProgress(ref mut coin) => coin
                 .handle_message(sender_id, msg)
                 .map_err(Error::HandleCoin)?,
@@ -307,9 +363,10 @@ impl<N: NodeIdT> BinaryAgreement<N> {
     /// Creates the initial coin state for the current epoch, i.e. sets it to the predetermined
     /// value, or initializes a `Coin` instance.
     fn coin_state(&self) -> CoinState<N> {
-        match self.epoch % 3 {
-            0 => CoinState::Decided(true),
-            1 => CoinState::Decided(false),
+        match (self.coin_schedule, self.epoch % 3) {
+            (CoinSchedule::TrueFalseCoin, 0) => CoinState::Decided(true),
+            (CoinSchedule::TrueFalseCoin, 1) => CoinState::Decided(false),
+            _ if self.shared_coin => CoinState::Shared,
             _ => {
                 let nonce = Nonce::new(
                     self.netinfo.invocation_id().as_ref(),
@@ -348,13 +405,14 @@ impl<N: NodeIdT> BinaryAgreement<N> {
 
     /// Checks whether the _N - f_ `Conf` messages have arrived, and if so, activates the coin.
     fn try_finish_conf_round(&mut self) -> Result<Step<N>> {
-        if self.conf_values.is_none() || self.count_conf() < self.netinfo.num_correct() {
+        if self.conf_values.is_none() || self.conf_weight() < self.netinfo.correct_weight() {
             return Ok(Step::default());
         }
 
         // Invoke the coin.
         let coin_step = match self.coin_state {
             CoinState::Decided(_) => return Ok(Step::default()), // Coin has already decided.
+            CoinState::Shared => return Ok(Step::default()), // Waiting for the shared coin.
             CoinState::InProgress(ref mut coin) => coin
                 .handle_input(())
                 .map_err(Error::TryFinishConfRoundCoin)?,
@@ -364,10 +422,16 @@ impl<N: NodeIdT> BinaryAgreement<N> {
         Ok(step)
     }
 
-    /// Counts the number of received `Conf` messages with values in `bin_values`.
-    fn count_conf(&self) -> usize {
-        let is_bin_val = |conf: &&BoolSet| conf.is_subset(self.sbv_broadcast.bin_values());
-        self.received_conf.values().filter(is_bin_val).count()
+    /// Returns the total weight of the senders of received `Conf` messages with values in
+    /// `bin_values`.
+    fn conf_weight(&self) -> u64 {
+        let bin_values = self.sbv_broadcast.bin_values();
+        let ids = self
+            .received_conf
+            .iter()
+            .filter(|(_, conf)| conf.is_subset(bin_values))
+            .map(|(id, _)| id);
+        self.netinfo.weight_of(ids)
     }
 
     /// Increments the epoch, sets the new estimate and handles queued messages.
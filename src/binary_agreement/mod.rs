@@ -44,8 +44,10 @@
 //!
 //!   * If both values are candidates, we set `e = s` and proceed to the next epoch.
 //!
-//! In epochs that are 0 modulo 3, the value `s` is `true`. In 1 modulo 3, it is `false`. In the
-//! case 2 modulo 3, we flip a coin to determine a pseudorandom `s`.
+//! By default, following Mostéfaoui et al., in epochs that are 0 modulo 3, the value `s` is `true`.
+//! In 1 modulo 3, it is `false`. In the case 2 modulo 3, we flip a coin to determine a pseudorandom
+//! `s`. Most instances therefore terminate in the first two epochs, without computing any
+//! threshold signatures. Alternatively, `CoinSchedule::AlwaysCoin` flips a coin in every epoch.
 //!
 //! An adversary that knows each coin value, controls a few validators and controls network
 //! scheduling can delay the delivery of `Aux` and `BVal` messages to influence which candidate
@@ -92,6 +94,22 @@ pub enum Error {
 /// An Binary Agreement result.
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+/// The schedule of coin values used in the epochs of Binary Agreement.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CoinSchedule {
+    /// The coin is `true` in epochs that are 0 modulo 3 and `false` in epochs that are 1 modulo 3.
+    /// Only in epochs that are 2 modulo 3 a threshold signature coin is flipped.
+    TrueFalseCoin,
+    /// A threshold signature coin is flipped in every epoch.
+    AlwaysCoin,
+}
+
+impl Default for CoinSchedule {
+    fn default() -> Self {
+        CoinSchedule::TrueFalseCoin
+    }
+}
+
 pub type Step<N> = messaging::Step<BinaryAgreement<N>>;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -112,16 +112,21 @@ impl<N: NodeIdT> SbvBroadcast<N> {
     /// Upon receiving _f + 1_ `BVal(b)`, multicasts `BVal(b)`. Upon receiving _2 f + 1_ `BVal(b)`,
     /// updates `bin_values`. When `bin_values` gets its first entry, multicasts `Aux(b)`.
     pub fn handle_bval(&mut self, sender_id: &N, b: bool) -> Result<Step<N>> {
-        let count_bval = {
+        // The total weight of the `BVal(b)` senders, before and after this message.
+        let (old_weight, new_weight) = {
             if !self.received_bval[b].insert(sender_id.clone()) {
                 return Ok(Fault::new(sender_id.clone(), FaultKind::DuplicateBVal).into());
             }
-            self.received_bval[b].len()
+            let new_weight = self.netinfo.weight_of(&self.received_bval[b]);
+            (new_weight - self.netinfo.weight(sender_id), new_weight)
         };
+        // Returns `true` if this message made the weight reach the given threshold.
+        let reached = |threshold: u64| old_weight < threshold && new_weight >= threshold;
+        let faulty_weight = self.netinfo.faulty_weight();
 
         let mut step = Step::default();
 
-        if count_bval == 2 * self.netinfo.num_faulty() + 1 {
+        if reached(2 * faulty_weight + 1) {
             self.bin_values.insert(b);
 
             if self.bin_values != bool_set::BOTH {
@@ -131,7 +136,7 @@ impl<N: NodeIdT> SbvBroadcast<N> {
             }
         }
 
-        if count_bval == self.netinfo.num_faulty() + 1 {
+        if reached(faulty_weight + 1) {
             step.extend(self.send_bval(b)?);
         }
 
@@ -176,29 +181,29 @@ impl<N: NodeIdT> SbvBroadcast<N> {
         if self.terminated || self.bin_values == bool_set::NONE {
             return Ok(Step::default());
         }
-        let (aux_count, aux_vals) = self.count_aux();
-        if aux_count < self.netinfo.num_correct() {
+        let (aux_weight, aux_vals) = self.count_aux();
+        if aux_weight < self.netinfo.correct_weight() {
             return Ok(Step::default());
         }
         self.terminated = true;
         Ok(Step::default().with_output(aux_vals))
     }
 
-    /// The count of `Aux` messages such that the set of values carried by those messages is a
-    /// subset of `bin_values`.
+    /// The total weight of the senders of `Aux` messages such that the set of values carried by
+    /// those messages is a subset of `bin_values`. Without custom weights, this is their count.
     ///
     /// In general, we can't expect every good node to send the same `Aux` value, so waiting for
     /// _N - f_ agreeing messages would not always terminate. We can, however, expect every good
     /// node to send an `Aux` value that will eventually end up in our `bin_values`.
-    fn count_aux(&self) -> (usize, BoolSet) {
+    fn count_aux(&self) -> (u64, BoolSet) {
         let mut values = bool_set::NONE;
-        let mut count = 0;
+        let mut weight = 0;
         for b in self.bin_values {
             if !self.received_aux[b].is_empty() {
                 values.insert(b);
-                count += self.received_aux[b].len();
+                weight += self.netinfo.weight_of(&self.received_aux[b]);
             }
         }
-        (count, values)
+        (weight, values)
     }
 }
@@ -125,8 +125,10 @@ impl<N: NodeIdT> Broadcast<N> {
     /// Creates a new broadcast instance to be used by node `our_id` which expects a value proposal
     /// from node `proposer_id`.
     pub fn new(netinfo: Arc<NetworkInfo<N>>, proposer_id: N) -> Result<Self> {
-        let parity_shard_num = 2 * netinfo.num_faulty();
-        let data_shard_num = netinfo.num_nodes() - parity_shard_num;
+        // Any set of validators with weight at least `W - 2 f` must be able to decode the value.
+        let min_weight = netinfo.total_weight() - 2 * netinfo.faulty_weight();
+        let data_shard_num = netinfo.min_nodes_with_weight(min_weight);
+        let parity_shard_num = netinfo.num_nodes() - data_shard_num;
         let coding = Coding::new(data_shard_num, parity_shard_num)?;
 
         Ok(Broadcast {
@@ -142,6 +144,11 @@ impl<N: NodeIdT> Broadcast<N> {
         })
     }
 
+    /// Returns `true` if we have already echoed a value from the proposer.
+    pub fn echo_sent(&self) -> bool {
+        self.echo_sent
+    }
+
     /// Breaks the input value into shards of equal length and encodes them --
     /// and some extra parity shards -- with a Reed-Solomon erasure coding
     /// scheme. The returned value contains the shard assigned to this
@@ -261,11 +268,12 @@ impl<N: NodeIdT> Broadcast<N> {
         // Save the proof for reconstructing the tree later.
         self.echos.insert(sender_id.clone(), p);
 
-        if self.ready_sent || self.count_echos(&hash) < self.netinfo.num_correct() {
+        if self.ready_sent || self.echo_weight(&hash) < self.netinfo.correct_weight() {
             return self.compute_output(&hash);
         }
 
-        // Upon receiving `N - f` `Echo`s with this root hash, multicast `Ready`.
+        // Upon receiving `N - f` `Echo`s with this root hash, multicast `Ready`. With weighted
+        // validators, the `Echo`s' senders need to have a total weight of at least `W - f`.
         self.send_ready(&hash)
     }
 
@@ -286,7 +294,7 @@ impl<N: NodeIdT> Broadcast<N> {
         let mut step = Step::default();
         // Upon receiving f + 1 matching Ready(h) messages, if Ready
         // has not yet been sent, multicast Ready(h).
-        if self.count_readys(hash) == self.netinfo.num_faulty() + 1 && !self.ready_sent {
+        if self.ready_weight(hash) > self.netinfo.faulty_weight() && !self.ready_sent {
             // Enqueue a broadcast of a Ready message.
             step.extend(self.send_ready(hash)?);
         }
@@ -324,7 +332,7 @@ impl<N: NodeIdT> Broadcast<N> {
     /// value.
     fn compute_output(&mut self, hash: &Digest) -> Result<Step<N>> {
         if self.decided
-            || self.count_readys(hash) <= 2 * self.netinfo.num_faulty()
+            || self.ready_weight(hash) <= 2 * self.netinfo.faulty_weight()
             || self.count_echos(hash) < self.coding.data_shard_count()
         {
             return Ok(Step::default());
@@ -383,12 +391,24 @@ impl<N: NodeIdT> Broadcast<N> {
             .count()
     }
 
-    /// Returns the number of nodes that have sent us a `Ready` message with this hash.
-    fn count_readys(&self, hash: &Digest) -> usize {
-        self.readys
-            .values()
-            .filter(|h| h.as_slice() == hash)
-            .count()
+    /// Returns the total weight of the nodes that have sent us an `Echo` message with this hash.
+    fn echo_weight(&self, hash: &Digest) -> u64 {
+        let ids = self
+            .echos
+            .iter()
+            .filter(|(_, p)| p.root_hash() == hash)
+            .map(|(id, _)| id);
+        self.netinfo.weight_of(ids)
+    }
+
+    /// Returns the total weight of the nodes that have sent us a `Ready` message with this hash.
+    fn ready_weight(&self, hash: &Digest) -> u64 {
+        let ids = self
+            .readys
+            .iter()
+            .filter(|(_, h)| h.as_slice() == hash)
+            .map(|(id, _)| id);
+        self.netinfo.weight_of(ids)
     }
 }
 
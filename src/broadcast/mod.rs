@@ -41,6 +41,10 @@
 //! * So a node with _2 f + 1_ `Ready`s and _N - 2 f_ `Echos` will decode and _output_ the value,
 //! knowing that every other correct node will eventually do the same.
 //!
+//! With weighted validators, the thresholds above refer to the senders' total weight _W_ instead
+//! of their number, and to the maximum faulty weight instead of _f_. The value is split into as
+//! many data chunks as the smallest set of validators with weight _W - 2 f_ has members.
+//!
 //! ## Example
 //!
 //! In this example, we manually pass messages between instantiated nodes to simulate a network. The
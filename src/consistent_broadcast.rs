@@ -0,0 +1,341 @@
+//! # Consistent Broadcast
+//!
+//! Like `Broadcast`, this allows one node, the _proposer_, to send a value to all other nodes, with
+//! the guarantee that either none or all of the correct nodes output a value, and that they all
+//! output the same one. Instead of erasure coding and Merkle proofs, it uses signatures, which is
+//! cheaper for small values.
+//!
+//! ## How it works
+//!
+//! * The proposer sends `Value(v)` to everyone.
+//! * Every validator that receives `Value(v)` from the proposer signs `v`, together with the
+//! session ID and the proposer, with its own secret key, and sends the signature back to the
+//! proposer in an `Echo` message. Any further `Value` messages are ignored.
+//! * Once the proposer has received _⌈(N + f + 1) / 2⌉_ valid `Echo`s, it sends `v` together with
+//! these signatures to everyone, in a `Final` message.
+//! * Any two sets of _⌈(N + f + 1) / 2⌉_ validators have at least one correct validator in common,
+//! so there cannot be valid `Final` messages for two different values. A node that receives a valid
+//! `Final` message outputs `v`, and forwards the `Final` message to everyone else, so that all
+//! correct nodes output `v` once one of them has.
+//!
+//! The proposer sends only _N_ `Value` and `Final` messages, and every node sends a single `Echo`,
+//! but the forwarded `Final` messages contain _⌈(N + f + 1) / 2⌉_ signatures each. So this is
+//! preferable to `Broadcast` for small values in small networks: `Subset` can be configured to use
+//! it for values up to a given size.
+//!
+//! With weighted validators, the signers need to have a total weight of at least
+//! _⌈(W + f + 1) / 2⌉_ instead, where _W_ is the total weight and _f_ the maximum faulty weight.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+use crypto::{SecretKey, Signature};
+use rand;
+use tiny_keccak::sha3_256;
+
+use fault_log::{Fault, FaultKind};
+use fmt::HexBytes;
+use messaging::{self, DistAlgorithm, NetworkInfo, Target};
+use traits::NodeIdT;
+
+/// A consistent broadcast error.
+#[derive(Clone, PartialEq, Debug, Fail)]
+pub enum Error {
+    #[fail(display = "Instance cannot propose")]
+    InstanceCannotPropose,
+    #[fail(display = "Multiple inputs")]
+    MultipleInputs,
+    #[fail(display = "Unknown proposer")]
+    UnknownProposer,
+    #[fail(display = "Unknown sender")]
+    UnknownSender,
+}
+
+/// A consistent broadcast result.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// The three kinds of message sent during consistent broadcast.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+pub enum Message {
+    /// The proposer's value.
+    Value(Vec<u8>),
+    /// A validator's signature of the value, sent to the proposer.
+    Echo(Signature),
+    /// The value, together with enough validators' signatures, by node index.
+    Final(Vec<u8>, BTreeMap<u64, Signature>),
+}
+
+// A random generation impl is provided for test cases. Unfortunately `#[cfg(test)]` does not work
+// for integration tests.
+impl rand::Rand for Message {
+    fn rand<R: rand::Rng>(rng: &mut R) -> Self {
+        let message_type = *rng.choose(&["value", "echo", "final"]).unwrap();
+
+        let mut value = vec![0; 32];
+        rng.fill_bytes(&mut value);
+        let sk: SecretKey = rng.gen();
+        let sig = sk.sign(&value);
+
+        match message_type {
+            "value" => Message::Value(value),
+            "echo" => Message::Echo(sig),
+            "final" => Message::Final(value, Some((0, sig)).into_iter().collect()),
+            _ => unreachable!(),
+        }
+    }
+}
+
+impl Debug for Message {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Message::Value(ref v) => f.debug_tuple("Value").field(&HexBytes(v)).finish(),
+            Message::Echo(ref sig) => f.debug_tuple("Echo").field(sig).finish(),
+            Message::Final(ref v, ref sigs) => f
+                .debug_tuple("Final")
+                .field(&HexBytes(v))
+                .field(&sigs.keys().collect::<Vec<_>>())
+                .finish(),
+        }
+    }
+}
+
+/// Consistent broadcast algorithm instance.
+#[derive(Debug)]
+pub struct ConsistentBroadcast<N> {
+    /// Shared network data.
+    netinfo: Arc<NetworkInfo<N>>,
+    /// Session ID, e.g. the `Subset` session.
+    session_id: u64,
+    /// The ID of the sending node.
+    proposer_id: N,
+    /// The index of the sending node.
+    proposer_index: usize,
+    /// Our own input, if we are the proposer.
+    value: Option<Vec<u8>>,
+    /// Whether we have already sent `Echo`.
+    echo_sent: bool,
+    /// The valid signatures we have received via `Echo` messages, if we are the proposer.
+    echos: BTreeMap<N, Signature>,
+    /// Whether we have already multicast `Final`.
+    final_sent: bool,
+    /// Whether we have already output a value.
+    decided: bool,
+}
+
+pub type Step<N> = messaging::Step<ConsistentBroadcast<N>>;
+
+impl<N: NodeIdT> DistAlgorithm for ConsistentBroadcast<N> {
+    type NodeId = N;
+    type Input = Vec<u8>;
+    type Output = Self::Input;
+    type Message = Message;
+    type Error = Error;
+
+    fn handle_input(&mut self, input: Self::Input) -> Result<Step<N>> {
+        if *self.netinfo.our_id() != self.proposer_id {
+            return Err(Error::InstanceCannotPropose);
+        }
+        if self.value.is_some() {
+            return Err(Error::MultipleInputs);
+        }
+        self.value = Some(input.clone());
+        let mut step: Step<_> = Target::All.message(Message::Value(input.clone())).into();
+        let our_id = &self.netinfo.our_id().clone();
+        step.extend(self.handle_value(our_id, input)?);
+        Ok(step)
+    }
+
+    fn handle_message(&mut self, sender_id: &N, message: Self::Message) -> Result<Step<N>> {
+        if !self.netinfo.is_node_validator(sender_id) {
+            return Err(Error::UnknownSender);
+        }
+        match message {
+            Message::Value(v) => self.handle_value(sender_id, v),
+            Message::Echo(sig) => self.handle_echo(sender_id, sig),
+            Message::Final(v, sigs) => self.handle_final(sender_id, v, &sigs),
+        }
+    }
+
+    fn terminated(&self) -> bool {
+        self.decided
+    }
+
+    fn our_id(&self) -> &N {
+        self.netinfo.our_id()
+    }
+}
+
+impl<N: NodeIdT> ConsistentBroadcast<N> {
+    /// Creates a new consistent broadcast instance to be used by node `our_id` which expects a
+    /// value proposal from node `proposer_id`. The session ID must be unique, so that signatures
+    /// cannot be reused across instances.
+    pub fn new(netinfo: Arc<NetworkInfo<N>>, session_id: u64, proposer_id: N) -> Result<Self> {
+        let proposer_index = netinfo
+            .node_index(&proposer_id)
+            .ok_or(Error::UnknownProposer)?;
+        Ok(ConsistentBroadcast {
+            netinfo,
+            session_id,
+            proposer_id,
+            proposer_index,
+            value: None,
+            echo_sent: false,
+            echos: BTreeMap::new(),
+            final_sent: false,
+            decided: false,
+        })
+    }
+
+    /// Returns `true` if we have already signed a value from the proposer.
+    pub fn echo_sent(&self) -> bool {
+        self.echo_sent
+    }
+
+    /// Handles a received value: Signs it and sends the signature to the proposer.
+    fn handle_value(&mut self, sender_id: &N, v: Vec<u8>) -> Result<Step<N>> {
+        // If the sender is not the proposer or if this is not the first `Value`, ignore.
+        if *sender_id != self.proposer_id {
+            info!(
+                "Node {:?} received Value from {:?} instead of {:?}.",
+                self.netinfo.our_id(),
+                sender_id,
+                self.proposer_id
+            );
+            let fault_kind = FaultKind::ReceivedValueFromNonProposer;
+            return Ok(Fault::new(sender_id.clone(), fault_kind).into());
+        }
+        if self.echo_sent {
+            info!("Node {:?} received multiple Values.", self.netinfo.our_id());
+            return Ok(Step::default());
+        }
+        self.echo_sent = true;
+        if !self.netinfo.is_validator() {
+            return Ok(Step::default());
+        }
+        let sig = self.netinfo.secret_key().sign(self.signed_doc(&v));
+        if *self.netinfo.our_id() == self.proposer_id {
+            let our_id = &self.netinfo.our_id().clone();
+            self.handle_echo(our_id, sig)
+        } else {
+            let echo_msg = Message::Echo(sig);
+            Ok(Target::Node(self.proposer_id.clone())
+                .message(echo_msg)
+                .into())
+        }
+    }
+
+    /// Handles a received `Echo` message. Once we have enough of them, sends `Final`.
+    fn handle_echo(&mut self, sender_id: &N, sig: Signature) -> Result<Step<N>> {
+        if self.final_sent || self.echos.contains_key(sender_id) {
+            return Ok(Step::default());
+        }
+        let is_valid = match (self.value.as_ref(), self.netinfo.public_key(sender_id)) {
+            (Some(v), Some(pk)) => pk.verify(&sig, self.signed_doc(v)),
+            (_, _) => false, // We are not the proposer, or the sender is unknown.
+        };
+        if !is_valid {
+            return Ok(Fault::new(sender_id.clone(), FaultKind::InvalidEcho).into());
+        }
+        self.echos.insert(sender_id.clone(), sig);
+        if self.netinfo.weight_of(self.echos.keys()) < self.quorum() {
+            return Ok(Step::default());
+        }
+        let sigs = {
+            let to_idx = |(id, sig): (&N, &Signature)| {
+                let idx = self
+                    .netinfo
+                    .node_index(id)
+                    .expect("echo senders are validators");
+                (idx as u64, sig.clone())
+            };
+            self.echos.iter().map(to_idx).collect()
+        };
+        let v = self
+            .value
+            .clone()
+            .expect("only the proposer collects echos");
+        self.send_final(v, sigs)
+    }
+
+    /// Handles a received `Final` message: If it is valid, outputs the value.
+    fn handle_final(
+        &mut self,
+        sender_id: &N,
+        v: Vec<u8>,
+        sigs: &BTreeMap<u64, Signature>,
+    ) -> Result<Step<N>> {
+        if self.decided {
+            return Ok(Step::default());
+        }
+        if !self.is_final_valid(&v, sigs) {
+            return Ok(Fault::new(sender_id.clone(), FaultKind::InvalidFinal).into());
+        }
+        self.decided = true;
+        let mut step = if self.final_sent {
+            Step::default()
+        } else {
+            // Forward the message, so that every correct node outputs.
+            self.send_final(v.clone(), sigs.clone())?
+        };
+        debug!(
+            "Node {:?} output {:?} from {:?}",
+            self.netinfo.our_id(),
+            HexBytes(&v),
+            self.proposer_id
+        );
+        step.output.push_back(v);
+        Ok(step)
+    }
+
+    /// Sends a `Final` message and handles it. Does nothing if we are only an observer.
+    fn send_final(&mut self, v: Vec<u8>, sigs: BTreeMap<u64, Signature>) -> Result<Step<N>> {
+        self.final_sent = true;
+        let mut step = Step::default();
+        if self.netinfo.is_validator() {
+            let final_msg = Message::Final(v.clone(), sigs.clone());
+            step.messages.push_back(Target::All.message(final_msg));
+        }
+        let our_id = &self.netinfo.our_id().clone();
+        step.extend(self.handle_final(our_id, v, &sigs)?);
+        Ok(step)
+    }
+
+    /// Returns `true` if `sigs` contains valid signatures of `v` with at least `quorum` weight.
+    fn is_final_valid(&self, v: &[u8], sigs: &BTreeMap<u64, Signature>) -> bool {
+        let signer_ids: Option<Vec<&N>> = sigs
+            .keys()
+            .map(|idx| self.netinfo.all_ids().nth(*idx as usize))
+            .collect();
+        let signer_ids = match signer_ids {
+            Some(ids) => ids,
+            None => return false, // Unknown signer.
+        };
+        if self.netinfo.weight_of(signer_ids.iter().cloned()) < self.quorum() {
+            return false;
+        }
+        let doc = self.signed_doc(v);
+        signer_ids.into_iter().zip(sigs.values()).all(|(id, sig)| {
+            let opt_pk = self.netinfo.public_key(id);
+            opt_pk.map_or(false, |pk| pk.verify(sig, &doc))
+        })
+    }
+
+    /// Returns the signers' total weight that proves that no other value can be delivered:
+    /// _⌈(N + f + 1) / 2⌉_, or _⌈(W + f + 1) / 2⌉_ with weighted validators.
+    fn quorum(&self) -> u64 {
+        (self.netinfo.total_weight() + self.netinfo.faulty_weight() + 2) / 2
+    }
+
+    /// Returns the document the validators sign to echo the value `v`.
+    fn signed_doc(&self, v: &[u8]) -> Vec<u8> {
+        let mut doc = Vec::from(format!(
+            "Consistent broadcast {:?}@{}:{}",
+            self.netinfo.invocation_id().as_ref(),
+            self.session_id,
+            self.proposer_index
+        ));
+        doc.extend_from_slice(&sha3_256(v));
+        doc
+    }
+}
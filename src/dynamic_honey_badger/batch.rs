@@ -1,13 +1,27 @@
 use std::collections::BTreeMap;
+use std::mem;
 
+use bincode;
 use rand::Rand;
 use serde::{Deserialize, Serialize};
+use tiny_keccak::sha3_256;
 
-use super::{ChangeState, JoinPlan};
+use super::votes::Vote;
+use super::{ChangeState, ErrorKind, JoinPlan, Params, Result};
 use crypto::{PublicKey, PublicKeySet};
-use messaging::NetworkInfo;
+use messaging::{NetworkInfo, Threshold};
 use traits::NodeIdT;
 
+/// The public information a `JoinPlan` is created from: the public key set, the validators'
+/// public keys, the threshold rule, the parameters, and the votes carried over into the new era.
+type JoinInfo<N> = (
+    PublicKeySet,
+    BTreeMap<N, PublicKey>,
+    Threshold,
+    Params,
+    BTreeMap<N, (Vote<N>, u64)>,
+);
+
 /// A batch of transactions the algorithm has output.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Batch<C, N> {
@@ -15,11 +29,14 @@ pub struct Batch<C, N> {
     pub(super) epoch: u64,
     /// The user contributions committed in this epoch.
     pub(super) contributions: BTreeMap<N, C>,
+    /// The agreed time of the batch, in seconds since the Unix epoch.
+    pub(super) timestamp: u64,
     /// The current state of adding or removing a node: whether any is in progress, or completed
     /// this epoch.
     change: ChangeState<N>,
-    /// The public network info, if `change` is not `None`.
-    pub_netinfo: Option<(PublicKeySet, BTreeMap<N, PublicKey>)>,
+    /// The public network info, threshold rule, parameters and carried over votes, if a new era
+    /// begins in the next epoch.
+    pub_netinfo: Option<JoinInfo<N>>,
 }
 
 impl<C, N: NodeIdT + Rand> Batch<C, N> {
@@ -28,6 +45,7 @@ impl<C, N: NodeIdT + Rand> Batch<C, N> {
         Batch {
             epoch,
             contributions: BTreeMap::new(),
+            timestamp: 0,
             change: ChangeState::None,
             pub_netinfo: None,
         }
@@ -37,6 +55,13 @@ impl<C, N: NodeIdT + Rand> Batch<C, N> {
         self.epoch
     }
 
+    /// Returns the agreed time of the batch in seconds since the Unix epoch: the weighted median of
+    /// the timestamps the accepted proposers attached to their contributions, but not earlier than
+    /// the previous batch's timestamp.
+    pub fn timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
     /// Returns whether any change to the set of participating nodes is in progress or was
     /// completed in this epoch.
     pub fn change(&self) -> &ChangeState<N> {
@@ -59,6 +84,18 @@ impl<C, N: NodeIdT + Rand> Batch<C, N> {
         self.contributions.into_iter().flat_map(|(_, vec)| vec)
     }
 
+    /// Returns an iterator over the contributions in the batch, together with the IDs of the
+    /// validators that proposed them.
+    pub fn contributions<'a>(&'a self) -> impl Iterator<Item = (&'a N, &'a C)> {
+        self.contributions.iter()
+    }
+
+    /// Returns the number of contributions in the batch, i.e. the number of proposers whose
+    /// contributions were accepted, including empty ones.
+    pub fn num_contributions(&self) -> usize {
+        self.contributions.len()
+    }
+
     /// Returns the number of transactions in the batch (without detecting duplicates).
     pub fn len<T>(&self) -> usize
     where
@@ -88,25 +125,115 @@ impl<C, N: NodeIdT + Rand> Batch<C, N> {
     where
         N: Serialize + for<'r> Deserialize<'r>,
     {
-        self.pub_netinfo
-            .as_ref()
-            .map(|&(ref pub_key_set, ref pub_keys)| JoinPlan {
+        self.pub_netinfo.as_ref().map(
+            |&(ref pub_key_set, ref pub_keys, threshold, ref params, ref votes)| JoinPlan {
                 epoch: self.epoch + 1,
                 change: self.change.clone(),
                 pub_key_set: pub_key_set.clone(),
                 pub_keys: pub_keys.clone(),
-            })
+                threshold,
+                params: params.clone(),
+                votes: votes.clone(),
+                timestamp: self.timestamp,
+            },
+        )
     }
 
     /// Sets the current change state, and if it is not `None`, inserts the network information so
-    /// that a `JoinPlan` can be generated for the next epoch.
-    pub(super) fn set_change(&mut self, change: ChangeState<N>, netinfo: &NetworkInfo<N>) {
+    /// that a `JoinPlan` can be generated for the next epoch. `votes` are the committed votes that
+    /// were carried over into the new era.
+    pub(super) fn set_change(
+        &mut self,
+        change: ChangeState<N>,
+        netinfo: &NetworkInfo<N>,
+        threshold: Threshold,
+        params: &Params,
+        votes: BTreeMap<N, (Vote<N>, u64)>,
+    ) {
         self.change = change;
         if self.change != ChangeState::None {
             self.pub_netinfo = Some((
                 netinfo.public_key_set().clone(),
                 netinfo.public_key_map().clone(),
+                threshold,
+                params.clone(),
+                votes,
             ));
         }
     }
+
+    /// Sets the current change state without network information. This is used for `Parameters`
+    /// changes, which don't start a new era: No `JoinPlan` can be generated for the next epoch,
+    /// since the new node wouldn't know the votes and key generation messages of the current era.
+    pub(super) fn set_params_change(&mut self, change: ChangeState<N>) {
+        self.change = change;
+    }
+}
+
+impl<T, N: NodeIdT + Rand> Batch<Vec<T>, N> {
+    /// Returns the transactions in the batch in a canonical order, without duplicates.
+    ///
+    /// The transactions are sorted by the hash of a seed together with the serialized transaction,
+    /// where the seed is the hash of all contributions in the batch. This order is the same on
+    /// every node, but it is pseudorandom: Since the contributions are only revealed once they have
+    /// been agreed on, a submitter can't choose a transaction's contents to make it come first.
+    ///
+    /// Returns an error if the contributions can't be serialized.
+    pub fn canonical_txs(&self) -> Result<Vec<&T>>
+    where
+        T: Serialize + Eq,
+        N: Serialize,
+    {
+        let ser_contribs = bincode::serialize(&self.contributions)
+            .map_err(|err| ErrorKind::CanonicalTxsBincode(*err))?;
+        let seed = sha3_256(&ser_contribs);
+        let mut txs: Vec<([u8; 32], &T)> = Vec::new();
+        for tx in self.iter() {
+            let ser_tx = bincode::serialize(&(&seed, tx))
+                .map_err(|err| ErrorKind::CanonicalTxsBincode(*err))?;
+            txs.push((sha3_256(&ser_tx), tx));
+        }
+        txs.sort_by_key(|&(hash, _)| hash);
+        txs.dedup();
+        Ok(txs.into_iter().map(|(_, tx)| tx).collect())
+    }
+
+    /// Replaces each transaction with the value `f` returns for it and its proposer's ID, or
+    /// removes it if that is `None`. The transactions are passed to `f` in the same order as by
+    /// `iter`.
+    pub(crate) fn filter_map_txs<F>(&mut self, mut f: F)
+    where
+        F: FnMut(&N, T) -> Option<T>,
+    {
+        for (id, txs) in &mut self.contributions {
+            *txs = mem::replace(txs, Vec::new())
+                .into_iter()
+                .filter_map(|tx| f(id, tx))
+                .collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Batch;
+
+    #[test]
+    fn test_canonical_txs() {
+        let mut batch: Batch<Vec<u32>, usize> = Batch::new(5);
+        batch.contributions.insert(0, vec![1, 2, 3]);
+        batch.contributions.insert(1, vec![3, 4, 1]);
+        batch.contributions.insert(2, vec![4, 4]);
+        let canonical = batch.canonical_txs().expect("canonical transactions");
+
+        // Every transaction appears once, even if it was proposed several times.
+        let mut txs: Vec<u32> = canonical.iter().map(|tx| **tx).collect();
+        txs.sort();
+        assert_eq!(vec![1, 2, 3, 4], txs);
+
+        // The order only depends on the batch's contents.
+        let copy = batch.clone();
+        let copy_canonical = copy.canonical_txs().expect("canonical transactions");
+        assert_eq!(canonical, copy_canonical);
+    }
 }
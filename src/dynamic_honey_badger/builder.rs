@@ -1,38 +1,45 @@
+use std::collections::BTreeMap;
 use std::default::Default;
 use std::iter::once;
 use std::marker::PhantomData;
 use std::sync::Arc;
 
-use crypto::{SecretKey, SecretKeySet, SecretKeyShare};
+use crypto::{PublicKey, SecretKey, SecretKeySet, SecretKeyShare};
 use rand::{self, Rand, Rng};
 use serde::{Deserialize, Serialize};
 
-use super::{ChangeState, DynamicHoneyBadger, JoinPlan, Result, Step, VoteCounter};
-use honey_badger::{HoneyBadger, SubsetHandlingStrategy};
-use messaging::NetworkInfo;
+use super::{
+    ChangeState, DynamicHoneyBadger, ErrorKind, JoinPlan, Params, Result, SignedJoinPlan, Step,
+    VoteCounter,
+};
+use honey_badger::{EncryptionSchedule, HoneyBadger, SubsetHandlingStrategy};
+use messaging::{NetworkInfo, Threshold};
+use sync_key_gen::ShareValue;
 use traits::{Contribution, NodeIdT};
 use util::SubRng;
 
 /// A Dynamic Honey Badger builder, to configure the parameters and create new instances of
 /// `DynamicHoneyBadger`.
 pub struct DynamicHoneyBadgerBuilder<C, N> {
-    /// The maximum number of future epochs for which we handle messages simultaneously.
-    max_future_epochs: usize,
+    /// The initial protocol parameters.
+    params: Params,
     /// Random number generator passed on to algorithm instance for key generation. Also used to
     /// instantiate `HoneyBadger`.
     rng: Box<dyn rand::Rng>,
-    /// Strategy used to handle the output of the `Subset` algorithm.
-    subset_handling_strategy: SubsetHandlingStrategy,
+    /// The rule that determines the threshold for key generation.
+    threshold: Threshold,
+    /// The value of our initial secret key share, if known.
+    share_value: Option<ShareValue>,
     _phantom: PhantomData<(C, N)>,
 }
 
 impl<C, N> Default for DynamicHoneyBadgerBuilder<C, N> {
     fn default() -> Self {
-        // TODO: Use the defaults from `HoneyBadgerBuilder`.
         DynamicHoneyBadgerBuilder {
-            max_future_epochs: 3,
+            params: Params::default(),
             rng: Box::new(rand::thread_rng()),
-            subset_handling_strategy: SubsetHandlingStrategy::Incremental,
+            threshold: Threshold::default(),
+            share_value: None,
             _phantom: PhantomData,
         }
     }
@@ -51,7 +58,7 @@ where
 
     /// Sets the maximum number of future epochs for which we handle messages simultaneously.
     pub fn max_future_epochs(&mut self, max_future_epochs: usize) -> &mut Self {
-        self.max_future_epochs = max_future_epochs;
+        self.params.max_future_epochs = max_future_epochs;
         self
     }
 
@@ -66,36 +73,90 @@ where
         &mut self,
         subset_handling_strategy: SubsetHandlingStrategy,
     ) -> &mut Self {
-        self.subset_handling_strategy = subset_handling_strategy;
+        self.params.subset_handling_strategy = subset_handling_strategy;
+        self
+    }
+
+    /// Sets the number of epochs for which a committed vote counts, or `None` if votes should
+    /// never expire.
+    pub fn vote_expiry(&mut self, vote_expiry: Option<u64>) -> &mut Self {
+        self.params.vote_expiry = vote_expiry;
+        self
+    }
+
+    /// Sets the epochs in which the contributions are encrypted.
+    pub fn encryption_schedule(&mut self, encryption_schedule: EncryptionSchedule) -> &mut Self {
+        self.params.encryption_schedule = encryption_schedule;
+        self
+    }
+
+    /// Sets all protocol parameters. They must be the same for all nodes, and can later be
+    /// changed by a `Change::Parameters` vote.
+    pub fn params(&mut self, params: Params) -> &mut Self {
+        self.params = params;
+        self
+    }
+
+    /// Sets the rule that determines the threshold of the keys created by key generation, whenever
+    /// the set of validators changes. It must be the same for all nodes. A threshold higher than
+    /// the default `Threshold::Faulty` requires more nodes to cooperate to decrypt contributions.
+    ///
+    /// This doesn't affect the initial keys: Their threshold is that of the `NetworkInfo`.
+    pub fn threshold(&mut self, threshold: Threshold) -> &mut Self {
+        self.threshold = threshold;
+        self
+    }
+
+    /// Sets the value of the initial secret key share, as returned by
+    /// `SyncKeyGen::generate_share` or `DynamicHoneyBadger::share_value`. Without it, the node
+    /// cannot take part in resharing the initial key set.
+    ///
+    /// It must match the secret key share of the `NetworkInfo` passed to `build`.
+    pub fn share_value(&mut self, share_value: Option<ShareValue>) -> &mut Self {
+        self.share_value = share_value;
         self
     }
 
     /// Creates a new Dynamic Honey Badger instance with an empty buffer.
-    pub fn build(&mut self, netinfo: NetworkInfo<N>) -> DynamicHoneyBadger<C, N> {
+    ///
+    /// Returns an error if the validators in `netinfo` have custom weights: Key generation and
+    /// `JoinPlan`s don't support weights, so they would be lost with the first change.
+    pub fn build(&mut self, netinfo: NetworkInfo<N>) -> Result<DynamicHoneyBadger<C, N>> {
+        if netinfo.is_weighted() {
+            return Err(ErrorKind::WeightedValidators.into());
+        }
         let DynamicHoneyBadgerBuilder {
-            max_future_epochs,
+            params,
             rng,
-            subset_handling_strategy,
+            threshold,
+            share_value,
             _phantom,
         } = self;
-        let max_future_epochs = *max_future_epochs;
         let arc_netinfo = Arc::new(netinfo.clone());
         let honey_badger = HoneyBadger::builder(arc_netinfo.clone())
-            .max_future_epochs(max_future_epochs)
+            .max_future_epochs(params.max_future_epochs)
             .rng(rng.sub_rng())
-            .subset_handling_strategy(subset_handling_strategy.clone())
+            .subset_handling_strategy(params.subset_handling_strategy.clone())
+            .encryption_schedule(params.encryption_schedule)
             .build();
-        DynamicHoneyBadger {
+        Ok(DynamicHoneyBadger {
             netinfo,
-            max_future_epochs,
+            params: params.clone(),
+            threshold: *threshold,
+            era: 0,
             start_epoch: 0,
-            vote_counter: VoteCounter::new(arc_netinfo, 0),
+            vote_counter: VoteCounter::new(arc_netinfo, 0, *threshold),
             key_gen_msg_buffer: Vec::new(),
             honey_badger,
             key_gen_state: None,
+            share_value: share_value.clone(),
             incoming_queue: Vec::new(),
+            join_plan_signings: BTreeMap::new(),
+            join_plan_sig_queue: BTreeMap::new(),
+            signed_join_plan: None,
+            era_certificates: Vec::new(),
             rng: Box::new(rng.sub_rng()),
-        }
+        })
     }
 
     /// Creates a new `DynamicHoneyBadger` configured to start a new network as a single validator.
@@ -106,11 +167,17 @@ where
         let sk: SecretKey = self.rng.gen();
         let pub_keys = once((our_id.clone(), sk.public_key())).collect();
         let netinfo = NetworkInfo::new(our_id, sks, pk_set, sk, pub_keys);
-        Ok(self.build(netinfo))
+        self.build(netinfo)
     }
 
     /// Creates a new `DynamicHoneyBadger` configured to join the network at the epoch specified in
-    /// the `JoinPlan`.
+    /// the `JoinPlan`. The threshold rule and the protocol parameters are taken from the
+    /// `JoinPlan`, too.
+    ///
+    /// The `JoinPlan` must come from a trusted source. Use `build_joining_signed` otherwise.
+    ///
+    /// Returns an error if the threshold of the plan's public key set is invalid for its number of
+    /// validators.
     pub fn build_joining(
         &mut self,
         our_id: N,
@@ -124,25 +191,86 @@ where
             secret_key,
             join_plan.pub_keys,
         );
+        if !netinfo.is_threshold_valid() {
+            let threshold = Threshold::Fixed(netinfo.threshold());
+            return Err(ErrorKind::InvalidThreshold(threshold, netinfo.num_nodes()).into());
+        }
         let arc_netinfo = Arc::new(netinfo.clone());
         let honey_badger = HoneyBadger::builder(arc_netinfo.clone())
-            .max_future_epochs(self.max_future_epochs)
+            .max_future_epochs(join_plan.params.max_future_epochs)
+            .subset_handling_strategy(join_plan.params.subset_handling_strategy.clone())
+            .encryption_schedule(join_plan.params.encryption_schedule)
+            .min_timestamp(join_plan.timestamp)
             .build();
         let mut dhb = DynamicHoneyBadger {
             netinfo,
-            max_future_epochs: self.max_future_epochs,
+            params: join_plan.params,
+            threshold: join_plan.threshold,
+            era: join_plan.epoch,
             start_epoch: join_plan.epoch,
-            vote_counter: VoteCounter::new(arc_netinfo, join_plan.epoch),
+            vote_counter: VoteCounter::new(arc_netinfo, join_plan.epoch, join_plan.threshold),
             key_gen_msg_buffer: Vec::new(),
             honey_badger,
             key_gen_state: None,
+            share_value: None,
             incoming_queue: Vec::new(),
+            join_plan_signings: BTreeMap::new(),
+            join_plan_sig_queue: BTreeMap::new(),
+            signed_join_plan: None,
+            era_certificates: Vec::new(),
             rng: Box::new(self.rng.sub_rng()),
         };
         let step = match join_plan.change {
             ChangeState::InProgress(ref change) => dhb.update_key_gen(join_plan.epoch, change)?,
             ChangeState::None | ChangeState::Complete(..) => Step::default(),
         };
+        dhb.vote_counter.carry_over(join_plan.votes);
+        Ok((dhb, step))
+    }
+
+    /// Creates a new `DynamicHoneyBadger` configured to join the network with a `JoinPlan` that
+    /// was threshold-signed by the validators, so that it can be obtained from an untrusted source.
+    ///
+    /// `trusted_key` is a public master key of the validators that is already known to be genuine,
+    /// and `certificates` are the era certificates that lead from it to the key that signed the
+    /// plan: Each must be signed with the master key introduced by the previous one, and the first
+    /// with `trusted_key`. Certificates from the plan's epoch or later are ignored, so
+    /// `DynamicHoneyBadger::era_certificates` can be passed as is. If the plan is signed with
+    /// `trusted_key` itself, `certificates` can be empty.
+    ///
+    /// Note that the signature doesn't prove that the plan is recent: If it is outdated, the new
+    /// node will not be able to follow the network.
+    ///
+    /// Returns an error if any of the signatures is invalid.
+    pub fn build_joining_signed(
+        &mut self,
+        our_id: N,
+        secret_key: SecretKey,
+        trusted_key: &PublicKey,
+        mut certificates: Vec<SignedJoinPlan<N>>,
+        signed_plan: SignedJoinPlan<N>,
+    ) -> Result<(DynamicHoneyBadger<C, N>, Step<C, N>)> {
+        certificates.retain(|cert| cert.epoch() < signed_plan.epoch());
+        let mut key = trusted_key.clone();
+        let mut epoch = 0;
+        for cert in &certificates {
+            if cert.epoch() < epoch || !cert.verify(&key)? {
+                return Err(ErrorKind::InvalidJoinPlanSignature.into());
+            }
+            key = cert.public_key();
+            epoch = cert.epoch();
+        }
+        if signed_plan.epoch() < epoch || !signed_plan.verify(&key)? {
+            return Err(ErrorKind::InvalidJoinPlanSignature.into());
+        }
+        if signed_plan.public_key() != key {
+            // The plan itself introduces a new master key.
+            certificates.push(signed_plan.clone());
+        }
+        let (mut dhb, step) =
+            self.build_joining(our_id, secret_key, signed_plan.join_plan().clone())?;
+        dhb.era_certificates = certificates;
+        dhb.signed_join_plan = Some(signed_plan);
         Ok((dhb, step))
     }
 }
@@ -1,20 +1,86 @@
+use std::collections::BTreeMap;
+
 use crypto::PublicKey;
 
-/// A node change action: adding or removing a node.
+use super::Params;
+use messaging::NetworkInfo;
+use traits::NodeIdT;
+
+/// A node change action: adding or removing a node, replacing the whole set of validators,
+/// refreshing the validators' key shares, or changing the protocol parameters.
 #[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Hash, Debug)]
 pub enum Change<N> {
     /// Add a node. The public key is used only temporarily, for key generation.
     Add(N, PublicKey),
     /// Remove a node.
     Remove(N),
+    /// Replace the set of validators with the given one, in a single key generation. The public
+    /// keys of the joining nodes are used only temporarily, for key generation.
+    ///
+    /// Two such votes count as votes for the same change only if they specify exactly the same
+    /// nodes and keys.
+    NodeChange(BTreeMap<N, PublicKey>),
+    /// Keep the current validators, but replace their secret key shares with new ones. The
+    /// public master key remains the same, so certificates for earlier batches can still be
+    /// verified with it. This requires that the current key shares were created by key generation,
+    /// i.e. by a previous `Add` or `Remove`, and not by a trusted dealer.
+    ///
+    /// The shares are always reshared to the current set of validators: Changing the set at the
+    /// same time is not supported yet. `SyncKeyGen::new_reshare` itself allows a different set.
+    Reshare,
+    /// Change the protocol parameters. This doesn't require key generation: Once the vote has
+    /// passed, the change is `Complete` immediately, and the new parameters are used from the next
+    /// epoch on.
+    ///
+    /// Any ongoing key generation continues, and votes for other changes still count. The batch
+    /// in which the change completes has no `JoinPlan`.
+    Parameters(Params),
 }
 
 impl<N> Change<N> {
-    /// Returns the ID of the current candidate for being added, if any.
+    /// Returns the ID of the current candidate for being added, if any. For a `NodeChange`, this
+    /// is `None`, even if it adds nodes.
     pub fn candidate(&self) -> Option<&N> {
         match *self {
             Change::Add(ref id, _) => Some(id),
-            Change::Remove(_) => None,
+            Change::Remove(_) | Change::NodeChange(_) | Change::Reshare | Change::Parameters(_) => {
+                None
+            }
+        }
+    }
+}
+
+impl<N: NodeIdT> Change<N> {
+    /// Returns the number of validators after applying this change to the given network.
+    pub(super) fn num_validators(&self, netinfo: &NetworkInfo<N>) -> usize {
+        let num_nodes = netinfo.num_nodes();
+        match *self {
+            Change::Add(ref id, _) if !netinfo.is_node_validator(id) => num_nodes + 1,
+            Change::Remove(ref id) if netinfo.is_node_validator(id) => num_nodes - 1,
+            Change::NodeChange(ref pub_keys) => pub_keys.len(),
+            Change::Add(..) | Change::Remove(_) | Change::Reshare | Change::Parameters(_) => {
+                num_nodes
+            }
+        }
+    }
+
+    /// Returns `false` if the change is `Parameters` and the parameters are invalid.
+    pub(super) fn are_params_valid(&self) -> bool {
+        match *self {
+            Change::Parameters(ref params) => params.is_valid(),
+            Change::Add(..) | Change::Remove(_) | Change::NodeChange(_) | Change::Reshare => true,
+        }
+    }
+
+    /// Returns `false` if this change assigns a different public key to a current validator.
+    /// Validators' keys are used for key generation, so they can't be replaced by a change.
+    pub(super) fn keeps_validator_keys(&self, netinfo: &NetworkInfo<N>) -> bool {
+        let is_key_kept =
+            |id: &N, pk: &PublicKey| netinfo.public_key(id).map_or(true, |old| old == pk);
+        match *self {
+            Change::Add(ref id, ref pk) => is_key_kept(id, pk),
+            Change::NodeChange(ref pub_keys) => pub_keys.iter().all(|(id, pk)| is_key_kept(id, pk)),
+            Change::Remove(_) | Change::Reshare | Change::Parameters(_) => true,
         }
     }
 }
@@ -1,4 +1,6 @@
 use rand::Rand;
+use std::collections::BTreeMap;
+use std::iter::once;
 use std::sync::Arc;
 use std::{fmt, mem};
 
@@ -10,12 +12,16 @@ use serde::{Deserialize, Serialize};
 use super::votes::{SignedVote, VoteCounter};
 use super::{
     Batch, Change, ChangeState, DynamicHoneyBadgerBuilder, Error, ErrorKind, Input,
-    InternalContrib, KeyGenMessage, KeyGenState, Message, Result, SignedKeyGenMsg, Step,
+    InternalContrib, JoinPlan, JoinPlanSigning, KeyGenMessage, KeyGenState, Message, Params,
+    Result, SignedJoinPlan, SignedKeyGenMsg, Step,
 };
 use fault_log::{Fault, FaultKind, FaultLog};
 use honey_badger::{self, HoneyBadger, Message as HbMessage};
-use messaging::{DistAlgorithm, NetworkInfo, Target};
-use sync_key_gen::{Ack, Part, PartOutcome, SyncKeyGen};
+use messaging::{DistAlgorithm, NetworkInfo, Target, Threshold};
+use sync_key_gen::{
+    Ack, Complaint, ComplaintOutcome, Justification, Part, PartOutcome, ShareValue, SyncKeyGen,
+};
+use threshold_sign::{self, Message as ThresholdSignMessage, ThresholdSign};
 use traits::{Contribution, NodeIdT};
 use util::SubRng;
 
@@ -23,9 +29,15 @@ use util::SubRng;
 pub struct DynamicHoneyBadger<C, N: Rand> {
     /// Shared network data.
     pub(super) netinfo: NetworkInfo<N>,
-    /// The maximum number of future epochs for which we handle messages simultaneously.
-    pub(super) max_future_epochs: usize,
-    /// The first epoch after the latest node change.
+    /// The current protocol parameters.
+    pub(super) params: Params,
+    /// The rule that determines the threshold for key generation.
+    pub(super) threshold: Threshold,
+    /// The first epoch after the latest node change or start of key generation. Votes and key
+    /// generation messages belong to an era.
+    pub(super) era: u64,
+    /// The first epoch of the current `HoneyBadger` instance. This is the era, or the first epoch
+    /// after the latest parameter change.
     pub(super) start_epoch: u64,
     /// The buffer and counter for the pending and committed change votes.
     pub(super) vote_counter: VoteCounter<N>,
@@ -35,8 +47,20 @@ pub struct DynamicHoneyBadger<C, N: Rand> {
     pub(super) honey_badger: HoneyBadger<InternalContrib<C, N>, N>,
     /// The current key generation process, and the change it applies to.
     pub(super) key_gen_state: Option<KeyGenState<N>>,
+    /// The value of our secret key share, if it was created by key generation. This is needed to
+    /// reshare it.
+    pub(super) share_value: Option<ShareValue>,
     /// A queue for messages from future epochs that cannot be handled yet.
     pub(super) incoming_queue: Vec<(N, Message<N>)>,
+    /// The ongoing threshold signing rounds for `JoinPlan`s, by the plans' epochs.
+    pub(super) join_plan_signings: BTreeMap<u64, JoinPlanSigning<N>>,
+    /// Signature shares for the `JoinPlan`s of batches that we haven't output yet, by epoch and
+    /// sender.
+    pub(super) join_plan_sig_queue: BTreeMap<u64, BTreeMap<N, ThresholdSignMessage>>,
+    /// The latest `JoinPlan` that has been signed by the validators.
+    pub(super) signed_join_plan: Option<SignedJoinPlan<N>>,
+    /// The signed `JoinPlan`s that introduced a new public master key, ordered by epoch.
+    pub(super) era_certificates: Vec<SignedJoinPlan<N>>,
     /// A random number generator used for secret key generation.
     // Boxed to avoid overloading the algorithm's type with more generics.
     pub(super) rng: Box<dyn rand::Rng + Send + Sync>,
@@ -50,13 +74,20 @@ where
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("DynamicHoneyBadger")
             .field("netinfo", &self.netinfo)
-            .field("max_future_epochs", &self.max_future_epochs)
+            .field("params", &self.params)
+            .field("threshold", &self.threshold)
+            .field("era", &self.era)
             .field("start_epoch", &self.start_epoch)
             .field("vote_counter", &self.vote_counter)
             .field("key_gen_msg_buffer", &self.key_gen_msg_buffer)
             .field("honey_badger", &self.honey_badger)
             .field("key_gen_state", &self.key_gen_state)
+            .field("share_value", &self.share_value)
             .field("incoming_queue", &self.incoming_queue)
+            .field("join_plan_signings", &self.join_plan_signings)
+            .field("join_plan_sig_queue", &self.join_plan_sig_queue)
+            .field("signed_join_plan", &self.signed_join_plan)
+            .field("era_certificates", &self.era_certificates)
             .field("rng", &"<RNG>")
             .finish()
     }
@@ -79,15 +110,27 @@ where
         match input {
             Input::User(contrib) => self.propose(contrib),
             Input::Change(change) => self.vote_for(change),
+            Input::WithdrawVote => self.withdraw_vote(),
         }
     }
 
     fn handle_message(&mut self, sender_id: &N, message: Self::Message) -> Result<Step<C, N>> {
+        if let Message::JoinPlanSignature(epoch, ts_msg) = message {
+            // Signing rounds belong to a batch, not to the current `HoneyBadger` instance.
+            return Ok(self.handle_join_plan_sig_message(sender_id, epoch, ts_msg));
+        }
+        // `HoneyBadger` messages belong to the current instance, the others to the current era.
+        let current = match message {
+            Message::HoneyBadger(..) => self.start_epoch,
+            Message::KeyGen(..) | Message::SignedVote(_) | Message::JoinPlanSignature(..) => {
+                self.era
+            }
+        };
         let epoch = message.start_epoch();
-        if epoch < self.start_epoch {
+        if epoch < current {
             // Obsolete message.
             Ok(Step::default())
-        } else if epoch > self.start_epoch {
+        } else if epoch > current {
             // Message cannot be handled yet. Save it for later.
             let entry = (sender_id.clone(), message);
             self.incoming_queue.push(entry);
@@ -104,6 +147,7 @@ where
                     .vote_counter
                     .add_pending_vote(sender_id, signed_vote)
                     .map(FaultLog::into),
+                Message::JoinPlanSignature(..) => Ok(Step::default()), // Handled above.
             }
         }
     }
@@ -145,20 +189,91 @@ where
     }
 
     /// Cast a vote to change the set of validators.
+    ///
+    /// Returns an error if the threshold rule doesn't allow the resulting number of validators, if
+    /// the change would replace a current validator's public key, if the change is `Parameters`
+    /// and they are invalid (see `Params::is_valid`), or if the change is `Reshare` and we don't
+    /// know the value of our key share.
     pub fn vote_for(&mut self, change: Change<N>) -> Result<Step<C, N>> {
         if !self.netinfo.is_validator() {
             return Ok(Step::default()); // TODO: Return an error?
         }
+        if change == Change::Reshare && self.share_value.is_none() {
+            return Err(ErrorKind::MissingShareValue.into());
+        }
+        if !change.keeps_validator_keys(&self.netinfo) {
+            return Err(ErrorKind::ValidatorKeyChange.into());
+        }
+        if !change.are_params_valid() {
+            return Err(ErrorKind::InvalidParams.into());
+        }
+        let new_num_nodes = change.num_validators(&self.netinfo);
+        if self.threshold.for_num_nodes(new_num_nodes).is_none() {
+            return Err(ErrorKind::InvalidThreshold(self.threshold, new_num_nodes).into());
+        }
         let signed_vote = self.vote_counter.sign_vote_for(change)?.clone();
         let msg = Message::SignedVote(signed_vote);
         Ok(Target::All.message(msg).into())
     }
 
+    /// Withdraw our current vote, without voting for a different change.
+    pub fn withdraw_vote(&mut self) -> Result<Step<C, N>> {
+        if !self.netinfo.is_validator() {
+            return Ok(Step::default()); // TODO: Return an error?
+        }
+        let signed_vote = self.vote_counter.sign_withdrawal()?.clone();
+        let msg = Message::SignedVote(signed_vote);
+        Ok(Target::All.message(msg).into())
+    }
+
+    /// Returns the epoch of the next batch to be output.
+    pub fn next_epoch(&self) -> u64 {
+        self.start_epoch + self.honey_badger.next_epoch()
+    }
+
     /// Returns the information about the node IDs in the network, and the cryptographic keys.
     pub fn netinfo(&self) -> &NetworkInfo<N> {
         &self.netinfo
     }
 
+    /// Returns the rule that determines the threshold for key generation.
+    pub fn threshold(&self) -> Threshold {
+        self.threshold
+    }
+
+    /// Returns the current protocol parameters.
+    pub fn params(&self) -> &Params {
+        &self.params
+    }
+
+    /// Returns the value of our secret key share, if it was created by key generation or passed to
+    /// `DynamicHoneyBadgerBuilder::share_value`. It should be stored with the secret key share, so
+    /// that the node can still reshare the key set after a restart.
+    pub fn share_value(&self) -> Option<&ShareValue> {
+        self.share_value.as_ref()
+    }
+
+    /// Returns the latest `JoinPlan` whose signing round has completed, if any. It can be handed
+    /// to new nodes via untrusted channels.
+    pub fn signed_join_plan(&self) -> Option<&SignedJoinPlan<N>> {
+        self.signed_join_plan.as_ref()
+    }
+
+    /// Returns the signed `JoinPlan`s that introduced a new public master key, ordered by epoch.
+    /// Each of them is signed with the master key introduced by the previous one.
+    ///
+    /// This only contains the certificates for the key changes we observed, or that were passed to
+    /// `DynamicHoneyBadgerBuilder::build_joining_signed`.
+    pub fn era_certificates(&self) -> &[SignedJoinPlan<N>] {
+        &self.era_certificates
+    }
+
+    /// Sets the target number of transactions per batch. This is used by `QueueingHoneyBadger` to
+    /// configure the initial value.
+    pub(crate) fn set_batch_size(&mut self, batch_size: usize) {
+        self.params.batch_size = batch_size;
+    }
+
     /// Returns `true` if we should make our contribution for the next epoch, even if we don't have
     /// content ourselves, to avoid stalling the network.
     ///
@@ -169,7 +284,7 @@ where
         if self.has_input() {
             return false; // We have already proposed.
         }
-        if self.honey_badger.received_proposals() > self.netinfo.num_faulty() {
+        if self.honey_badger.received_proposal_weight() > self.netinfo.faulty_weight() {
             return true; // At least one correct node wants to move on to the next epoch.
         }
         let is_our_vote = |signed_vote: &SignedVote<_>| signed_vote.voter() == self.our_id();
@@ -180,9 +295,9 @@ where
             None => return false, // No ongoing key generation.
             Some(ref kgs) => kgs,
         };
-        // If either we or the candidate have a pending key gen message, we should propose.
+        // If either we or a candidate have a pending key gen message, we should propose.
         let ours_or_candidates = |msg: &SignedKeyGenMsg<_>| {
-            msg.1 == *self.our_id() || Some(&msg.1) == kgs.change.candidate()
+            msg.1 == *self.our_id() || kgs.candidates.contains_key(&msg.1)
         };
         self.key_gen_msg_buffer.iter().any(ours_or_candidates)
     }
@@ -229,11 +344,15 @@ where
             }
         };
 
-        // If the joining node is correct, it will send at most (N + 1)² + 1 key generation
-        // messages.
-        if Some(sender_id) == kgs.change.candidate() {
-            let n = self.netinfo.num_nodes() + 1;
-            if kgs.candidate_msg_count > n * n {
+        // If a joining node is correct, it will send at most (N + C)² + 1 key generation
+        // messages, where C is the number of candidates.
+        if kgs.candidates.contains_key(sender_id) {
+            let n = self.netinfo.num_nodes() + kgs.candidates.len();
+            let count = kgs
+                .candidate_msg_counts
+                .entry(sender_id.clone())
+                .or_insert(0);
+            if *count > n * n {
                 info!(
                     "Too many key gen messages from candidate {:?}: {:?}.",
                     sender_id, kg_msg
@@ -241,10 +360,10 @@ where
                 let fault_kind = FaultKind::TooManyCandidateKeyGenMessages;
                 return Ok(Fault::new(sender_id.clone(), fault_kind).into());
             }
-            kgs.candidate_msg_count += 1;
+            *count += 1;
         }
 
-        let tx = SignedKeyGenMsg(self.start_epoch, sender_id.clone(), kg_msg, sig);
+        let tx = SignedKeyGenMsg(self.era, sender_id.clone(), kg_msg, sig);
         self.key_gen_msg_buffer.push(tx);
         Ok(FaultLog::default())
     }
@@ -261,6 +380,7 @@ where
             // Create the batch we output ourselves. It will contain the _user_ transactions of
             // `hb_batch`, and the current change state.
             let mut batch = Batch::new(hb_batch.epoch + self.start_epoch);
+            batch.timestamp = hb_batch.timestamp;
 
             // Add the user transactions to `batch` and handle votes and DKG messages.
             for (id, int_contrib) in hb_batch.contributions {
@@ -269,13 +389,16 @@ where
                     key_gen_messages,
                     contrib,
                 } = int_contrib;
-                step.fault_log
-                    .extend(self.vote_counter.add_committed_votes(&id, votes)?);
+                step.fault_log.extend(self.vote_counter.add_committed_votes(
+                    &id,
+                    votes,
+                    batch.epoch,
+                )?);
                 batch.contributions.insert(id.clone(), contrib);
                 self.key_gen_msg_buffer
                     .retain(|skgm| !key_gen_messages.contains(skgm));
                 for SignedKeyGenMsg(epoch, s_id, kg_msg, sig) in key_gen_messages {
-                    if epoch < self.start_epoch {
+                    if epoch < self.era {
                         info!("Obsolete key generation message: {:?}.", kg_msg);
                         continue;
                     }
@@ -291,20 +414,56 @@ where
                     step.extend(match kg_msg {
                         KeyGenMessage::Part(part) => self.handle_part(&s_id, part)?,
                         KeyGenMessage::Ack(ack) => self.handle_ack(&s_id, ack)?.into(),
+                        KeyGenMessage::Complaint(complaint) => {
+                            self.handle_complaint(&s_id, complaint)?
+                        }
+                        KeyGenMessage::Justification(justification) => {
+                            self.handle_justification(&s_id, justification)?
+                        }
                     });
                 }
             }
 
+            // Votes that have been committed too long ago without leading to a change lapse.
+            if let Some(vote_expiry) = self.params.vote_expiry {
+                let first_valid_epoch = (batch.epoch + 1).saturating_sub(vote_expiry);
+                self.vote_counter.expire_votes(first_valid_epoch);
+            }
+
+            // The validators that output this batch, who will sign its `JoinPlan`.
+            let mut signer_netinfo = None;
             if let Some(kgs) = self.take_ready_key_gen() {
                 // If DKG completed, apply the change, restart Honey Badger, and inform the user.
                 debug!("{:?} DKG for {:?} complete!", self.our_id(), kgs.change);
-                self.netinfo = kgs.key_gen.into_network_info()?;
-                self.restart_honey_badger(batch.epoch + 1);
-                batch.set_change(ChangeState::Complete(kgs.change), &self.netinfo);
+                let (netinfo, share_value) = kgs.key_gen.into_network_info_with_share()?;
+                signer_netinfo = Some(mem::replace(&mut self.netinfo, netinfo));
+                self.share_value = share_value;
+                self.start_era(batch.epoch + 1);
+                let change_state = ChangeState::Complete(kgs.change);
+                let (netinfo, params, no_votes) = (&self.netinfo, &self.params, BTreeMap::new());
+                batch.set_change(change_state, netinfo, self.threshold, params, no_votes);
             } else if let Some(change) = self.vote_counter.compute_winner().cloned() {
-                // If there is a new change, restart DKG. Inform the user about the current change.
-                step.extend(self.update_key_gen(batch.epoch + 1, &change)?);
-                batch.set_change(ChangeState::InProgress(change), &self.netinfo);
+                // Inform the user about the current change.
+                if let Change::Parameters(ref params) = change {
+                    // New parameters don't need key generation: Apply them from the next epoch on.
+                    // The era continues, so new nodes can't join in the next epoch.
+                    self.update_params(batch.epoch + 1, params.clone());
+                    batch.set_params_change(ChangeState::Complete(change.clone()));
+                } else if self.key_gen_state.as_ref().map(|kgs| &kgs.change) != Some(&change) {
+                    // If there is a new change, restart DKG.
+                    step.extend(self.update_key_gen(batch.epoch + 1, &change)?);
+                    let change_state = ChangeState::InProgress(change.clone());
+                    let votes = self.vote_counter.committed_votes_for(&change);
+                    let (netinfo, params) = (&self.netinfo, &self.params);
+                    batch.set_change(change_state, netinfo, self.threshold, params, votes);
+                }
+            } else if self.key_gen_state.is_some() {
+                // The votes for the change in progress have been withdrawn or have expired.
+                self.abort_key_gen(batch.epoch + 1);
+            }
+            if let Some(join_plan) = batch.join_plan() {
+                let netinfo = signer_netinfo.unwrap_or_else(|| self.netinfo.clone());
+                step.extend(self.sign_join_plan(join_plan, netinfo));
             }
             step.output.push_back(batch);
         }
@@ -318,29 +477,244 @@ where
         Ok(step)
     }
 
+    /// Starts the threshold signing round for the given `JoinPlan`, with the keys of the validators
+    /// that output the batch it was derived from.
+    ///
+    /// Since the batch has already been processed at this point, errors are not returned: They are
+    /// logged, and the signing round is abandoned.
+    fn sign_join_plan(&mut self, join_plan: JoinPlan<N>, netinfo: NetworkInfo<N>) -> Step<C, N> {
+        let epoch = join_plan.epoch;
+        let doc = match bincode::serialize(&join_plan) {
+            Ok(doc) => doc,
+            Err(err) => {
+                let our_id = self.our_id();
+                error!("{:?} Failed to serialize {:?}: {}", our_id, join_plan, err);
+                return Step::default();
+            }
+        };
+        let is_certificate =
+            netinfo.public_key_set().public_key() != join_plan.pub_key_set.public_key();
+        let mut threshold_sign = ThresholdSign::new(Arc::new(netinfo), doc);
+        let ts_step = match threshold_sign.handle_input(()) {
+            Ok(ts_step) => ts_step,
+            Err(err) => {
+                error!("{:?} Failed to sign the join plan: {}", self.our_id(), err);
+                return Step::default();
+            }
+        };
+        let signing = JoinPlanSigning {
+            join_plan,
+            is_certificate,
+            threshold_sign,
+        };
+        self.join_plan_signings.insert(epoch, signing);
+        let mut step = self.process_join_plan_sig_step(epoch, ts_step);
+        // Handle the signature shares that arrived before we output the batch.
+        let queued = self.join_plan_sig_queue.remove(&epoch).unwrap_or_default();
+        for (sender_id, ts_msg) in queued {
+            step.extend(self.handle_join_plan_sig_message(&sender_id, epoch, ts_msg));
+        }
+        self.remove_obsolete_join_plan_signings(epoch);
+        step
+    }
+
+    /// Abandons signing rounds that are too old to complete, and drops queued shares for them.
+    /// Rounds for era certificates are only abandoned once there is a later one.
+    fn remove_obsolete_join_plan_signings(&mut self, epoch: u64) {
+        let max_future_epochs = self.params.max_future_epochs as u64;
+        let first_kept = epoch.saturating_sub(max_future_epochs);
+        let last_cert_epoch = self
+            .join_plan_signings
+            .iter()
+            .filter(|&(_, signing)| signing.is_certificate)
+            .map(|(plan_epoch, _)| *plan_epoch)
+            .last();
+        let obsolete: Vec<u64> = self
+            .join_plan_signings
+            .range(..first_kept)
+            .filter(|&(plan_epoch, signing)| {
+                !signing.is_certificate || Some(*plan_epoch) != last_cert_epoch
+            })
+            .map(|(plan_epoch, _)| *plan_epoch)
+            .collect();
+        for plan_epoch in obsolete {
+            self.join_plan_signings.remove(&plan_epoch);
+        }
+        self.join_plan_sig_queue = self.join_plan_sig_queue.split_off(&first_kept);
+    }
+
+    /// Handles a signature share for a `JoinPlan`. If we haven't output the plan's batch yet, the
+    /// message is queued. Shares from nodes that are not among the plan's signers are faults.
+    fn handle_join_plan_sig_message(
+        &mut self,
+        sender_id: &N,
+        epoch: u64,
+        ts_msg: ThresholdSignMessage,
+    ) -> Step<C, N> {
+        if !self.join_plan_signings.contains_key(&epoch) {
+            self.queue_join_plan_sig_message(sender_id, epoch, ts_msg);
+            return Step::default();
+        }
+        let result = match self.join_plan_signings.get_mut(&epoch) {
+            Some(signing) => signing.threshold_sign.handle_message(sender_id, ts_msg),
+            None => return Step::default(),
+        };
+        match result {
+            Ok(ts_step) => self.process_join_plan_sig_step(epoch, ts_step),
+            Err(threshold_sign::Error::UnknownSender) => {
+                let fault_kind = FaultKind::UnexpectedJoinPlanSignature;
+                Fault::new(sender_id.clone(), fault_kind).into()
+            }
+            Err(err) => {
+                error!(
+                    "{:?} Abandoning the join plan signing for epoch {}: {}",
+                    self.our_id(),
+                    epoch,
+                    err
+                );
+                self.join_plan_signings.remove(&epoch);
+                Step::default()
+            }
+        }
+    }
+
+    /// Queues a signature share for a `JoinPlan` whose batch we haven't output yet. At most one
+    /// share per sender and epoch is kept, only from current validators and candidates, and only
+    /// for batches that `HoneyBadger` would handle messages for.
+    fn queue_join_plan_sig_message(
+        &mut self,
+        sender_id: &N,
+        epoch: u64,
+        ts_msg: ThresholdSignMessage,
+    ) {
+        // The plan for `epoch` is derived from the batch in `epoch - 1`.
+        let next_batch_epoch = self.next_epoch();
+        let max_epoch = next_batch_epoch + self.params.max_future_epochs as u64 + 1;
+        if epoch <= next_batch_epoch || epoch > max_epoch {
+            return; // The round is already complete or obsolete, or too far in the future.
+        }
+        let is_candidate = self
+            .key_gen_state
+            .as_ref()
+            .map_or(false, |kgs| kgs.candidates.contains_key(sender_id));
+        if !self.netinfo.is_node_validator(sender_id) && !is_candidate {
+            return; // The sender can't be one of the plan's signers.
+        }
+        self.join_plan_sig_queue
+            .entry(epoch)
+            .or_insert_with(BTreeMap::new)
+            .entry(sender_id.clone())
+            .or_insert(ts_msg);
+    }
+
+    /// Converts the step of a `JoinPlan` signing round. If the round completed, stores the signed
+    /// plan and, if it introduced a new master key, adds it to the era certificates.
+    fn process_join_plan_sig_step(
+        &mut self,
+        epoch: u64,
+        ts_step: threshold_sign::Step<N, Vec<u8>>,
+    ) -> Step<C, N> {
+        let mut step = Step::default();
+        let output = step.extend_with(ts_step, |ts_msg| Message::JoinPlanSignature(epoch, ts_msg));
+        let sig = match output.into_iter().next() {
+            Some(sig) => sig,
+            None => return step,
+        };
+        let signing = match self.join_plan_signings.remove(&epoch) {
+            Some(signing) => signing,
+            None => return step,
+        };
+        let signed_plan = SignedJoinPlan {
+            join_plan: signing.join_plan,
+            sig,
+        };
+        if signing.is_certificate {
+            let pos = self
+                .era_certificates
+                .iter()
+                .position(|cert| cert.epoch() > epoch)
+                .unwrap_or_else(|| self.era_certificates.len());
+            self.era_certificates.insert(pos, signed_plan.clone());
+        }
+        if self
+            .signed_join_plan
+            .as_ref()
+            .map_or(true, |plan| plan.epoch() < epoch)
+        {
+            self.signed_join_plan = Some(signed_plan);
+        }
+        step
+    }
+
     /// If the winner of the vote has changed, restarts Key Generation for the set of nodes implied
     /// by the current change.
     pub(super) fn update_key_gen(&mut self, epoch: u64, change: &Change<N>) -> Result<Step<C, N>> {
+        let is_reshare = match *change {
+            Change::Reshare => true,
+            Change::Add(..) | Change::Remove(_) | Change::NodeChange(_) => false,
+            // New parameters are applied right away, in `update_params`.
+            Change::Parameters(_) => return Err(ErrorKind::ParametersKeyGen.into()),
+        };
         if self.key_gen_state.as_ref().map(|kgs| &kgs.change) == Some(change) {
             return Ok(Step::default()); // The change is the same as before. Continue DKG as is.
         }
+        if !change.keeps_validator_keys(&self.netinfo) {
+            // `VoteCounter` rejects votes for such changes, so they can't win.
+            return Err(ErrorKind::ValidatorKeyChange.into());
+        }
         debug!("{:?} Restarting DKG for {:?}.", self.our_id(), change);
         // Use the existing key shares - with the change applied - as keys for DKG.
         let mut pub_keys = self.netinfo.public_key_map().clone();
         if match *change {
             Change::Remove(ref id) => pub_keys.remove(id).is_none(),
             Change::Add(ref id, ref pk) => pub_keys.insert(id.clone(), pk.clone()).is_some(),
+            Change::NodeChange(ref keys) => mem::replace(&mut pub_keys, keys.clone()) == *keys,
+            Change::Reshare | Change::Parameters(_) => false,
         } {
             info!("{:?} No-op change: {:?}", self.our_id(), change);
         }
-        self.restart_honey_badger(epoch);
+        // The joining nodes use their public keys only for key generation.
+        let candidates = match *change {
+            Change::Add(ref id, ref pk) => once((id.clone(), pk.clone())).collect(),
+            Change::NodeChange(ref keys) => keys
+                .iter()
+                .filter(|&(id, _)| !self.netinfo.is_node_validator(id))
+                .map(|(id, pk)| (id.clone(), pk.clone()))
+                .collect(),
+            Change::Remove(_) | Change::Reshare | Change::Parameters(_) => BTreeMap::new(),
+        };
+        let threshold = match self.threshold.for_num_nodes(pub_keys.len()) {
+            Some(threshold) => threshold,
+            None => {
+                // `VoteCounter` rejects votes for such changes, so they can't win.
+                let err = ErrorKind::InvalidThreshold(self.threshold, pub_keys.len());
+                return Err(err.into());
+            }
+        };
+        // The votes for the change still count: If they are withdrawn or expire, DKG is aborted.
+        let votes = self.vote_counter.committed_votes_for(change);
+        self.start_era(epoch);
+        self.vote_counter.carry_over(votes);
         // TODO: This needs to be the same as `num_faulty` will be in the _new_
         // `NetworkInfo` if the change goes through. It would be safer to deduplicate.
-        let threshold = (pub_keys.len() - 1) / 3;
+        let num_faulty = (pub_keys.len() - 1) / 3;
         let sk = self.netinfo.secret_key().clone();
         let our_id = self.our_id().clone();
-        let (key_gen, part) = SyncKeyGen::new(&mut self.rng, our_id, sk, pub_keys, threshold)?;
-        self.key_gen_state = Some(KeyGenState::new(key_gen, change.clone()));
+        let (key_gen, part) = if is_reshare {
+            SyncKeyGen::new_reshare(
+                &mut self.rng,
+                our_id,
+                sk,
+                pub_keys,
+                threshold,
+                &self.netinfo,
+                self.share_value.as_ref(),
+            )?
+        } else {
+            SyncKeyGen::new(&mut self.rng, our_id, sk, pub_keys, threshold)?
+        };
+        let key_gen = key_gen.with_num_faulty(num_faulty);
+        self.key_gen_state = Some(KeyGenState::new(key_gen, change.clone(), candidates));
         if let Some(part) = part {
             self.send_transaction(KeyGenMessage::Part(part))
         } else {
@@ -348,16 +722,48 @@ where
         }
     }
 
-    /// Starts a new `HoneyBadger` instance and resets the vote counter.
-    fn restart_honey_badger(&mut self, epoch: u64) {
-        self.start_epoch = epoch;
+    /// Aborts the ongoing key generation and starts a new era at the given epoch. All committed
+    /// votes are carried over into the new era.
+    fn abort_key_gen(&mut self, epoch: u64) {
+        debug!("{:?} Aborting DKG: The change lost support.", self.our_id());
+        self.key_gen_state = None;
+        let votes = self.vote_counter.committed_votes();
+        self.start_era(epoch);
+        self.vote_counter.carry_over(votes);
+    }
+
+    /// Switches to the new protocol parameters, starting at the given epoch. This restarts
+    /// `HoneyBadger`, but the era continues: Any ongoing key generation and the votes for other
+    /// changes are kept.
+    fn update_params(&mut self, epoch: u64, params: Params) {
+        debug!("{:?} Changing parameters to {:?}.", self.our_id(), params);
+        let change = Change::Parameters(params.clone());
+        self.vote_counter.withdraw_votes_for(&change);
+        self.params = params;
+        self.restart_honey_badger(epoch);
+    }
+
+    /// Starts a new era: Resets the vote counter and starts a new `HoneyBadger` instance.
+    fn start_era(&mut self, epoch: u64) {
+        self.era = epoch;
         self.key_gen_msg_buffer.retain(|kg_msg| kg_msg.0 >= epoch);
         let netinfo = Arc::new(self.netinfo.clone());
-        let counter = VoteCounter::new(netinfo.clone(), epoch);
+        let counter = VoteCounter::new(netinfo, epoch, self.threshold);
         mem::replace(&mut self.vote_counter, counter);
+        self.restart_honey_badger(epoch);
+    }
+
+    /// Starts a new `HoneyBadger` instance with the current parameters.
+    fn restart_honey_badger(&mut self, epoch: u64) {
+        self.start_epoch = epoch;
+        let netinfo = Arc::new(self.netinfo.clone());
+        let timestamp = self.honey_badger.last_timestamp();
         self.honey_badger = HoneyBadger::builder(netinfo)
-            .max_future_epochs(self.max_future_epochs)
+            .max_future_epochs(self.params.max_future_epochs)
             .rng(self.rng.sub_rng())
+            .subset_handling_strategy(self.params.subset_handling_strategy.clone())
+            .encryption_schedule(self.params.encryption_schedule)
+            .min_timestamp(timestamp)
             .build();
     }
 
@@ -370,8 +776,52 @@ where
             return Ok(Step::default());
         };
 
+        self.handle_part_outcome(outcome)
+    }
+
+    /// Handles a `Complaint` message that was output by Honey Badger.
+    fn handle_complaint(&mut self, sender_id: &N, complaint: Complaint) -> Result<Step<C, N>> {
+        let outcome = if let Some(kgs) = self.key_gen_state.as_mut() {
+            kgs.key_gen.handle_complaint(&sender_id, complaint)
+        } else {
+            // No key generation ongoing. Return early.
+            return Ok(Step::default());
+        };
+
+        match outcome {
+            ComplaintOutcome::Justify(justification) => {
+                self.send_transaction(KeyGenMessage::Justification(justification))
+            }
+            ComplaintOutcome::Handled(fault_log) => Ok(fault_log.into()),
+        }
+    }
+
+    /// Handles a `Justification` message that was output by Honey Badger.
+    fn handle_justification(
+        &mut self,
+        sender_id: &N,
+        justification: Justification,
+    ) -> Result<Step<C, N>> {
+        let outcome = if let Some(kgs) = self.key_gen_state.as_mut() {
+            kgs.key_gen
+                .handle_justification(&mut self.rng, &sender_id, justification)
+        } else {
+            // No key generation ongoing. Return early.
+            return Ok(Step::default());
+        };
+
+        self.handle_part_outcome(outcome)
+    }
+
+    /// Sends our `Ack` or `Complaint`, if any, and returns the faults.
+    fn handle_part_outcome(&mut self, outcome: Option<PartOutcome<N>>) -> Result<Step<C, N>> {
         match outcome {
             Some(PartOutcome::Valid(ack)) => self.send_transaction(KeyGenMessage::Ack(ack)),
+            Some(PartOutcome::Complaint(complaint, fault_log)) => {
+                let mut step = self.send_transaction(KeyGenMessage::Complaint(complaint))?;
+                step.fault_log.extend(fault_log);
+                Ok(step)
+            }
             Some(PartOutcome::Invalid(fault_log)) => Ok(fault_log.into()),
             None => Ok(Step::default()),
         }
@@ -393,19 +843,18 @@ where
         let sig = Box::new(self.netinfo.secret_key().sign(ser));
         if self.netinfo.is_validator() {
             let our_id = self.netinfo.our_id().clone();
-            let signed_msg =
-                SignedKeyGenMsg(self.start_epoch, our_id, kg_msg.clone(), *sig.clone());
+            let signed_msg = SignedKeyGenMsg(self.era, our_id, kg_msg.clone(), *sig.clone());
             self.key_gen_msg_buffer.push(signed_msg);
         }
-        let msg = Message::KeyGen(self.start_epoch, kg_msg, sig);
+        let msg = Message::KeyGen(self.era, kg_msg, sig);
         Ok(Target::All.message(msg).into())
     }
 
     /// If the current Key Generation process is ready, returns the `KeyGenState`.
     ///
-    /// We require the minimum number of completed proposals (`SyncKeyGen::is_ready`) and if a new
-    /// node is joining, we require in addition that the new node's proposal is complete. That way
-    /// the new node knows that it's key is secret, without having to trust any number of nodes.
+    /// We require the minimum number of completed proposals (`SyncKeyGen::is_ready`) and if new
+    /// nodes are joining, we require in addition that the new nodes' proposals are complete. That
+    /// way each new node knows that its key is secret, without having to trust any number of nodes.
     fn take_ready_key_gen(&mut self) -> Option<KeyGenState<N>> {
         if self
             .key_gen_state
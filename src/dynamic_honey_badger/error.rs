@@ -5,6 +5,7 @@ use crypto;
 use failure::{Backtrace, Context, Fail};
 
 use honey_badger;
+use messaging::Threshold;
 use sync_key_gen;
 
 /// Dynamic honey badger error variants.
@@ -18,6 +19,8 @@ pub enum ErrorKind {
     SignVoteForBincode(bincode::ErrorKind),
     #[fail(display = "ValidateBincode error: {}", _0)]
     ValidateBincode(bincode::ErrorKind),
+    #[fail(display = "CanonicalTxsBincode error: {}", _0)]
+    CanonicalTxsBincode(bincode::ErrorKind),
     #[fail(display = "Crypto error: {}", _0)]
     Crypto(crypto::error::Error),
     #[fail(display = "ProposeHoneyBadger error: {}", _0)]
@@ -29,8 +32,22 @@ pub enum ErrorKind {
     HandleHoneyBadgerMessageHoneyBadger(honey_badger::Error),
     #[fail(display = "SyncKeyGen error: {}", _0)]
     SyncKeyGen(sync_key_gen::Error),
+    #[fail(display = "Invalid join plan signature")]
+    InvalidJoinPlanSignature,
     #[fail(display = "Unknown sender")]
     UnknownSender,
+    #[fail(display = "Threshold {:?} is invalid for {} validators", _0, _1)]
+    InvalidThreshold(Threshold, usize),
+    #[fail(display = "Cannot vote for resharing without the value of our key share")]
+    MissingShareValue,
+    #[fail(display = "A change cannot replace a current validator's public key")]
+    ValidatorKeyChange,
+    #[fail(display = "Invalid protocol parameters")]
+    InvalidParams,
+    #[fail(display = "Parameter changes don't need key generation")]
+    ParametersKeyGen,
+    #[fail(display = "Weighted validators are not supported")]
+    WeightedValidators,
 }
 
 /// A dynamic honey badger error.
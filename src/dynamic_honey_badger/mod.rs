@@ -11,25 +11,52 @@
 //! Unlike Honey Badger, this algorithm allows dynamically adding and removing validators.
 //! As a signal to initiate converting observers to validators or vice versa, it defines a special
 //! `Change` input variant, which contains either a vote `Add(node_id, public_key)`, to add an
-//! existing observer to the set of validators, or `Remove(node_id)` to remove it. Each
+//! existing observer to the set of validators, or `Remove(node_id)` to remove it. A vote
+//! `NodeChange(public_keys)` replaces the whole set of validators at once, so that several nodes
+//! can be added and removed with a single key generation. A vote `Reshare` keeps the set of
+//! validators, but replaces their secret key shares without changing the public master key. Each
 //! validator can have at most one active vote, and casting another vote revokes the previous one.
-//! Once _f + 1_ validators have the same active vote, a reconfiguration process begins: They
-//! create new cryptographic key shares for the new group of validators.
+//! A `WithdrawVote` input revokes it without casting a new one, and if `Params::vote_expiry` is
+//! set, committed votes lapse automatically after that number of epochs. Once _f + 1_ validators
+//! have the same active vote, a reconfiguration process begins: They create new cryptographic key
+//! shares for the new group of validators. If conflicting changes reach _f + 1_ votes in the same
+//! epoch, the one with the most votes wins.
+//!
+//! The same mechanism is used to change the protocol parameters, like `max_future_epochs` or the
+//! `EncryptionSchedule`, in a running network: Once a `Parameters(params)` vote has _f + 1_ votes,
+//! the change is complete, and all nodes use the new parameters from the next epoch on. Key
+//! generation in progress continues, and only the votes for the new parameters are withdrawn.
 //!
 //! The state of that process after each epoch is communicated via the `change` field in `Batch`.
-//! When this contains an `InProgress(..)` value, key generation begins. The joining validator (in
-//! the case of an `Add` change) must be an observer starting in the following epoch or earlier.
+//! When this contains an `InProgress(..)` value, key generation begins. The joining validators (in
+//! the case of an `Add` or `NodeChange` change) must be observers starting in the following epoch
+//! or earlier, and all of them must take part in key generation for it to complete.
 //! When `change` is `Complete(..)`, the following epochs will be produced by the new set of
-//! validators.
+//! validators. The votes for a change in progress keep counting while its key generation is
+//! running: If enough of them are withdrawn or expire that the change no longer wins, key
+//! generation is aborted, and if a different change wins instead, key generation restarts for
+//! that one.
+//!
+//! New observers can only join the network after an epoch where `change` was `InProgress(..)`, or
+//! `Complete(..)` with a change other than `Parameters`. These epochs' batches contain a
+//! `JoinPlan`, which can be sent as an invitation to the new node: The `DynamicHoneyBadger`
+//! instance created from a `JoinPlan` will start as an observer in the following epoch. All
+//! `Target::All` messages from that and later epochs must be sent to the new node.
 //!
-//! New observers can only join the network after an epoch where `change` was not `None`. These
-//! epochs' batches contain a `JoinPlan`, which can be sent as an invitation to the new node: The
-//! `DynamicHoneyBadger` instance created from a `JoinPlan` will start as an observer in the
-//! following epoch. All `Target::All` messages from that and later epochs must be sent to the new
-//! node.
+//! To let the new node join without trusting whoever hands it the `JoinPlan`, the validators that
+//! output the batch threshold-sign its plan. Once that signing round completes, the
+//! `SignedJoinPlan` is available from `DynamicHoneyBadger::signed_join_plan`. It is signed with the
+//! validators' public master key _before_ any change in that batch, so if the batch completed a
+//! change that created a new master key, the plan also certifies the new key. These _era
+//! certificates_ form a chain, and a joining node that knows any earlier master key can verify the
+//! current plan using `DynamicHoneyBadgerBuilder::build_joining_signed`.
 //!
 //! Observer nodes can leave the network at any time.
 //!
+//! Each batch has the agreed timestamp of the internal Honey Badger batch. When Honey Badger is
+//! restarted, or a new node joins, the new instance continues from the last batch's timestamp, so
+//! that the timestamps never decrease.
+//!
 //! These mechanisms create a dynamic network where you can:
 //!
 //! * introduce new nodes as observers,
@@ -54,22 +81,33 @@
 //! change begins. If key generation completes successfully, the Honey Badger instance is dropped,
 //! and replaced by a new one with the new set of participants. If a different change wins a
 //! vote before that happens, key generation resets again, and is attempted for the new change.
+//!
+//! For a `Reshare` change, the validators that hold a key share generated in an earlier key
+//! generation reshare it among the validators instead of creating new random secrets, so the
+//! public master key stays the same, and nodes that have been removed earlier cannot use their old
+//! key shares together with the new ones. The set of validators doesn't change in a `Reshare`, and
+//! the other changes always create a new master key, so the master key can't be kept while adding
+//! or removing validators.
 
 mod batch;
 mod builder;
 mod change;
 mod dynamic_honey_badger;
 mod error;
+mod params;
 mod votes;
 
+use bincode;
 use crypto::{PublicKey, PublicKeySet, Signature};
 use rand::Rand;
+use serde::Serialize;
 use std::collections::BTreeMap;
 
-use self::votes::{SignedVote, VoteCounter};
+use self::votes::{SignedVote, Vote, VoteCounter};
 use honey_badger::Message as HbMessage;
-use messaging;
-use sync_key_gen::{Ack, Part, SyncKeyGen};
+use messaging::{self, Threshold};
+use sync_key_gen::{Ack, Complaint, Justification, Part, SyncKeyGen};
+use threshold_sign::{Message as ThresholdSignMessage, ThresholdSign};
 use traits::NodeIdT;
 
 pub use self::batch::Batch;
@@ -77,6 +115,7 @@ pub use self::builder::DynamicHoneyBadgerBuilder;
 pub use self::change::{Change, ChangeState};
 pub use self::dynamic_honey_badger::DynamicHoneyBadger;
 pub use self::error::{Error, ErrorKind, Result};
+pub use self::params::{Params, MAX_MAX_FUTURE_EPOCHS};
 
 pub type Step<C, N> = messaging::Step<DynamicHoneyBadger<C, N>>;
 
@@ -87,6 +126,8 @@ pub enum Input<C, N> {
     User(C),
     /// A vote to change the set of validators.
     Change(Change<N>),
+    /// A withdrawal of our current vote, without voting for anything else.
+    WithdrawVote,
 }
 
 /// An internal message containing a vote for adding or removing a validator, or a message for key
@@ -98,6 +139,10 @@ pub enum KeyGenMessage {
     Part(Part),
     /// A `SyncKeyGen::Ack` message for key generation.
     Ack(Ack),
+    /// A `SyncKeyGen::Complaint` message for key generation.
+    Complaint(Complaint),
+    /// A `SyncKeyGen::Justification` message for key generation.
+    Justification(Justification),
 }
 
 /// A message sent to or received from another node's Honey Badger instance.
@@ -109,6 +154,8 @@ pub enum Message<N: Rand> {
     KeyGen(u64, KeyGenMessage, Box<Signature>),
     /// A vote to be committed, signed by a validator.
     SignedVote(SignedVote<N>),
+    /// A message for threshold-signing the `JoinPlan` for the given epoch.
+    JoinPlanSignature(u64, ThresholdSignMessage),
 }
 
 impl<N: Rand> Message<N> {
@@ -117,6 +164,7 @@ impl<N: Rand> Message<N> {
             Message::HoneyBadger(epoch, _) => epoch,
             Message::KeyGen(epoch, _, _) => epoch,
             Message::SignedVote(ref signed_vote) => signed_vote.era(),
+            Message::JoinPlanSignature(epoch, _) => epoch,
         }
     }
 
@@ -125,6 +173,7 @@ impl<N: Rand> Message<N> {
             Message::HoneyBadger(start_epoch, ref msg) => start_epoch + msg.epoch(),
             Message::KeyGen(epoch, _, _) => epoch,
             Message::SignedVote(ref signed_vote) => signed_vote.era(),
+            Message::JoinPlanSignature(epoch, _) => epoch,
         }
     }
 }
@@ -142,6 +191,53 @@ pub struct JoinPlan<N: Ord> {
     pub_key_set: PublicKeySet,
     /// The public keys of the nodes taking part in key generation.
     pub_keys: BTreeMap<N, PublicKey>,
+    /// The rule that determines the threshold for key generation.
+    threshold: Threshold,
+    /// The current protocol parameters.
+    params: Params,
+    /// The committed votes for the change in progress, with the epochs in which they were
+    /// committed. If they are withdrawn or expire, key generation is aborted.
+    votes: BTreeMap<N, (Vote<N>, u64)>,
+    /// The timestamp of the batch before `epoch`.
+    timestamp: u64,
+}
+
+/// A `JoinPlan`, threshold-signed by the validators that output the batch it was derived from.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SignedJoinPlan<N: Ord> {
+    /// The signed plan.
+    join_plan: JoinPlan<N>,
+    /// The validators' signature of the serialized plan.
+    sig: Signature,
+}
+
+impl<N: Ord + Serialize> SignedJoinPlan<N> {
+    /// Returns the signed plan.
+    pub fn join_plan(&self) -> &JoinPlan<N> {
+        &self.join_plan
+    }
+
+    /// Returns the validators' signature.
+    pub fn signature(&self) -> &Signature {
+        &self.sig
+    }
+
+    /// Returns the first epoch the new node will observe.
+    pub fn epoch(&self) -> u64 {
+        self.join_plan.epoch
+    }
+
+    /// Returns the public master key of the validators starting at the plan's epoch.
+    pub fn public_key(&self) -> PublicKey {
+        self.join_plan.pub_key_set.public_key()
+    }
+
+    /// Returns `true` if the plan was signed with the given public master key.
+    pub fn verify(&self, pk: &PublicKey) -> Result<bool> {
+        let ser_plan = bincode::serialize(&self.join_plan)
+            .map_err(|err| ErrorKind::VerifySignatureBincode(*err))?;
+        Ok(pk.verify(&self.sig, ser_plan))
+    }
 }
 
 /// The ongoing key generation, together with information about the validator change.
@@ -151,36 +247,47 @@ struct KeyGenState<N> {
     key_gen: SyncKeyGen<N>,
     /// The change for which key generation is performed.
     change: Change<N>,
-    /// The number of key generation messages received from the candidate. At most _N² + 1_ are
+    /// The joining candidates, with the public keys they use for key generation.
+    candidates: BTreeMap<N, PublicKey>,
+    /// The number of key generation messages received from each candidate. At most _N² + 1_ are
     /// accepted.
-    candidate_msg_count: usize,
+    candidate_msg_counts: BTreeMap<N, usize>,
 }
 
 impl<N: NodeIdT> KeyGenState<N> {
-    fn new(key_gen: SyncKeyGen<N>, change: Change<N>) -> Self {
+    fn new(key_gen: SyncKeyGen<N>, change: Change<N>, candidates: BTreeMap<N, PublicKey>) -> Self {
         KeyGenState {
             key_gen,
             change,
-            candidate_msg_count: 0,
+            candidates,
+            candidate_msg_counts: BTreeMap::new(),
         }
     }
 
-    /// Returns `true` if the candidate's, if any, as well as enough validators' key generation
-    /// parts have been completed.
+    /// Returns `true` if all candidates', as well as enough validators' key generation parts have
+    /// been completed.
     fn is_ready(&self) -> bool {
         let candidate_ready = |id: &N| self.key_gen.is_node_ready(id);
-        self.key_gen.is_ready() && self.change.candidate().map_or(true, candidate_ready)
+        self.key_gen.is_ready() && self.candidates.keys().all(candidate_ready)
     }
 
-    /// If the node `node_id` is the currently joining candidate, returns its public key.
+    /// If the node `node_id` is a currently joining candidate, returns its public key.
     fn candidate_key(&self, node_id: &N) -> Option<&PublicKey> {
-        match self.change {
-            Change::Add(ref id, ref pk) if id == node_id => Some(pk),
-            Change::Add(_, _) | Change::Remove(_) => None,
-        }
+        self.candidates.get(node_id)
     }
 }
 
+/// An ongoing threshold signing round for a `JoinPlan`.
+#[derive(Debug)]
+struct JoinPlanSigning<N: Ord> {
+    /// The plan being signed.
+    join_plan: JoinPlan<N>,
+    /// Whether the plan's public master key differs from the one it is signed with.
+    is_certificate: bool,
+    /// The signing instance, with the keys of the validators that output the plan's batch.
+    threshold_sign: ThresholdSign<N, Vec<u8>>,
+}
+
 /// The contribution for the internal `HoneyBadger` instance: this includes a user-defined
 /// application-level contribution as well as internal signed messages.
 #[derive(Clone, Eq, PartialEq, Debug, Serialize, Deserialize, Hash)]
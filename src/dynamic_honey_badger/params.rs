@@ -0,0 +1,52 @@
+use honey_badger::{EncryptionSchedule, SubsetHandlingStrategy};
+
+/// The maximum value of `Params::max_future_epochs`. Larger values would let faulty nodes make us
+/// buffer messages for too many epochs.
+pub const MAX_MAX_FUTURE_EPOCHS: usize = 1000;
+
+/// Protocol parameters that must be the same for all nodes. They can be changed by a
+/// `Change::Parameters` vote.
+#[derive(Clone, Eq, PartialEq, Serialize, Deserialize, Hash, Debug)]
+pub struct Params {
+    /// The maximum number of future epochs for which we handle messages simultaneously.
+    pub max_future_epochs: usize,
+    /// The strategy used to handle the output of the `Subset` algorithm.
+    pub subset_handling_strategy: SubsetHandlingStrategy,
+    /// The target number of transactions per batch. This is not used by `DynamicHoneyBadger`
+    /// itself, only by `QueueingHoneyBadger`.
+    pub batch_size: usize,
+    /// The number of epochs for which a committed vote counts. After that, it is treated as a
+    /// withdrawal, unless the validator has cast a new vote. If `None`, votes never expire.
+    ///
+    /// The expiry is measured in epochs rather than eras: Eras only end when a change completes,
+    /// so a vote that expired after a number of eras could block other changes indefinitely if
+    /// its change never gains enough support.
+    pub vote_expiry: Option<u64>,
+    /// The epochs in which the contributions are encrypted. The epochs are counted from the start
+    /// of the current internal `HoneyBadger` instance, which restarts whenever the validators, the
+    /// keys or the parameters change.
+    pub encryption_schedule: EncryptionSchedule,
+}
+
+impl Default for Params {
+    fn default() -> Self {
+        Params {
+            max_future_epochs: 3,
+            subset_handling_strategy: SubsetHandlingStrategy::Incremental,
+            batch_size: 100,
+            vote_expiry: None,
+            encryption_schedule: EncryptionSchedule::Always,
+        }
+    }
+}
+
+impl Params {
+    /// Returns `true` if the parameters are valid: The batch size must be positive,
+    /// `max_future_epochs` must be at most `MAX_MAX_FUTURE_EPOCHS`, and votes must not expire
+    /// before they can be counted.
+    pub fn is_valid(&self) -> bool {
+        self.batch_size > 0
+            && self.max_future_epochs <= MAX_MAX_FUTURE_EPOCHS
+            && self.vote_expiry != Some(0)
+    }
+}
@@ -1,3 +1,4 @@
+use std::cmp;
 use std::collections::{BTreeMap, HashMap};
 use std::sync::Arc;
 
@@ -7,24 +8,38 @@ use serde::{Deserialize, Serialize};
 
 use super::{Change, ErrorKind, Result};
 use fault_log::{FaultKind, FaultLog};
-use messaging::NetworkInfo;
+use messaging::{NetworkInfo, Threshold};
 use traits::NodeIdT;
 
 /// A buffer and counter collecting pending and committed votes for validator set changes.
 ///
-/// This is reset whenever the set of validators changes or a change reaches _f + 1_ votes. We call
-/// the epochs since the last reset the current _era_.
+/// This is reset whenever the set of validators changes or key generation for a change begins. We
+/// call the epochs since the last reset the current _era_. The committed votes for the change
+/// whose key generation begins are carried over into the new era with `carry_over`, so that it
+/// loses them if they are withdrawn or expire. A `Parameters` change doesn't start a new era: Only
+/// the votes for it are withdrawn once it has passed.
+///
+/// Each validator has at most one active vote: a vote with a higher number replaces the previous
+/// one, even if it is for a different change. A _withdrawal_ is a vote for no change at all: It
+/// revokes the validator's previous vote without replacing it. Committed votes can also be made to
+/// expire after a number of epochs with `expire_votes`, which turns them into withdrawals.
+///
+/// Votes for a change that would result in a number of validators for which the threshold rule
+/// gives no valid threshold, that would replace a current validator's public key, or that would
+/// set invalid parameters, are rejected, so that such a change can never win.
 #[derive(Debug)]
 pub struct VoteCounter<N> {
     /// Shared network data.
     netinfo: Arc<NetworkInfo<N>>,
     /// The epoch when voting was reset.
     era: u64,
+    /// The rule that determines the threshold for key generation.
+    threshold: Threshold,
     /// Pending node transactions that we will propose in the next epoch.
     pending: BTreeMap<N, SignedVote<N>>,
-    /// Collected votes for adding or removing nodes. Each node has one vote, and casting another
-    /// vote revokes the previous one.
-    committed: BTreeMap<N, Vote<N>>,
+    /// Collected votes for adding or removing nodes, with the epoch in which they were committed.
+    /// Each node has one vote, and casting another vote revokes the previous one.
+    committed: BTreeMap<N, (Vote<N>, u64)>,
 }
 
 impl<N> VoteCounter<N>
@@ -32,9 +47,10 @@ where
     N: NodeIdT + Serialize + for<'r> Deserialize<'r>,
 {
     /// Creates a new `VoteCounter` object with empty buffer and counter.
-    pub fn new(netinfo: Arc<NetworkInfo<N>>, era: u64) -> Self {
+    pub fn new(netinfo: Arc<NetworkInfo<N>>, era: u64, threshold: Threshold) -> Self {
         VoteCounter {
             era,
+            threshold,
             netinfo,
             pending: BTreeMap::new(),
             committed: BTreeMap::new(),
@@ -43,11 +59,29 @@ where
 
     /// Creates a signed vote for the given change, and inserts it into the pending votes buffer.
     pub fn sign_vote_for(&mut self, change: Change<N>) -> Result<&SignedVote<N>> {
+        self.sign_vote(Some(change))
+    }
+
+    /// Creates a signed withdrawal of our previous vote, and inserts it into the pending votes
+    /// buffer.
+    pub fn sign_withdrawal(&mut self) -> Result<&SignedVote<N>> {
+        self.sign_vote(None)
+    }
+
+    /// Creates a signed vote for the given change, or a withdrawal if `change` is `None`, and
+    /// inserts it into the pending votes buffer.
+    fn sign_vote(&mut self, change: Option<Change<N>>) -> Result<&SignedVote<N>> {
         let voter = self.netinfo.our_id().clone();
+        // The number must be higher than that of our vote carried over from the previous era.
+        let pending_num = self.pending.get(&voter).map_or(0, |sv| sv.vote.num + 1);
+        let committed_num = self
+            .committed
+            .get(&voter)
+            .map_or(0, |&(ref vote, _)| vote.num + 1);
         let vote = Vote {
             change,
             era: self.era,
-            num: self.pending.get(&voter).map_or(0, |sv| sv.vote.num + 1),
+            num: cmp::max(pending_num, committed_num),
         };
         let ser_vote =
             bincode::serialize(&vote).map_err(|err| ErrorKind::SignVoteForBincode(*err))?;
@@ -79,6 +113,12 @@ where
                 FaultKind::InvalidVoteSignature,
             ));
         }
+        if !self.is_change_valid(&signed_vote.vote) {
+            return Ok(FaultLog::init(
+                sender_id.clone(),
+                FaultKind::InvalidVoteChange,
+            ));
+        }
         self.pending.insert(signed_vote.voter.clone(), signed_vote);
         Ok(FaultLog::new())
     }
@@ -89,7 +129,7 @@ where
         self.pending.values().filter(move |signed_vote| {
             self.committed
                 .get(&signed_vote.voter)
-                .map_or(true, |vote| vote.num < signed_vote.vote.num)
+                .map_or(true, |&(ref vote, _)| vote.num < signed_vote.vote.num)
         })
     }
 
@@ -98,52 +138,125 @@ where
         &mut self,
         proposer_id: &N,
         signed_votes: I,
+        epoch: u64,
     ) -> Result<FaultLog<N>>
     where
         I: IntoIterator<Item = SignedVote<N>>,
     {
         let mut fault_log = FaultLog::new();
         for signed_vote in signed_votes {
-            fault_log.extend(self.add_committed_vote(proposer_id, signed_vote)?);
+            fault_log.extend(self.add_committed_vote(proposer_id, signed_vote, epoch)?);
         }
         Ok(fault_log)
     }
 
-    /// Inserts a committed vote into the counter, if it has a higher number than the existing one.
+    /// Inserts a vote that was committed in the given epoch into the counter, if it has a higher
+    /// number than the existing one.
     pub fn add_committed_vote(
         &mut self,
         proposer_id: &N,
         signed_vote: SignedVote<N>,
+        epoch: u64,
     ) -> Result<FaultLog<N>> {
         if self
             .committed
             .get(&signed_vote.voter)
-            .map_or(false, |vote| vote.num >= signed_vote.vote.num)
+            .map_or(false, |&(ref vote, _)| vote.num >= signed_vote.vote.num)
         {
             return Ok(FaultLog::new()); // The vote is obsolete or already exists.
         }
-        if signed_vote.vote.era != self.era || !self.validate(&signed_vote)? {
+        if signed_vote.vote.era != self.era
+            || !self.is_change_valid(&signed_vote.vote)
+            || !self.validate(&signed_vote)?
+        {
             return Ok(FaultLog::init(
                 proposer_id.clone(),
                 FaultKind::InvalidCommittedVote,
             ));
         }
-        self.committed.insert(signed_vote.voter, signed_vote.vote);
+        self.committed
+            .insert(signed_vote.voter, (signed_vote.vote, epoch));
         Ok(FaultLog::new())
     }
 
-    /// Returns the change that has at least _f + 1_ votes, if any.
+    /// Turns all votes that were committed before the given epoch into withdrawals.
+    pub fn expire_votes(&mut self, epoch: u64) {
+        for &mut (ref mut vote, commit_epoch) in self.committed.values_mut() {
+            if commit_epoch < epoch {
+                vote.change = None;
+            }
+        }
+    }
+
+    /// Returns the committed votes for the given change, with the epochs in which they were
+    /// committed.
+    pub fn committed_votes_for(&self, change: &Change<N>) -> BTreeMap<N, (Vote<N>, u64)> {
+        self.committed
+            .iter()
+            .filter(|&(_, &(ref vote, _))| vote.change.as_ref() == Some(change))
+            .map(|(voter, entry)| (voter.clone(), entry.clone()))
+            .collect()
+    }
+
+    /// Returns all committed votes, with the epochs in which they were committed.
+    pub fn committed_votes(&self) -> BTreeMap<N, (Vote<N>, u64)> {
+        self.committed.clone()
+    }
+
+    /// Inserts committed votes from the previous era. They count, expire and can be replaced like
+    /// votes committed in this era.
+    pub fn carry_over(&mut self, votes: BTreeMap<N, (Vote<N>, u64)>) {
+        self.committed.extend(votes);
+    }
+
+    /// Turns all committed votes for the given change into withdrawals.
+    pub fn withdraw_votes_for(&mut self, change: &Change<N>) {
+        for &mut (ref mut vote, _) in self.committed.values_mut() {
+            if vote.change.as_ref() == Some(change) {
+                vote.change = None;
+            }
+        }
+    }
+
+    /// Returns the change that has at least _f + 1_ votes, if any. Withdrawals don't count as votes
+    /// for any change.
+    ///
+    /// Conflicting proposals, e.g. `NodeChange`s with different sets of validators, are counted
+    /// separately. If more than one of them has _f + 1_ votes, the one with the most votes wins.
+    /// Ties are broken in favor of the change whose first voter has the lowest ID, so that all
+    /// nodes agree on the winner.
     pub fn compute_winner(&self) -> Option<&Change<N>> {
+        let changes = || {
+            self.committed
+                .values()
+                .filter_map(|&(ref vote, _)| vote.change.as_ref())
+        };
         let mut vote_counts: HashMap<&Change<N>, usize> = HashMap::new();
-        for vote in self.committed.values() {
-            let change = &vote.change;
-            let entry = vote_counts.entry(change).or_insert(0);
-            *entry += 1;
-            if *entry > self.netinfo.num_faulty() {
-                return Some(change);
+        for change in changes() {
+            *vote_counts.entry(change).or_insert(0) += 1;
+        }
+        let mut winner = None;
+        let mut winner_count = self.netinfo.num_faulty();
+        for change in changes() {
+            let count = vote_counts[change];
+            if count > winner_count {
+                winner = Some(change);
+                winner_count = count;
             }
         }
-        None
+        winner
+    }
+
+    /// Returns `true` if the vote is a withdrawal, or if its change keeps the current validators'
+    /// keys, has valid parameters, and the threshold rule is valid for the resulting number of
+    /// validators.
+    fn is_change_valid(&self, vote: &Vote<N>) -> bool {
+        vote.change.as_ref().map_or(true, |change| {
+            let num_nodes = change.num_validators(&self.netinfo);
+            change.keeps_validator_keys(&self.netinfo)
+                && change.are_params_valid()
+                && self.threshold.for_num_nodes(num_nodes).is_some()
+        })
     }
 
     /// Returns `true` if the signature is valid.
@@ -157,9 +270,9 @@ where
 
 /// A vote fore removing or adding a validator.
 #[derive(Eq, PartialEq, Debug, Serialize, Deserialize, Hash, Clone)]
-struct Vote<N> {
-    /// The change this vote is for.
-    change: Change<N>,
+pub struct Vote<N> {
+    /// The change this vote is for, or `None` if this is a withdrawal.
+    change: Option<Change<N>>,
     /// The epoch in which the current era began.
     era: u64,
     /// The vote number: VoteCounter can be changed by casting another vote with a higher number.
@@ -188,11 +301,12 @@ impl<N> SignedVote<N> {
 mod tests {
     use std::sync::Arc;
 
+    use crypto::SecretKey;
     use rand;
 
     use super::{Change, SignedVote, VoteCounter};
     use fault_log::{FaultKind, FaultLog};
-    use messaging::NetworkInfo;
+    use messaging::{NetworkInfo, Threshold};
 
     /// Returns a vector of `node_num` `VoteCounter`s, and some signed example votes.
     ///
@@ -205,8 +319,9 @@ mod tests {
             .expect("Failed to generate `NetworkInfo` map");
 
         // Create a `VoteCounter` instance for each node.
-        let create_counter =
-            |(_, netinfo): (_, NetworkInfo<_>)| VoteCounter::new(Arc::new(netinfo), era);
+        let create_counter = |(_, netinfo): (_, NetworkInfo<_>)| {
+            VoteCounter::new(Arc::new(netinfo), era, Threshold::default())
+        };
         let mut counters: Vec<_> = netinfos.into_iter().map(create_counter).collect();
 
         // Sign a few votes.
@@ -268,7 +383,7 @@ mod tests {
 
         // Adding a committed vote removes it from the pending ones, unless it is older.
         let vote_batch = vec![sv[1][3].clone(), sv[2][1].clone(), sv[0][3].clone()];
-        ct.add_committed_votes(&1, vote_batch)
+        ct.add_committed_votes(&1, vote_batch, era)
             .expect("add committed");
         assert_eq!(ct.pending_votes().collect::<Vec<_>>(), vec![&sv[2][2]]);
     }
@@ -289,7 +404,7 @@ mod tests {
             ..sv[3][1].clone()
         });
         let faults = ct
-            .add_committed_votes(&1, vote_batch)
+            .add_committed_votes(&1, vote_batch, era)
             .expect("add committed");
         let expected_faults = FaultLog::init(1, FaultKind::InvalidCommittedVote);
         assert_eq!(faults, expected_faults);
@@ -297,9 +412,183 @@ mod tests {
 
         // Adding the second vote for `Remove(1)` should return the change: It has f + 1 votes.
         let faults = ct
-            .add_committed_vote(&1, sv[2][1].clone())
+            .add_committed_vote(&1, sv[2][1].clone(), era)
+            .expect("add committed");
+        assert!(faults.is_empty());
+        assert_eq!(ct.compute_winner(), Some(&Change::Remove(1)));
+    }
+
+    #[test]
+    fn test_conflicting_node_changes() {
+        let node_num = 4; // At most one faulty node.
+        let era = 5;
+        let (mut counters, _) = setup(node_num, era);
+        let mut keys_a = counters[0].netinfo.public_key_map().clone();
+        let mut keys_b = keys_a.clone();
+        keys_a.remove(&3);
+        keys_b.remove(&2);
+        let change_a = Change::NodeChange(keys_a);
+        let change_b = Change::NodeChange(keys_b);
+
+        // Nodes 0 and 1 vote for `change_a`, nodes 2 and 3 for `change_b`, and node 1 then changes
+        // its vote to `change_b`.
+        let votes = {
+            let mut sign_vote = |i: usize, change: &Change<usize>| {
+                counters[i]
+                    .sign_vote_for(change.clone())
+                    .expect("sign vote")
+                    .clone()
+            };
+            vec![
+                sign_vote(0, &change_a),
+                sign_vote(1, &change_a),
+                sign_vote(2, &change_b),
+                sign_vote(3, &change_b),
+                sign_vote(1, &change_b),
+            ]
+        };
+        let mut ct = VoteCounter::new(counters[0].netinfo.clone(), era, Threshold::default());
+
+        // Both changes have f + 1 votes. The tie is broken in favor of node 0's vote.
+        let faults = ct
+            .add_committed_votes(&0, votes[..4].to_vec(), era)
+            .expect("add committed");
+        assert!(faults.is_empty());
+        assert_eq!(ct.compute_winner(), Some(&change_a));
+
+        // With node 1's new vote, `change_b` has the most votes.
+        let faults = ct
+            .add_committed_vote(&1, votes[4].clone(), era)
+            .expect("add committed");
+        assert!(faults.is_empty());
+        assert_eq!(ct.compute_winner(), Some(&change_b));
+    }
+
+    #[test]
+    fn test_withdrawn_and_expired_votes() {
+        let node_num = 4; // At most one faulty node.
+        let era = 5;
+        let (mut counters, sv) = setup(node_num, era);
+        let withdrawal = counters[2]
+            .sign_withdrawal()
+            .expect("sign withdrawal")
+            .clone();
+        let ct = &mut counters[0];
+
+        // Nodes 1 and 2 vote for `Remove(1)`, which wins.
+        let faults = ct
+            .add_committed_votes(&1, vec![sv[1][1].clone(), sv[2][1].clone()], era)
+            .expect("add committed");
+        assert!(faults.is_empty());
+        assert_eq!(ct.compute_winner(), Some(&Change::Remove(1)));
+
+        // Node 2 withdraws its vote, so `Remove(1)` has only f votes left.
+        let faults = ct
+            .add_committed_vote(&2, withdrawal, era + 1)
+            .expect("add committed");
+        assert!(faults.is_empty());
+        assert_eq!(ct.compute_winner(), None);
+
+        // Node 3 votes for `Remove(1)`, too. Once node 1's vote expires, it loses again.
+        let faults = ct
+            .add_committed_vote(&3, sv[3][1].clone(), era + 2)
+            .expect("add committed");
+        assert!(faults.is_empty());
+        assert_eq!(ct.compute_winner(), Some(&Change::Remove(1)));
+        ct.expire_votes(era + 2);
+        assert_eq!(ct.compute_winner(), None);
+    }
+
+    #[test]
+    fn test_carried_over_votes() {
+        let node_num = 4; // At most one faulty node.
+        let era = 5;
+        let new_era = 8;
+        let (mut counters, sv) = setup(node_num, era);
+
+        // Nodes 1 and 2 vote for `Remove(1)`, which wins, so key generation begins in a new era.
+        let faults = counters[0]
+            .add_committed_votes(&1, vec![sv[1][1].clone(), sv[2][1].clone()], era)
+            .expect("add committed");
+        assert!(faults.is_empty());
+        let votes = counters[0].committed_votes_for(&Change::Remove(1));
+        let new_counter = |counter: &VoteCounter<usize>| {
+            let mut new_ct =
+                VoteCounter::new(counter.netinfo.clone(), new_era, Threshold::default());
+            new_ct.carry_over(votes.clone());
+            new_ct
+        };
+        let mut ct = new_counter(&counters[0]);
+        assert_eq!(ct.compute_winner(), Some(&Change::Remove(1)));
+
+        // Node 2 withdraws its vote in the new era. Its number is higher than the carried over
+        // vote's, so `Remove(1)` loses.
+        let withdrawal = new_counter(&counters[2])
+            .sign_withdrawal()
+            .expect("sign withdrawal")
+            .clone();
+        let faults = ct
+            .add_committed_vote(&2, withdrawal, new_era)
+            .expect("add committed");
+        assert!(faults.is_empty());
+        assert_eq!(ct.compute_winner(), None);
+
+        // The carried over votes expire like the ones committed in the new era.
+        let mut ct = new_counter(&counters[0]);
+        ct.expire_votes(new_era);
+        assert_eq!(ct.compute_winner(), None);
+    }
+
+    #[test]
+    fn test_invalid_threshold_votes() {
+        let node_num = 4;
+        let era = 5;
+        let (mut counters, sv) = setup(node_num, era);
+        // With a threshold of 0, the network can have at most three validators.
+        let netinfo = counters[0].netinfo.clone();
+        let mut ct = VoteCounter::new(netinfo.clone(), era, Threshold::Fixed(0));
+        let keys = netinfo.public_key_map().clone();
+        let invalid_vote = counters[1]
+            .sign_vote_for(Change::NodeChange(keys))
+            .expect("sign vote")
+            .clone();
+
+        // The vote for keeping all four validators is rejected, both pending and committed.
+        let faults = ct
+            .add_pending_vote(&1, invalid_vote.clone())
+            .expect("add pending");
+        assert_eq!(faults, FaultLog::init(1, FaultKind::InvalidVoteChange));
+        let faults = ct
+            .add_committed_vote(&2, invalid_vote, era)
+            .expect("add committed");
+        assert_eq!(faults, FaultLog::init(2, FaultKind::InvalidCommittedVote));
+
+        // Votes for removing a validator are valid.
+        let faults = ct
+            .add_committed_votes(&1, vec![sv[1][1].clone(), sv[2][1].clone()], era)
             .expect("add committed");
         assert!(faults.is_empty());
         assert_eq!(ct.compute_winner(), Some(&Change::Remove(1)));
     }
+
+    #[test]
+    fn test_validator_key_change_votes() {
+        let node_num = 4;
+        let era = 5;
+        let (mut counters, _) = setup(node_num, era);
+        let mut keys = counters[0].netinfo.public_key_map().clone();
+        keys.insert(3, rand::random::<SecretKey>().public_key());
+        let invalid_vote = counters[1]
+            .sign_vote_for(Change::NodeChange(keys))
+            .expect("sign vote")
+            .clone();
+
+        // A vote that replaces node 3's key is rejected.
+        let ct = &mut counters[0];
+        let faults = ct
+            .add_committed_vote(&2, invalid_vote, era)
+            .expect("add committed");
+        assert_eq!(faults, FaultLog::init(2, FaultKind::InvalidCommittedVote));
+        assert_eq!(ct.compute_winner(), None);
+    }
 }
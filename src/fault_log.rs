@@ -37,7 +37,7 @@ pub enum FaultKind {
     ShareDecryptionFailed,
     /// `ThresholdDecryption` received multiple shares from the same sender.
     MultipleDecryptionShares,
-    /// `Broadcast` received a `Value` from a node other than the proposer.
+    /// `Broadcast` or `ConsistentBroadcast` received a `Value` from a node other than the proposer.
     ReceivedValueFromNonProposer,
     /// `Broadcast` recevied an Echo message containing an invalid proof.
     InvalidProof,
@@ -55,18 +55,47 @@ pub enum FaultKind {
     /// `DynamicHoneyBadger` received a message (Accept, Propose, or Change)
     /// with an invalid signature.
     IncorrectPayloadSignature,
-    /// `DynamicHoneyBadger`/`SyncKeyGen` received an invalid Ack message.
+    /// `DynamicHoneyBadger`/`SyncKeyGen` received an invalid Ack message, or `AsyncKeyGen` an
+    /// invalid `Echo` or `Ready` message.
     AckMessage(AckMessageFault),
-    /// `DynamicHoneyBadger`/`SyncKeyGen` received an invalid Part message.
+    /// `DynamicHoneyBadger`/`SyncKeyGen`/`AsyncKeyGen` received an invalid Part message.
     InvalidPartMessage,
+    /// `DynamicHoneyBadger`/`SyncKeyGen` received a complaint about an unknown part, or from a
+    /// node that has already acked or complained about it.
+    InvalidComplaint,
+    /// `DynamicHoneyBadger`/`SyncKeyGen` received an unsolicited or invalid justification.
+    InvalidJustification,
     /// `DynamicHoneyBadger` received a change vote with an invalid signature.
     InvalidVoteSignature,
     /// A validator committed an invalid vote in `DynamicHoneyBadger`.
     InvalidCommittedVote,
+    /// `DynamicHoneyBadger` received a `JoinPlan` signature share from a node that is not among
+    /// the plan's signers.
+    UnexpectedJoinPlanSignature,
+    /// `DynamicHoneyBadger` received a vote for an invalid change, e.g. one that results in an
+    /// invalid threshold.
+    InvalidVoteChange,
     /// `BinaryAgreement` received a duplicate `BVal` message.
     DuplicateBVal,
     /// `BinaryAgreement` received a duplicate `Aux` message.
     DuplicateAux,
+    /// `Subset` received a proposed value that does not satisfy the validity predicate.
+    InvalidProposedValue,
+    /// `HoneyBadger` decrypted a contribution that does not satisfy the validity predicate.
+    InvalidContribution,
+    /// `QueueingHoneyBadger` output a contribution with a transaction that the validator rejects in
+    /// the state before the batch.
+    InvalidTransaction,
+    /// `QueueingHoneyBadger` received more transactions from a peer in one of its epochs than the
+    /// gossip limit allows.
+    GossipLimitExceeded,
+    /// `MvbaSubset` received a proposal that does not contain enough valid delivery proofs.
+    InvalidProposedSet,
+    /// `ConsistentBroadcast` received an `Echo` with an invalid signature, or while not being the
+    /// proposer.
+    InvalidEcho,
+    /// `ConsistentBroadcast` received a `Final` message without enough valid signatures.
+    InvalidFinal,
 }
 
 /// A structure representing the context of a faulty node. This structure
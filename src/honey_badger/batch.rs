@@ -7,6 +7,10 @@ use traits::NodeIdT;
 pub struct Batch<C, N> {
     pub epoch: u64,
     pub contributions: BTreeMap<N, C>,
+    /// The agreed time of the batch in seconds since the Unix epoch: the median of the accepted
+    /// proposers' timestamps, weighted by their voting power, but not earlier than the previous
+    /// batch's timestamp.
+    pub timestamp: u64,
 }
 
 impl<C, N: NodeIdT> Batch<C, N> {
@@ -6,8 +6,9 @@ use rand::{self, Rand, Rng};
 use serde::{Deserialize, Serialize};
 
 use super::HoneyBadger;
-use honey_badger::SubsetHandlingStrategy;
+use honey_badger::{EncryptionSchedule, SubsetAlgorithm, SubsetHandlingStrategy};
 use messaging::NetworkInfo;
+use subset::ValidityPredicate;
 use traits::{Contribution, NodeIdT};
 use util::SubRng;
 
@@ -21,6 +22,16 @@ pub struct HoneyBadgerBuilder<C, N> {
     rng: Box<dyn Rng>,
     /// Strategy used to handle the output of the `Subset` algorithm.
     subset_handling_strategy: SubsetHandlingStrategy,
+    /// The algorithm used to agree on the set of accepted contributions.
+    subset_algorithm: SubsetAlgorithm,
+    /// Whether the Binary Agreement instances in each epoch share one coin per round.
+    shared_coin: bool,
+    /// The epochs in which the contributions are encrypted.
+    encryption_schedule: EncryptionSchedule,
+    /// The predicate that every contribution must satisfy to be included in a batch.
+    validity_predicate: Option<ValidityPredicate<N, C>>,
+    /// The minimum timestamp of the batches.
+    min_timestamp: u64,
     _phantom: PhantomData<C>,
 }
 
@@ -37,6 +48,11 @@ where
             max_future_epochs: 3,
             rng: Box::new(rand::thread_rng()),
             subset_handling_strategy: SubsetHandlingStrategy::Incremental,
+            subset_algorithm: SubsetAlgorithm::default(),
+            shared_coin: false,
+            encryption_schedule: EncryptionSchedule::default(),
+            validity_predicate: None,
+            min_timestamp: 0,
             _phantom: PhantomData,
         }
     }
@@ -62,17 +78,61 @@ where
         self
     }
 
+    /// Sets the algorithm used to agree on the set of accepted contributions in each epoch.
+    pub fn subset_algorithm(&mut self, subset_algorithm: SubsetAlgorithm) -> &mut Self {
+        self.subset_algorithm = subset_algorithm;
+        self
+    }
+
+    /// Sets whether all Binary Agreement instances of an epoch's `Subset` share one coin per
+    /// Binary Agreement round. See the `subset` module documentation for the tradeoff. This only
+    /// applies to `SubsetAlgorithm::ParallelBinaryAgreement`.
+    pub fn shared_coin(&mut self, shared_coin: bool) -> &mut Self {
+        self.shared_coin = shared_coin;
+        self
+    }
+
+    /// Sets the epochs in which the contributions are encrypted. The default is to encrypt them in
+    /// every epoch. All nodes must use the same schedule.
+    pub fn encryption_schedule(&mut self, encryption_schedule: EncryptionSchedule) -> &mut Self {
+        self.encryption_schedule = encryption_schedule;
+        self
+    }
+
+    /// Sets a predicate that every contribution must satisfy to be included in a batch. Invalid
+    /// contributions are discarded and reported as faults of their proposers. The predicate must be
+    /// deterministic: All correct nodes have to come to the same conclusion.
+    pub fn validity_predicate<F>(&mut self, f: F) -> &mut Self
+    where
+        F: Fn(&N, &C) -> bool + Send + Sync + 'static,
+    {
+        self.validity_predicate = Some(ValidityPredicate::new(f));
+        self
+    }
+
+    /// Sets the minimum timestamp of the batches, e.g. the timestamp of the last batch a previous
+    /// instance has output. All nodes must use the same value.
+    pub fn min_timestamp(&mut self, min_timestamp: u64) -> &mut Self {
+        self.min_timestamp = min_timestamp;
+        self
+    }
+
     /// Creates a new Honey Badger instance.
     pub fn build(&mut self) -> HoneyBadger<C, N> {
         HoneyBadger {
             netinfo: self.netinfo.clone(),
             epoch: 0,
             has_input: false,
+            last_timestamp: self.min_timestamp,
             epochs: BTreeMap::new(),
             max_future_epochs: self.max_future_epochs as u64,
             incoming_queue: BTreeMap::new(),
             rng: Box::new(self.rng.sub_rng()),
             subset_handling_strategy: self.subset_handling_strategy.clone(),
+            subset_algorithm: self.subset_algorithm,
+            shared_coin: self.shared_coin,
+            encryption_schedule: self.encryption_schedule,
+            validity_predicate: self.validity_predicate.clone(),
         }
     }
 }
@@ -0,0 +1,39 @@
+/// Determines in which epochs the contributions are encrypted before they are proposed.
+///
+/// Encryption protects against censorship, but costs one round of decryption shares per epoch.
+/// All nodes must use the same schedule. The epoch numbers are those of the `HoneyBadger`
+/// instance, which start at 0.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum EncryptionSchedule {
+    /// Encrypt the contributions in every epoch.
+    Always,
+    /// Never encrypt the contributions.
+    Never,
+    /// Encrypt the contributions only in every _n_-th epoch, starting with epoch 0. With _n = 0_,
+    /// they are never encrypted.
+    EveryNthEpoch(u32),
+    /// Encrypt the contributions in the given number of consecutive epochs, followed by the second
+    /// number of epochs without encryption, starting with epoch 0.
+    TickTock(u32, u32),
+}
+
+impl Default for EncryptionSchedule {
+    fn default() -> Self {
+        EncryptionSchedule::Always
+    }
+}
+
+impl EncryptionSchedule {
+    /// Returns `true` if the contributions in the given epoch are encrypted.
+    pub fn use_on_epoch(self, epoch: u64) -> bool {
+        match self {
+            EncryptionSchedule::Always => true,
+            EncryptionSchedule::Never => false,
+            EncryptionSchedule::EveryNthEpoch(n) => n != 0 && epoch % u64::from(n) == 0,
+            EncryptionSchedule::TickTock(on, off) => {
+                let period = u64::from(on) + u64::from(off);
+                period != 0 && epoch % period < u64::from(on)
+            }
+        }
+    }
+}
@@ -12,7 +12,8 @@ use serde::{Deserialize, Serialize};
 use super::{Batch, ErrorKind, MessageContent, Result, Step};
 use fault_log::{Fault, FaultKind, FaultLog};
 use messaging::{DistAlgorithm, NetworkInfo};
-use subset::{self as cs, Subset, SubsetOutput};
+use mvba_subset::{self as mvba, MvbaSubset};
+use subset::{self as cs, Subset, SubsetOutput, ValidityPredicate};
 use threshold_decryption::{self as td, ThresholdDecryption};
 use traits::{Contribution, NodeIdT};
 
@@ -64,43 +65,81 @@ where
 enum SubsetState<N: Rand> {
     /// The algorithm is ongoing: the set of accepted contributions is still undecided.
     Ongoing(Subset<N>),
+    /// The `MvbaSubset` algorithm is ongoing.
+    OngoingMvba(MvbaSubset<N>),
     /// The algorithm is complete. This contains the set of accepted proposers.
     Complete(BTreeSet<N>),
 }
 
+/// A step of whichever subset algorithm the `EpochState` uses.
+enum SubsetStep<N: NodeIdT + Rand> {
+    Subset(cs::Step<N>),
+    Mvba(mvba::Step<N>),
+}
+
 impl<N> SubsetState<N>
 where
     N: NodeIdT + Rand,
 {
-    /// Provides input to the Subset instance, unless it has already completed.
-    fn handle_input(&mut self, proposal: Vec<u8>) -> Result<cs::Step<N>> {
+    /// Provides input to the subset instance, unless it has already completed.
+    fn handle_input(&mut self, proposal: Vec<u8>) -> Result<SubsetStep<N>> {
         match self {
-            SubsetState::Ongoing(ref mut cs) => cs.handle_input(proposal),
-            SubsetState::Complete(_) => return Ok(cs::Step::default()),
-        }.map_err(|err| ErrorKind::InputSubset(err).into())
+            SubsetState::Ongoing(ref mut cs) => cs
+                .handle_input(proposal)
+                .map(SubsetStep::Subset)
+                .map_err(|err| ErrorKind::InputSubset(err).into()),
+            SubsetState::OngoingMvba(ref mut mvba) => mvba
+                .handle_input(proposal)
+                .map(SubsetStep::Mvba)
+                .map_err(|err| ErrorKind::InputMvbaSubset(err).into()),
+            SubsetState::Complete(_) => Ok(SubsetStep::Subset(cs::Step::default())),
+        }
     }
 
     /// Handles a message in the Subset instance, unless it has already completed.
-    fn handle_message(&mut self, sender_id: &N, msg: cs::Message<N>) -> Result<cs::Step<N>> {
+    fn handle_message(&mut self, sender_id: &N, msg: cs::Message<N>) -> Result<SubsetStep<N>> {
+        match self {
+            SubsetState::Ongoing(ref mut cs) => cs
+                .handle_message(sender_id, msg)
+                .map(SubsetStep::Subset)
+                .map_err(|err| ErrorKind::HandleSubsetMessage(err).into()),
+            SubsetState::OngoingMvba(_) | SubsetState::Complete(_) => {
+                Ok(SubsetStep::Subset(cs::Step::default()))
+            }
+        }
+    }
+
+    /// Handles a message in the `MvbaSubset` instance, unless it has already completed.
+    fn handle_mvba_message(
+        &mut self,
+        sender_id: &N,
+        msg: mvba::Message<N>,
+    ) -> Result<SubsetStep<N>> {
         match self {
-            SubsetState::Ongoing(ref mut cs) => cs.handle_message(sender_id, msg),
-            SubsetState::Complete(_) => return Ok(cs::Step::default()),
-        }.map_err(|err| ErrorKind::HandleSubsetMessage(err).into())
+            SubsetState::OngoingMvba(ref mut mvba) => mvba
+                .handle_message(sender_id, msg)
+                .map(SubsetStep::Mvba)
+                .map_err(|err| ErrorKind::HandleMvbaSubsetMessage(err).into()),
+            SubsetState::Ongoing(_) | SubsetState::Complete(_) => {
+                Ok(SubsetStep::Mvba(mvba::Step::default()))
+            }
+        }
     }
 
-    /// Returns the number of contributions that we have already received or, after completion, how
-    /// many have been accepted.
-    pub fn received_proposals(&self) -> usize {
+    /// Returns the total weight of the proposers whose contributions we have already received or,
+    /// after completion, that have been accepted.
+    pub fn received_proposal_weight(&self, netinfo: &NetworkInfo<N>) -> u64 {
         match self {
-            SubsetState::Ongoing(ref cs) => cs.received_proposals(),
-            SubsetState::Complete(ref proposer_ids) => proposer_ids.len(),
+            SubsetState::Ongoing(ref cs) => cs.received_proposal_weight(),
+            SubsetState::OngoingMvba(ref mvba) => mvba.received_proposal_weight(),
+            SubsetState::Complete(ref proposer_ids) => netinfo.weight_of(proposer_ids),
         }
     }
 
     /// Returns the IDs of the accepted proposers, if that has already been decided.
     pub fn accepted_ids(&self) -> Option<&BTreeSet<N>> {
         match self {
-            SubsetState::Ongoing(_) => None,
+            SubsetState::Ongoing(_) | SubsetState::OngoingMvba(_) => None,
             SubsetState::Complete(ref ids) => Some(ids),
         }
     }
@@ -108,7 +147,7 @@ where
 
 /// A flag used when constructing an `EpochState` to determine which behavior to use when receiving
 /// proposals from a `Subset` instance.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
 pub enum SubsetHandlingStrategy {
     /// Sets the `EpochState` to return proposals as they are contributed.
     Incremental,
@@ -116,6 +155,23 @@ pub enum SubsetHandlingStrategy {
     AllAtEnd,
 }
 
+/// The algorithm an `EpochState` uses to agree on the set of accepted proposals. Both have the same
+/// guarantees and produce the same kind of output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SubsetAlgorithm {
+    /// `Subset`: Runs one Binary Agreement instance per validator in parallel.
+    ParallelBinaryAgreement,
+    /// `MvbaSubset`: Agrees on one validator's set of proposals, using an expected constant number
+    /// of Binary Agreement instances, at the cost of additional threshold signatures.
+    Mvba,
+}
+
+impl Default for SubsetAlgorithm {
+    fn default() -> Self {
+        SubsetAlgorithm::ParallelBinaryAgreement
+    }
+}
+
 /// Used in an `EpochState` to encapsulate the state necessary to maintain each
 /// `SubsetHandlingStrategy`.
 #[derive(Debug, Clone)]
@@ -192,6 +248,10 @@ pub struct EpochState<C, N: Rand> {
     accepted_proposers: BTreeSet<N>,
     /// Determines the behavior upon receiving proposals from `subset`.
     subset_handler: SubsetHandler<N>,
+    /// Whether the contributions in this epoch are encrypted.
+    require_decryption: bool,
+    /// The predicate that every decrypted contribution must satisfy.
+    validity_predicate: Option<ValidityPredicate<N, C>>,
     _phantom: PhantomData<C>,
 }
 
@@ -200,39 +260,72 @@ where
     C: Contribution + Serialize + for<'r> Deserialize<'r>,
     N: NodeIdT + Rand,
 {
-    /// Creates a new `Subset` instance.
+    /// Creates a new `Subset` instance. If `require_decryption` is `false`, the contributions
+    /// are proposed and output in plain text.
     pub fn new(
         netinfo: Arc<NetworkInfo<N>>,
         epoch: u64,
         subset_handling_strategy: SubsetHandlingStrategy,
+        subset_algorithm: SubsetAlgorithm,
+        shared_coin: bool,
+        require_decryption: bool,
+        validity_predicate: Option<ValidityPredicate<N, C>>,
     ) -> Result<Self> {
-        let cs = Subset::new(netinfo.clone(), epoch).map_err(ErrorKind::CreateSubset)?;
+        // Only vote for proposals that are valid ciphertexts: Otherwise no correct node could
+        // decrypt them after they have been accepted.
+        let is_ciphertext = |_: &N, value: &[u8]| {
+            bincode::deserialize::<Ciphertext>(value)
+                .map(|ct| ct.verify())
+                .unwrap_or(false)
+        };
+        let subset = match subset_algorithm {
+            SubsetAlgorithm::ParallelBinaryAgreement => {
+                let mut cs =
+                    Subset::new(netinfo.clone(), epoch).map_err(ErrorKind::CreateSubset)?;
+                if require_decryption {
+                    cs = cs.with_validity_predicate(ValidityPredicate::new(is_ciphertext));
+                }
+                if shared_coin {
+                    cs = cs.with_shared_coin();
+                }
+                SubsetState::Ongoing(cs)
+            }
+            SubsetAlgorithm::Mvba => {
+                let mut mvba =
+                    MvbaSubset::new(netinfo.clone(), epoch).map_err(ErrorKind::CreateMvbaSubset)?;
+                if require_decryption {
+                    mvba = mvba.with_validity_predicate(ValidityPredicate::new(is_ciphertext));
+                }
+                SubsetState::OngoingMvba(mvba)
+            }
+        };
         Ok(EpochState {
             epoch,
             netinfo,
-            subset: SubsetState::Ongoing(cs),
+            subset,
             decryption: BTreeMap::default(),
             accepted_proposers: Default::default(),
             subset_handler: subset_handling_strategy.into(),
+            require_decryption,
+            validity_predicate,
             _phantom: PhantomData,
         })
     }
 
-    /// If the instance hasn't terminated yet, inputs our encrypted contribution.
-    pub fn propose(&mut self, ciphertext: &Ciphertext) -> Result<Step<C, N>> {
-        let ser_ct =
-            bincode::serialize(ciphertext).map_err(|err| ErrorKind::ProposeBincode(*err))?;
-        let cs_step = self.subset.handle_input(ser_ct)?;
-        self.process_subset(cs_step)
+    /// If the instance hasn't terminated yet, inputs our serialized contribution: a ciphertext if
+    /// this epoch requires decryption, otherwise the plain text.
+    pub fn propose(&mut self, proposal: Vec<u8>) -> Result<Step<C, N>> {
+        let subset_step = self.subset.handle_input(proposal)?;
+        self.process_subset(subset_step)
     }
 
-    /// Returns the number of contributions that we have already received or, after completion, how
-    /// many have been accepted.
-    pub fn received_proposals(&self) -> usize {
-        self.subset.received_proposals()
+    /// Returns the total weight of the proposers whose contributions we have already received or,
+    /// after completion, that have been accepted.
+    pub fn received_proposal_weight(&self) -> u64 {
+        self.subset.received_proposal_weight(&self.netinfo)
     }
 
-    /// Handles a message for the Subset or a Threshold Decryption instance.
+    /// Handles a message for the subset or a Threshold Decryption instance.
     pub fn handle_message_content(
         &mut self,
         sender_id: &N,
@@ -240,10 +333,18 @@ where
     ) -> Result<Step<C, N>> {
         match content {
             MessageContent::Subset(cs_msg) => {
-                let cs_step = self.subset.handle_message(sender_id, cs_msg)?;
-                self.process_subset(cs_step)
+                let subset_step = self.subset.handle_message(sender_id, cs_msg)?;
+                self.process_subset(subset_step)
+            }
+            MessageContent::MvbaSubset(mvba_msg) => {
+                let subset_step = self.subset.handle_mvba_message(sender_id, mvba_msg)?;
+                self.process_subset(subset_step)
             }
             MessageContent::DecryptionShare { proposer_id, share } => {
+                if !self.require_decryption {
+                    let fault_kind = FaultKind::UnexpectedDecryptionShare;
+                    return Ok(Fault::new(sender_id.clone(), fault_kind).into());
+                }
                 if let Some(ref ids) = self.subset.accepted_ids() {
                     if !ids.contains(&proposer_id) {
                         let fault_kind = FaultKind::UnexpectedDecryptionShare;
@@ -279,16 +380,36 @@ where
         let mut batch = Batch {
             epoch: self.epoch,
             contributions: BTreeMap::new(),
+            timestamp: 0,
         };
+        let mut timestamps = Vec::new();
         // Deserialize the output. If it fails, the proposer of that item is faulty.
         for (id, plaintext) in plaintexts {
-            match bincode::deserialize::<C>(plaintext) {
-                Ok(contrib) => {
+            match bincode::deserialize::<(u64, C)>(plaintext) {
+                Ok((_, ref contrib)) if !self.is_valid(&id, contrib) => {
+                    fault_log.append(id, FaultKind::InvalidContribution)
+                }
+                Ok((timestamp, contrib)) => {
+                    timestamps.push((timestamp, self.netinfo.weight(&id)));
                     batch.contributions.insert(id, contrib);
                 }
                 Err(_) => fault_log.append(id, FaultKind::BatchDeserializationFailed),
             }
         }
+        // The accepted proposers have a total weight of at least _2 f + 1_, with at most _f_ of it
+        // faulty. So the lower weighted median, i.e. the lowest timestamp such that the proposers
+        // up to and including it have at least half of the weight, is between two correct
+        // proposers' timestamps.
+        timestamps.sort();
+        let total_weight: u64 = timestamps.iter().map(|&(_, weight)| weight).sum();
+        let mut weight_so_far = 0;
+        for (timestamp, weight) in timestamps {
+            weight_so_far += weight;
+            if 2 * weight_so_far >= total_weight {
+                batch.timestamp = timestamp;
+                break;
+            }
+        }
         debug!(
             "{:?} Epoch {} output {:?}",
             self.netinfo.our_id(),
@@ -298,12 +419,26 @@ where
         Some((batch, fault_log))
     }
 
+    /// Returns `true` if the contribution satisfies the validity predicate, if there is one.
+    fn is_valid(&self, proposer_id: &N, contrib: &C) -> bool {
+        match self.validity_predicate {
+            None => true,
+            Some(ref predicate) => predicate.is_valid(proposer_id, contrib),
+        }
+    }
+
     /// Checks whether the subset has output, and if it does, sends out our decryption shares.
-    fn process_subset(&mut self, cs_step: cs::Step<N>) -> Result<Step<C, N>> {
+    fn process_subset(&mut self, subset_step: SubsetStep<N>) -> Result<Step<C, N>> {
         let mut step = Step::default();
-        let cs_outputs: VecDeque<_> = step.extend_with(cs_step, |cs_msg| {
-            MessageContent::Subset(cs_msg).with_epoch(self.epoch)
-        });
+        let epoch = self.epoch;
+        let cs_outputs: VecDeque<_> = match subset_step {
+            SubsetStep::Subset(cs_step) => step.extend_with(cs_step, |cs_msg| {
+                MessageContent::Subset(cs_msg).with_epoch(epoch)
+            }),
+            SubsetStep::Mvba(mvba_step) => step.extend_with(mvba_step, |mvba_msg| {
+                MessageContent::MvbaSubset(mvba_msg).with_epoch(epoch)
+            }),
+        };
         let mut has_seen_done = false;
         for cs_output in cs_outputs {
             if has_seen_done {
@@ -316,7 +451,12 @@ where
             } = self.subset_handler.handle(cs_output);
 
             for (k, v) in contributions {
-                step.extend(self.send_decryption_share(k.clone(), &v)?);
+                if self.require_decryption {
+                    step.extend(self.send_decryption_share(k.clone(), &v)?);
+                } else {
+                    self.decryption
+                        .insert(k.clone(), DecryptionState::Complete(v));
+                }
                 self.accepted_proposers.insert(k);
             }
 
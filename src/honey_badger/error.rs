@@ -3,6 +3,7 @@ use std::fmt::{self, Display, Formatter};
 use bincode;
 use failure::{Backtrace, Context, Fail};
 
+use mvba_subset;
 use subset;
 use threshold_decryption;
 
@@ -17,6 +18,12 @@ pub enum ErrorKind {
     InputSubset(subset::Error),
     #[fail(display = "Failed to handle Subset message: {}", _0)]
     HandleSubsetMessage(subset::Error),
+    #[fail(display = "Failed to instantiate MVBA subset: {}", _0)]
+    CreateMvbaSubset(mvba_subset::Error),
+    #[fail(display = "Failed to input contribution to MVBA subset: {}", _0)]
+    InputMvbaSubset(mvba_subset::Error),
+    #[fail(display = "Failed to handle MVBA subset message: {}", _0)]
+    HandleMvbaSubsetMessage(mvba_subset::Error),
     #[fail(display = "Threshold decryption error: {}", _0)]
     ThresholdDecryption(threshold_decryption::Error),
     #[fail(display = "Unknown sender")]
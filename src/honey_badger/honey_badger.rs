@@ -1,18 +1,23 @@
 use std::collections::btree_map::Entry;
 use std::collections::BTreeMap;
-use std::fmt;
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{cmp, fmt};
 
 use bincode;
 use rand::{Rand, Rng};
 use serde::{Deserialize, Serialize};
 
 use super::epoch_state::EpochState;
-use super::{Batch, Error, ErrorKind, HoneyBadgerBuilder, Message, MessageContent, Result};
+use super::{
+    Batch, EncryptionSchedule, Error, ErrorKind, HoneyBadgerBuilder, Message, MessageContent,
+    Result,
+};
 use messaging::{self, DistAlgorithm, NetworkInfo};
+use subset::ValidityPredicate;
 use traits::{Contribution, NodeIdT};
 
-pub use super::epoch_state::SubsetHandlingStrategy;
+pub use super::epoch_state::{SubsetAlgorithm, SubsetHandlingStrategy};
 
 /// An instance of the Honey Badger Byzantine fault tolerant consensus algorithm.
 pub struct HoneyBadger<C, N: Rand> {
@@ -22,6 +27,8 @@ pub struct HoneyBadger<C, N: Rand> {
     pub(super) epoch: u64,
     /// Whether we have already submitted a proposal for the current epoch.
     pub(super) has_input: bool,
+    /// The timestamp of the last batch, or the configured minimum if there was none.
+    pub(super) last_timestamp: u64,
     /// The subalgorithms for ongoing epochs.
     pub(super) epochs: BTreeMap<u64, EpochState<C, N>>,
     /// The maximum number of `Subset` instances that we run simultaneously.
@@ -33,6 +40,14 @@ pub struct HoneyBadger<C, N: Rand> {
     pub(super) rng: Box<dyn Rng + Send + Sync>,
     /// Represents the optimization strategy to use for output of the `Subset` algorithm.
     pub(super) subset_handling_strategy: SubsetHandlingStrategy,
+    /// The algorithm used to agree on the set of accepted contributions.
+    pub(super) subset_algorithm: SubsetAlgorithm,
+    /// Whether the Binary Agreement instances in each epoch share one coin per round.
+    pub(super) shared_coin: bool,
+    /// The epochs in which the contributions are encrypted.
+    pub(super) encryption_schedule: EncryptionSchedule,
+    /// The predicate that every contribution must satisfy to be included in a batch.
+    pub(super) validity_predicate: Option<ValidityPredicate<N, C>>,
 }
 
 impl<C, N> fmt::Debug for HoneyBadger<C, N>
@@ -45,9 +60,11 @@ where
             .field("netinfo", &self.netinfo)
             .field("epoch", &self.epoch)
             .field("has_input", &self.has_input)
+            .field("last_timestamp", &self.last_timestamp)
             .field("epochs", &self.epochs)
             .field("max_future_epochs", &self.max_future_epochs)
             .field("incoming_queue", &self.incoming_queue)
+            .field("encryption_schedule", &self.encryption_schedule)
             .field("rng", &"<RNG>")
             .finish()
     }
@@ -94,21 +111,27 @@ where
         HoneyBadgerBuilder::new(netinfo)
     }
 
-    /// Proposes a new item in the current epoch.
+    /// Proposes a new item in the current epoch, together with our current time. It is encrypted
+    /// if the encryption schedule says so.
     pub fn propose(&mut self, proposal: &C) -> Result<Step<C, N>> {
         if !self.netinfo.is_validator() {
             return Ok(Step::default());
         }
         self.has_input = true;
-        let ser_prop =
-            bincode::serialize(&proposal).map_err(|err| ErrorKind::ProposeBincode(*err))?;
-        let ciphertext = self
-            .netinfo
-            .public_key_set()
-            .public_key()
-            .encrypt_with_rng(&mut self.rng, ser_prop);
+        let ser_prop = bincode::serialize(&(current_timestamp(), proposal))
+            .map_err(|err| ErrorKind::ProposeBincode(*err))?;
         let epoch = self.epoch;
-        let mut step = self.epoch_state_mut(epoch)?.propose(&ciphertext)?;
+        let ser_prop = if self.encryption_schedule.use_on_epoch(epoch) {
+            let ciphertext = self
+                .netinfo
+                .public_key_set()
+                .public_key()
+                .encrypt_with_rng(&mut self.rng, ser_prop);
+            bincode::serialize(&ciphertext).map_err(|err| ErrorKind::ProposeBincode(*err))?
+        } else {
+            ser_prop
+        };
+        let mut step = self.epoch_state_mut(epoch)?.propose(ser_prop)?;
         step.extend(self.try_output_batches()?);
         Ok(step)
     }
@@ -140,12 +163,22 @@ where
         !self.netinfo.is_validator() || self.has_input
     }
 
-    /// Returns the number of validators from which we have already received a proposal for the
-    /// current epoch.
-    pub(crate) fn received_proposals(&self) -> usize {
+    /// Returns the earliest epoch whose batch we have not output yet.
+    pub fn next_epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Returns the timestamp of the last batch, or the configured minimum if none was output yet.
+    pub fn last_timestamp(&self) -> u64 {
+        self.last_timestamp
+    }
+
+    /// Returns the total weight of the validators from which we have already received a proposal
+    /// for the current epoch.
+    pub(crate) fn received_proposal_weight(&self) -> u64 {
         self.epochs
             .get(&self.epoch)
-            .map_or(0, EpochState::received_proposals)
+            .map_or(0, EpochState::received_proposal_weight)
     }
 
     /// Increments the epoch number and clears any state that is local to the finished epoch.
@@ -168,11 +201,14 @@ where
     /// Tries to decrypt contributions from all proposers and output those in a batch.
     fn try_output_batches(&mut self) -> Result<Step<C, N>> {
         let mut step = Step::default();
-        while let Some((batch, fault_log)) = self
+        while let Some((mut batch, fault_log)) = self
             .epochs
             .get(&self.epoch)
             .and_then(EpochState::try_output_batch)
         {
+            // Timestamps never decrease.
+            batch.timestamp = cmp::max(batch.timestamp, self.last_timestamp);
+            self.last_timestamp = batch.timestamp;
             // Queue the output and advance the epoch.
             step.output.push_back(batch);
             step.fault_log.extend(fault_log);
@@ -190,7 +226,19 @@ where
                 self.netinfo.clone(),
                 epoch,
                 self.subset_handling_strategy.clone(),
+                self.subset_algorithm,
+                self.shared_coin,
+                self.encryption_schedule.use_on_epoch(epoch),
+                self.validity_predicate.clone(),
             )?),
         })
     }
 }
+
+/// Returns the current time in seconds since the Unix epoch, or `0` if the clock is set earlier.
+fn current_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or(0)
+}
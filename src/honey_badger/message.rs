@@ -1,5 +1,6 @@
 use rand::Rand;
 
+use mvba_subset;
 use subset;
 use threshold_decryption;
 
@@ -8,6 +9,8 @@ use threshold_decryption;
 pub enum MessageContent<N: Rand> {
     /// A message belonging to the subset algorithm in the given epoch.
     Subset(subset::Message<N>),
+    /// A message belonging to the MVBA subset algorithm in the given epoch.
+    MvbaSubset(mvba_subset::Message<N>),
     /// A decrypted share of the output of `proposer_id`.
     DecryptionShare {
         proposer_id: N,
@@ -17,6 +17,25 @@
 //! proposers must be faulty -, and the remaining ones are output as the new batch. The next epoch
 //! begins as soon as the validators propose new contributions again.
 //!
+//! Optionally, the application can provide a validity predicate for contributions, using
+//! `HoneyBadgerBuilder::validity_predicate`. Since the contributions are encrypted while the
+//! `Subset` instance runs, the nodes can only check that the proposals are valid ciphertexts
+//! before they vote for them. The predicate is applied after decryption instead: Contributions that
+//! don't satisfy it are discarded, too, and their proposers are reported as faulty.
+//!
+//! Each proposer attaches its local time to its contribution. The batch's timestamp is the median
+//! of the accepted proposers' timestamps, weighted by their voting power, so it is between two
+//! correct nodes' clocks. It is increased to the previous batch's timestamp if necessary, so that
+//! it never decreases.
+//!
+//! Encryption costs an additional round of decryption shares per epoch. With
+//! `HoneyBadgerBuilder::encryption_schedule`, the nodes can agree to encrypt only in some of the
+//! epochs and propose their contributions in plain text in the others. In those epochs, faulty
+//! nodes can see the contributions before the subset is decided.
+//!
+//! Instead of `Subset`, the nodes can use `MvbaSubset`, which has the same guarantees but needs
+//! fewer Binary Agreement instances in large networks. See `HoneyBadgerBuilder::subset_algorithm`.
+//!
 //! So it is essentially an endlessly repeating `Subset`, but with the proposed values
 //! encrypted. The encryption makes it harder for an attacker to try and censor a particular value
 //! by influencing the set of proposals that make it into the subset, because they don't
@@ -24,6 +43,7 @@
 
 mod batch;
 mod builder;
+mod encryption_schedule;
 mod epoch_state;
 mod error;
 mod honey_badger;
@@ -31,6 +51,7 @@ mod message;
 
 pub use self::batch::Batch;
 pub use self::builder::HoneyBadgerBuilder;
+pub use self::encryption_schedule::EncryptionSchedule;
 pub use self::error::{Error, ErrorKind, Result};
-pub use self::honey_badger::{HoneyBadger, Step, SubsetHandlingStrategy};
+pub use self::honey_badger::{HoneyBadger, Step, SubsetAlgorithm, SubsetHandlingStrategy};
 pub use self::message::{Message, MessageContent};
@@ -0,0 +1,344 @@
+//! # Encrypted Key Store
+//!
+//! Serializes a node's keys, i.e. everything needed to create its `NetworkInfo`, so that a
+//! validator can persist them between restarts. The public parts (our ID, the public key set and
+//! the nodes' public keys) are stored in the clear, but authenticated. The secret parts (our
+//! secret key and secret key share) are encrypted under a key derived from a passphrase.
+//!
+//! Since `SecretKey` and `SecretKeyShare` cannot be serialized, the store keeps their values
+//! instead: a `SecretValue` for the node's own secret key, and the `ShareValue` returned by
+//! `SyncKeyGen::generate_share`, `into_network_info_with_share` or
+//! `DynamicHoneyBadger::share_value`.
+//!
+//! To restart a `DynamicHoneyBadger` node, pass `KeyStore::network_info` to
+//! `DynamicHoneyBadgerBuilder::build`, and `KeyStore::share_value` to
+//! `DynamicHoneyBadgerBuilder::share_value`, so that the node can still take part in resharing.
+//!
+//! ## File format
+//!
+//! * The magic bytes `HBKS`, followed by the format version as a big-endian `u16`.
+//! * The bincode-serialized `Sealed` contents: the salt and the number of iterations for the key
+//! derivation, the nonce, the serialized public data and the encrypted secret data.
+//!
+//! The key is derived from the passphrase with PBKDF2-HMAC-SHA256. The secret data is encrypted
+//! with ChaCha20-Poly1305, with the version and all other contents as additional authenticated
+//! data. A wrong passphrase is detected when the decryption fails. Key stores that specify more
+//! than ten times `KDF_ITERATIONS` iterations are rejected without deriving the key.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug, Formatter};
+use std::io::{self, Read, Write};
+
+use bincode;
+use byteorder::{BigEndian, ByteOrder};
+use crypto::{
+    error::Error as CryptoError, serde_impl::field_vec::FieldWrap, PublicKey, PublicKeySet,
+    SecretKey, SecretKeyShare,
+};
+use pairing::bls12_381::Fr;
+use rand;
+use ring::aead::{self, OpeningKey, SealingKey, CHACHA20_POLY1305};
+use ring::{digest, pbkdf2};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use messaging::NetworkInfo;
+use sync_key_gen::ShareValue;
+use traits::NodeIdT;
+
+/// The magic bytes at the beginning of every key store.
+const MAGIC: &[u8; 4] = b"HBKS";
+/// The current version of the key store format.
+pub const VERSION: u16 = 1;
+/// The number of PBKDF2 iterations used to derive the encryption key from the passphrase.
+pub const KDF_ITERATIONS: u32 = 100_000;
+/// The maximum number of PBKDF2 iterations accepted when loading a key store. Since the number is
+/// read from the file before it is authenticated, a higher one could be used to waste our time.
+const MAX_KDF_ITERATIONS: u32 = 10 * KDF_ITERATIONS;
+/// The length of the encryption key.
+const KEY_LEN: usize = 32;
+/// The length of the nonce.
+const NONCE_LEN: usize = 12;
+
+/// A key store error.
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "I/O error: {}", _0)]
+    Io(#[cause] io::Error),
+    #[fail(display = "Serialization error: {}", _0)]
+    Serialization(#[cause] bincode::Error),
+    #[fail(display = "Not a key store")]
+    InvalidMagic,
+    #[fail(display = "Unsupported key store version {}", _0)]
+    UnsupportedVersion(u16),
+    #[fail(display = "Unsupported number of key derivation iterations {}", _0)]
+    UnsupportedIterations(u32),
+    #[fail(display = "Wrong passphrase or corrupted key store")]
+    Authentication,
+    #[fail(display = "Error encrypting the secret keys")]
+    Encryption,
+    #[fail(display = "Error creating secret key: {}", _0)]
+    Crypto(CryptoError),
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<bincode::Error> for Error {
+    fn from(err: bincode::Error) -> Error {
+        Error::Serialization(err)
+    }
+}
+
+/// A key store result.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// The secret value of a node's own secret key.
+#[derive(Clone)]
+pub struct SecretValue(Fr);
+
+impl Debug for SecretValue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("SecretValue").field(&"...").finish()
+    }
+}
+
+impl rand::Rand for SecretValue {
+    fn rand<R: rand::Rng>(rng: &mut R) -> Self {
+        SecretValue(rng.gen())
+    }
+}
+
+impl SecretValue {
+    /// Returns the secret key with this value.
+    pub fn secret_key(&self) -> Result<SecretKey> {
+        let mut fr = self.0;
+        SecretKey::from_mut_ptr(&mut fr as *mut Fr).map_err(Error::Crypto)
+    }
+}
+
+/// The serialized contents of a key store, following the header.
+#[derive(Serialize, Deserialize)]
+struct Sealed {
+    /// The random salt for the key derivation.
+    salt: [u8; 32],
+    /// The number of PBKDF2 iterations for the key derivation. At most `MAX_KDF_ITERATIONS`.
+    iterations: u32,
+    /// The random nonce for the encryption.
+    nonce: [u8; NONCE_LEN],
+    /// Our serialized node ID, public key set and nodes' public keys.
+    public: Vec<u8>,
+    /// The encrypted secret key value and key share value, followed by the authentication tag.
+    secret: Vec<u8>,
+}
+
+impl Sealed {
+    /// Returns the additional authenticated data: the version and all contents except the secret
+    /// data.
+    fn auth_data(&self) -> Result<Vec<u8>> {
+        let data = (
+            VERSION,
+            &self.salt,
+            self.iterations,
+            &self.nonce,
+            &self.public,
+        );
+        Ok(bincode::serialize(&data)?)
+    }
+
+    /// Returns the encryption key derived from the passphrase.
+    fn derive_key(&self, passphrase: &[u8]) -> [u8; KEY_LEN] {
+        let mut key = [0u8; KEY_LEN];
+        pbkdf2::derive(
+            &digest::SHA256,
+            self.iterations,
+            &self.salt,
+            passphrase,
+            &mut key,
+        );
+        key
+    }
+
+    /// Encrypts the secret data with the key derived from the passphrase, and stores it.
+    fn seal(&mut self, passphrase: &[u8], mut secret: Vec<u8>) -> Result<()> {
+        let tag_len = CHACHA20_POLY1305.tag_len();
+        secret.extend(vec![0u8; tag_len]);
+        let auth_data = self.auth_data()?;
+        let mut key = self.derive_key(passphrase);
+        let sealing_key = SealingKey::new(&CHACHA20_POLY1305, &key);
+        clear(&mut key);
+        let sealing_key = sealing_key.map_err(|_| Error::Encryption)?;
+        aead::seal_in_place(&sealing_key, &self.nonce, &auth_data, &mut secret, tag_len)
+            .map_err(|_| Error::Encryption)?;
+        self.secret = secret;
+        Ok(())
+    }
+
+    /// Decrypts the secret data in place with the key derived from the passphrase, and returns
+    /// it. Fails if the passphrase is wrong or the contents have been modified.
+    ///
+    /// The number of iterations is checked before deriving the key, since it is not authenticated
+    /// yet at that point.
+    fn open(&mut self, passphrase: &[u8]) -> Result<&[u8]> {
+        if self.iterations == 0 {
+            return Err(Error::Authentication);
+        }
+        if self.iterations > MAX_KDF_ITERATIONS {
+            return Err(Error::UnsupportedIterations(self.iterations));
+        }
+        let auth_data = self.auth_data()?;
+        let mut key = self.derive_key(passphrase);
+        let opening_key = OpeningKey::new(&CHACHA20_POLY1305, &key);
+        clear(&mut key);
+        let opening_key = opening_key.map_err(|_| Error::Authentication)?;
+        let nonce = &self.nonce;
+        let plaintext = aead::open_in_place(&opening_key, nonce, &auth_data, 0, &mut self.secret)
+            .map_err(|_| Error::Authentication)?;
+        Ok(plaintext)
+    }
+}
+
+/// A node's keys, which can be saved to and loaded from an encrypted key store.
+#[derive(Clone, Debug)]
+pub struct KeyStore<N> {
+    our_id: N,
+    secret_key: SecretValue,
+    share_value: Option<ShareValue>,
+    public_key_set: PublicKeySet,
+    public_keys: BTreeMap<N, PublicKey>,
+}
+
+impl<N: NodeIdT + Serialize + DeserializeOwned> KeyStore<N> {
+    /// Creates a key store with the given keys. The share value is `None` if we are not a
+    /// validator.
+    pub fn new(
+        our_id: N,
+        secret_key: SecretValue,
+        share_value: Option<ShareValue>,
+        public_key_set: PublicKeySet,
+        public_keys: BTreeMap<N, PublicKey>,
+    ) -> Self {
+        KeyStore {
+            our_id,
+            secret_key,
+            share_value,
+            public_key_set,
+            public_keys,
+        }
+    }
+
+    /// Returns our node ID.
+    pub fn our_id(&self) -> &N {
+        &self.our_id
+    }
+
+    /// Returns the value of our own secret key.
+    pub fn secret_key(&self) -> &SecretValue {
+        &self.secret_key
+    }
+
+    /// Returns the value of our secret key share, if we are a validator.
+    pub fn share_value(&self) -> Option<&ShareValue> {
+        self.share_value.as_ref()
+    }
+
+    /// Returns the public key set.
+    pub fn public_key_set(&self) -> &PublicKeySet {
+        &self.public_key_set
+    }
+
+    /// Returns the public keys of all nodes.
+    pub fn public_keys(&self) -> &BTreeMap<N, PublicKey> {
+        &self.public_keys
+    }
+
+    /// Creates a `NetworkInfo` with the stored keys.
+    pub fn network_info(&self) -> Result<NetworkInfo<N>> {
+        let sk_share = match self.share_value {
+            Some(ShareValue(mut fr)) => {
+                SecretKeyShare::from_mut_ptr(&mut fr as *mut Fr).map_err(Error::Crypto)?
+            }
+            None => SecretKeyShare::default(),
+        };
+        Ok(NetworkInfo::new(
+            self.our_id.clone(),
+            sk_share,
+            self.public_key_set.clone(),
+            self.secret_key.secret_key()?,
+            self.public_keys.clone(),
+        ))
+    }
+
+    /// Encrypts the secret keys with the passphrase and writes the key store.
+    pub fn save<R: rand::Rng, W: Write>(
+        &self,
+        rng: &mut R,
+        passphrase: &[u8],
+        mut writer: W,
+    ) -> Result<()> {
+        let public_data = (&self.our_id, &self.public_key_set, &self.public_keys);
+        let secret = {
+            let sk_wrap = FieldWrap::new(self.secret_key.0);
+            let share_wrap = self
+                .share_value
+                .as_ref()
+                .map(|share| FieldWrap::new(share.0));
+            bincode::serialize(&(sk_wrap, share_wrap))?
+        };
+        let mut sealed = Sealed {
+            salt: [0u8; 32],
+            iterations: KDF_ITERATIONS,
+            nonce: [0u8; NONCE_LEN],
+            public: bincode::serialize(&public_data)?,
+            secret: Vec::new(),
+        };
+        rng.fill_bytes(&mut sealed.salt);
+        rng.fill_bytes(&mut sealed.nonce);
+        sealed.seal(passphrase, secret)?;
+        let mut version = [0u8; 2];
+        BigEndian::write_u16(&mut version, VERSION);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&version)?;
+        bincode::serialize_into(&mut writer, &sealed)?;
+        writer.flush()?;
+        Ok(())
+    }
+
+    /// Reads a key store and decrypts the secret keys with the passphrase.
+    pub fn load<R: Read>(mut reader: R, passphrase: &[u8]) -> Result<Self> {
+        let mut header = [0u8; 6];
+        reader.read_exact(&mut header)?;
+        if header[..4] != MAGIC[..] {
+            return Err(Error::InvalidMagic);
+        }
+        let version = BigEndian::read_u16(&header[4..]);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let mut sealed: Sealed = bincode::deserialize_from(&mut reader)?;
+        let secrets = {
+            let plaintext = sealed.open(passphrase)?;
+            bincode::deserialize::<(FieldWrap<Fr, Fr>, Option<FieldWrap<Fr, Fr>>)>(plaintext)
+        };
+        clear(&mut sealed.secret);
+        let (sk_wrap, share_wrap) = secrets?;
+        let (our_id, public_key_set, public_keys) = bincode::deserialize(&sealed.public)?;
+        Ok(KeyStore {
+            our_id,
+            secret_key: SecretValue(sk_wrap.into_inner()),
+            share_value: share_wrap.map(|wrap| ShareValue(wrap.into_inner())),
+            public_key_set,
+            public_keys,
+        })
+    }
+}
+
+/// Overwrites the secret data with zeros.
+fn clear(data: &mut [u8]) {
+    for byte in data {
+        *byte = 0;
+    }
+}
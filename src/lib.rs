@@ -68,6 +68,12 @@
 //! transactions. Using the Subset protocol, they agree on at least _N - f_ of those
 //! proposals. The batch contains the union of these sets of transactions.
 //!
+//! [**MVBA Subset**](mvba_subset/index.html)
+//!
+//! An alternative to Subset with the same interface, based on multi-valued validated Byzantine
+//! agreement: Instead of one Binary Agreement per node, it only needs an expected constant number
+//! of them per run, at the cost of additional threshold signatures.
+//!
 //! [**Broadcast**](broadcast/index.html)
 //!
 //! One node, the _proposer_, inputs an item, and every node receives that item as an output. Even
@@ -76,6 +82,11 @@
 //!
 //! This is used in Subset to send each node's proposal to the other nodes.
 //!
+//! [**Consistent Broadcast**](consistent_broadcast/index.html)
+//!
+//! Like Broadcast, but based on signatures instead of erasure coding. This is cheaper for small
+//! items, and Subset can be configured to use it for proposals up to a given size.
+//!
 //! [**Binary Agreement**](binary_agreement/index.html)
 //!
 //! Each node inputs a binary value: `true` or `false`. As output, either all correct nodes receive
@@ -92,6 +103,11 @@
 //! before at least one correct node has provided input, and is uniformly distributed and
 //! pseudorandom.
 //!
+//! [**Threshold Signing**](threshold_sign/index.html)
+//!
+//! Each node inputs `()` to sign a common document. Once _f + 1_ nodes have input, all nodes
+//! output the same signature of the document by the public master key. Coin is based on it.
+//!
 //! [**Threshold Decryption**](threshold_decryption/index.html)
 //!
 //! Each node inputs the same ciphertext, encrypted to the public master key. Once _f + 1_
@@ -107,6 +123,18 @@
 //! Unlike the other algorithms, this one is _not_ asynchronous: All nodes must handle the same
 //! messages, in the same order.
 //!
+//! [**Asynchronous Key Generation**](async_key_gen/index.html)
+//!
+//! Like Synchronous Key Generation, but the messages can be handled in any order, so it can be
+//! used to generate a network's initial key set without a trusted dealer and without running
+//! consensus. It tolerates _f_ faulty nodes if there are at least _5 f + 1_, and generates keys
+//! with any threshold _t_ with _f <= t_ and _t + 2 f < N_.
+//!
+//! [**Key Store**](keystore/index.html)
+//!
+//! Not an algorithm, but a versioned file format to persist a node's keys between restarts, with
+//! the secret key and key share encrypted under a passphrase.
+//!
 //! ## Serialization
 //!
 //! `hbbft` supports [serde](https://serde.rs/): All message types implement the `Serialize` and
@@ -130,24 +158,30 @@ extern crate rand;
 #[macro_use]
 extern crate rand_derive;
 extern crate reed_solomon_erasure;
+extern crate ring;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
 pub extern crate threshold_crypto as crypto;
 extern crate tiny_keccak;
 
+pub mod async_key_gen;
 pub mod binary_agreement;
 pub mod broadcast;
 pub mod coin;
+pub mod consistent_broadcast;
 pub mod dynamic_honey_badger;
 pub mod fault_log;
 mod fmt;
 pub mod honey_badger;
+pub mod keystore;
 pub mod messaging;
+pub mod mvba_subset;
 pub mod queueing_honey_badger;
 pub mod subset;
 pub mod sync_key_gen;
 pub mod threshold_decryption;
+pub mod threshold_sign;
 pub mod transaction_queue;
 pub mod util;
 
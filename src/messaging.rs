@@ -1,5 +1,6 @@
-use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::collections::{BTreeMap, VecDeque};
 use std::iter::once;
+use std::ops::Range;
 
 use failure::Fail;
 use rand;
@@ -219,7 +220,72 @@ pub trait DistAlgorithm: Send + Sync {
     fn our_id(&self) -> &Self::NodeId;
 }
 
+/// The rule that determines the threshold _t_ of the threshold cryptography keys created by key
+/// generation for a network of _N_ validators, at most _f = (N - 1) / 3_ of which are faulty:
+/// _t + 1_ key shares are needed to sign or decrypt.
+///
+/// The faulty nodes must not be able to sign or decrypt on their own, so _t ≥ f_ is required.
+/// And the correct nodes must be able to complete key generation without them, which requires
+/// _t + f + 1_ of them, so _t + 2 f < N_ is required, too.
+///
+/// Since key generation gives every validator a weight of 1, the rule is based on the number of
+/// validators, not on weights.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub enum Threshold {
+    /// _t = f_: Any _f + 1_ nodes can sign and decrypt.
+    Faulty,
+    /// _t = N - 2 f - 1_: the highest threshold that doesn't break liveness. E.g. with _N = 3 f +
+    /// 1_ validators, this is _f_, and with _N = 3 f + 3_, it is _f + 2_.
+    Max,
+    /// A fixed threshold. This is only valid for network sizes where _f ≤ t_ and _t + 2 f < N_.
+    Fixed(usize),
+}
+
+impl Default for Threshold {
+    fn default() -> Self {
+        Threshold::Faulty
+    }
+}
+
+impl Threshold {
+    /// Returns the threshold for a network with `num_nodes` validators, or `None` if the rule
+    /// results in a threshold that is invalid for that number.
+    pub fn for_num_nodes(&self, num_nodes: usize) -> Option<usize> {
+        if num_nodes == 0 {
+            return None;
+        }
+        let num_faulty = (num_nodes - 1) / 3;
+        let threshold = match *self {
+            Threshold::Faulty => num_faulty,
+            Threshold::Max => num_nodes - 2 * num_faulty - 1,
+            Threshold::Fixed(threshold) => threshold,
+        };
+        if num_faulty <= threshold && threshold + 2 * num_faulty < num_nodes {
+            Some(threshold)
+        } else {
+            None
+        }
+    }
+}
+
 /// Common data shared between algorithms: the nodes' IDs and key shares.
+///
+/// Every validator has a _weight_, i.e. its voting power. By default, all weights are 1, so that
+/// _N_ is the total weight, and up to _f = (N - 1) / 3_ nodes can be faulty. With custom weights
+/// (see `new_weighted`), the total weight _W_ takes the place of _N_: The faulty validators'
+/// total weight must be at most _(W - 1) / 3_, and all quorums are determined by weight sums
+/// instead of node counts. A validator with weight _w_ holds _w_ shares of the threshold key.
+///
+/// Since every unit of weight is a key share, the cost of threshold signing and decryption grows
+/// linearly with the total weight: Each validator creates one signature or decryption share per
+/// unit, and the shares of more than a third of the total weight need to be verified and combined.
+/// The weights should therefore be small numbers, e.g. stakes rounded to a coarse unit, and not
+/// raw token amounts.
+///
+/// Key generation (`SyncKeyGen` and `AsyncKeyGen`) doesn't support weights: The keys it creates
+/// give every validator a weight of 1. For that reason, weights can only be used with the
+/// algorithms up to `HoneyBadger`: `DynamicHoneyBadgerBuilder::build`, and thus also
+/// `QueueingHoneyBadger`, return an error for weighted validators.
 #[derive(Debug, Clone)]
 pub struct NetworkInfo<N> {
     our_id: N,
@@ -227,12 +293,20 @@ pub struct NetworkInfo<N> {
     num_faulty: usize,
     is_validator: bool,
     // TODO: Should this be an option? It only makes sense for validators.
-    secret_key_share: SecretKeyShare,
+    secret_key_shares: Vec<SecretKeyShare>,
     secret_key: SecretKey,
     public_key_set: PublicKeySet,
     public_key_shares: BTreeMap<N, PublicKeyShare>,
     public_keys: BTreeMap<N, PublicKey>,
     node_indices: BTreeMap<N, usize>,
+    /// The weight of each validator.
+    weights: BTreeMap<N, u64>,
+    /// The index of each validator's first key share.
+    share_offsets: BTreeMap<N, usize>,
+    /// The sum _W_ of all validators' weights.
+    total_weight: u64,
+    /// The maximum total weight _(W - 1) / 3_ of faulty validators.
+    faulty_weight: u64,
 }
 
 impl<N: NodeIdT> NetworkInfo<N> {
@@ -243,14 +317,55 @@ impl<N: NodeIdT> NetworkInfo<N> {
         secret_key: SecretKey,
         public_keys: BTreeMap<N, PublicKey>,
     ) -> Self {
+        let weights = public_keys.keys().map(|id| (id.clone(), 1)).collect();
+        NetworkInfo::new_weighted(
+            our_id,
+            vec![secret_key_share],
+            public_key_set,
+            secret_key,
+            public_keys,
+            weights,
+        )
+    }
+
+    /// Creates a `NetworkInfo` for a network where the validators have the given weights. The
+    /// validators' key shares are numbered consecutively, in the order of their IDs: The first
+    /// validator with weight _w_ holds the shares with indices _0_ to _w - 1_, the next one
+    /// continues with index _w_, etc. `secret_key_shares` are our own shares, in that order.
+    ///
+    /// The key set needs at least as many shares as the total weight, so the weights should be
+    /// small. See the type's documentation.
+    ///
+    /// Panics if the weights don't match the public keys, or if any weight is zero.
+    pub fn new_weighted(
+        our_id: N,
+        mut secret_key_shares: Vec<SecretKeyShare>,
+        public_key_set: PublicKeySet,
+        secret_key: SecretKey,
+        public_keys: BTreeMap<N, PublicKey>,
+        weights: BTreeMap<N, u64>,
+    ) -> Self {
+        assert!(
+            weights.keys().eq(public_keys.keys()) && weights.values().all(|w| *w > 0),
+            "every validator needs a positive weight"
+        );
         let num_nodes = public_keys.len();
         let is_validator = public_keys.contains_key(&our_id);
+        if secret_key_shares.is_empty() {
+            secret_key_shares.push(SecretKeyShare::default());
+        }
         let node_indices: BTreeMap<N, usize> = public_keys
             .keys()
             .enumerate()
             .map(|(n, id)| (id.clone(), n))
             .collect();
-        let public_key_shares = node_indices
+        let mut share_offsets = BTreeMap::new();
+        let mut total_weight = 0;
+        for (id, weight) in &weights {
+            share_offsets.insert(id.clone(), total_weight as usize);
+            total_weight += *weight;
+        }
+        let public_key_shares = share_offsets
             .iter()
             .map(|(id, idx)| (id.clone(), public_key_set.public_key_share(*idx)))
             .collect();
@@ -259,12 +374,16 @@ impl<N: NodeIdT> NetworkInfo<N> {
             num_nodes,
             num_faulty: (num_nodes - 1) / 3,
             is_validator,
-            secret_key_share,
+            secret_key_shares,
             secret_key,
             public_key_set,
             public_key_shares,
             node_indices,
             public_keys,
+            weights,
+            share_offsets,
+            total_weight,
+            faulty_weight: total_weight.saturating_sub(1) / 3,
         }
     }
 
@@ -295,9 +414,94 @@ impl<N: NodeIdT> NetworkInfo<N> {
         self.num_nodes - self.num_faulty
     }
 
-    /// Returns our secret key share for threshold cryptography.
+    /// Returns `true` if the validators have custom weights, i.e. not all of them have weight 1.
+    pub fn is_weighted(&self) -> bool {
+        self.total_weight != self.num_nodes as u64
+    }
+
+    /// Returns the weight of the given node, or `0` if it is not a validator.
+    pub fn weight(&self, id: &N) -> u64 {
+        self.weights.get(id).cloned().unwrap_or(0)
+    }
+
+    /// Returns a map of all validators' IDs to their weights.
+    pub fn weight_map(&self) -> &BTreeMap<N, u64> {
+        &self.weights
+    }
+
+    /// Returns the sum of the weights of the given nodes.
+    pub fn weight_of<'a, I>(&self, ids: I) -> u64
+    where
+        I: IntoIterator<Item = &'a N>,
+        N: 'a,
+    {
+        ids.into_iter().map(|id| self.weight(id)).sum()
+    }
+
+    /// The total weight _W_ of all validators. Without custom weights, this is _N_.
+    pub fn total_weight(&self) -> u64 {
+        self.total_weight
+    }
+
+    /// The maximum total weight _(W - 1) / 3_ of faulty validators up to which Honey Badger is
+    /// guaranteed to be correct. Without custom weights, this is _f_.
+    pub fn faulty_weight(&self) -> u64 {
+        self.faulty_weight
+    }
+
+    /// The minimum total weight _W - (W - 1) / 3_ of correct validators. Without custom weights,
+    /// this is _N - f_.
+    pub fn correct_weight(&self) -> u64 {
+        self.total_weight - self.faulty_weight
+    }
+
+    /// Returns the smallest number of validators whose total weight is at least `weight`.
+    pub fn min_nodes_with_weight(&self, weight: u64) -> usize {
+        let mut weights: Vec<u64> = self.weights.values().cloned().collect();
+        weights.sort_unstable_by(|w0, w1| w1.cmp(w0));
+        let (mut sum, mut count) = (0, 0);
+        for w in weights {
+            if sum >= weight {
+                break;
+            }
+            sum += w;
+            count += 1;
+        }
+        count
+    }
+
+    /// The threshold _t_ of the public key set: _t + 1_ shares are needed to sign or decrypt. By
+    /// default, this is equal to `num_faulty`, but it can be any value from _f_ to _N - 2 f - 1_
+    /// (see `Threshold`). With custom weights, the bounds are in terms of `faulty_weight` and
+    /// `total_weight` instead.
+    pub fn threshold(&self) -> usize {
+        self.public_key_set.threshold()
+    }
+
+    /// Returns `true` if the threshold is at least `faulty_weight`, so that the faulty nodes can't
+    /// sign or decrypt on their own, and if _t + 2 f < W_, so that the correct nodes can complete
+    /// key generation without them. This is the same rule as in `Threshold::for_num_nodes`.
+    pub fn is_threshold_valid(&self) -> bool {
+        let threshold = self.threshold() as u64;
+        self.faulty_weight <= threshold && threshold + 2 * self.faulty_weight < self.total_weight
+    }
+
+    /// Returns our secret key share for threshold cryptography. With custom weights, this is only
+    /// the first one of our shares.
     pub fn secret_key_share(&self) -> &SecretKeyShare {
-        &self.secret_key_share
+        &self.secret_key_shares[0]
+    }
+
+    /// Returns all our secret key shares, one per unit of our weight.
+    pub fn secret_key_shares(&self) -> &[SecretKeyShare] {
+        &self.secret_key_shares
+    }
+
+    /// Returns the range of indices of the given validator's key shares, or `None` if it is not a
+    /// validator.
+    pub fn share_indices(&self, id: &N) -> Option<Range<usize>> {
+        let offset = *self.share_offsets.get(id)?;
+        Some(offset..(offset + self.weight(id) as usize))
     }
 
     /// Returns our secret key for encryption and signing.
@@ -310,7 +514,8 @@ impl<N: NodeIdT> NetworkInfo<N> {
         &self.public_key_set
     }
 
-    /// Returns the public key share if a node with that ID exists, otherwise `None`.
+    /// Returns the public key share if a node with that ID exists, otherwise `None`. With custom
+    /// weights, this is the public key of the node's first share.
     pub fn public_key_share(&self, id: &N) -> Option<&PublicKeyShare> {
         self.public_key_shares.get(id)
     }
@@ -365,39 +570,58 @@ impl<N: NodeIdT> NetworkInfo<N> {
     where
         I: IntoIterator<Item = N>,
         R: rand::Rng,
+    {
+        NetworkInfo::generate_map_weighted(ids.into_iter().map(|id| (id, 1)), rng)
+    }
+
+    /// Generates a map of matching `NetworkInfo`s with the given validator weights for testing.
+    /// The threshold of the key set is the maximum faulty weight _(W - 1) / 3_.
+    pub fn generate_map_weighted<I, R>(
+        weights: I,
+        rng: &mut R,
+    ) -> Result<BTreeMap<N, NetworkInfo<N>>, crypto::error::Error>
+    where
+        I: IntoIterator<Item = (N, u64)>,
+        R: rand::Rng,
     {
         use crypto::SecretKeySet;
 
-        let all_ids: BTreeSet<N> = ids.into_iter().collect();
-        let num_faulty = (all_ids.len() - 1) / 3;
+        let weights: BTreeMap<N, u64> = weights.into_iter().collect();
+        let total_weight: u64 = weights.values().sum();
+        let faulty_weight = total_weight.saturating_sub(1) / 3;
 
         // Generate the keys for threshold cryptography.
-        let sk_set = SecretKeySet::random(num_faulty, rng)?;
+        let sk_set = SecretKeySet::random(faulty_weight as usize, rng)?;
         let pk_set = sk_set.public_keys();
 
         // Generate keys for individually signing and encrypting messages.
         let sec_keys: BTreeMap<_, SecretKey> =
-            all_ids.iter().map(|id| (id.clone(), rng.gen())).collect();
+            weights.keys().map(|id| (id.clone(), rng.gen())).collect();
         let pub_keys: BTreeMap<_, PublicKey> = sec_keys
             .iter()
             .map(|(id, sk)| (id.clone(), sk.public_key()))
             .collect();
 
-        // Create the corresponding `NetworkInfo` for each node.
-        let create_netinfo = |(i, id): (usize, N)| {
-            let netinfo = NetworkInfo::new(
+        // Create the corresponding `NetworkInfo` for each node, with one key share per unit of
+        // weight.
+        let mut offset = 0;
+        let mut netinfos = BTreeMap::new();
+        for (id, weight) in &weights {
+            let end = offset + *weight as usize;
+            let sk_shares = (offset..end)
+                .map(|i| sk_set.secret_key_share(i))
+                .collect::<Result<_, _>>()?;
+            offset = end;
+            let netinfo = NetworkInfo::new_weighted(
                 id.clone(),
-                sk_set.secret_key_share(i)?,
+                sk_shares,
                 pk_set.clone(),
-                sec_keys[&id].clone(),
+                sec_keys[id].clone(),
                 pub_keys.clone(),
+                weights.clone(),
             );
-            Ok((id, netinfo))
-        };
-        all_ids
-            .into_iter()
-            .enumerate()
-            .map(create_netinfo)
-            .collect()
+            netinfos.insert(id.clone(), netinfo);
+        }
+        Ok(netinfos)
     }
 }
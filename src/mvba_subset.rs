@@ -0,0 +1,642 @@
+//! # Subset via multi-valued validated Byzantine agreement
+//!
+//! An alternative implementation of the Subset protocol, with the same guarantees, input and
+//! output as `Subset`: Each node proposes an element for inclusion, and all correct nodes output
+//! the same set, consisting of at least _N - f_ of the proposed elements.
+//!
+//! `Subset` runs one Binary Agreement instance per node. This implementation follows the
+//! structure of the Dumbo2 protocol instead: The nodes agree on _one_ node's set of proposers,
+//! using an expected constant number of Binary Agreement instances.
+//!
+//! ## How it works
+//!
+//! * Every node broadcasts its element using a `Broadcast` instance, like in `Subset`.
+//! * When a node has received node _j_'s element, it signs the statement "_j_'s element was
+//! delivered" with its threshold key share. _f + 1_ such signature shares are combined into a
+//! signature by the master key, which proves that at least one correct node has received _j_'s
+//! element, so that every correct node eventually will.
+//! * Once a validator has such proofs for _N - f_ nodes, it broadcasts them as its own _proposed
+//! set_. A proposed set is valid if it contains valid proofs for at least _N - f_ nodes. Invalid
+//! ones are reported as faulty.
+//! * Once a node has received _N - f_ valid proposed sets, it provides its signature share to
+//! elect a leader for the current round: The leader is derived from the threshold signature of
+//! the round number, so it cannot be predicted before at least one correct node has started the
+//! election.
+//! * The nodes run Binary Agreement on whether to accept the leader's proposed set, each voting
+//! "yes" if it has received it. If the decision is "yes", every node outputs the elements of the
+//! nodes in the leader's set: They are guaranteed to be delivered eventually, since the
+//! proofs are valid. Otherwise a new round with a new election starts.
+//!
+//! ## Termination and cost
+//!
+//! Since at least _N - f_ proposed sets are known to at least one correct node before the
+//! election, and the leader is unpredictable until then, a round succeeds with constant
+//! probability if the adversary does not control message scheduling. An adversarial scheduler can
+//! delay the leader's proposed set to some correct nodes until after the vote, but every round has
+//! a fresh, unpredictable leader, so the algorithm still terminates with probability 1.
+//!
+//! Compared to `Subset`, each run requires twice as many broadcasts and _N + 1_ threshold
+//! signatures, and every node verifies up to _N (N - f)_ signatures in the proposed sets. This
+//! pays off for larger networks, where _N_ Binary Agreement instances dominate the cost.
+//!
+//! With weighted validators, the proofs and proposed sets need to cover a total weight of
+//! _W - f_ instead of _N - f_ nodes. The leader is still chosen uniformly among the validators.
+//!
+//! Election and vote messages are only accepted for the current round and the next
+//! `MAX_FUTURE_ROUNDS` rounds; earlier rounds' elections and queued votes are discarded when a new
+//! round starts.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::result;
+use std::sync::Arc;
+
+use bincode;
+use byteorder::{BigEndian, ByteOrder};
+use crypto::Signature;
+use rand::Rand;
+use tiny_keccak::sha3_256;
+
+use binary_agreement::{self, BinaryAgreement};
+use broadcast::{self, Broadcast};
+use fault_log::FaultKind;
+use fmt::HexBytes;
+use messaging::{self, DistAlgorithm, NetworkInfo};
+use subset::{SubsetOutput, ValidityPredicate};
+use threshold_sign::{self, ThresholdSign};
+use traits::NodeIdT;
+
+/// The number of rounds beyond the current one for which election and vote messages are accepted.
+const MAX_FUTURE_ROUNDS: u32 = 3;
+
+/// An MVBA subset error.
+#[derive(Debug, Fail)]
+pub enum Error {
+    #[fail(display = "NewBinaryAgreement error: {}", _0)]
+    NewBinaryAgreement(binary_agreement::Error),
+    #[fail(display = "ProcessBinaryAgreement error: {}", _0)]
+    ProcessBinaryAgreement(binary_agreement::Error),
+    #[fail(display = "NewBroadcast error: {}", _0)]
+    NewBroadcast(broadcast::Error),
+    #[fail(display = "ProcessBroadcast error: {}", _0)]
+    ProcessBroadcast(broadcast::Error),
+    #[fail(display = "ProcessThresholdSign error: {}", _0)]
+    ProcessThresholdSign(threshold_sign::Error),
+    #[fail(display = "No such broadcast instance")]
+    NoSuchBroadcastInstance,
+    #[fail(display = "Unknown proposer")]
+    UnknownProposer,
+    #[fail(display = "Bincode error: {}", _0)]
+    Bincode(bincode::ErrorKind),
+}
+
+/// An MVBA subset result.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// Message from `MvbaSubset` to remote nodes.
+#[derive(Serialize, Deserialize, Clone, Debug, Rand)]
+pub enum Message<N: Rand> {
+    /// A message for the broadcast of the element proposed by the given node.
+    Value(N, broadcast::Message),
+    /// A signature share for the proof that the given node's element was delivered.
+    Done(N, threshold_sign::Message),
+    /// A message for the broadcast of the set of proofs proposed by the given node.
+    Proposal(N, broadcast::Message),
+    /// A signature share for the leader election in the given round.
+    Election(u32, threshold_sign::Message),
+    /// A message for the Binary Agreement on the leader's proposed set in the given round.
+    Vote(u32, binary_agreement::Message),
+}
+
+/// MVBA subset algorithm instance
+#[derive(Debug)]
+pub struct MvbaSubset<N: Rand> {
+    /// Shared network information.
+    netinfo: Arc<NetworkInfo<N>>,
+    /// Session ID, e.g, the Honey Badger algorithm epoch.
+    session_id: u64,
+    /// The broadcast instances for the proposed elements.
+    value_broadcasts: BTreeMap<N, Broadcast<N>>,
+    /// The elements we have received so far, by proposer.
+    values: BTreeMap<N, Vec<u8>>,
+    /// The ongoing threshold signatures proving delivery of an element, by proposer.
+    done_signs: BTreeMap<N, ThresholdSign<N, Vec<u8>>>,
+    /// The proofs of delivery we have already obtained, by proposer.
+    proofs: BTreeMap<N, Signature>,
+    /// The broadcast instances for the proposed sets.
+    proposal_broadcasts: BTreeMap<N, Broadcast<N>>,
+    /// Whether we have already broadcast our own proposed set.
+    has_proposed: bool,
+    /// The valid proposed sets we have received so far, by proposer.
+    proposals: BTreeMap<N, BTreeSet<N>>,
+    /// The current round.
+    round: u32,
+    /// The ongoing leader elections, by round.
+    elections: BTreeMap<u32, ThresholdSign<N, Vec<u8>>>,
+    /// The leader of the current round, if it has been elected already.
+    leader: Option<N>,
+    /// The Binary Agreement instance on the current leader's proposed set.
+    binary_agreement: Option<BinaryAgreement<N>>,
+    /// Binary Agreement messages for rounds whose instance has not been created yet.
+    incoming_votes: BTreeMap<u32, Vec<(N, binary_agreement::Message)>>,
+    /// The leader whose proposed set has been accepted.
+    accepted_leader: Option<N>,
+    /// The proposers whose elements we have already output.
+    output_ids: BTreeSet<N>,
+    /// Whether we have output `Done`.
+    decided: bool,
+    /// The predicate a proposed value must satisfy for us to sign its delivery.
+    validity_predicate: Option<ValidityPredicate<N>>,
+}
+
+pub type Step<N> = messaging::Step<MvbaSubset<N>>;
+
+impl<N: NodeIdT + Rand> DistAlgorithm for MvbaSubset<N> {
+    type NodeId = N;
+    type Input = Vec<u8>;
+    type Output = SubsetOutput<N>;
+    type Message = Message<N>;
+    type Error = Error;
+
+    fn handle_input(&mut self, input: Self::Input) -> Result<Step<N>> {
+        debug!(
+            "{:?} Proposing {:?}",
+            self.netinfo.our_id(),
+            HexBytes(&input)
+        );
+        self.send_proposed_value(input)
+    }
+
+    fn handle_message(
+        &mut self,
+        sender_id: &Self::NodeId,
+        message: Self::Message,
+    ) -> Result<Step<N>> {
+        match message {
+            Message::Value(p_id, b_msg) => {
+                self.process_value(&p_id, |bc| bc.handle_message(sender_id, b_msg))
+            }
+            Message::Done(p_id, ts_msg) => {
+                self.process_done(&p_id, |ts| ts.handle_message(sender_id, ts_msg))
+            }
+            Message::Proposal(p_id, b_msg) => {
+                self.process_proposal(&p_id, |bc| bc.handle_message(sender_id, b_msg))
+            }
+            Message::Election(round, ts_msg) => self.handle_election(sender_id, round, ts_msg),
+            Message::Vote(round, ba_msg) => self.handle_vote(sender_id, round, ba_msg),
+        }
+    }
+
+    fn terminated(&self) -> bool {
+        self.decided
+    }
+
+    fn our_id(&self) -> &Self::NodeId {
+        self.netinfo.our_id()
+    }
+}
+
+impl<N: NodeIdT + Rand> MvbaSubset<N> {
+    pub fn new(netinfo: Arc<NetworkInfo<N>>, session_id: u64) -> Result<Self> {
+        let mut value_broadcasts = BTreeMap::new();
+        let mut proposal_broadcasts = BTreeMap::new();
+        for proposer_id in netinfo.all_ids() {
+            let new_bc = || Broadcast::new(netinfo.clone(), proposer_id.clone());
+            value_broadcasts.insert(proposer_id.clone(), new_bc().map_err(Error::NewBroadcast)?);
+            let proposal_bc = new_bc().map_err(Error::NewBroadcast)?;
+            proposal_broadcasts.insert(proposer_id.clone(), proposal_bc);
+        }
+
+        Ok(MvbaSubset {
+            netinfo,
+            session_id,
+            value_broadcasts,
+            values: BTreeMap::new(),
+            done_signs: BTreeMap::new(),
+            proofs: BTreeMap::new(),
+            proposal_broadcasts,
+            has_proposed: false,
+            proposals: BTreeMap::new(),
+            round: 0,
+            elections: BTreeMap::new(),
+            leader: None,
+            binary_agreement: None,
+            incoming_votes: BTreeMap::new(),
+            accepted_leader: None,
+            output_ids: BTreeSet::new(),
+            decided: false,
+            validity_predicate: None,
+        })
+    }
+
+    /// Sets a predicate that every proposed value must satisfy: We only sign the delivery of
+    /// values that are valid according to it, and report the proposers of invalid ones as faulty.
+    /// This must be called before any input or message is handled.
+    pub fn with_validity_predicate(mut self, validity_predicate: ValidityPredicate<N>) -> Self {
+        self.validity_predicate = Some(validity_predicate);
+        self
+    }
+
+    /// Broadcasts our proposed value, if we are a validator.
+    pub fn send_proposed_value(&mut self, value: Vec<u8>) -> Result<Step<N>> {
+        if !self.netinfo.is_validator() {
+            return Ok(Step::default());
+        }
+        let id = self.netinfo.our_id().clone();
+        self.process_value(&id, |bc| bc.handle_input(value))
+    }
+
+    /// Returns the total weight of the validators from which we have already received a proposal.
+    pub(crate) fn received_proposal_weight(&self) -> u64 {
+        self.netinfo.weight_of(self.values.keys())
+    }
+
+    /// Handles a leader election signature share, unless the election is already over.
+    fn handle_election(
+        &mut self,
+        sender_id: &N,
+        round: u32,
+        ts_msg: threshold_sign::Message,
+    ) -> Result<Step<N>> {
+        let is_obsolete = round < self.round || (round == self.round && self.leader.is_some());
+        if is_obsolete || !self.is_round_accepted(round) {
+            return Ok(Step::default());
+        }
+        self.process_election(round, |ts| ts.handle_message(sender_id, ts_msg))
+    }
+
+    /// Handles a Binary Agreement message, or queues it if its round hasn't started yet.
+    fn handle_vote(
+        &mut self,
+        sender_id: &N,
+        round: u32,
+        ba_msg: binary_agreement::Message,
+    ) -> Result<Step<N>> {
+        if round < self.round || !self.is_round_accepted(round) {
+            return Ok(Step::default());
+        }
+        if round > self.round || self.binary_agreement.is_none() {
+            let queue = self.incoming_votes.entry(round).or_insert_with(Vec::new);
+            queue.push((sender_id.clone(), ba_msg));
+            return Ok(Step::default());
+        }
+        self.process_vote(|ba| ba.handle_message(sender_id, ba_msg))
+    }
+
+    /// Returns `true` if messages for the given round can still be relevant and are not too far in
+    /// the future.
+    fn is_round_accepted(&self, round: u32) -> bool {
+        self.accepted_leader.is_none() && round <= self.round.saturating_add(MAX_FUTURE_ROUNDS)
+    }
+
+    /// Applies `f` to the broadcast instance of `proposer_id`'s value. Upon delivery of a valid
+    /// value, provides our signature share to the proof of its delivery.
+    fn process_value<F>(&mut self, proposer_id: &N, f: F) -> Result<Step<N>>
+    where
+        F: FnOnce(&mut Broadcast<N>) -> result::Result<broadcast::Step<N>, broadcast::Error>,
+    {
+        let mut step = Step::default();
+        let value = {
+            let broadcast = self
+                .value_broadcasts
+                .get_mut(proposer_id)
+                .ok_or(Error::NoSuchBroadcastInstance)?;
+            let to_msg = |b_msg| Message::Value(proposer_id.clone(), b_msg);
+            let output = step.extend_with(f(broadcast).map_err(Error::ProcessBroadcast)?, to_msg);
+            if let Some(output) = output.into_iter().next() {
+                output
+            } else {
+                return Ok(step);
+            }
+        };
+
+        let is_valid = match self.validity_predicate {
+            None => true,
+            Some(ref predicate) => predicate.is_valid(proposer_id, &value),
+        };
+        // Keep the value even if it is invalid: It might still be in the accepted set.
+        self.values.insert(proposer_id.clone(), value);
+        if !is_valid {
+            warn!(
+                "{:?} Invalid value proposed by {:?}",
+                self.netinfo.our_id(),
+                proposer_id
+            );
+            let fault_kind = FaultKind::InvalidProposedValue;
+            step.fault_log.append(proposer_id.clone(), fault_kind);
+        } else {
+            step.extend(self.process_done(proposer_id, |ts| ts.handle_input(()))?);
+        }
+        step.extend(self.try_output());
+        Ok(step)
+    }
+
+    /// Applies `f` to the threshold signature proving delivery of `proposer_id`'s value. Once we
+    /// have enough proofs, proposes our set.
+    fn process_done<F>(&mut self, proposer_id: &N, f: F) -> Result<Step<N>>
+    where
+        F: FnOnce(
+            &mut ThresholdSign<N, Vec<u8>>,
+        ) -> threshold_sign::Result<threshold_sign::Step<N, Vec<u8>>>,
+    {
+        let mut step = Step::default();
+        if self.proofs.contains_key(proposer_id) {
+            return Ok(step);
+        }
+        let sig = {
+            let index = self
+                .netinfo
+                .node_index(proposer_id)
+                .ok_or(Error::UnknownProposer)?;
+            let doc = self.done_doc(index);
+            let netinfo = &self.netinfo;
+            let ts = self
+                .done_signs
+                .entry(proposer_id.clone())
+                .or_insert_with(|| ThresholdSign::new(netinfo.clone(), doc));
+            let to_msg = |ts_msg| Message::Done(proposer_id.clone(), ts_msg);
+            let output = step.extend_with(f(ts).map_err(Error::ProcessThresholdSign)?, to_msg);
+            if let Some(sig) = output.into_iter().next() {
+                sig
+            } else {
+                return Ok(step);
+            }
+        };
+        self.done_signs.remove(proposer_id);
+        self.proofs.insert(proposer_id.clone(), sig);
+        step.extend(self.try_propose()?);
+        Ok(step)
+    }
+
+    /// Broadcasts our proposed set, if we have _N - f_ proofs and haven't done so yet.
+    fn try_propose(&mut self) -> Result<Step<N>> {
+        let proof_weight = self.netinfo.weight_of(self.proofs.keys());
+        if self.has_proposed
+            || !self.netinfo.is_validator()
+            || proof_weight < self.netinfo.correct_weight()
+        {
+            return Ok(Step::default());
+        }
+        self.has_proposed = true;
+        let ser_proofs = {
+            let netinfo = &self.netinfo;
+            let to_idx = |(id, sig)| (netinfo.node_index(id).unwrap() as u64, sig);
+            let proofs: BTreeMap<u64, &Signature> = self.proofs.iter().map(to_idx).collect();
+            bincode::serialize(&proofs).map_err(|err| Error::Bincode(*err))?
+        };
+        let our_id = self.netinfo.our_id().clone();
+        self.process_proposal(&our_id, |bc| bc.handle_input(ser_proofs))
+    }
+
+    /// Applies `f` to the broadcast instance of `proposer_id`'s set. Upon delivery, validates the
+    /// set and, once we have _N - f_ valid sets, starts the leader election.
+    fn process_proposal<F>(&mut self, proposer_id: &N, f: F) -> Result<Step<N>>
+    where
+        F: FnOnce(&mut Broadcast<N>) -> result::Result<broadcast::Step<N>, broadcast::Error>,
+    {
+        let mut step = Step::default();
+        let ser_proofs = {
+            let broadcast = self
+                .proposal_broadcasts
+                .get_mut(proposer_id)
+                .ok_or(Error::NoSuchBroadcastInstance)?;
+            let to_msg = |b_msg| Message::Proposal(proposer_id.clone(), b_msg);
+            let output = step.extend_with(f(broadcast).map_err(Error::ProcessBroadcast)?, to_msg);
+            if let Some(output) = output.into_iter().next() {
+                output
+            } else {
+                return Ok(step);
+            }
+        };
+        match self.verify_proposal(&ser_proofs) {
+            Some(ids) => {
+                debug!(
+                    "{:?} Received set {:?} from {:?}",
+                    self.netinfo.our_id(),
+                    ids,
+                    proposer_id
+                );
+                self.proposals.insert(proposer_id.clone(), ids);
+            }
+            None => {
+                warn!(
+                    "{:?} Invalid set proposed by {:?}",
+                    self.netinfo.our_id(),
+                    proposer_id
+                );
+                let fault_kind = FaultKind::InvalidProposedSet;
+                step.fault_log.append(proposer_id.clone(), fault_kind);
+                return Ok(step);
+            }
+        }
+        step.extend(self.try_elect()?);
+        step.extend(self.try_output());
+        Ok(step)
+    }
+
+    /// Returns the set of proposers in the serialized set of proofs, if it contains at least
+    /// _N - f_ valid ones, and no invalid ones.
+    fn verify_proposal(&self, ser_proofs: &[u8]) -> Option<BTreeSet<N>> {
+        let proofs: BTreeMap<u64, Signature> = bincode::deserialize(ser_proofs).ok()?;
+        let pk = self.netinfo.public_key_set().public_key();
+        let mut ids = BTreeSet::new();
+        for (index, sig) in proofs {
+            let id = self.netinfo.all_ids().nth(index as usize)?;
+            if !pk.verify(&sig, self.done_doc(index as usize)) {
+                return None;
+            }
+            ids.insert(id.clone());
+        }
+        if self.netinfo.weight_of(&ids) < self.netinfo.correct_weight() {
+            return None;
+        }
+        Some(ids)
+    }
+
+    /// Provides our signature share for the current round's election, once we have _N - f_ valid
+    /// proposed sets.
+    fn try_elect(&mut self) -> Result<Step<N>> {
+        let proposal_weight = self.netinfo.weight_of(self.proposals.keys());
+        if self.accepted_leader.is_some()
+            || self.leader.is_some()
+            || proposal_weight < self.netinfo.correct_weight()
+        {
+            return Ok(Step::default());
+        }
+        let round = self.round;
+        self.process_election(round, |ts| ts.handle_input(()))
+    }
+
+    /// Applies `f` to the election in the given round. If the election terminates, starts the
+    /// Binary Agreement on the leader's proposed set.
+    fn process_election<F>(&mut self, round: u32, f: F) -> Result<Step<N>>
+    where
+        F: FnOnce(
+            &mut ThresholdSign<N, Vec<u8>>,
+        ) -> threshold_sign::Result<threshold_sign::Step<N, Vec<u8>>>,
+    {
+        let mut step = Step::default();
+        let sig = {
+            let doc = self.election_doc(round);
+            let netinfo = &self.netinfo;
+            let ts = self
+                .elections
+                .entry(round)
+                .or_insert_with(|| ThresholdSign::new(netinfo.clone(), doc));
+            let to_msg = |ts_msg| Message::Election(round, ts_msg);
+            let output = step.extend_with(f(ts).map_err(Error::ProcessThresholdSign)?, to_msg);
+            if let Some(sig) = output.into_iter().next() {
+                sig
+            } else {
+                return Ok(step);
+            }
+        };
+        self.elections.remove(&round);
+        // The election only outputs after our own input, which we only provide in the current
+        // round, before the leader is known.
+        let leader = self.leader_from(&sig)?;
+        debug!(
+            "{:?} Round {} leader: {:?}",
+            self.netinfo.our_id(),
+            round,
+            leader
+        );
+        let vote = self.proposals.contains_key(&leader);
+        let ba_session_id = self.vote_session_id(round);
+        let ba = BinaryAgreement::new(self.netinfo.clone(), ba_session_id, leader.clone())
+            .map_err(Error::NewBinaryAgreement)?;
+        self.leader = Some(leader);
+        self.binary_agreement = Some(ba);
+        step.extend(self.process_vote(|ba| ba.handle_input(vote))?);
+        let queued = self.incoming_votes.remove(&round).into_iter().flatten();
+        for (sender_id, ba_msg) in queued {
+            if self.round != round {
+                break; // The Binary Agreement has decided "no"; the rest is obsolete.
+            }
+            step.extend(self.process_vote(|ba| ba.handle_message(&sender_id, ba_msg))?);
+        }
+        Ok(step)
+    }
+
+    /// Applies `f` to the current round's Binary Agreement instance. If it decides "yes", outputs
+    /// the leader's set, otherwise moves on to the next round.
+    fn process_vote<F>(&mut self, f: F) -> Result<Step<N>>
+    where
+        F: FnOnce(&mut BinaryAgreement<N>) -> binary_agreement::Result<binary_agreement::Step<N>>,
+    {
+        let mut step = Step::default();
+        let round = self.round;
+        let accepted = {
+            let ba = match self.binary_agreement {
+                Some(ref mut ba) => ba,
+                None => return Ok(step),
+            };
+            if ba.terminated() {
+                return Ok(step);
+            }
+            let to_msg = |ba_msg| Message::Vote(round, ba_msg);
+            let output = step.extend_with(f(ba).map_err(Error::ProcessBinaryAgreement)?, to_msg);
+            if let Some(accepted) = output.into_iter().next() {
+                accepted
+            } else {
+                return Ok(step);
+            }
+        };
+        if accepted {
+            debug!(
+                "{:?} Round {}: accepted the set of {:?}",
+                self.netinfo.our_id(),
+                round,
+                self.leader
+            );
+            self.accepted_leader = self.leader.clone();
+            self.elections.clear();
+            self.incoming_votes.clear();
+            step.extend(self.try_output());
+        } else {
+            self.round += 1;
+            self.leader = None;
+            self.binary_agreement = None;
+            self.elections = self.elections.split_off(&self.round);
+            self.incoming_votes = self.incoming_votes.split_off(&self.round);
+            step.extend(self.try_elect()?);
+        }
+        Ok(step)
+    }
+
+    /// Outputs the values in the accepted set that we have received and not output yet, and
+    /// `Done` once all of them have been output.
+    fn try_output(&mut self) -> Step<N> {
+        let mut step = Step::default();
+        if self.decided {
+            return step;
+        }
+        let ids = match self.accepted_leader {
+            Some(ref leader) => match self.proposals.get(leader) {
+                Some(ids) => ids.clone(),
+                None => return step, // We haven't received the leader's set yet.
+            },
+            None => return step,
+        };
+        for id in &ids {
+            if self.output_ids.contains(id) {
+                continue;
+            }
+            if let Some(value) = self.values.get(id) {
+                debug!("    {:?} → {:?}", id, HexBytes(value));
+                let output = SubsetOutput::Contribution(id.clone(), value.clone());
+                step.output.push_back(output);
+                self.output_ids.insert(id.clone());
+            }
+        }
+        if self.output_ids.len() == ids.len() {
+            debug!("{:?} MVBA subset completed", self.netinfo.our_id());
+            self.decided = true;
+            step.output.push_back(SubsetOutput::Done);
+        }
+        step
+    }
+
+    /// Returns the node with the index given by the hash of the election signature.
+    fn leader_from(&self, sig: &Signature) -> Result<N> {
+        let ser_sig = bincode::serialize(sig).map_err(|err| Error::Bincode(*err))?;
+        let hash = sha3_256(&ser_sig);
+        let index = BigEndian::read_u64(&hash[..8]) % self.netinfo.num_nodes() as u64;
+        let leader = self.netinfo.all_ids().nth(index as usize);
+        Ok(leader
+            .expect("index is less than the number of nodes")
+            .clone())
+    }
+
+    /// Returns the document whose signature proves that the value of the node with the given
+    /// index was delivered.
+    fn done_doc(&self, index: usize) -> Vec<u8> {
+        Vec::from(format!(
+            "Nonce for MVBA subset delivery {:?}@{}:{}",
+            self.netinfo.invocation_id().as_ref(),
+            self.session_id,
+            index
+        ))
+    }
+
+    /// Returns the document whose signature determines the leader in the given round.
+    fn election_doc(&self, round: u32) -> Vec<u8> {
+        Vec::from(format!(
+            "Nonce for MVBA subset election {:?}@{}:{}",
+            self.netinfo.invocation_id().as_ref(),
+            self.session_id,
+            round
+        ))
+    }
+
+    /// Returns the session ID of the given round's Binary Agreement. It must be different in each
+    /// round, so that the instances' coins differ.
+    fn vote_session_id(&self, round: u32) -> u64 {
+        let label = format!(
+            "MVBA subset vote {:?}@{}:{}",
+            self.netinfo.invocation_id().as_ref(),
+            self.session_id,
+            round
+        );
+        BigEndian::read_u64(&sha3_256(label.as_bytes())[..8])
+    }
+}
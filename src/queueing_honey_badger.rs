@@ -13,7 +13,20 @@
 //! Queueing Honey Badger runs a Dynamic Honey Badger internally, and automatically inputs a list
 //! of pending transactions as its contribution at the beginning of each epoch. These are selected
 //! by making a random choice of _B / N_ out of the first _B_ entries in the queue, where _B_ is the
-//! configurable `batch_size` parameter, and _N_ is the current number of validators.
+//! configurable `batch_size` parameter, and _N_ is the current number of validators. Like the other
+//! protocol parameters, the batch size can be changed by a `Change::Parameters` vote.
+//!
+//! Instead of the fixed batch size, an `AdaptiveBatchSize` controller can be configured: It adjusts
+//! the number of transactions each node proposes after every batch, based on how long the recent
+//! epochs took, how many transactions are pending and how full the batch was, so that the epochs
+//! stay close to a target latency. This only affects the node's own proposals, so different nodes
+//! can use different settings.
+//!
+//! Optionally, the size of the contributions can be limited, too: If a `max_contribution_size` is
+//! configured, the transactions are chosen such that their serialized list doesn't exceed that
+//! many bytes, and transactions that wouldn't even fit into a contribution on their own are
+//! rejected when they are input. This only limits the user payload: The key generation messages
+//! and votes that Dynamic Honey Badger adds, and the encryption, make the messages larger.
 //!
 //! After each output, the transactions that made it into the new batch are removed from the queue.
 //!
@@ -21,21 +34,84 @@
 //! roughly the same entries in their queues. By selecting a random fraction of the first _B_
 //! entries, any two nodes will likely make almost disjoint contributions instead of proposing
 //! the same transaction multiple times.
+//!
+//! This is the behavior of the default queue, a `VecDeque`. Any other implementation of the
+//! `TransactionQueue` trait can be used instead, to select the transactions differently.
+//!
+//! ## Gossip
+//!
+//! A transaction is normally only proposed by the nodes it was input into, so if those are slow or
+//! faulty, it might never be committed. If `Gossip` is configured, every transaction that is input
+//! into a node, or that it receives from another node for the first time, is forwarded to a number
+//! of randomly chosen validators, which add it to their own queues. Transactions are recognized by
+//! their `TransactionIdentifier`, so that duplicates are ignored. Transactions from nodes that
+//! aren't validators are ignored. Each node accepts only a limited number of transactions from each
+//! peer in each of its own epochs, and reports peers that send more in the fault log, unless they
+//! are ahead of it. It doesn't send more than that to any peer either, keeping the rest until the
+//! next epoch; if too many are waiting, the oldest ones are dropped. Transactions received from
+//! peers are subject to the same expiry as the ones input into the node.
+//!
+//! ## Validation
+//!
+//! A `TransactionValidator` can be configured to reject invalid transactions. It is applied to
+//! every transaction that is input, to the transactions chosen from the queue before they are
+//! proposed, since they could have become invalid in the meantime, and to the transactions in
+//! every output batch. Invalid transactions in a batch are removed from it. The validators that
+//! contributed them are only reported in the fault log if the transactions were already invalid in
+//! the state before the batch: A transaction that only conflicts with an earlier one in the same
+//! batch could have been proposed by a correct node. Since all nodes must remove the same
+//! transactions, the validator's decisions must only depend on the state all nodes agree on: The
+//! validator is updated with every accepted transaction of a batch before the next one is
+//! validated, so it only depends on the committed transactions, in order, and doesn't need to be
+//! updated by the application. By default, all transactions are accepted.
+//!
+//! ## Receipts
+//!
+//! For each transaction that was input into this node, a `TxEvent` is generated once it has been
+//! committed in a batch, or once it has been dropped because it became invalid, wasn't committed
+//! within the configured `tx_expiry` number of epochs, was evicted by the queue or was replaced by
+//! a committed duplicate. The events can be retrieved with
+//! `take_tx_events`. They refer to the transactions by the IDs their `TransactionIdentifier`
+//! computes, which by default is a hash of the serialized transaction.
+//!
+//! ## Encrypted transactions
+//!
+//! Honey Badger only keeps the contributions secret until the set of contributions for an epoch
+//! has been agreed on. To prevent front-running, clients can also encrypt individual transactions
+//! to the network's master public key, and a `TransactionEncryption` can be configured that
+//! extracts the ciphertext from such a transaction. Once an encrypted transaction has been
+//! committed in a batch, the validators that committed it collectively decrypt it using
+//! `ThresholdDecryption`, and the decrypted transaction is deserialized and replaces the
+//! ciphertext in the output. The batches are still output in order, so a batch waits until its own
+//! and all earlier batches' transactions have been decrypted. Only then are the batch's
+//! transactions validated. Ciphertexts that are invalid, or that don't decrypt to a valid,
+//! unencrypted transaction are removed from the batch. Their proposers are not reported, since
+//! they couldn't see what the ciphertexts contain.
 
 use std::cmp;
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
 use std::fmt::{self, Display};
+use std::iter::once;
 use std::marker::PhantomData;
+use std::mem;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+use bincode;
+use crypto::Ciphertext;
 use failure::{Backtrace, Context, Fail};
-use rand::Rand;
+use rand::{self, Rand};
 use serde::{Deserialize, Serialize};
+use tiny_keccak::sha3_256;
 
-use dynamic_honey_badger::{self, Batch as DhbBatch, DynamicHoneyBadger, Message};
-use messaging::{self, DistAlgorithm};
+use dynamic_honey_badger::{self, Batch as DhbBatch, DynamicHoneyBadger};
+use fault_log::{Fault, FaultKind};
+use messaging::{self, DistAlgorithm, NetworkInfo, Target};
+use threshold_decryption::{self, ThresholdDecryption};
 use traits::{Contribution, NodeIdT};
-use transaction_queue::TransactionQueue;
+use transaction_queue::{contribution_size, TransactionQueue};
 
-pub use dynamic_honey_badger::{Change, ChangeState, Input};
+pub use dynamic_honey_badger::{Change, ChangeState, Input, Params};
 
 /// Queueing honey badger error variants.
 #[derive(Debug, Fail)]
@@ -46,6 +122,16 @@ pub enum ErrorKind {
     HandleMessage(dynamic_honey_badger::Error),
     #[fail(display = "Propose error: {}", _0)]
     Propose(dynamic_honey_badger::Error),
+    #[fail(display = "Invalid transaction: {}", _0)]
+    InvalidTransaction(String),
+    #[fail(display = "Transaction serialization error: {}", _0)]
+    TransactionBincode(bincode::ErrorKind),
+    #[fail(display = "Transaction ID error: {}", _0)]
+    TransactionId(String),
+    #[fail(display = "Transaction too large: {} > {} bytes", _0, _1)]
+    TransactionTooLarge(u64, u64),
+    #[fail(display = "Decryption error: {}", _0)]
+    Decryption(threshold_decryption::Error),
 }
 
 /// A queueing honey badger error.
@@ -92,42 +178,415 @@ impl Display for Error {
 
 pub type Result<T> = ::std::result::Result<T, Error>;
 
+/// Decides which transactions are valid.
+///
+/// The validator keeps track of the state the transactions are validated against: The
+/// transactions in each committed batch are validated in order, and each one that is accepted is
+/// applied before the next one is validated. That way, conflicts between transactions in the same
+/// batch are detected, too. All nodes must come to the same decision about the transactions in a
+/// batch, so the result must be deterministic, and only depend on the transaction, the validator's
+/// initial state and the transactions applied so far.
+pub trait TransactionValidator<T>: fmt::Debug + Default + Send + Sync {
+    /// Returns an error describing the problem if the transaction is invalid in the current state.
+    fn validate(&self, tx: &T) -> ::std::result::Result<(), String>;
+
+    /// Updates the state with a transaction that has been accepted in a committed batch. The
+    /// default implementation does nothing, which is correct if the validity of a transaction
+    /// doesn't depend on the earlier ones.
+    fn apply(&mut self, _tx: &T) {}
+}
+
+/// A validator that accepts all transactions.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct AcceptAll;
+
+impl<T> TransactionValidator<T> for AcceptAll {
+    fn validate(&self, _tx: &T) -> ::std::result::Result<(), String> {
+        Ok(())
+    }
+}
+
+/// Computes the IDs by which `TxEvent`s refer to transactions.
+pub trait TransactionIdentifier<T>: fmt::Debug + Default + Send + Sync {
+    /// A transaction ID. Different transactions must have different IDs.
+    type Id: Ord + Clone + fmt::Debug + Send + Sync;
+
+    /// Returns the transaction's ID, or an error describing the problem if it has none. Input
+    /// transactions without an ID are rejected, and no events are generated for the others.
+    fn id(&self, tx: &T) -> ::std::result::Result<Self::Id, String>;
+}
+
+/// Identifies transactions by the SHA3-256 hash of their serialized representation.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct HashId;
+
+impl<T: Serialize> TransactionIdentifier<T> for HashId {
+    type Id = [u8; 32];
+
+    fn id(&self, tx: &T) -> ::std::result::Result<[u8; 32], String> {
+        let ser_tx = bincode::serialize(tx).map_err(|err| err.to_string())?;
+        Ok(sha3_256(&ser_tx))
+    }
+}
+
+/// An event concerning a transaction that was input into this node.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TxEvent<I, N> {
+    /// The transaction was committed in the batch of the given epoch, in the contribution of the
+    /// given validator.
+    Committed { id: I, epoch: u64, proposer_id: N },
+    /// The transaction became invalid, and was removed from the queue before it could be proposed
+    /// in the given epoch, or from the batch of the given epoch.
+    Invalid { id: I, epoch: u64 },
+    /// The transaction was removed from the queue after the batch of the given epoch, because it
+    /// hadn't been committed within the configured number of epochs.
+    Expired { id: I, epoch: u64 },
+    /// The transaction was dropped by the queue before it could be proposed in the given epoch,
+    /// e.g. because the queue was full, or it contains a duplicate with a higher priority.
+    Evicted { id: I, epoch: u64 },
+    /// A different transaction that is a duplicate of this one, e.g. with the same sender and
+    /// nonce, was committed in the batch of the given epoch, and this one was removed from the
+    /// queue.
+    Replaced { id: I, epoch: u64 },
+}
+
+/// Recognizes the transactions that are encrypted to the network's master public key, and need to
+/// be decrypted once they are committed.
+pub trait TransactionEncryption<T>: fmt::Debug + Default + Send + Sync {
+    /// Returns the ciphertext if the transaction is encrypted, or `None` if it is plaintext.
+    fn ciphertext<'a>(&self, tx: &'a T) -> Option<&'a Ciphertext>;
+}
+
+/// Treats all transactions as unencrypted.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Unencrypted;
+
+impl<T> TransactionEncryption<T> for Unencrypted {
+    fn ciphertext<'a>(&self, _tx: &'a T) -> Option<&'a Ciphertext> {
+        None
+    }
+}
+
+/// A Queueing Honey Badger message.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Message<T, N: Rand> {
+    /// A message belonging to the internal `DynamicHoneyBadger` instance.
+    DynamicHoneyBadger(dynamic_honey_badger::Message<N>),
+    /// A decryption share for the encrypted transaction with the given index in the batch of the
+    /// given epoch.
+    Decryption(u64, usize, threshold_decryption::Message),
+    /// Transactions forwarded by the sender in the given epoch, to be added to the queue.
+    Transactions(u64, Vec<T>),
+}
+
+/// The settings for forwarding transactions to other validators.
+#[derive(Clone, Debug)]
+pub struct Gossip {
+    /// The number of validators each new transaction is sent to.
+    fanout: usize,
+    /// The maximum number of transactions sent to or accepted from each peer per epoch.
+    max_txs_per_peer: usize,
+    /// The number of epochs for which a transaction is remembered after it was last seen.
+    memory: u64,
+    /// The maximum number of transactions waiting to be sent to each peer.
+    max_outbox: usize,
+}
+
+impl Gossip {
+    /// Returns new gossip settings: Each new transaction is sent to `fanout` randomly chosen
+    /// validators. At most `max_txs_per_peer` transactions are exchanged with each peer per epoch,
+    /// and transactions are recognized as duplicates for `memory` epochs after they were last seen.
+    /// All nodes must use the same limit: A peer that sends more is reported in the fault log.
+    ///
+    /// At most as many transactions as can be sent within `memory` epochs wait to be sent to each
+    /// peer. If there are more, the oldest ones are dropped.
+    pub fn new(fanout: usize, max_txs_per_peer: usize, memory: u64) -> Self {
+        let max_outbox = max_txs_per_peer.saturating_mul(memory as usize);
+        Gossip {
+            fanout,
+            max_txs_per_peer,
+            memory,
+            max_outbox,
+        }
+    }
+}
+
+/// The state of the transaction gossip.
+#[derive(Debug)]
+struct GossipState<T, N, Id> {
+    /// The gossip settings.
+    config: Gossip,
+    /// The IDs of the transactions that were input, received or committed, with the epoch in which
+    /// they were last seen.
+    seen: BTreeMap<Id, u64>,
+    /// The number of transactions received from each peer in the current epoch.
+    received: BTreeMap<N, usize>,
+    /// The number of transactions sent to each peer in the current epoch.
+    sent: BTreeMap<N, usize>,
+    /// The transactions that still need to be sent to each peer.
+    outbox: BTreeMap<N, VecDeque<T>>,
+}
+
+impl<T, N, Id> GossipState<T, N, Id> {
+    /// Returns a new gossip state with the given settings.
+    fn new(config: Gossip) -> Self {
+        GossipState {
+            config,
+            seen: BTreeMap::new(),
+            received: BTreeMap::new(),
+            sent: BTreeMap::new(),
+            outbox: BTreeMap::new(),
+        }
+    }
+}
+
+/// A committed batch whose encrypted transactions are being decrypted.
+#[derive(Debug)]
+struct PendingBatch<T, N, Id> {
+    /// The batch, still containing the encrypted transactions.
+    batch: Batch<T, N>,
+    /// The number of encrypted transactions in the batch.
+    num_encrypted: usize,
+    /// The decrypted transactions by index in the batch, or `None` if they turned out invalid.
+    plaintexts: BTreeMap<usize, Option<T>>,
+    /// The IDs of the transactions in the batch that were input into this node.
+    local_ids: BTreeSet<Id>,
+}
+
+impl<T, N, Id> PendingBatch<T, N, Id> {
+    /// Returns `true` if all encrypted transactions have been decrypted.
+    fn is_decrypted(&self) -> bool {
+        self.plaintexts.len() == self.num_encrypted
+    }
+}
+
+/// A controller that adapts the number of transactions a node proposes per epoch to the observed
+/// epoch latency.
+///
+/// After each batch, it updates the moving average of the epoch durations. If that exceeds the
+/// target latency, the proposal size is halved. Otherwise, if there are more pending transactions
+/// than fit into one proposal, and the batch was at least half full, i.e. the other proposers had
+/// enough transactions, too, the proposal size is increased by an eighth. It always stays within
+/// the configured bounds.
+#[derive(Clone, Debug)]
+pub struct AdaptiveBatchSize {
+    /// The minimum number of transactions per proposal.
+    min_amount: usize,
+    /// The maximum number of transactions per proposal.
+    max_amount: usize,
+    /// The epoch duration the controller aims for.
+    target_latency: Duration,
+    /// The current number of transactions per proposal.
+    amount: usize,
+    /// The moving average of the recent epochs' durations, if any epoch has been output yet.
+    avg_latency: Option<Duration>,
+}
+
+impl AdaptiveBatchSize {
+    /// Returns a new controller that keeps the number of transactions per proposal between
+    /// `min_amount` and `max_amount`, aiming for epochs that take `target_latency`. The bounds are
+    /// increased to at least 1 and `min_amount`, respectively. `QueueingHoneyBadger` starts it at
+    /// the amount corresponding to the configured batch size.
+    pub fn new(min_amount: usize, max_amount: usize, target_latency: Duration) -> Self {
+        let min_amount = cmp::max(1, min_amount);
+        AdaptiveBatchSize {
+            min_amount,
+            max_amount: cmp::max(min_amount, max_amount),
+            target_latency,
+            amount: min_amount,
+            avg_latency: None,
+        }
+    }
+
+    /// Returns the current number of transactions per proposal.
+    pub fn amount(&self) -> usize {
+        self.amount
+    }
+
+    /// Returns the moving average of the recent epochs' durations, if any epoch has been output.
+    pub fn average_latency(&self) -> Option<Duration> {
+        self.avg_latency
+    }
+
+    /// Adjusts the proposal size after a batch was output. `latency` is the duration of the epoch,
+    /// `queue_len` the number of pending transactions, and `num_txs` and `num_contributions` the
+    /// number of transactions and contributions in the batch.
+    pub fn update(
+        &mut self,
+        latency: Duration,
+        queue_len: usize,
+        num_txs: usize,
+        num_contributions: usize,
+    ) {
+        let avg_latency = match self.avg_latency {
+            None => latency,
+            Some(avg_latency) => (avg_latency * 3 + latency) / 4,
+        };
+        self.avg_latency = Some(avg_latency);
+        if avg_latency > self.target_latency {
+            self.amount = cmp::max(self.min_amount, self.amount / 2);
+        } else if queue_len > self.amount && 2 * num_txs >= self.amount * num_contributions {
+            let increment = cmp::max(1, self.amount / 8);
+            self.amount = cmp::min(self.max_amount, self.amount + increment);
+        }
+    }
+
+    /// Sets the proposal size to the given amount, within the bounds.
+    fn set_amount(&mut self, amount: usize) {
+        self.amount = cmp::min(self.max_amount, cmp::max(self.min_amount, amount));
+    }
+}
+
 /// A Queueing Honey Badger builder, to configure the parameters and create new instances of
 /// `QueueingHoneyBadger`.
-pub struct QueueingHoneyBadgerBuilder<T, N: Rand> {
+pub struct QueueingHoneyBadgerBuilder<
+    T,
+    N: Rand,
+    Q = VecDeque<T>,
+    V = AcceptAll,
+    I = HashId,
+    E = Unencrypted,
+> {
     /// Shared network data.
     dyn_hb: DynamicHoneyBadger<Vec<T>, N>,
-    /// The target number of transactions to be included in each batch.
-    batch_size: usize,
+    /// The target number of transactions to be included in each batch, if it was set explicitly.
+    batch_size: Option<usize>,
+    /// The controller that adapts the proposal size, if any.
+    adaptive_batch_size: Option<AdaptiveBatchSize>,
+    /// The maximum size of a serialized contribution in bytes, if any.
+    max_contribution_size: Option<u64>,
+    /// The queue of pending transactions.
+    queue: Q,
+    /// The validator that decides which transactions are valid.
+    validator: V,
+    /// The rule that computes the transaction IDs.
+    identifier: I,
+    /// The number of epochs after which uncommitted transactions are dropped, if any.
+    tx_expiry: Option<u64>,
+    /// The rule that recognizes encrypted transactions.
+    encryption: E,
+    /// The gossip settings, if transactions are forwarded to other validators.
+    gossip: Option<Gossip>,
+    /// The maximum number of early decryption shares stored per peer and epoch, if it was set
+    /// explicitly.
+    max_early_shares: Option<usize>,
     _phantom: PhantomData<T>,
 }
 
-impl<T, N> QueueingHoneyBadgerBuilder<T, N>
+impl<T, N, Q, V, I, E> QueueingHoneyBadgerBuilder<T, N, Q, V, I, E>
 where
     T: Contribution + Serialize + for<'r> Deserialize<'r> + Clone,
     N: NodeIdT + Serialize + for<'r> Deserialize<'r> + Rand,
+    Q: TransactionQueue<T>,
+    V: TransactionValidator<T>,
+    I: TransactionIdentifier<T>,
+    E: TransactionEncryption<T>,
 {
     /// Returns a new `QueueingHoneyBadgerBuilder` configured to use the node IDs and cryptographic
     /// keys specified by `netinfo`.
     // TODO: Make it easier to build a `QueueingHoneyBadger` with a `JoinPlan`. Handle `Step`
     // conversion internally.
     pub fn new(dyn_hb: DynamicHoneyBadger<Vec<T>, N>) -> Self {
-        // TODO: Use the defaults from `HoneyBadgerBuilder`.
         QueueingHoneyBadgerBuilder {
             dyn_hb,
-            batch_size: 100,
+            batch_size: None,
+            adaptive_batch_size: None,
+            max_contribution_size: None,
+            queue: Q::default(),
+            validator: V::default(),
+            identifier: I::default(),
+            tx_expiry: None,
+            encryption: E::default(),
+            gossip: None,
+            max_early_shares: None,
             _phantom: PhantomData,
         }
     }
 
-    /// Sets the target number of transactions per batch.
+    /// Sets the target number of transactions per batch. By default, the value from the
+    /// `DynamicHoneyBadger` instance's parameters is used.
     pub fn batch_size(mut self, batch_size: usize) -> Self {
-        self.batch_size = batch_size;
+        self.batch_size = Some(batch_size);
+        self
+    }
+
+    /// Sets a controller that adapts the number of transactions this node proposes to the epoch
+    /// latency. It starts with the amount corresponding to the batch size. By default, the batch
+    /// size is fixed.
+    pub fn adaptive_batch_size(mut self, adaptive_batch_size: AdaptiveBatchSize) -> Self {
+        self.adaptive_batch_size = Some(adaptive_batch_size);
+        self
+    }
+
+    /// Sets the maximum size in bytes of the serialized list of transactions this node proposes.
+    /// This doesn't include the key generation messages and votes that `DynamicHoneyBadger` adds
+    /// to the contribution, or the encryption overhead. Transactions that are too large to fit into
+    /// a contribution on their own are rejected, and removed from the configured queue when the
+    /// instance is built. By default, there is no limit.
+    pub fn max_contribution_size(mut self, max_contribution_size: u64) -> Self {
+        self.max_contribution_size = Some(max_contribution_size);
+        self
+    }
+
+    /// Sets the queue of pending transactions. By default, an empty queue is used.
+    pub fn queue(mut self, queue: Q) -> Self {
+        self.queue = queue;
+        self
+    }
+
+    /// Sets the transaction validator. By default, all transactions are accepted.
+    pub fn validator(mut self, validator: V) -> Self {
+        self.validator = validator;
+        self
+    }
+
+    /// Sets the rule that computes the transaction IDs in the `TxEvent`s. By default, transactions
+    /// are identified by their hash.
+    pub fn identifier(mut self, identifier: I) -> Self {
+        self.identifier = identifier;
+        self
+    }
+
+    /// Sets the number of epochs after which transactions that were input into this node or
+    /// received from a peer are removed from the queue if they haven't been committed. By default,
+    /// they are never removed.
+    pub fn tx_expiry(mut self, tx_expiry: u64) -> Self {
+        self.tx_expiry = Some(tx_expiry);
+        self
+    }
+
+    /// Sets the rule that recognizes transactions encrypted to the network's master public key,
+    /// which are decrypted after they have been committed. By default, no transaction is
+    /// considered encrypted.
+    pub fn encryption(mut self, encryption: E) -> Self {
+        self.encryption = encryption;
         self
     }
 
-    /// Creates a new Queueing Honey Badger instance with an empty buffer.
-    pub fn build(self) -> (QueueingHoneyBadger<T, N>, Step<T, N>)
+    /// Enables forwarding transactions to other validators, with the given settings. By default,
+    /// transactions are not forwarded.
+    pub fn gossip(mut self, gossip: Gossip) -> Self {
+        self.gossip = Some(gossip);
+        self
+    }
+
+    /// Sets the maximum number of decryption shares that are stored per peer and epoch for batches
+    /// that this node hasn't committed yet. Shares from epochs more than `max_future_epochs` ahead
+    /// are never stored. If a batch contains more encrypted transactions than that, this node can
+    /// only decrypt them if it doesn't fall behind. By default, the limit is the batch size times
+    /// the number of validators.
+    pub fn max_early_shares(mut self, max_early_shares: usize) -> Self {
+        self.max_early_shares = Some(max_early_shares);
+        self
+    }
+
+    /// Creates a new Queueing Honey Badger instance with the configured queue.
+    pub fn build(
+        self,
+    ) -> (
+        QueueingHoneyBadger<T, N, Q, V, I, E>,
+        Step<T, N, Q, V, I, E>,
+    )
     where
         T: Contribution + Serialize + for<'r> Deserialize<'r>,
     {
@@ -135,23 +594,67 @@ where
             .expect("building without transactions cannot fail")
     }
 
-    /// Returns a new Queueing Honey Badger instance that starts with the given transactions in its
-    /// buffer.
+    /// Returns a new Queueing Honey Badger instance that starts with the given transactions added
+    /// to its queue. Returns an error if any of them is invalid or too large.
     pub fn build_with_transactions<TI>(
         self,
         txs: TI,
-    ) -> Result<(QueueingHoneyBadger<T, N>, Step<T, N>)>
+    ) -> Result<(
+        QueueingHoneyBadger<T, N, Q, V, I, E>,
+        Step<T, N, Q, V, I, E>,
+    )>
     where
         TI: IntoIterator<Item = T>,
         T: Contribution + Serialize + for<'r> Deserialize<'r>,
     {
-        let queue = TransactionQueue(txs.into_iter().collect());
+        let mut dyn_hb = self.dyn_hb;
+        if let Some(batch_size) = self.batch_size {
+            dyn_hb.set_batch_size(batch_size);
+        }
+        let mut adaptive_batch_size = self.adaptive_batch_size;
+        if let Some(ref mut abs) = adaptive_batch_size {
+            abs.set_amount(dyn_hb.params().batch_size / dyn_hb.netinfo().num_nodes());
+        }
+        let decryption_netinfo = Arc::new(dyn_hb.netinfo().clone());
         let mut qhb = QueueingHoneyBadger {
-            dyn_hb: self.dyn_hb,
-            queue,
-            batch_size: self.batch_size,
+            dyn_hb,
+            adaptive_batch_size,
+            epoch_start: Instant::now(),
+            max_contribution_size: self.max_contribution_size,
+            queue: self.queue,
+            validator: self.validator,
+            identifier: self.identifier,
+            tx_expiry: self.tx_expiry,
+            local_txs: BTreeMap::new(),
+            gossiped_txs: BTreeMap::new(),
+            tx_events: Vec::new(),
+            encryption: self.encryption,
+            decryption_netinfo,
+            decryptions: BTreeMap::new(),
+            early_shares: BTreeMap::new(),
+            max_early_shares: self.max_early_shares,
+            pending_batches: VecDeque::new(),
+            gossip: self.gossip.map(GossipState::new),
         };
-        let step = qhb.propose()?;
+        if let Some(max_size) = qhb.max_contribution_size {
+            // Transactions that don't fit into a contribution could never be proposed.
+            let oversized = qhb.queue.remove_where(|tx| {
+                contribution_size(once(tx)).map_or(true, |size| size > max_size)
+            });
+            if !oversized.is_empty() {
+                warn!(
+                    "Removed {} transactions larger than {} bytes from the queue.",
+                    oversized.len(),
+                    max_size
+                );
+            }
+        }
+        for tx in txs {
+            qhb.check_transaction(&tx)?;
+            qhb.enqueue(tx)?;
+        }
+        let mut step = qhb.propose()?;
+        qhb.send_gossip(&mut step);
         Ok((qhb, step))
     }
 }
@@ -159,60 +662,121 @@ where
 /// A Honey Badger instance that can handle adding and removing nodes and manages a transaction
 /// queue.
 #[derive(Debug)]
-pub struct QueueingHoneyBadger<T, N>
+pub struct QueueingHoneyBadger<T, N, Q = VecDeque<T>, V = AcceptAll, I = HashId, E = Unencrypted>
 where
     T: Contribution + Serialize + for<'r> Deserialize<'r>,
     N: NodeIdT + Serialize + for<'r> Deserialize<'r> + Rand,
+    I: TransactionIdentifier<T>,
+    E: TransactionEncryption<T>,
 {
-    /// The target number of transactions to be included in each batch.
-    batch_size: usize,
     /// The internal `DynamicHoneyBadger` instance.
     dyn_hb: DynamicHoneyBadger<Vec<T>, N>,
+    /// The controller that adapts the proposal size, if any.
+    adaptive_batch_size: Option<AdaptiveBatchSize>,
+    /// The time when the current epoch started, i.e. when the previous batch was output.
+    epoch_start: Instant,
+    /// The maximum size of a serialized contribution in bytes, if any.
+    max_contribution_size: Option<u64>,
     /// The queue of pending transactions that haven't been output in a batch yet.
-    queue: TransactionQueue<T>,
+    queue: Q,
+    /// The validator that decides which transactions are valid.
+    validator: V,
+    /// The rule that computes the transaction IDs.
+    identifier: I,
+    /// The number of epochs after which uncommitted transactions are dropped, if any.
+    tx_expiry: Option<u64>,
+    /// The transactions that were input into this node and are not committed yet, with the epoch
+    /// in which they were queued, by ID. Once committed, they are tracked by their pending batch.
+    local_txs: BTreeMap<I::Id, (u64, T)>,
+    /// The transactions received from peers that were added to the queue, with the epoch in which
+    /// they were queued, by ID.
+    gossiped_txs: BTreeMap<I::Id, (u64, T)>,
+    /// The events concerning the transactions that were input into this node.
+    tx_events: Vec<TxEvent<I::Id, N>>,
+    /// The rule that recognizes encrypted transactions.
+    encryption: E,
+    /// The network info of the validators that decrypt the next committed batch.
+    decryption_netinfo: Arc<NetworkInfo<N>>,
+    /// The ongoing decryptions of committed transactions, by epoch and index in the batch.
+    decryptions: BTreeMap<u64, BTreeMap<usize, ThresholdDecryption<N>>>,
+    /// Decryption shares for batches that haven't been committed yet, by epoch, sender and index
+    /// in the batch.
+    early_shares: BTreeMap<u64, BTreeMap<N, BTreeMap<usize, threshold_decryption::Message>>>,
+    /// The maximum number of early decryption shares stored per peer and epoch, if it was set
+    /// explicitly.
+    max_early_shares: Option<usize>,
+    /// The committed batches that wait for their transactions, or earlier batches, to be decrypted.
+    pending_batches: VecDeque<PendingBatch<T, N, I::Id>>,
+    /// The state of the transaction gossip, if it is enabled.
+    gossip: Option<GossipState<T, N, I::Id>>,
 }
 
-pub type Step<T, N> = messaging::Step<QueueingHoneyBadger<T, N>>;
+pub type Step<T, N, Q = VecDeque<T>, V = AcceptAll, I = HashId, E = Unencrypted> =
+    messaging::Step<QueueingHoneyBadger<T, N, Q, V, I, E>>;
 
-impl<T, N> DistAlgorithm for QueueingHoneyBadger<T, N>
+impl<T, N, Q, V, I, E> DistAlgorithm for QueueingHoneyBadger<T, N, Q, V, I, E>
 where
     T: Contribution + Serialize + for<'r> Deserialize<'r> + Clone,
     N: NodeIdT + Serialize + for<'r> Deserialize<'r> + Rand,
+    Q: TransactionQueue<T>,
+    V: TransactionValidator<T>,
+    I: TransactionIdentifier<T>,
+    E: TransactionEncryption<T>,
 {
     type NodeId = N;
     type Input = Input<T, N>;
     type Output = Batch<T, N>;
-    type Message = Message<N>;
+    type Message = Message<T, N>;
     type Error = Error;
 
-    fn handle_input(&mut self, input: Self::Input) -> Result<Step<T, N>> {
+    fn handle_input(&mut self, input: Self::Input) -> Result<Step<T, N, Q, V, I, E>> {
         // User transactions are forwarded to `HoneyBadger` right away. Internal messages are
         // in addition signed and broadcast.
         let mut step = match input {
             Input::User(tx) => {
-                self.queue.0.push_back(tx);
+                self.check_transaction(&tx)?;
+                self.enqueue(tx)?;
                 Step::default()
             }
             Input::Change(change) => self
                 .dyn_hb
                 .handle_input(Input::Change(change))
                 .map_err(ErrorKind::Input)?
-                .convert(),
+                .map(|batch| batch, Message::DynamicHoneyBadger),
+            Input::WithdrawVote => self
+                .dyn_hb
+                .handle_input(Input::WithdrawVote)
+                .map_err(ErrorKind::Input)?
+                .map(|batch| batch, Message::DynamicHoneyBadger),
         };
+        self.process_output(&mut step)?;
         step.extend(self.propose()?);
+        self.send_gossip(&mut step);
         Ok(step)
     }
 
-    fn handle_message(&mut self, sender_id: &N, message: Self::Message) -> Result<Step<T, N>> {
-        let mut step = self
-            .dyn_hb
-            .handle_message(sender_id, message)
-            .map_err(ErrorKind::HandleMessage)?
-            .convert::<Self>();
-        for batch in &step.output {
-            self.queue.remove_all(batch.iter());
-        }
+    fn handle_message(
+        &mut self,
+        sender_id: &N,
+        message: Self::Message,
+    ) -> Result<Step<T, N, Q, V, I, E>> {
+        let mut step = match message {
+            Message::DynamicHoneyBadger(msg) => self
+                .dyn_hb
+                .handle_message(sender_id, msg)
+                .map_err(ErrorKind::HandleMessage)?
+                .map(|batch| batch, Message::DynamicHoneyBadger),
+            Message::Decryption(epoch, index, msg) => {
+                let mut step = Step::default();
+                self.handle_decryption_message(sender_id, epoch, index, msg, &mut step)?;
+                self.output_decrypted(&mut step);
+                return Ok(step);
+            }
+            Message::Transactions(epoch, txs) => self.handle_gossip(sender_id, epoch, txs),
+        };
+        self.process_output(&mut step)?;
         step.extend(self.propose()?);
+        self.send_gossip(&mut step);
         Ok(step)
     }
 
@@ -225,14 +789,20 @@ where
     }
 }
 
-impl<T, N> QueueingHoneyBadger<T, N>
+impl<T, N, Q, V, I, E> QueueingHoneyBadger<T, N, Q, V, I, E>
 where
     T: Contribution + Serialize + for<'r> Deserialize<'r> + Clone,
     N: NodeIdT + Serialize + for<'r> Deserialize<'r> + Rand,
+    Q: TransactionQueue<T>,
+    V: TransactionValidator<T>,
+    I: TransactionIdentifier<T>,
+    E: TransactionEncryption<T>,
 {
     /// Returns a new `QueueingHoneyBadgerBuilder` configured to use the node IDs and cryptographic
     /// keys specified by `netinfo`.
-    pub fn builder(dyn_hb: DynamicHoneyBadger<Vec<T>, N>) -> QueueingHoneyBadgerBuilder<T, N> {
+    pub fn builder(
+        dyn_hb: DynamicHoneyBadger<Vec<T>, N>,
+    ) -> QueueingHoneyBadgerBuilder<T, N, Q, V, I, E> {
         QueueingHoneyBadgerBuilder::new(dyn_hb)
     }
 
@@ -241,6 +811,539 @@ where
         &self.dyn_hb
     }
 
+    /// Returns a reference to the queue of pending transactions.
+    pub fn queue(&self) -> &Q {
+        &self.queue
+    }
+
+    /// Returns the events concerning the transactions that were input into this node, that
+    /// occurred since the last call, and removes them.
+    pub fn take_tx_events(&mut self) -> Vec<TxEvent<I::Id, N>> {
+        mem::replace(&mut self.tx_events, Vec::new())
+    }
+
+    /// Returns a reference to the controller that adapts the proposal size, if there is one.
+    pub fn adaptive_batch_size(&self) -> Option<&AdaptiveBatchSize> {
+        self.adaptive_batch_size.as_ref()
+    }
+
+    /// Returns a reference to the transaction validator.
+    pub fn validator(&self) -> &V {
+        &self.validator
+    }
+
+    /// Returns an error if the transaction is invalid, or too large to fit into a contribution.
+    fn check_transaction(&self, tx: &T) -> Result<()> {
+        self.validator
+            .validate(tx)
+            .map_err(ErrorKind::InvalidTransaction)?;
+        if let Some(max_size) = self.max_contribution_size {
+            let size =
+                contribution_size(once(tx)).map_err(|err| ErrorKind::TransactionBincode(*err))?;
+            if size > max_size {
+                return Err(ErrorKind::TransactionTooLarge(size, max_size).into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds a transaction that was input into this node to the queue, starts tracking it and
+    /// forwards it to other validators if it is new. Returns an error if it has no ID.
+    fn enqueue(&mut self, tx: T) -> Result<()> {
+        let id = self.identifier.id(&tx).map_err(ErrorKind::TransactionId)?;
+        let epoch = self.dyn_hb.next_epoch();
+        let is_new = self.gossip.as_mut().map_or(false, |gossip| {
+            gossip.seen.insert(id.clone(), epoch).is_none()
+        });
+        if is_new {
+            self.gossip_tx(&tx, None);
+        }
+        self.local_txs.insert(id.clone(), (epoch, tx.clone()));
+        // If we received it from a peer before, it is already queued.
+        if self.gossiped_txs.remove(&id).is_none() {
+            self.push_to_queue(tx);
+        }
+        Ok(())
+    }
+
+    /// Adds a transaction to the queue. If the queue drops a transaction that was input into this
+    /// node as a result, it is reported as evicted.
+    fn push_to_queue(&mut self, tx: T) {
+        let dropped_id = match self.queue.push(tx) {
+            Some(dropped) => self.identifier.id(&dropped).ok(),
+            None => None,
+        };
+        if let Some(id) = dropped_id {
+            self.gossiped_txs.remove(&id);
+            if self.local_txs.remove(&id).is_some() {
+                let epoch = self.dyn_hb.next_epoch();
+                self.tx_events.push(TxEvent::Evicted { id, epoch });
+            }
+        }
+    }
+
+    /// Removes the committed transactions from the queue, updates the gossip state and the adaptive
+    /// batch size, if there is one, and starts decrypting the encrypted transactions. The batches
+    /// are only validated and output once they are decrypted. Generates the `TxEvent`s for the
+    /// transactions that were replaced by committed duplicates, or that expired.
+    fn process_output(&mut self, step: &mut Step<T, N, Q, V, I, E>) -> Result<()> {
+        let batches: Vec<_> = step.output.drain(..).collect();
+        for batch in batches {
+            let epoch = batch.epoch();
+            let removed = self.queue.remove_all(batch.iter());
+            // The transactions input into this node are tracked by the pending batch from now on.
+            let mut local_ids = BTreeSet::new();
+            let ids: Vec<I::Id> = batch
+                .iter()
+                .filter_map(|tx| self.identifier.id(tx).ok())
+                .collect();
+            for id in ids {
+                self.gossiped_txs.remove(&id);
+                if self.local_txs.remove(&id).is_some() {
+                    local_ids.insert(id);
+                }
+            }
+            // The remaining local transactions that were removed were replaced by duplicates.
+            let removed_ids: Vec<I::Id> = removed
+                .iter()
+                .filter_map(|tx| self.identifier.id(tx).ok())
+                .collect();
+            for id in removed_ids {
+                self.gossiped_txs.remove(&id);
+                if self.local_txs.remove(&id).is_some() {
+                    self.tx_events.push(TxEvent::Replaced { id, epoch });
+                }
+            }
+            self.update_gossip(&batch);
+            let now = Instant::now();
+            if let Some(ref mut abs) = self.adaptive_batch_size {
+                let latency = now.duration_since(self.epoch_start);
+                let num_txs = batch.iter().count();
+                abs.update(
+                    latency,
+                    self.queue.len(),
+                    num_txs,
+                    batch.num_contributions(),
+                );
+            }
+            self.epoch_start = now;
+            self.start_decryption(batch, local_ids, step)?;
+            self.expire_txs(epoch);
+        }
+        Ok(())
+    }
+
+    /// Starts decrypting the encrypted transactions in the committed batch, and outputs all
+    /// batches that are fully decrypted.
+    fn start_decryption(
+        &mut self,
+        batch: Batch<T, N>,
+        local_ids: BTreeSet<I::Id>,
+        step: &mut Step<T, N, Q, V, I, E>,
+    ) -> Result<()> {
+        let epoch = batch.epoch();
+        let encrypted: Vec<(usize, Ciphertext)> = {
+            let encryption = &self.encryption;
+            batch
+                .iter()
+                .enumerate()
+                .filter_map(|(index, tx)| Some((index, encryption.ciphertext(tx)?.clone())))
+                .collect()
+        };
+        // After a change of the validator set, the new validators decrypt the following batches.
+        let netinfo = match *batch.change() {
+            ChangeState::Complete(_) => Some(Arc::new(self.dyn_hb.netinfo().clone())),
+            ChangeState::None | ChangeState::InProgress(_) => None,
+        };
+        self.pending_batches.push_back(PendingBatch {
+            batch,
+            num_encrypted: encrypted.len(),
+            plaintexts: BTreeMap::new(),
+            local_ids,
+        });
+        let mut decryptions = BTreeMap::new();
+        for (index, ct) in encrypted {
+            if !ct.verify() {
+                self.set_plaintext(epoch, index, None); // It is removed on output.
+                continue;
+            }
+            let mut td = ThresholdDecryption::new(self.decryption_netinfo.clone());
+            let td_step = td.set_ciphertext(ct).map_err(ErrorKind::Decryption)?;
+            let output = step.extend_with(td_step, |msg| Message::Decryption(epoch, index, msg));
+            match output.into_iter().next() {
+                Some(plaintext) => self.set_plaintext(epoch, index, Some(&plaintext)),
+                None => {
+                    decryptions.insert(index, td);
+                }
+            }
+        }
+        self.decryptions.insert(epoch, decryptions);
+        if let Some(netinfo) = netinfo {
+            self.decryption_netinfo = netinfo;
+        }
+        // Shares can arrive before we committed the batch ourselves.
+        let early_shares = self.early_shares.remove(&epoch).unwrap_or_default();
+        for (sender_id, shares) in early_shares {
+            for (index, msg) in shares {
+                self.handle_decryption_message(&sender_id, epoch, index, msg, step)?;
+            }
+        }
+        self.output_decrypted(step);
+        Ok(())
+    }
+
+    /// Handles a decryption share for the encrypted transaction with the given index in the batch
+    /// of the given epoch. Shares for batches that haven't been committed yet are stored, unless
+    /// they are too far in the future, or the sender already sent the maximum number of early
+    /// shares for that epoch. Only the first share for each transaction and sender is stored.
+    /// Once the batch is committed, the shares for indices it doesn't have are discarded.
+    fn handle_decryption_message(
+        &mut self,
+        sender_id: &N,
+        epoch: u64,
+        index: usize,
+        msg: threshold_decryption::Message,
+        step: &mut Step<T, N, Q, V, I, E>,
+    ) -> Result<()> {
+        let td_step = match self
+            .decryptions
+            .get_mut(&epoch)
+            .and_then(|tds| tds.get_mut(&index))
+        {
+            Some(td) => {
+                DistAlgorithm::handle_message(td, sender_id, msg).map_err(ErrorKind::Decryption)?
+            }
+            None => {
+                let next_epoch = self.dyn_hb.next_epoch();
+                let max_epoch = next_epoch + self.dyn_hb.params().max_future_epochs as u64;
+                if epoch < next_epoch || epoch > max_epoch {
+                    return Ok(());
+                }
+                let max_shares = self.max_early_shares.unwrap_or_else(|| {
+                    let num_nodes = self.dyn_hb.netinfo().num_nodes();
+                    self.dyn_hb.params().batch_size.saturating_mul(num_nodes)
+                });
+                let shares = self
+                    .early_shares
+                    .entry(epoch)
+                    .or_insert_with(BTreeMap::new)
+                    .entry(sender_id.clone())
+                    .or_insert_with(BTreeMap::new);
+                if shares.len() < max_shares {
+                    shares.entry(index).or_insert(msg);
+                }
+                return Ok(());
+            }
+        };
+        let output = step.extend_with(td_step, |msg| Message::Decryption(epoch, index, msg));
+        if let Some(plaintext) = output.into_iter().next() {
+            if let Some(tds) = self.decryptions.get_mut(&epoch) {
+                tds.remove(&index);
+            }
+            self.set_plaintext(epoch, index, Some(&plaintext));
+        }
+        Ok(())
+    }
+
+    /// Stores the decrypted transaction with the given index in the pending batch of the given
+    /// epoch. It is replaced with `None` if it can't be deserialized, or is itself encrypted.
+    fn set_plaintext(&mut self, epoch: u64, index: usize, plaintext: Option<&[u8]>) {
+        let tx = plaintext
+            .and_then(|bytes| bincode::deserialize::<T>(bytes).ok())
+            .filter(|tx| self.encryption.ciphertext(tx).is_none());
+        let pending_batch = self
+            .pending_batches
+            .iter_mut()
+            .find(|pending| pending.batch.epoch() == epoch);
+        if let Some(pending) = pending_batch {
+            pending.plaintexts.insert(index, tx);
+        }
+    }
+
+    /// Outputs the pending batches whose encrypted transactions have all been decrypted, in order,
+    /// with the ciphertexts replaced by the decrypted transactions, and without the invalid ones.
+    fn output_decrypted(&mut self, step: &mut Step<T, N, Q, V, I, E>) {
+        while self
+            .pending_batches
+            .front()
+            .map_or(false, PendingBatch::is_decrypted)
+        {
+            let PendingBatch {
+                mut batch,
+                plaintexts,
+                local_ids,
+                ..
+            } = match self.pending_batches.pop_front() {
+                Some(pending) => pending,
+                None => break,
+            };
+            self.decryptions.remove(&batch.epoch());
+            self.validate_batch(&mut batch, plaintexts, local_ids, step);
+            step.output.push_back(batch);
+        }
+    }
+
+    /// Replaces the ciphertexts in a decrypted batch with the plaintexts. Then validates the
+    /// transactions in order, and applies the valid ones to the validator's state, so that the
+    /// result is the same on all nodes. The invalid ones are removed from the batch, and the
+    /// proposers of unencrypted ones that were already invalid before the batch are reported.
+    /// Generates the `TxEvent`s for the transactions in `local_ids`.
+    fn validate_batch(
+        &mut self,
+        batch: &mut Batch<T, N>,
+        mut plaintexts: BTreeMap<usize, Option<T>>,
+        mut local_ids: BTreeSet<I::Id>,
+        step: &mut Step<T, N, Q, V, I, E>,
+    ) {
+        let epoch = batch.epoch();
+        // Each transaction's plaintext, and whether its proposer is at fault if it is rejected.
+        let mut plaintexts: VecDeque<(Option<T>, bool)> = {
+            let validator = &self.validator;
+            batch
+                .iter()
+                .enumerate()
+                .map(|(index, tx)| match plaintexts.remove(&index) {
+                    // The proposer couldn't see what an encrypted transaction contains.
+                    Some(plaintext) => (plaintext, false),
+                    // Only transactions that are invalid in the state before the batch are their
+                    // proposer's fault: The others were made invalid by earlier transactions in
+                    // the same batch.
+                    None => (Some(tx.clone()), validator.validate(tx).is_err()),
+                }).collect()
+        };
+        let mut committed = Vec::new();
+        let mut invalid = Vec::new();
+        {
+            let validator = &mut self.validator;
+            // If several validators proposed the same transaction, it is only validated once.
+            let mut accepted = HashSet::new();
+            batch.filter_map_txs(|proposer_id, tx| {
+                let (plaintext, is_fault) = plaintexts.pop_front().unwrap_or((None, false));
+                let is_valid = plaintext.as_ref().map_or(false, |plaintext| {
+                    if accepted.contains(plaintext) {
+                        return true;
+                    }
+                    if validator.validate(plaintext).is_err() {
+                        return false;
+                    }
+                    validator.apply(plaintext);
+                    accepted.insert(plaintext.clone());
+                    true
+                });
+                if is_valid {
+                    committed.push((proposer_id.clone(), tx));
+                    plaintext
+                } else {
+                    invalid.push((proposer_id.clone(), tx, is_fault));
+                    None
+                }
+            });
+        }
+        // The events refer to the transactions as they were input, i.e. possibly encrypted.
+        for (proposer_id, tx, is_fault) in invalid {
+            if let Ok(id) = self.identifier.id(&tx) {
+                if local_ids.remove(&id) {
+                    self.tx_events.push(TxEvent::Invalid { id, epoch });
+                }
+            }
+            if is_fault {
+                step.fault_log
+                    .append(proposer_id, FaultKind::InvalidTransaction);
+            }
+        }
+        for (proposer_id, tx) in committed {
+            let id = match self.identifier.id(&tx) {
+                Ok(id) => id,
+                Err(_) => continue,
+            };
+            if local_ids.remove(&id) {
+                let event = TxEvent::Committed {
+                    id,
+                    epoch,
+                    proposer_id,
+                };
+                self.tx_events.push(event);
+            }
+        }
+    }
+
+    /// Adds the transaction to the outboxes of `fanout` randomly chosen validators, other than us
+    /// and the node that sent it to us.
+    fn gossip_tx(&mut self, tx: &T, sender_id: Option<&N>) {
+        let gossip = match self.gossip {
+            Some(ref mut gossip) => gossip,
+            None => return,
+        };
+        let netinfo = self.dyn_hb.netinfo();
+        let peer_ids = netinfo
+            .all_ids()
+            .filter(|id| *id != netinfo.our_id() && Some(*id) != sender_id);
+        let mut rng = rand::thread_rng();
+        let chosen = match rand::seq::sample_iter(&mut rng, peer_ids, gossip.config.fanout) {
+            Ok(chosen) => chosen,
+            Err(chosen) => chosen, // There are fewer than `fanout` peers.
+        };
+        for peer_id in chosen {
+            let outbox = gossip
+                .outbox
+                .entry(peer_id.clone())
+                .or_insert_with(VecDeque::new);
+            outbox.push_back(tx.clone());
+            if outbox.len() > gossip.config.max_outbox {
+                outbox.pop_front();
+            }
+        }
+    }
+
+    /// Adds the new and valid transactions received from a peer to the queue, and forwards them.
+    /// Transactions exceeding the peer's limit for our current epoch are ignored, and the peer is
+    /// reported, unless it sent them in a later epoch: A peer that is ahead of us can send its
+    /// limit in each of its epochs. Messages from nodes that aren't validators, and from epochs
+    /// that are too far from ours, are ignored entirely.
+    fn handle_gossip(&mut self, sender_id: &N, epoch: u64, txs: Vec<T>) -> Step<T, N, Q, V, I, E> {
+        if !self.dyn_hb.netinfo().is_node_validator(sender_id) {
+            return Step::default();
+        }
+        let our_epoch = self.dyn_hb.next_epoch();
+        let max_future_epochs = self.dyn_hb.params().max_future_epochs as u64;
+        if epoch + max_future_epochs < our_epoch || epoch > our_epoch + max_future_epochs {
+            return Step::default();
+        }
+        let (new_txs, exceeded): (Vec<(I::Id, T)>, bool) = match self.gossip {
+            None => return Step::default(),
+            Some(ref mut gossip) => {
+                let received = gossip.received.entry(sender_id.clone()).or_insert(0);
+                let allowed = gossip.config.max_txs_per_peer.saturating_sub(*received);
+                *received += cmp::min(allowed, txs.len());
+                let exceeded = txs.len() > allowed;
+                let identifier = &self.identifier;
+                let seen = &mut gossip.seen;
+                let new_txs = txs
+                    .into_iter()
+                    .take(allowed)
+                    .filter_map(|tx| Some((identifier.id(&tx).ok()?, tx)))
+                    .filter(|&(ref id, _)| seen.insert(id.clone(), our_epoch).is_none())
+                    .collect();
+                (new_txs, exceeded)
+            }
+        };
+        for (id, tx) in new_txs {
+            if self.check_transaction(&tx).is_ok() {
+                self.gossip_tx(&tx, Some(sender_id));
+                self.gossiped_txs.insert(id, (our_epoch, tx.clone()));
+                self.push_to_queue(tx);
+            }
+        }
+        if exceeded && epoch <= our_epoch {
+            let fault_kind = FaultKind::GossipLimitExceeded;
+            return Fault::new(sender_id.clone(), fault_kind).into();
+        }
+        Step::default()
+    }
+
+    /// Sends each peer as many transactions from its outbox as its limit for the current epoch
+    /// allows.
+    fn send_gossip(&mut self, step: &mut Step<T, N, Q, V, I, E>) {
+        let epoch = self.dyn_hb.next_epoch();
+        let gossip = match self.gossip {
+            Some(ref mut gossip) => gossip,
+            None => return,
+        };
+        let max_txs = gossip.config.max_txs_per_peer;
+        for (peer_id, outbox) in &mut gossip.outbox {
+            let sent = gossip.sent.entry(peer_id.clone()).or_insert(0);
+            let num_txs = cmp::min(max_txs.saturating_sub(*sent), outbox.len());
+            if num_txs > 0 {
+                *sent += num_txs;
+                let msg = Message::Transactions(epoch, outbox.drain(..num_txs).collect());
+                step.messages
+                    .push_back(Target::Node(peer_id.clone()).message(msg));
+            }
+        }
+    }
+
+    /// Resets the gossip limits after a batch, removes the committed transactions from the
+    /// outboxes, and forgets the transactions that haven't been seen for too long.
+    fn update_gossip(&mut self, batch: &Batch<T, N>) {
+        let gossip = match self.gossip {
+            Some(ref mut gossip) => gossip,
+            None => return,
+        };
+        let epoch = batch.epoch();
+        gossip.received.clear();
+        gossip.sent.clear();
+        let identifier = &self.identifier;
+        let committed: BTreeSet<I::Id> = batch
+            .iter()
+            .filter_map(|tx| identifier.id(tx).ok())
+            .collect();
+        for outbox in gossip.outbox.values_mut() {
+            outbox.retain(|tx| {
+                identifier
+                    .id(tx)
+                    .map_or(true, |id| !committed.contains(&id))
+            });
+        }
+        // Committed transactions count as seen, so that late copies aren't queued again.
+        for id in committed {
+            gossip.seen.insert(id, epoch);
+        }
+        let memory = gossip.config.memory;
+        let forgotten: Vec<I::Id> = gossip
+            .seen
+            .iter()
+            .filter(|&(_, &seen_epoch)| seen_epoch + memory <= epoch)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in forgotten {
+            gossip.seen.remove(&id);
+        }
+    }
+
+    /// Removes the transactions that haven't been committed within the configured number of epochs
+    /// after the batch of the given epoch from the queue. The ones that were input into this node
+    /// are reported as expired.
+    fn expire_txs(&mut self, epoch: u64) {
+        let expiry = match self.tx_expiry {
+            None => return,
+            Some(expiry) => expiry,
+        };
+        let expired: Vec<I::Id> = self
+            .gossiped_txs
+            .iter()
+            .filter(|&(_, &(queued_epoch, _))| queued_epoch + expiry <= epoch + 1)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            if let Some((_, tx)) = self.gossiped_txs.remove(&id) {
+                self.queue.remove_all(once(&tx));
+            }
+        }
+        let expired: Vec<I::Id> = self
+            .local_txs
+            .iter()
+            .filter(|&(_, &(queued_epoch, _))| queued_epoch + expiry <= epoch + 1)
+            .map(|(id, _)| id.clone())
+            .collect();
+        for id in expired {
+            if let Some((_, tx)) = self.local_txs.remove(&id) {
+                self.queue.remove_all(once(&tx));
+                self.tx_events.push(TxEvent::Expired { id, epoch });
+            }
+        }
+    }
+
+    /// Stops tracking the transaction if it was input into this node, and reports it as invalid.
+    fn report_invalid(&mut self, tx: &T, epoch: u64) {
+        let id = match self.identifier.id(tx) {
+            Ok(id) => id,
+            Err(_) => return,
+        };
+        if self.local_txs.remove(&id).is_some() {
+            self.tx_events.push(TxEvent::Invalid { id, epoch });
+        }
+    }
+
     /// Returns `true` if we are ready to propose our contribution for the next epoch, i.e. if the
     /// previous epoch has completed and we have either pending transactions or we are required to
     /// make a proposal to avoid stalling the network.
@@ -248,21 +1351,43 @@ where
         if self.dyn_hb.has_input() {
             return false; // Previous epoch is still in progress.
         }
-        !self.queue.0.is_empty() || self.dyn_hb.should_propose()
+        !self.queue.is_empty() || self.dyn_hb.should_propose()
     }
 
-    /// Initiates the next epoch by proposing a batch from the queue.
-    fn propose(&mut self) -> Result<Step<T, N>> {
+    /// Initiates the next epoch by proposing a batch from the queue. Transactions that have become
+    /// invalid are removed from the queue instead of being proposed.
+    fn propose(&mut self) -> Result<Step<T, N, Q, V, I, E>> {
         let mut step = Step::default();
         while self.can_propose() {
-            let amount = cmp::max(1, self.batch_size / self.dyn_hb.netinfo().num_nodes());
-            let proposal = self.queue.choose(amount, self.batch_size);
-            step.extend(
-                self.dyn_hb
-                    .handle_input(Input::User(proposal))
-                    .map_err(ErrorKind::Propose)?
-                    .convert(),
-            );
+            let num_nodes = self.dyn_hb.netinfo().num_nodes();
+            let (amount, batch_size) = match self.adaptive_batch_size {
+                Some(ref abs) => (abs.amount(), abs.amount() * num_nodes),
+                None => {
+                    let batch_size = self.dyn_hb.params().batch_size;
+                    (cmp::max(1, batch_size / num_nodes), batch_size)
+                }
+            };
+            let (proposal, invalid): (Vec<T>, Vec<T>) = {
+                let validator = &self.validator;
+                self.queue
+                    .choose(amount, batch_size, self.max_contribution_size)
+                    .into_iter()
+                    .partition(|tx| validator.validate(tx).is_ok())
+            };
+            self.queue.remove_all(&invalid);
+            let epoch = self.dyn_hb.next_epoch();
+            for tx in &invalid {
+                self.report_invalid(tx, epoch);
+            }
+            let mut propose_step = self
+                .dyn_hb
+                .handle_input(Input::User(proposal))
+                .map_err(ErrorKind::Propose)?
+                .map(|batch| batch, Message::DynamicHoneyBadger);
+            // If the epoch completed right away, its transactions must leave the queue before the
+            // next proposal is chosen.
+            self.process_output(&mut propose_step)?;
+            step.extend(propose_step);
         }
         Ok(step)
     }
@@ -18,22 +18,66 @@
 //! * It also instantiates Binary Agreement for each participating node, to decide whether
 //! that node's proposed element should be included in the set. Whenever an element is
 //! received via broadcast, we input "yes" (`true`) into the corresponding `BinaryAgreement` instance.
+//! If a validity predicate was configured and the element does not satisfy it, we don't input
+//! anything and report the proposer as faulty instead.
 //! * When _N - f_ `BinaryAgreement` instances have decided "yes", we input "no" (`false`) into the
-//! remaining ones, where we haven't provided input yet.
+//! remaining ones, where we haven't provided input yet. With weighted validators, we wait until
+//! the proposers of the "yes" instances have a total weight of at least _W - f_ instead.
 //! * Once all `BinaryAgreement` instances have decided, `Subset` returns the set of all proposed
 //! values for which the decision was "yes".
+//!
+//! ## Consistent broadcast
+//!
+//! With `with_consistent_broadcast_limit`, values up to the given size are sent using
+//! `ConsistentBroadcast` instead of `Broadcast`, which avoids the erasure coding and Merkle proofs.
+//! The proposer decides which one to use, and the other nodes accept either. To make sure that a
+//! faulty proposer cannot have different values delivered by the two, every correct node takes
+//! part in only one of them per proposer: It ignores the proposer's `Value` in one algorithm if it
+//! has already echoed a value in the other.
+//!
+//! ## Validity predicate
+//!
+//! With `with_validity_predicate`, only elements that satisfy the given predicate are voted for.
+//! Since Binary Agreement only decides "yes" if at least one correct node voted for it, the output
+//! set contains only valid elements, provided that the predicate is deterministic, i.e. that all
+//! correct nodes come to the same conclusion for the same element.
+//!
+//! ## Shared coin
+//!
+//! By default, each `BinaryAgreement` instance flips its own threshold coin whenever it needs one,
+//! so an epoch can require up to _N_ threshold signatures per Binary Agreement round. With
+//! `with_shared_coin`, all instances use a single coin per Binary Agreement epoch instead. We
+//! provide our signature share to that coin as soon as the first of our instances needs it.
+//!
+//! This reduces the number of signature shares sent and verified by a factor of up to _N_, but it
+//! weakens the randomness assumption Binary Agreement relies on for termination: Once one
+//! instance has revealed the coin of a given epoch, the adversary knows the coin value before the
+//! other instances' `Conf` rounds in that epoch are finished, and can use it to schedule their
+//! messages. Agreement and validity are not affected, but the expected number of epochs until all
+//! instances terminate can be higher under an adversarial scheduler.
+//!
+//! Coin messages are only handled for epochs between the lowest epoch of an undecided instance and
+//! `MAX_FUTURE_COIN_EPOCHS` epochs beyond the highest one. Coins of earlier epochs are discarded.
 
 use std::collections::{BTreeMap, BTreeSet};
 use std::result;
 use std::sync::Arc;
+use std::{fmt, mem};
 
 use binary_agreement::{self, BinaryAgreement};
 use broadcast::{self, Broadcast};
+use coin::{self, Coin, CoinMessage};
+use consistent_broadcast::{self, ConsistentBroadcast};
+use fault_log::FaultKind;
 use fmt::HexBytes;
 use messaging::{self, DistAlgorithm, NetworkInfo};
 use rand::Rand;
 use traits::NodeIdT;
 
+/// The number of Binary Agreement epochs beyond the current ones for which shared coin messages
+/// are accepted.
+const MAX_FUTURE_COIN_EPOCHS: u32 = 3;
+
 /// A subset error.
 #[derive(Clone, PartialEq, Debug, Fail)]
 pub enum Error {
@@ -53,6 +97,12 @@ pub enum Error {
     NoSuchBinaryAgreementInstance,
     #[fail(display = "No such broadcast instance")]
     NoSuchBroadcastInstance,
+    #[fail(display = "ProcessCoin error: {}", _0)]
+    ProcessCoin(coin::Error),
+    #[fail(display = "NewConsistentBroadcast error: {}", _0)]
+    NewConsistentBroadcast(consistent_broadcast::Error),
+    #[fail(display = "ProcessConsistentBroadcast error: {}", _0)]
+    ProcessConsistentBroadcast(consistent_broadcast::Error),
 }
 
 /// A subset result.
@@ -61,6 +111,37 @@ pub type Result<T> = ::std::result::Result<T, Error>;
 // TODO: Make this a generic argument of `Subset`.
 type ProposedValue = Vec<u8>;
 
+/// A predicate that decides whether a value of type `T` proposed by the node with the given ID is
+/// valid. It must be deterministic: All correct nodes have to come to the same conclusion.
+pub struct ValidityPredicate<N, T: ?Sized = [u8]>(Arc<dyn Fn(&N, &T) -> bool + Send + Sync>);
+
+impl<N, T: ?Sized> ValidityPredicate<N, T> {
+    /// Creates a new predicate from the given function.
+    pub fn new<F>(f: F) -> Self
+    where
+        F: Fn(&N, &T) -> bool + Send + Sync + 'static,
+    {
+        ValidityPredicate(Arc::new(f))
+    }
+
+    /// Returns `true` if the value proposed by `proposer_id` is valid.
+    pub fn is_valid(&self, proposer_id: &N, value: &T) -> bool {
+        (self.0)(proposer_id, value)
+    }
+}
+
+impl<N, T: ?Sized> Clone for ValidityPredicate<N, T> {
+    fn clone(&self) -> Self {
+        ValidityPredicate(self.0.clone())
+    }
+}
+
+impl<N, T: ?Sized> fmt::Debug for ValidityPredicate<N, T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "<validity predicate>")
+    }
+}
+
 /// Message from Subset to remote nodes.
 #[derive(Serialize, Deserialize, Clone, Debug, Rand)]
 pub enum Message<N: Rand> {
@@ -69,6 +150,11 @@ pub enum Message<N: Rand> {
     /// A message for the Binary Agreement algorithm concerning the set element proposed by the given
     /// node.
     BinaryAgreement(N, binary_agreement::Message),
+    /// A message for the coin shared by all Binary Agreement instances in the given epoch.
+    Coin(u32, CoinMessage),
+    /// A message for the consistent broadcast algorithm concerning the set element proposed by the
+    /// given node.
+    ConsistentBroadcast(N, consistent_broadcast::Message),
 }
 
 /// Subset algorithm instance
@@ -76,13 +162,26 @@ pub enum Message<N: Rand> {
 pub struct Subset<N: Rand> {
     /// Shared network information.
     netinfo: Arc<NetworkInfo<N>>,
+    /// Session ID, e.g, the Honey Badger algorithm epoch.
+    session_id: u64,
     broadcast_instances: BTreeMap<N, Broadcast<N>>,
+    cbc_instances: BTreeMap<N, ConsistentBroadcast<N>>,
     ba_instances: BTreeMap<N, BinaryAgreement<N>>,
     /// `None` means that that item has already been output.
     broadcast_results: BTreeMap<N, Option<ProposedValue>>,
     ba_results: BTreeMap<N, bool>,
     /// Whether the instance has decided on a value.
     decided: bool,
+    /// Whether all Binary Agreement instances use one shared coin per epoch.
+    shared_coin: bool,
+    /// The coins shared by the Binary Agreement instances, by Binary Agreement epoch.
+    coins: BTreeMap<u32, Coin<N, Vec<u8>>>,
+    /// The values of the shared coins that have already terminated.
+    coin_values: BTreeMap<u32, bool>,
+    /// The predicate a proposed value must satisfy for us to vote for its inclusion.
+    validity_predicate: Option<ValidityPredicate<N>>,
+    /// The maximum size of our proposed value for which we use consistent broadcast.
+    consistent_broadcast_limit: Option<usize>,
 }
 
 pub type Step<N> = messaging::Step<Subset<N>>;
@@ -113,6 +212,10 @@ impl<N: NodeIdT + Rand> DistAlgorithm for Subset<N> {
             Message::BinaryAgreement(p_id, a_msg) => {
                 self.handle_binary_agreement(sender_id, &p_id, a_msg)
             }
+            Message::Coin(epoch, c_msg) => self.handle_coin(sender_id, epoch, c_msg),
+            Message::ConsistentBroadcast(p_id, cbc_msg) => {
+                self.handle_consistent_broadcast(sender_id, &p_id, cbc_msg)
+            }
         }
     }
 
@@ -143,6 +246,16 @@ impl<N: NodeIdT + Rand> Subset<N> {
             );
         }
 
+        // Create all consistent broadcast instances.
+        let mut cbc_instances: BTreeMap<N, ConsistentBroadcast<N>> = BTreeMap::new();
+        for proposer_id in netinfo.all_ids() {
+            cbc_instances.insert(
+                proposer_id.clone(),
+                ConsistentBroadcast::new(netinfo.clone(), session_id, proposer_id.clone())
+                    .map_err(Error::NewConsistentBroadcast)?,
+            );
+        }
+
         // Create all Binary Agreement instances.
         let mut ba_instances: BTreeMap<N, BinaryAgreement<N>> = BTreeMap::new();
         for proposer_id in netinfo.all_ids() {
@@ -155,14 +268,49 @@ impl<N: NodeIdT + Rand> Subset<N> {
 
         Ok(Subset {
             netinfo,
+            session_id,
             broadcast_instances,
+            cbc_instances,
             ba_instances,
             broadcast_results: BTreeMap::new(),
             ba_results: BTreeMap::new(),
             decided: false,
+            shared_coin: false,
+            coins: BTreeMap::new(),
+            coin_values: BTreeMap::new(),
+            validity_predicate: None,
+            consistent_broadcast_limit: None,
         })
     }
 
+    /// Makes us propose values of at most `max_size` bytes using `ConsistentBroadcast` instead of
+    /// `Broadcast`. Other nodes' values are accepted via either algorithm, regardless of this
+    /// setting. This must be called before any input or message is handled.
+    pub fn with_consistent_broadcast_limit(mut self, max_size: usize) -> Self {
+        self.consistent_broadcast_limit = Some(max_size);
+        self
+    }
+
+    /// Sets a predicate that every proposed value must satisfy: We only vote for the inclusion of
+    /// values that are valid according to it, and report the proposers of invalid ones as faulty.
+    /// This must be called before any input or message is handled.
+    pub fn with_validity_predicate(mut self, validity_predicate: ValidityPredicate<N>) -> Self {
+        self.validity_predicate = Some(validity_predicate);
+        self
+    }
+
+    /// Makes all Binary Agreement instances use a single shared coin per Binary Agreement epoch.
+    /// This must be called before any input or message is handled.
+    pub fn with_shared_coin(mut self) -> Self {
+        self.shared_coin = true;
+        let ba_instances = mem::replace(&mut self.ba_instances, BTreeMap::new());
+        self.ba_instances = ba_instances
+            .into_iter()
+            .map(|(id, ba)| (id, ba.with_shared_coin()))
+            .collect();
+        self
+    }
+
     /// Subset input message handler. It receives a value for broadcast
     /// and redirects it to the corresponding broadcast instance.
     pub fn send_proposed_value(&mut self, value: ProposedValue) -> Result<Step<N>> {
@@ -170,13 +318,18 @@ impl<N: NodeIdT + Rand> Subset<N> {
             return Ok(Step::default());
         }
         let id = self.netinfo.our_id().clone();
+        if let Some(max_size) = self.consistent_broadcast_limit {
+            if value.len() <= max_size {
+                return self.process_consistent_broadcast(&id, |cbc| cbc.handle_input(value));
+            }
+        }
         // Upon receiving input v_i , input v_i to RBC_i. See Figure 2.
         self.process_broadcast(&id, |bc| bc.handle_input(value))
     }
 
-    /// Returns the number of validators from which we have already received a proposal.
-    pub(crate) fn received_proposals(&self) -> usize {
-        self.broadcast_results.len()
+    /// Returns the total weight of the validators from which we have already received a proposal.
+    pub(crate) fn received_proposal_weight(&self) -> u64 {
+        self.netinfo.weight_of(self.broadcast_results.keys())
     }
 
     /// Receives a broadcast message from a remote node `sender_id` concerning a
@@ -187,9 +340,42 @@ impl<N: NodeIdT + Rand> Subset<N> {
         proposer_id: &N,
         bmessage: broadcast::Message,
     ) -> Result<Step<N>> {
+        if let broadcast::Message::Value(_) = bmessage {
+            // Don't echo if we already took part in the proposer's consistent broadcast.
+            if self
+                .cbc_instances
+                .get(proposer_id)
+                .map_or(false, |cbc| cbc.echo_sent())
+            {
+                return Ok(Step::default());
+            }
+        }
         self.process_broadcast(proposer_id, |bc| bc.handle_message(sender_id, bmessage))
     }
 
+    /// Receives a consistent broadcast message from a remote node `sender_id` concerning a value
+    /// proposed by the node `proposer_id`.
+    fn handle_consistent_broadcast(
+        &mut self,
+        sender_id: &N,
+        proposer_id: &N,
+        cbc_message: consistent_broadcast::Message,
+    ) -> Result<Step<N>> {
+        if let consistent_broadcast::Message::Value(_) = cbc_message {
+            // Don't echo if we already took part in the proposer's reliable broadcast.
+            if self
+                .broadcast_instances
+                .get(proposer_id)
+                .map_or(false, |bc| bc.echo_sent())
+            {
+                return Ok(Step::default());
+            }
+        }
+        self.process_consistent_broadcast(proposer_id, |cbc| {
+            cbc.handle_message(sender_id, cbc_message)
+        })
+    }
+
     /// Receives a Binary Agreement message from a remote node `sender_id` concerning
     /// a value proposed by the node `proposer_id`.
     fn handle_binary_agreement(
@@ -204,8 +390,20 @@ impl<N: NodeIdT + Rand> Subset<N> {
         })
     }
 
-    /// Upon delivery of v_j from RBC_j, if input has not yet been provided to
-    /// BA_j, then provide input 1 to BA_j. See Figure 11.
+    /// Receives a message from a remote node `sender_id` concerning the shared coin of the given
+    /// Binary Agreement epoch.
+    fn handle_coin(&mut self, sender_id: &N, epoch: u32, cmessage: CoinMessage) -> Result<Step<N>> {
+        if !self.shared_coin || self.coin_values.contains_key(&epoch) {
+            return Ok(Step::default());
+        }
+        match self.ba_epochs() {
+            Some((min, max)) if min <= epoch && epoch <= max + MAX_FUTURE_COIN_EPOCHS => (),
+            _ => return Ok(Step::default()), // Obsolete, or too far in the future.
+        }
+        self.process_coin(epoch, |coin| coin.handle_message(sender_id, cmessage))
+    }
+
+    /// Applies `f` to the broadcast instance of `proposer_id`, and handles its output.
     fn process_broadcast<F>(&mut self, proposer_id: &N, f: F) -> Result<Step<N>>
     where
         F: FnOnce(&mut Broadcast<N>) -> result::Result<broadcast::Step<N>, broadcast::Error>,
@@ -227,6 +425,58 @@ impl<N: NodeIdT + Rand> Subset<N> {
                 return Ok(step);
             }
         };
+        step.extend(self.process_delivered_value(proposer_id, value)?);
+        Ok(step)
+    }
+
+    /// Applies `f` to the consistent broadcast instance of `proposer_id`, and handles its output.
+    fn process_consistent_broadcast<F>(&mut self, proposer_id: &N, f: F) -> Result<Step<N>>
+    where
+        F: FnOnce(
+            &mut ConsistentBroadcast<N>,
+        ) -> consistent_broadcast::Result<consistent_broadcast::Step<N>>,
+    {
+        let mut step = Step::default();
+        let value = {
+            let cbc = self
+                .cbc_instances
+                .get_mut(proposer_id)
+                .ok_or(Error::NoSuchBroadcastInstance)?;
+            let to_msg = |cbc_msg| Message::ConsistentBroadcast(proposer_id.clone(), cbc_msg);
+            let output =
+                step.extend_with(f(cbc).map_err(Error::ProcessConsistentBroadcast)?, to_msg);
+            if let Some(output) = output.into_iter().next() {
+                output
+            } else {
+                return Ok(step);
+            }
+        };
+        step.extend(self.process_delivered_value(proposer_id, value)?);
+        Ok(step)
+    }
+
+    /// Upon delivery of v_j from RBC_j, if input has not yet been provided to
+    /// BA_j, then provide input 1 to BA_j, unless v_j is invalid. See Figure 11.
+    fn process_delivered_value(
+        &mut self,
+        proposer_id: &N,
+        value: ProposedValue,
+    ) -> Result<Step<N>> {
+        let mut step = Step::default();
+        if self.broadcast_results.contains_key(proposer_id) {
+            // Only one of the two broadcast algorithms can deliver a value for each proposer.
+            error!(
+                "Duplicate delivery from {:?}: {:?}",
+                proposer_id,
+                HexBytes(&value)
+            );
+            return Ok(step);
+        }
+
+        let is_valid = match self.validity_predicate {
+            None => true,
+            Some(ref predicate) => predicate.is_valid(proposer_id, &value),
+        };
 
         let val_to_insert = if let Some(true) = self.ba_results.get(proposer_id) {
             debug!("    {:?} → {:?}", proposer_id, HexBytes(&value));
@@ -243,6 +493,18 @@ impl<N: NodeIdT + Rand> Subset<N> {
         {
             error!("Duplicate insert in broadcast_results: {:?}", inval)
         }
+        if !is_valid {
+            // If the other correct nodes agree, the instance will decide "no". We still kept the
+            // value above, so that we can output it in case it decides "yes" nonetheless.
+            warn!(
+                "{:?} Invalid value proposed by {:?}",
+                self.netinfo.our_id(),
+                proposer_id
+            );
+            let fault_kind = FaultKind::InvalidProposedValue;
+            step.fault_log.append(proposer_id.clone(), fault_kind);
+            return Ok(step);
+        }
         let set_binary_agreement_input = |ba: &mut BinaryAgreement<N>| {
             if ba.accepts_input() {
                 ba.handle_input(true)
@@ -254,9 +516,20 @@ impl<N: NodeIdT + Rand> Subset<N> {
         Ok(step)
     }
 
+    /// Applies `f` to the Binary Agreement instance `proposer_id` and, if we use a shared coin,
+    /// provides the coin to the instances that are waiting for it.
+    fn process_binary_agreement<F>(&mut self, proposer_id: &N, f: F) -> Result<Step<N>>
+    where
+        F: FnOnce(&mut BinaryAgreement<N>) -> binary_agreement::Result<binary_agreement::Step<N>>,
+    {
+        let mut step = self.update_binary_agreement(proposer_id, f)?;
+        step.extend(self.process_shared_coins()?);
+        Ok(step)
+    }
+
     /// Callback to be invoked on receipt of the decision value of the Binary Agreement
     /// instance `id`.
-    fn process_binary_agreement<F>(&mut self, proposer_id: &N, f: F) -> Result<Step<N>>
+    fn update_binary_agreement<F>(&mut self, proposer_id: &N, f: F) -> Result<Step<N>>
     where
         F: FnOnce(&mut BinaryAgreement<N>) -> binary_agreement::Result<binary_agreement::Step<N>>,
     {
@@ -297,9 +570,11 @@ impl<N: NodeIdT + Rand> Subset<N> {
         );
 
         if accepted {
-            if self.count_true() == self.netinfo.num_correct() {
+            if self.true_weight() >= self.netinfo.correct_weight() {
                 // Upon delivery of value 1 from at least N − f instances of BA, provide
-                // input 0 to each instance of BA that has not yet been provided input.
+                // input 0 to each instance of BA that has not yet been provided input. With
+                // weighted validators, the proposers need to have a total weight of at least
+                // W − f.
                 for (id, binary_agreement) in &mut self.ba_instances {
                     if binary_agreement.accepts_input() {
                         let to_msg = |a_msg| Message::BinaryAgreement(id.clone(), a_msg);
@@ -327,13 +602,92 @@ impl<N: NodeIdT + Rand> Subset<N> {
         Ok(step)
     }
 
-    /// Returns the number of Binary Agreement instances that have decided "yes".
-    fn count_true(&self) -> usize {
-        self.ba_results.values().filter(|v| **v).count()
+    /// Applies `f` to the shared coin of the given Binary Agreement epoch. If the coin terminates,
+    /// passes its value to the instances that are waiting for it.
+    fn process_coin<F>(&mut self, epoch: u32, f: F) -> Result<Step<N>>
+    where
+        F: FnOnce(&mut Coin<N, Vec<u8>>) -> coin::Result<coin::Step<N, Vec<u8>>>,
+    {
+        let mut step = Step::default();
+        let value = {
+            let netinfo = &self.netinfo;
+            let session_id = self.session_id;
+            let coin = self.coins.entry(epoch).or_insert_with(|| {
+                let nonce = Vec::from(format!(
+                    "Nonce for Subset shared coin {:?}@{}:{}",
+                    netinfo.invocation_id().as_ref(),
+                    session_id,
+                    epoch
+                ));
+                Coin::new(netinfo.clone(), nonce)
+            });
+            let to_msg = |c_msg| Message::Coin(epoch, c_msg);
+            let output = step.extend_with(f(coin).map_err(Error::ProcessCoin)?, to_msg);
+            if let Some(value) = output.into_iter().next() {
+                value
+            } else {
+                return Ok(step);
+            }
+        };
+        self.coins.remove(&epoch);
+        self.coin_values.insert(epoch, value);
+        step.extend(self.process_shared_coins()?);
+        Ok(step)
+    }
+
+    /// Provides the shared coin value to every Binary Agreement instance that is waiting for it.
+    /// If the value is not known yet, inputs our signature share to the coin.
+    fn process_shared_coins(&mut self) -> Result<Step<N>> {
+        let mut step = Step::default();
+        if !self.shared_coin {
+            return Ok(step);
+        }
+        // Coins of epochs that all undecided instances have left behind are not needed anymore.
+        let min_epoch = self.ba_epochs().map_or(u32::max_value(), |(min, _)| min);
+        self.coins = self.coins.split_off(&min_epoch);
+        self.coin_values = self.coin_values.split_off(&min_epoch);
+        let awaiting: Vec<(N, u32)> = self
+            .ba_instances
+            .iter()
+            .filter_map(|(id, ba)| ba.awaiting_shared_coin().map(|epoch| (id.clone(), epoch)))
+            .collect();
+        for (id, epoch) in awaiting {
+            if let Some(&value) = self.coin_values.get(&epoch) {
+                step.extend(
+                    self.process_binary_agreement(&id, |ba| ba.handle_shared_coin(epoch, value))?,
+                );
+            } else {
+                step.extend(self.process_coin(epoch, |coin| coin.handle_input(()))?);
+            }
+        }
+        Ok(step)
+    }
+
+    /// Returns the lowest and highest current epoch of the undecided Binary Agreement instances,
+    /// or `None` if all of them have decided.
+    fn ba_epochs(&self) -> Option<(u32, u32)> {
+        let mut epochs = self
+            .ba_instances
+            .values()
+            .filter(|ba| !ba.terminated())
+            .map(BinaryAgreement::epoch);
+        let first = epochs.next()?;
+        Some(epochs.fold((first, first), |(min, max), e| (min.min(e), max.max(e))))
+    }
+
+    /// Returns the total weight of the proposers whose Binary Agreement instances have decided
+    /// "yes".
+    fn true_weight(&self) -> u64 {
+        let ids = self
+            .ba_results
+            .iter()
+            .filter(|(_, v)| **v)
+            .map(|(id, _)| id);
+        self.netinfo.weight_of(ids)
     }
 
     fn try_binary_agreement_completion(&mut self) -> Option<SubsetOutput<N>> {
-        if self.decided || self.count_true() < self.netinfo.num_correct() {
+        if self.decided || self.true_weight() < self.netinfo.correct_weight() {
             return None;
         }
         // Once all instances of BA have completed, let C ⊂ [1..N] be
@@ -9,7 +9,9 @@
 //! When the protocol completes, every node receives a secret key share suitable for threshold
 //! signatures and encryption. The secret master key is not known by anyone. The protocol succeeds
 //! if up to _t_ nodes are faulty, where _t_ is the `threshold` parameter. The number of nodes must
-//! be at least _2 t + 1_.
+//! be at least _2 t + 1_. If the threshold is higher than the maximum number _f_ of faulty nodes,
+//! that number can be configured with `SyncKeyGen::with_num_faulty`: Then _t + f + 1_ nodes are
+//! required.
 //!
 //! ## Usage
 //!
@@ -31,7 +33,7 @@
 //!
 //! While not asynchronous, the algorithm is fault tolerant: It is not necessary to handle a
 //! `Part` and all `Ack` messages from every validator. A `Part` is _complete_ if it
-//! received at least _2 t + 1_ valid `Ack`s. Only complete `Part`s are used for key
+//! received at least _t + f + 1_ valid `Ack`s. Only complete `Part`s are used for key
 //! generation in the end, and as long as at least one complete `Part` is from a correct node,
 //! the new key set is secure. You can use `SyncKeyGen::is_ready` to check whether at least
 //! _t + 1_ `Part`s are complete. So all nodes can call `generate` as soon as `is_ready` returns
@@ -47,6 +49,30 @@
 //! messages will be created and they do not need to send anything. On completion, they will only
 //! receive the public key set, but no secret key share.
 //!
+//! ## Complaints
+//!
+//! If the row that a `Part` encrypted to us is invalid, `handle_part` returns a `Complaint`
+//! instead of an `Ack`. Like all other messages, it must be multicast and handled by everyone.
+//! When the proposer handles a complaint about its own part, `handle_complaint` returns a
+//! `Justification`, which reveals the disputed row in plain text, so that everyone can verify it
+//! against the commitment. If it is valid, the complaining node can now compute its `Ack` from it.
+//! A part is disqualified if its justification is invalid, or if more than _f_ nodes complained
+//! about it: In that case, at least one of them is correct. A part with unanswered complaints is
+//! not complete, so a proposer that sends invalid rows to only a few nodes cannot cause them to
+//! end up without a valid key share.
+//!
+//! ## Resharing
+//!
+//! `SyncKeyGen::new_reshare` creates fresh secret key shares for an _existing_ public key set,
+//! possibly for a different set of nodes: The old shares become useless, but the public master
+//! key stays the same, so signatures remain verifiable. Only nodes that hold a share of the
+//! existing key set can be dealers: instead of a random secret, each of them shares its own secret
+//! key share, so they need its `ShareValue`, which `SyncKeyGen::generate_share` returns. Each part
+//! contains a commitment to the dealer's share, which everyone verifies against the dealer's
+//! existing public key share and the part's polynomial. All other nodes take part only by sending
+//! `Ack`s. Resharing is ready once _t' + 1_ parts are complete,
+//! where _t'_ is the existing key set's threshold.
+//!
 //! ## Example
 //!
 //! ```
@@ -90,6 +116,7 @@
 //!     for (&id, node) in &mut nodes {
 //!         match node.handle_part(&mut rng, &sender_id, part.clone()) {
 //!             Some(PartOutcome::Valid(ack)) => acks.push((id, ack)),
+//!             Some(PartOutcome::Complaint(_, faults)) => panic!("Invalid row: {:?}", faults),
 //!             Some(PartOutcome::Invalid(faults)) => panic!("Invalid part: {:?}", faults),
 //!             None => panic!("We are not an observer, so we should send Ack."),
 //!         }
@@ -159,19 +186,20 @@
 //! method above. The sum of the secret keys we received from each node is then used as our secret
 //! key. No single node knows the secret master key.
 
-use std::collections::btree_map::Entry;
+use std::cmp;
 use std::collections::{BTreeMap, BTreeSet};
 use std::fmt::{self, Debug, Formatter};
+use std::iter::once;
 
 use bincode;
 use crypto::{
     error::Error as CryptoError,
-    poly::{BivarCommitment, BivarPoly, Poly},
+    poly::{BivarCommitment, BivarPoly, Commitment, Poly},
     serde_impl::field_vec::FieldWrap,
     Ciphertext, PublicKey, PublicKeySet, SecretKey, SecretKeyShare,
 };
 use pairing::bls12_381::{Fr, G1Affine};
-use pairing::{CurveAffine, Field};
+use pairing::{CurveAffine, CurveProjective, Field, PrimeField};
 use rand;
 
 use fault_log::{AckMessageFault as Fault, FaultKind, FaultLog};
@@ -197,14 +225,24 @@ pub enum Error {
 /// The message contains a commitment to a bivariate polynomial, and for each node, an encrypted
 /// row of values. If this message receives enough `Ack`s, it will be used as summand to produce
 /// the the key set in the end.
+///
+/// When resharing, it also contains the serialized offset that needs to be added to the
+/// polynomial's constant term to obtain the sender's existing secret key share, and a commitment
+/// to the constant polynomial with that share's value, i.e. to the sender's existing public key
+/// share.
 #[derive(Deserialize, Serialize, Clone, Hash, Eq, PartialEq)]
-pub struct Part(BivarCommitment, Vec<Ciphertext>);
+pub struct Part(
+    BivarCommitment,
+    Vec<Ciphertext>,
+    Option<(Vec<u8>, Commitment)>,
+);
 
 impl Debug for Part {
     fn fmt(&self, f: &mut Formatter) -> fmt::Result {
         f.debug_tuple("Part")
             .field(&format!("<degree {}>", self.0.degree()))
             .field(&format!("<{} rows>", self.1.len()))
+            .field(&self.2.as_ref().map(|_| "<offset>"))
             .finish()
     }
 }
@@ -226,30 +264,66 @@ impl Debug for Ack {
     }
 }
 
+/// A complaint about the `Part` of the proposer with the given index: The row encrypted to us was
+/// invalid. It must be sent to all participating nodes and handled by all of them, including
+/// ourselves.
+#[derive(Deserialize, Serialize, Clone, Hash, Eq, PartialEq, Debug)]
+pub struct Complaint(u64);
+
+/// A proposer's response to a `Complaint` about its `Part`. It must be sent to all participating
+/// nodes and handled by all of them, including ourselves.
+///
+/// The message contains the index of the complaining node and the serialized row of that node, in
+/// plain text, so that everyone can verify it against the commitment.
+#[derive(Deserialize, Serialize, Clone, Hash, Eq, PartialEq)]
+pub struct Justification(u64, Vec<u8>);
+
+impl Debug for Justification {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("Justification")
+            .field(&self.0)
+            .field(&"<row>")
+            .finish()
+    }
+}
+
 /// The information needed to track a single proposer's secret sharing process.
 #[derive(Debug)]
 struct ProposalState {
     /// The proposer's commitment.
     commit: BivarCommitment,
+    /// The offset to be added to the polynomial's constant term. This is zero unless resharing.
+    offset: Fr,
     /// The verified values we received from `Ack` messages.
     values: BTreeMap<u64, Fr>,
     /// The nodes which have acked this part, valid or not.
     acks: BTreeSet<u64>,
+    /// The nodes which have complained about this part, and whether the proposer has revealed
+    /// their row.
+    complaints: BTreeMap<u64, bool>,
+    /// Whether the proposer's justification was invalid, or too many nodes complained.
+    disqualified: bool,
 }
 
 impl ProposalState {
     /// Creates a new part state with a commitment.
-    fn new(commit: BivarCommitment) -> ProposalState {
+    fn new(commit: BivarCommitment, offset: Fr) -> ProposalState {
         ProposalState {
             commit,
+            offset,
             values: BTreeMap::new(),
             acks: BTreeSet::new(),
+            complaints: BTreeMap::new(),
+            disqualified: false,
         }
     }
 
-    /// Returns `true` if at least `2 * threshold + 1` nodes have acked.
-    fn is_complete(&self, threshold: usize) -> bool {
-        self.acks.len() > 2 * threshold
+    /// Returns `true` if at least `threshold + num_faulty + 1` nodes have acked, all complaints
+    /// have been answered, and the proposer is not disqualified.
+    fn is_complete(&self, threshold: usize, num_faulty: usize) -> bool {
+        !self.disqualified
+            && self.complaints.values().all(|justified| *justified)
+            && self.acks.len() > threshold + num_faulty
     }
 }
 
@@ -258,13 +332,53 @@ pub enum PartOutcome<N: Clone> {
     /// The message was valid: the part of it that was encrypted to us matched the public
     /// commitment, so we can multicast an `Ack` message for it.
     Valid(Ack),
+    /// The part encrypted to us was malformed or didn't match the commitment. We now know that the
+    /// proposer is faulty, and multicast a `Complaint` instead of an `Ack`.
+    Complaint(Complaint, FaultLog<N>),
     // If the Part message passed to `handle_part()` is invalid, the
     // fault is logged and passed onto the caller.
-    /// The message was invalid: the part encrypted to us was malformed or didn't match the
-    /// commitment. We now know that the proposer is faulty, and dont' send an `Ack`.
+    /// The message was invalid for everyone, e.g. it contained the wrong number of rows. We now
+    /// know that the proposer is faulty, and dont' send an `Ack`.
     Invalid(FaultLog<N>),
 }
 
+/// The outcome of handling a `Complaint` message.
+pub enum ComplaintOutcome<N: Clone> {
+    /// The complaint was about our own part: We need to multicast the `Justification`.
+    Justify(Justification),
+    /// Nothing needs to be sent. This contains the complaining node if the complaint was
+    /// invalid, or the proposer if it is now disqualified.
+    Handled(FaultLog<N>),
+}
+
+/// The secret value of a key share generated by `SyncKeyGen`. It is needed to act as a dealer when
+/// resharing the key set.
+#[derive(Clone)]
+pub struct ShareValue(pub(crate) Fr);
+
+impl Debug for ShareValue {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_tuple("ShareValue").field(&"...").finish()
+    }
+}
+
+impl ShareValue {
+    /// Returns the secret key share with this value.
+    pub fn secret_key_share(&self) -> Result<SecretKeyShare, Error> {
+        let mut fr = self.0;
+        SecretKeyShare::from_mut_ptr(&mut fr as *mut Fr).map_err(Error::Generation)
+    }
+}
+
+/// The existing key set that is being reshared.
+#[derive(Debug)]
+struct Reshare {
+    /// The existing public key set. It will remain the same.
+    pk_set: PublicKeySet,
+    /// The old node index of each node that has an existing key share, by new node index.
+    old_indices: BTreeMap<u64, u64>,
+}
+
 /// A synchronous algorithm for dealerless distributed key generation.
 ///
 /// It requires that all nodes handle all messages in the exact same order.
@@ -282,6 +396,12 @@ pub struct SyncKeyGen<N> {
     parts: BTreeMap<u64, ProposalState>,
     /// The degree of the generated polynomial.
     threshold: usize,
+    /// The maximum number of faulty nodes. This is at most `threshold`.
+    num_faulty: usize,
+    /// The existing key set, if we are resharing it.
+    reshare: Option<Reshare>,
+    /// Our own bivariate polynomial, if we are a proposer. It is needed to answer complaints.
+    our_part: Option<BivarPoly>,
 }
 
 impl<N: NodeIdT> SyncKeyGen<N> {
@@ -297,39 +417,132 @@ impl<N: NodeIdT> SyncKeyGen<N> {
         pub_keys: BTreeMap<N, PublicKey>,
         threshold: usize,
     ) -> Result<(SyncKeyGen<N>, Option<Part>), Error> {
+        let mut key_gen = SyncKeyGen::new_without_part(our_id, sec_key, pub_keys, threshold, None);
+        if key_gen.our_idx.is_none() {
+            return Ok((key_gen, None)); // No part: we are an observer.
+        }
+        let part = key_gen.create_part(rng, None)?;
+        Ok((key_gen, Some(part)))
+    }
+
+    /// Creates a new `SyncKeyGen` instance that reshares the existing key set of `old_netinfo` to
+    /// the nodes in `pub_keys`, together with the `Part` message that should be multicast to all
+    /// nodes.
+    ///
+    /// If we have a share of the existing key set, `our_share` must be its value. Otherwise, or if
+    /// we are an observer, no `Part` message is produced.
+    pub fn new_reshare<R: rand::Rng>(
+        rng: &mut R,
+        our_id: N,
+        sec_key: SecretKey,
+        pub_keys: BTreeMap<N, PublicKey>,
+        threshold: usize,
+        old_netinfo: &NetworkInfo<N>,
+        our_share: Option<&ShareValue>,
+    ) -> Result<(SyncKeyGen<N>, Option<Part>), Error> {
+        let old_indices = pub_keys
+            .keys()
+            .enumerate()
+            .filter_map(|(idx, id)| {
+                let old_idx = old_netinfo.node_index(id)?;
+                Some((idx as u64, old_idx as u64))
+            }).collect();
+        let reshare = Reshare {
+            pk_set: old_netinfo.public_key_set().clone(),
+            old_indices,
+        };
+        let mut key_gen =
+            SyncKeyGen::new_without_part(our_id, sec_key, pub_keys, threshold, Some(reshare));
+        let is_dealer = key_gen.our_idx.map_or(false, |idx| {
+            key_gen
+                .reshare
+                .as_ref()
+                .map_or(false, |reshare| reshare.old_indices.contains_key(&idx))
+        });
+        match our_share {
+            Some(share) if is_dealer => {
+                let part = key_gen.create_part(rng, Some(share.0))?;
+                Ok((key_gen, Some(part)))
+            }
+            _ => Ok((key_gen, None)), // No part: we don't have an existing key share.
+        }
+    }
+
+    /// Creates a new `SyncKeyGen` instance, without a `Part` message.
+    fn new_without_part(
+        our_id: N,
+        sec_key: SecretKey,
+        pub_keys: BTreeMap<N, PublicKey>,
+        threshold: usize,
+        reshare: Option<Reshare>,
+    ) -> SyncKeyGen<N> {
         let our_idx = pub_keys
             .keys()
             .position(|id| *id == our_id)
             .map(|idx| idx as u64);
-        let key_gen = SyncKeyGen {
+        SyncKeyGen {
             our_id,
             our_idx,
             sec_key,
             pub_keys,
             parts: BTreeMap::new(),
             threshold,
-        };
-        if our_idx.is_none() {
-            return Ok((key_gen, None)); // No part: we are an observer.
+            num_faulty: threshold,
+            reshare,
+            our_part: None,
         }
+    }
+
+    /// Sets the maximum number _f_ of faulty nodes, if it is lower than the threshold. By default,
+    /// it is assumed to be equal to the threshold _t_.
+    ///
+    /// A part is complete once _t + f + 1_ nodes have acknowledged it, and disqualified if more
+    /// than _f_ nodes complained about it. So with a lower _f_, fewer nodes need to be correct for
+    /// key generation to succeed: _t + f + 1_ instead of _2 t + 1_.
+    pub fn with_num_faulty(mut self, num_faulty: usize) -> Self {
+        self.num_faulty = cmp::min(num_faulty, self.threshold);
+        self
+    }
 
-        let our_part = BivarPoly::random(threshold, rng).map_err(Error::Creation)?;
+    /// Creates our `Part` message with a random polynomial. If `secret` is given, the part also
+    /// contains the offset from the polynomial's constant term to the secret, and a commitment to
+    /// the secret.
+    fn create_part<R: rand::Rng>(
+        &mut self,
+        rng: &mut R,
+        secret: Option<Fr>,
+    ) -> Result<Part, Error> {
+        let our_part = BivarPoly::random(self.threshold, rng).map_err(Error::Creation)?;
         let commit = our_part.commitment();
-        let encrypt = |(i, pk): (usize, &PublicKey)| {
-            let row = our_part.row(i + 1).map_err(Error::Creation)?;
-            let bytes = bincode::serialize(&row).expect("failed to serialize row");
-            Ok(pk.encrypt_with_rng(rng, &bytes))
+        let offset = match secret {
+            None => None,
+            Some(secret) => {
+                let mut offset = secret;
+                let constant = our_part.row(0).map_err(Error::Creation)?.evaluate(0);
+                offset.sub_assign(&constant);
+                let wrap = FieldWrap::new(offset);
+                let ser_offset = bincode::serialize(&wrap).expect("failed to serialize offset");
+                Some((ser_offset, constant_commitment(secret)?))
+            }
         };
-        let rows = key_gen
-            .pub_keys
-            .values()
-            .enumerate()
-            .map(encrypt)
-            .collect::<Result<Vec<_>, Error>>()?;
-        Ok((key_gen, Some(Part(commit, rows))))
+        let rows = {
+            let encrypt = |(i, pk): (usize, &PublicKey)| {
+                let row = our_part.row(i + 1).map_err(Error::Creation)?;
+                let bytes = bincode::serialize(&row).expect("failed to serialize row");
+                Ok(pk.encrypt_with_rng(rng, &bytes))
+            };
+            self.pub_keys
+                .values()
+                .enumerate()
+                .map(encrypt)
+                .collect::<Result<Vec<_>, Error>>()?
+        };
+        self.our_part = Some(our_part);
+        Ok(Part(commit, rows, offset))
     }
 
-    /// Handles a `Part` message. If it is valid, returns an `Ack` message to be broadcast.
+    /// Handles a `Part` message. If it is valid, returns an `Ack` message to be broadcast. If only
+    /// the row encrypted to us is invalid, returns a `Complaint` message to be broadcast instead.
     ///
     /// If we are only an observer, `None` is returned instead and no messages need to be sent.
     ///
@@ -339,36 +552,174 @@ impl<N: NodeIdT> SyncKeyGen<N> {
         &mut self,
         rng: &mut R,
         sender_id: &N,
-        Part(commit, rows): Part,
+        Part(commit, rows, opt_offset): Part,
     ) -> Option<PartOutcome<N>> {
         let sender_idx = self.node_index(sender_id)?;
-        let opt_commit_row = self.our_idx.map(|idx| commit.row(idx + 1));
-        match self.parts.entry(sender_idx) {
-            Entry::Occupied(_) => {
-                debug!("Received multiple parts from node {:?}.", sender_id);
-                return None;
-            }
-            Entry::Vacant(entry) => {
-                entry.insert(ProposalState::new(commit));
-            }
+        if self.parts.contains_key(&sender_idx) {
+            debug!("Received multiple parts from node {:?}.", sender_id);
+            return None;
+        }
+        if rows.len() != self.pub_keys.len() {
+            error!("Wrong number of rows in part from node {:?}.", sender_id);
+            let fault_log = FaultLog::init(sender_id.clone(), FaultKind::InvalidPartMessage);
+            return Some(PartOutcome::Invalid(fault_log));
         }
+        let offset = match self.verify_offset(sender_idx, &commit, opt_offset.as_ref()) {
+            Some(offset) => offset,
+            None => {
+                error!("Invalid offset in part from node {:?}.", sender_id);
+                let fault_log = FaultLog::init(sender_id.clone(), FaultKind::InvalidPartMessage);
+                return Some(PartOutcome::Invalid(fault_log));
+            }
+        };
+        let opt_commit_row = self.our_idx.map(|idx| commit.row(idx + 1));
+        self.parts
+            .insert(sender_idx, ProposalState::new(commit, offset));
         // If we are only an observer, return `None`. We don't need to send `Ack`.
         let our_idx = self.our_idx?;
         let commit_row = opt_commit_row?;
-        let ser_row = self.sec_key.decrypt(rows.get(our_idx as usize)?)?;
-        let row: Poly = if let Ok(row) = bincode::deserialize(&ser_row) {
-            row
-        } else {
-            // Log the faulty node and ignore invalid messages.
-            let fault_log = FaultLog::init(sender_id.clone(), FaultKind::InvalidPartMessage);
-            return Some(PartOutcome::Invalid(fault_log));
+        let opt_row = self
+            .sec_key
+            .decrypt(&rows[our_idx as usize])
+            .and_then(|ser_row| bincode::deserialize::<Poly>(&ser_row).ok());
+        match opt_row {
+            Some(ref row) if row.commitment() == commit_row => {
+                Some(PartOutcome::Valid(self.create_ack(rng, sender_idx, row)))
+            }
+            _ => {
+                // Log the faulty node and ask it to reveal our row.
+                error!("Invalid part from node {:?}.", sender_id);
+                let fault_log = FaultLog::init(sender_id.clone(), FaultKind::InvalidPartMessage);
+                Some(PartOutcome::Complaint(Complaint(sender_idx), fault_log))
+            }
+        }
+    }
+
+    /// Handles a `Complaint` message.
+    ///
+    /// All participating nodes must handle the exact same sequence of messages.
+    /// Note that `handle_complaint` also needs to explicitly be called with this instance's own
+    /// `Complaint`s.
+    pub fn handle_complaint(
+        &mut self,
+        sender_id: &N,
+        Complaint(proposer_idx): Complaint,
+    ) -> ComplaintOutcome<N> {
+        let mut fault_log = FaultLog::new();
+        let sender_idx = match self.node_index(sender_id) {
+            Some(sender_idx) => sender_idx,
+            None => return ComplaintOutcome::Handled(fault_log),
         };
-        if row.commitment() != commit_row {
-            error!("Invalid part from node {:?}.", sender_id);
-            let fault_log = FaultLog::init(sender_id.clone(), FaultKind::InvalidPartMessage);
-            return Some(PartOutcome::Invalid(fault_log));
+        let num_faulty = self.num_faulty;
+        let proposer_id = match self.pub_keys.keys().nth(proposer_idx as usize) {
+            Some(proposer_id) => proposer_id.clone(),
+            None => {
+                fault_log.append(sender_id.clone(), FaultKind::InvalidComplaint);
+                return ComplaintOutcome::Handled(fault_log);
+            }
+        };
+        {
+            let part = match self.parts.get_mut(&proposer_idx) {
+                Some(part) => part,
+                None => {
+                    fault_log.append(sender_id.clone(), FaultKind::InvalidComplaint);
+                    return ComplaintOutcome::Handled(fault_log);
+                }
+            };
+            if part.acks.contains(&sender_idx) || part.complaints.contains_key(&sender_idx) {
+                // The sender has already acked or complained about this part.
+                fault_log.append(sender_id.clone(), FaultKind::InvalidComplaint);
+                return ComplaintOutcome::Handled(fault_log);
+            }
+            if part.disqualified {
+                return ComplaintOutcome::Handled(fault_log);
+            }
+            part.complaints.insert(sender_idx, false);
+            if part.complaints.len() > num_faulty {
+                // At least one of the complaining nodes is correct.
+                debug!(
+                    "Too many complaints about part from node {:?}.",
+                    proposer_id
+                );
+                part.disqualified = true;
+                fault_log.append(proposer_id, FaultKind::InvalidPartMessage);
+                return ComplaintOutcome::Handled(fault_log);
+            }
+        }
+        if self.our_idx != Some(proposer_idx) {
+            return ComplaintOutcome::Handled(fault_log);
+        }
+        // The complaint is about our own part: Reveal the complaining node's row.
+        let row = match self
+            .our_part
+            .as_ref()
+            .map(|our_part| our_part.row(sender_idx + 1))
+        {
+            Some(Ok(row)) => row,
+            Some(Err(err)) => {
+                error!("Failed to compute row for node {:?}: {:?}", sender_id, err);
+                return ComplaintOutcome::Handled(fault_log);
+            }
+            None => return ComplaintOutcome::Handled(fault_log),
+        };
+        let ser_row = bincode::serialize(&row).expect("failed to serialize row");
+        ComplaintOutcome::Justify(Justification(sender_idx, ser_row))
+    }
+
+    /// Handles a `Justification` message.
+    ///
+    /// If the justification answers our own complaint and the revealed row is valid, the outcome
+    /// contains the `Ack` that we need to multicast now. If the row is invalid, the outcome
+    /// contains the fault, and the proposer is disqualified.
+    ///
+    /// All participating nodes must handle the exact same sequence of messages.
+    /// Note that `handle_justification` also needs to explicitly be called with this instance's
+    /// own `Justification`s.
+    pub fn handle_justification<R: rand::Rng>(
+        &mut self,
+        rng: &mut R,
+        sender_id: &N,
+        Justification(complainer_idx, ser_row): Justification,
+    ) -> Option<PartOutcome<N>> {
+        let sender_idx = self.node_index(sender_id)?;
+        let invalid = || {
+            let fault_log = FaultLog::init(sender_id.clone(), FaultKind::InvalidJustification);
+            Some(PartOutcome::Invalid(fault_log))
+        };
+        let row = {
+            let part = match self.parts.get_mut(&sender_idx) {
+                Some(part) => part,
+                None => return invalid(),
+            };
+            if part.complaints.get(&complainer_idx) != Some(&false) {
+                // Nobody complained, or the row has already been revealed.
+                return invalid();
+            }
+            if part.disqualified {
+                return None;
+            }
+            let opt_row = bincode::deserialize::<Poly>(&ser_row).ok();
+            match opt_row {
+                Some(ref row) if row.commitment() == part.commit.row(complainer_idx + 1) => {
+                    part.complaints.insert(complainer_idx, true);
+                }
+                _ => {
+                    error!("Invalid justification from node {:?}.", sender_id);
+                    part.disqualified = true;
+                    return invalid();
+                }
+            }
+            opt_row?
+        };
+        if self.our_idx != Some(complainer_idx) {
+            return None;
         }
-        // The row is valid: now encrypt one value for each node.
+        Some(PartOutcome::Valid(self.create_ack(rng, sender_idx, &row)))
+    }
+
+    /// Returns an `Ack` for the given proposer's part, with one encrypted value of our row for
+    /// each node.
+    fn create_ack<R: rand::Rng>(&self, rng: &mut R, proposer_idx: u64, row: &Poly) -> Ack {
         let encrypt = |(idx, pk): (usize, &PublicKey)| {
             let val = row.evaluate(idx + 1);
             let wrap = FieldWrap::new(val);
@@ -377,7 +728,7 @@ impl<N: NodeIdT> SyncKeyGen<N> {
             pk.encrypt_with_rng(rng, ser_val)
         };
         let values = self.pub_keys.values().enumerate().map(encrypt).collect();
-        Some(PartOutcome::Valid(Ack(sender_idx, values)))
+        Ack(proposer_idx, values)
     }
 
     /// Handles an `Ack` message.
@@ -400,7 +751,7 @@ impl<N: NodeIdT> SyncKeyGen<N> {
     pub fn count_complete(&self) -> usize {
         self.parts
             .values()
-            .filter(|part| part.is_complete(self.threshold))
+            .filter(|part| part.is_complete(self.threshold, self.num_faulty))
             .count()
     }
 
@@ -408,12 +759,18 @@ impl<N: NodeIdT> SyncKeyGen<N> {
     pub fn is_node_ready(&self, proposer_id: &N) -> bool {
         self.node_index(proposer_id)
             .and_then(|proposer_idx| self.parts.get(&proposer_idx))
-            .map_or(false, |part| part.is_complete(self.threshold))
+            .map_or(false, |part| part.is_complete(self.threshold, self.num_faulty))
     }
 
     /// Returns `true` if enough parts are complete to safely generate the new key.
+    ///
+    /// When resharing, this requires _t' + 1_ complete parts, where _t'_ is the existing key set's
+    /// threshold.
     pub fn is_ready(&self) -> bool {
-        self.count_complete() > self.threshold
+        match self.reshare {
+            None => self.count_complete() > self.threshold,
+            Some(ref reshare) => self.count_complete() > reshare.pk_set.threshold(),
+        }
     }
 
     /// Returns the new secret key share and the public key set.
@@ -426,24 +783,32 @@ impl<N: NodeIdT> SyncKeyGen<N> {
     /// All participating nodes must have handled the exact same sequence of `Part` and `Ack`
     /// messages before calling this method. Otherwise their key shares will not match.
     pub fn generate(&self) -> Result<(PublicKeySet, Option<SecretKeyShare>), Error> {
+        let (pk_set, opt_share) = self.generate_share()?;
+        let opt_sk = match opt_share {
+            Some(share) => Some(share.secret_key_share()?),
+            None => None,
+        };
+        Ok((pk_set, opt_sk))
+    }
+
+    /// Returns the value of the new secret key share, and the public key set. The value can be
+    /// used to reshare the key set later.
+    ///
+    /// The same conditions as for `generate` apply.
+    pub fn generate_share(&self) -> Result<(PublicKeySet, Option<ShareValue>), Error> {
+        if let Some(ref reshare) = self.reshare {
+            return self.generate_reshared(reshare);
+        }
         let mut pk_commit = Poly::zero().map_err(Error::Generation)?.commitment();
         let mut opt_sk_val = self.our_idx.map(|_| Fr::zero());
-        let is_complete = |part: &&ProposalState| part.is_complete(self.threshold);
+        let is_complete = |part: &&ProposalState| part.is_complete(self.threshold, self.num_faulty);
         for part in self.parts.values().filter(is_complete) {
             pk_commit += part.commit.row(0);
             if let Some(sk_val) = opt_sk_val.as_mut() {
-                let row = Poly::interpolate(part.values.iter().take(self.threshold + 1))
-                    .map_err(Error::Generation)?;
-                sk_val.add_assign(&row.evaluate(0));
+                sk_val.add_assign(&self.row_secret(part)?);
             }
         }
-        let opt_sk = if let Some(mut fr) = opt_sk_val {
-            let sk = SecretKeyShare::from_mut_ptr(&mut fr as *mut Fr).map_err(Error::Generation)?;
-            Some(sk)
-        } else {
-            None
-        };
-        Ok((pk_commit.into(), opt_sk))
+        Ok((pk_commit.into(), opt_sk_val.map(ShareValue)))
     }
 
     /// Consumes the instance, generates the key set and returns a new `NetworkInfo` with the new
@@ -452,10 +817,100 @@ impl<N: NodeIdT> SyncKeyGen<N> {
     /// All participating nodes must have handled the exact same sequence of `Part` and `Ack`
     /// messages before calling this method. Otherwise their key shares will not match.
     pub fn into_network_info(self) -> Result<NetworkInfo<N>, Error> {
-        let (pk_set, opt_sk_share) = self.generate()?;
-        let sk_share = opt_sk_share.unwrap_or_default(); // TODO: Make this an option.
+        Ok(self.into_network_info_with_share()?.0)
+    }
+
+    /// Consumes the instance, generates the key set and returns a new `NetworkInfo` with the new
+    /// keys, together with the value of our new secret key share, if any.
+    ///
+    /// The same conditions as for `into_network_info` apply.
+    pub fn into_network_info_with_share(
+        self,
+    ) -> Result<(NetworkInfo<N>, Option<ShareValue>), Error> {
+        let (pk_set, opt_share) = self.generate_share()?;
+        let sk_share = match opt_share {
+            Some(ref share) => share.secret_key_share()?,
+            None => SecretKeyShare::default(), // TODO: Make this an option.
+        };
         let netinfo = NetworkInfo::new(self.our_id, sk_share, pk_set, self.sec_key, self.pub_keys);
-        Ok(netinfo)
+        Ok((netinfo, opt_share))
+    }
+
+    /// Generates the reshared key set: The first _t' + 1_ complete parts are combined with the
+    /// Lagrange coefficients of their dealers' old indices, so that the secret master key remains
+    /// the same.
+    fn generate_reshared(
+        &self,
+        reshare: &Reshare,
+    ) -> Result<(PublicKeySet, Option<ShareValue>), Error> {
+        let (threshold, num_faulty) = (self.threshold, self.num_faulty);
+        let is_complete =
+            |&(_, part): &(&u64, &ProposalState)| part.is_complete(threshold, num_faulty);
+        let dealers: Vec<(u64, &ProposalState)> = self
+            .parts
+            .iter()
+            .filter(is_complete)
+            .take(reshare.pk_set.threshold() + 1)
+            .map(|(idx, part)| (reshare.old_indices[idx] + 1, part))
+            .collect();
+        let xs: Vec<u64> = dealers.iter().map(|&(x, _)| x).collect();
+        let mut pk_commit = Poly::zero().map_err(Error::Generation)?.commitment();
+        let mut opt_sk_val = self.our_idx.map(|_| Fr::zero());
+        for (x, part) in dealers {
+            let coeff = lagrange_coefficient(x, &xs)?;
+            let mut commit = part.commit.row(0);
+            commit += constant_commitment(part.offset)?;
+            pk_commit += mul_commitment(&commit, coeff)?;
+            if let Some(sk_val) = opt_sk_val.as_mut() {
+                let mut val = self.row_secret(part)?;
+                val.add_assign(&part.offset);
+                val.mul_assign(&coeff);
+                sk_val.add_assign(&val);
+            }
+        }
+        Ok((pk_commit.into(), opt_sk_val.map(ShareValue)))
+    }
+
+    /// Returns the value at 0 of our row of the part's polynomial.
+    fn row_secret(&self, part: &ProposalState) -> Result<Fr, Error> {
+        let row = Poly::interpolate(part.values.iter().take(self.threshold + 1))
+            .map_err(Error::Generation)?;
+        Ok(row.evaluate(0))
+    }
+
+    /// Returns the offset of a part with the given commitment: zero, unless we are resharing. In
+    /// that case, returns `None` if the sender had no existing key share, if the commitment to the
+    /// sender's share is not a constant that matches its existing public key share, or if the
+    /// polynomial's constant term plus the offset doesn't match that commitment.
+    fn verify_offset(
+        &self,
+        sender_idx: u64,
+        commit: &BivarCommitment,
+        opt_offset: Option<&(Vec<u8>, Commitment)>,
+    ) -> Option<Fr> {
+        let reshare = match self.reshare {
+            None if opt_offset.is_none() => return Some(Fr::zero()),
+            None => return None,
+            Some(ref reshare) => reshare,
+        };
+        let old_idx = *reshare.old_indices.get(&sender_idx)?;
+        let &(ref ser_offset, ref share_commit) = opt_offset?;
+        // A constant polynomial's value at any index is its value at 0.
+        let share_pk_set = PublicKeySet::from(share_commit.clone());
+        if share_commit.degree() != 0
+            || share_pk_set.public_key_share(old_idx) != reshare.pk_set.public_key_share(old_idx)
+        {
+            return None;
+        }
+        let offset = bincode::deserialize::<FieldWrap<Fr, Fr>>(ser_offset)
+            .ok()?
+            .into_inner();
+        let mut pk_share = commit.evaluate(0u64, 0u64);
+        pk_share.add_assign(&G1Affine::one().mul(offset));
+        if pk_share != share_commit.evaluate(0u64) {
+            return None;
+        }
+        Some(offset)
     }
 
     /// Handles an `Ack` message or returns an error string.
@@ -507,3 +962,202 @@ impl<N: NodeIdT> SyncKeyGen<N> {
         }
     }
 }
+
+/// Returns the commitment to the constant polynomial with the given value.
+fn constant_commitment(value: Fr) -> Result<Commitment, Error> {
+    let poly = Poly::interpolate(once((0u64, value))).map_err(Error::Generation)?;
+    Ok(poly.commitment())
+}
+
+/// Returns the Lagrange coefficient of the sample at `x`, for interpolating the samples at `xs`
+/// at 0.
+fn lagrange_coefficient(x: u64, xs: &[u64]) -> Result<Fr, Error> {
+    let samples = xs.iter().map(|&x_i| {
+        let value = if x_i == x { Fr::one() } else { Fr::zero() };
+        (x_i, value)
+    });
+    let poly = Poly::interpolate(samples).map_err(Error::Generation)?;
+    Ok(poly.evaluate(0))
+}
+
+/// Returns the commitment to the committed polynomial multiplied by `scalar`.
+fn mul_commitment(commit: &Commitment, scalar: Fr) -> Result<Commitment, Error> {
+    let mut result = Poly::zero().map_err(Error::Generation)?.commitment();
+    // Double and add, starting with the most significant bit.
+    for limb in scalar.into_repr().as_ref().iter().rev() {
+        for bit in (0..64).rev() {
+            let double = result.clone();
+            result += double;
+            if (limb >> bit) & 1 == 1 {
+                result += commit.clone();
+            }
+        }
+    }
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+
+    use bincode;
+    use crypto::poly::BivarPoly;
+    use crypto::{PublicKey, SecretKey};
+    use rand;
+
+    use super::{Ack, ComplaintOutcome, Part, PartOutcome, SyncKeyGen};
+    use fault_log::FaultKind;
+
+    /// Returns `node_num` `SyncKeyGen` instances, and their parts. The part of node 0 contains
+    /// invalid rows for the nodes in `bad_rows`.
+    fn setup(node_num: usize, bad_rows: &[usize]) -> (Vec<SyncKeyGen<usize>>, Vec<Part>) {
+        let mut rng = rand::thread_rng();
+        let threshold = (node_num - 1) / 3;
+        let sec_keys: Vec<SecretKey> = (0..node_num).map(|_| SecretKey::random()).collect();
+        let pub_keys: BTreeMap<usize, PublicKey> = sec_keys
+            .iter()
+            .map(SecretKey::public_key)
+            .enumerate()
+            .collect();
+        let (nodes, mut parts): (Vec<_>, Vec<_>) = sec_keys
+            .into_iter()
+            .enumerate()
+            .map(|(id, sk)| {
+                let (key_gen, part) =
+                    SyncKeyGen::new(&mut rng, id, sk, pub_keys.clone(), threshold)
+                        .expect("new SyncKeyGen instance");
+                (key_gen, part.expect("part"))
+            }).unzip();
+        // Replace some of node 0's rows with rows of a different polynomial.
+        let other_poly = BivarPoly::random(threshold, &mut rng).expect("random polynomial");
+        for &idx in bad_rows {
+            let row = other_poly.row(idx + 1).expect("row");
+            let bytes = bincode::serialize(&row).expect("serialize row");
+            parts[0].1[idx] = pub_keys[&idx].encrypt_with_rng(&mut rng, &bytes);
+        }
+        (nodes, parts)
+    }
+
+    /// Handles the part in all nodes, and returns the acks and the complaining nodes.
+    fn handle_part(
+        nodes: &mut [SyncKeyGen<usize>],
+        sender_id: usize,
+        part: &Part,
+    ) -> (Vec<(usize, Ack)>, Vec<usize>) {
+        let mut acks = Vec::new();
+        let mut complainers = Vec::new();
+        for (id, node) in nodes.iter_mut().enumerate() {
+            match node.handle_part(&mut rand::thread_rng(), &sender_id, part.clone()) {
+                Some(PartOutcome::Valid(ack)) => acks.push((id, ack)),
+                Some(PartOutcome::Complaint(complaint, faults)) => {
+                    assert_eq!(complaint.0, sender_id as u64);
+                    assert_eq!(faults.0[0].kind, FaultKind::InvalidPartMessage);
+                    complainers.push(id);
+                }
+                _ => panic!("unexpected part outcome"),
+            }
+        }
+        (acks, complainers)
+    }
+
+    /// Handles the acks in all nodes.
+    fn handle_acks(nodes: &mut [SyncKeyGen<usize>], acks: Vec<(usize, Ack)>) {
+        for (sender_id, ack) in acks {
+            for node in nodes.iter_mut() {
+                assert!(node.handle_ack(&sender_id, ack.clone()).is_empty());
+            }
+        }
+    }
+
+    #[test]
+    fn test_justified_complaint() {
+        let (mut nodes, parts) = setup(4, &[1]);
+        let mut acks = Vec::new();
+        let mut complainers = Vec::new();
+        for (sender_id, part) in parts.iter().enumerate() {
+            let (part_acks, part_complainers) = handle_part(&mut nodes, sender_id, part);
+            acks.extend(part_acks);
+            complainers.extend(part_complainers);
+        }
+        assert_eq!(vec![1], complainers);
+        handle_acks(&mut nodes, acks);
+
+        // Node 1 complains about node 0's part, and node 0 reveals the row.
+        let mut justifications = Vec::new();
+        for (id, node) in nodes.iter_mut().enumerate() {
+            let complaint = super::Complaint(0);
+            match node.handle_complaint(&1, complaint) {
+                ComplaintOutcome::Justify(justification) => justifications.push(justification),
+                ComplaintOutcome::Handled(faults) => assert!(id != 0 && faults.is_empty()),
+            }
+            // The part cannot be complete before the complaint is answered.
+            assert!(!node.is_node_ready(&0));
+            assert!(node.is_ready());
+        }
+        assert_eq!(1, justifications.len());
+
+        // The row is valid, so node 1 can now acknowledge the part.
+        let mut acks = Vec::new();
+        for (id, node) in nodes.iter_mut().enumerate() {
+            let justification = justifications[0].clone();
+            match node.handle_justification(&mut rand::thread_rng(), &0, justification) {
+                Some(PartOutcome::Valid(ack)) => acks.push((id, ack)),
+                None => assert_ne!(1, id),
+                _ => panic!("unexpected justification outcome"),
+            }
+        }
+        assert_eq!(1, acks.len());
+        handle_acks(&mut nodes, acks);
+
+        // All parts are complete, and all nodes agree on the keys.
+        let pk_set = nodes[0].generate().expect("generate keys").0;
+        let msg = "All rows are valid now";
+        let mut sig_shares = BTreeMap::new();
+        for (id, node) in nodes.iter().enumerate() {
+            assert_eq!(4, node.count_complete());
+            let (node_pk_set, opt_sk) = node.generate().expect("generate keys");
+            assert_eq!(pk_set, node_pk_set);
+            let sig_share = opt_sk.expect("secret key share").sign(msg);
+            assert!(pk_set.public_key_share(id).verify(&sig_share, msg));
+            sig_shares.insert(id, sig_share);
+        }
+        let sig = pk_set
+            .combine_signatures(sig_shares.iter().take(2))
+            .expect("combine signatures");
+        assert!(pk_set.public_key().verify(&sig, msg));
+    }
+
+    #[test]
+    fn test_too_many_complaints() {
+        let (mut nodes, parts) = setup(4, &[1, 2]);
+        let (acks, complainers) = handle_part(&mut nodes, 0, &parts[0]);
+        assert_eq!(vec![1, 2], complainers);
+        handle_acks(&mut nodes, acks);
+
+        // After the second complaint, node 0 is disqualified and doesn't need to justify.
+        for node in &mut nodes {
+            match node.handle_complaint(&1, super::Complaint(0)) {
+                ComplaintOutcome::Justify(_) => (),
+                ComplaintOutcome::Handled(faults) => assert!(faults.is_empty()),
+            }
+            match node.handle_complaint(&2, super::Complaint(0)) {
+                ComplaintOutcome::Handled(faults) => {
+                    assert_eq!(faults.0[0].node_id, 0);
+                    assert_eq!(faults.0[0].kind, FaultKind::InvalidPartMessage);
+                }
+                ComplaintOutcome::Justify(_) => panic!("disqualified part justified"),
+            }
+        }
+
+        // The other parts complete without node 0.
+        for (sender_id, part) in parts.iter().enumerate().skip(1) {
+            let (acks, complainers) = handle_part(&mut nodes, sender_id, part);
+            assert!(complainers.is_empty());
+            handle_acks(&mut nodes, acks);
+        }
+        for node in &nodes {
+            assert!(!node.is_node_ready(&0));
+            assert_eq!(3, node.count_complete());
+        }
+    }
+}
@@ -9,6 +9,9 @@
 //! key can be collaboratively decrypted by combining at least _f + 1_ decryption shares. Each
 //! validator holds a secret key share, and uses it to produce and multicast a decryption share.
 //! The algorithm outputs as soon as _f + 1_ of them have been received.
+//!
+//! With weighted validators, each node produces one decryption share per key share it holds, and
+//! sends them in a single message.
 
 use std::collections::BTreeMap;
 use std::sync::Arc;
@@ -17,6 +20,7 @@ use crypto::error as cerror;
 use crypto::{Ciphertext, DecryptionShare};
 use fault_log::{Fault, FaultKind, FaultLog};
 use messaging::{self, DistAlgorithm, NetworkInfo, Target};
+use rand;
 use traits::NodeIdT;
 
 /// A threshold decryption error.
@@ -35,9 +39,18 @@ pub enum Error {
 /// A threshold decryption result.
 pub type Result<T> = ::std::result::Result<T, Error>;
 
-/// A Threshold Decryption message.
-#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Rand)]
-pub struct Message(pub DecryptionShare);
+/// A Threshold Decryption message, containing the sender's decryption shares: one per unit of its
+/// weight.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Message(pub Vec<DecryptionShare>);
+
+// A random generation impl is provided for test cases. Unfortunately `#[cfg(test)]` does not work
+// for integration tests.
+impl rand::Rand for Message {
+    fn rand<R: rand::Rng>(rng: &mut R) -> Self {
+        Message(vec![rng.gen()])
+    }
+}
 
 /// A Threshold Decryption algorithm instance. If every node inputs the same data, encrypted to the
 /// network's public key, every node will output the decrypted data.
@@ -46,8 +59,8 @@ pub struct ThresholdDecryption<N> {
     netinfo: Arc<NetworkInfo<N>>,
     /// The encrypted data.
     ciphertext: Option<Ciphertext>,
-    /// All received threshold decryption shares.
-    shares: BTreeMap<N, DecryptionShare>,
+    /// All received threshold decryption shares, by sender.
+    shares: BTreeMap<N, Vec<DecryptionShare>>,
     /// Whether we have already returned the output.
     terminated: bool,
 }
@@ -95,18 +108,24 @@ impl<N: NodeIdT> ThresholdDecryption<N> {
         if self.ciphertext.is_some() {
             return Err(Error::MultipleInputs(Box::new(ct)));
         }
-        let share = match self.netinfo.secret_key_share().decrypt_share(&ct) {
+        let opt_shares: Option<Vec<DecryptionShare>> = self
+            .netinfo
+            .secret_key_shares()
+            .iter()
+            .map(|sk_share| sk_share.decrypt_share(&ct))
+            .collect();
+        let shares = match opt_shares {
             None => return Err(Error::InvalidCiphertext(Box::new(ct))),
-            Some(share) => share,
+            Some(shares) => shares,
         };
         self.ciphertext = Some(ct);
         let our_id = self.our_id().clone();
         let mut step = Step::default();
         step.fault_log.extend(self.remove_invalid_shares());
         if self.netinfo.is_validator() {
-            let msg = Target::All.message(Message(share.clone()));
+            let msg = Target::All.message(Message(shares.clone()));
             step.messages.push_back(msg);
-            self.shares.insert(our_id, share);
+            self.shares.insert(our_id, shares);
         }
         step.extend(self.try_output()?);
         Ok(step)
@@ -121,12 +140,12 @@ impl<N: NodeIdT> ThresholdDecryption<N> {
         if self.terminated {
             return Ok(Step::default()); // Don't waste time on redundant shares.
         }
-        let Message(share) = message;
-        if !self.is_share_valid(sender_id, &share) {
+        let Message(shares) = message;
+        if !self.are_shares_valid(sender_id, &shares) {
             let fault_kind = FaultKind::UnverifiedDecryptionShareSender;
             return Ok(Fault::new(sender_id.clone(), fault_kind).into());
         }
-        if self.shares.insert(sender_id.clone(), share).is_some() {
+        if self.shares.insert(sender_id.clone(), shares).is_some() {
             return Ok(Fault::new(sender_id.clone(), FaultKind::MultipleDecryptionShares).into());
         }
         self.try_output()
@@ -137,7 +156,7 @@ impl<N: NodeIdT> ThresholdDecryption<N> {
         let faulty_senders: Vec<N> = self
             .shares
             .iter()
-            .filter(|(id, share)| !self.is_share_valid(id, share))
+            .filter(|(id, shares)| !self.are_shares_valid(id, shares))
             .map(|(id, _)| id.clone())
             .collect();
         let mut fault_log = FaultLog::default();
@@ -148,21 +167,35 @@ impl<N: NodeIdT> ThresholdDecryption<N> {
         fault_log
     }
 
-    /// Returns `true` if the share is valid, or if we don't have the ciphertext yet.
-    fn is_share_valid(&self, id: &N, share: &DecryptionShare) -> bool {
+    /// Returns `true` if the sender's shares are valid, or if we don't have the ciphertext yet.
+    fn are_shares_valid(&self, id: &N, shares: &[DecryptionShare]) -> bool {
+        let indices = match self.netinfo.share_indices(id) {
+            None => return false, // Unknown sender.
+            Some(indices) => indices,
+        };
+        if indices.len() != shares.len() {
+            return false;
+        }
         let ct = match self.ciphertext {
             None => return true, // No ciphertext yet. Verification postponed.
             Some(ref ct) => ct,
         };
-        match self.netinfo.public_key_share(id) {
-            None => false, // Unknown sender.
-            Some(pk) => pk.verify_decryption_share(share, ct),
-        }
+        let pk_set = self.netinfo.public_key_set();
+        indices.zip(shares).all(|(i, share)| {
+            pk_set
+                .public_key_share(i)
+                .verify_decryption_share(share, ct)
+        })
+    }
+
+    /// Returns the number of shares we have received, i.e. the total weight of their senders.
+    fn num_shares(&self) -> usize {
+        self.netinfo.weight_of(self.shares.keys()) as usize
     }
 
     /// Outputs the decrypted message, if we have the ciphertext and enough shares.
     fn try_output(&mut self) -> Result<Step<N>> {
-        if self.terminated || self.shares.len() <= self.netinfo.num_faulty() {
+        if self.terminated || self.num_shares() <= self.netinfo.threshold() {
             return Ok(Step::default()); // Not enough shares yet, or already terminated.
         }
         let ct = match self.ciphertext {
@@ -171,14 +204,14 @@ impl<N: NodeIdT> ThresholdDecryption<N> {
         };
         self.terminated = true;
         let plaintext = {
-            let to_idx = |(id, share)| {
-                let idx = self
-                    .netinfo
-                    .node_index(id)
+            let netinfo = &self.netinfo;
+            let to_indexed = |(id, shares): (&N, &Vec<DecryptionShare>)| {
+                let indices = netinfo
+                    .share_indices(id)
                     .expect("we put only validators' shares in the map; qed");
-                (idx, share)
+                indices.zip(shares)
             };
-            let share_itr = self.shares.iter().map(to_idx);
+            let share_itr = self.shares.iter().flat_map(to_indexed);
             self.netinfo
                 .public_key_set()
                 .decrypt(share_itr, ct)
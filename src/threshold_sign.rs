@@ -0,0 +1,231 @@
+//! # Collaborative Threshold Signing
+//!
+//! The algorithm is instantiated with a document to sign, e.g. a nonce. Each node inputs `()` to
+//! initiate signing, and once _f + 1_ nodes have input, all nodes receive the same signature of
+//! the document by the public master key.
+//!
+//! The signature is unique: For each public key and document, there is exactly one valid
+//! signature. It cannot be computed by the adversary before at least one correct node has
+//! provided input. This makes it suitable as a source of shared pseudorandomness: e.g. `Coin`
+//! outputs the signature's parity.
+//!
+//! ## How it works
+//!
+//! * On input, a node signs the document with its secret key share and sends its signature share
+//! to everyone else.
+//! * When a node has received _f + 1_ valid shares, it combines them into the main signature and
+//! outputs it.
+//!
+//! With weighted validators, each node signs with all of its key shares, and sends them in a
+//! single message.
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crypto::error as cerror;
+use crypto::{SecretKeyShare, Signature, SignatureShare};
+use fault_log::{Fault, FaultKind};
+use messaging::{self, DistAlgorithm, NetworkInfo, Target};
+use rand;
+use traits::NodeIdT;
+
+/// A threshold signing error.
+#[derive(Clone, Eq, PartialEq, Debug, Fail)]
+pub enum Error {
+    #[fail(display = "CombineAndVerifySigCrypto error: {}", _0)]
+    CombineAndVerifySigCrypto(cerror::Error),
+    #[fail(display = "Unknown sender")]
+    UnknownSender,
+    #[fail(display = "Signature verification failed")]
+    VerificationFailed,
+}
+
+/// A threshold signing result.
+pub type Result<T> = ::std::result::Result<T, Error>;
+
+/// A message containing the sender's signature shares: one per unit of its weight.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Message(pub Vec<SignatureShare>);
+
+// A random generation impl is provided for test cases. Unfortunately `#[cfg(test)]` does not work
+// for integration tests.
+impl rand::Rand for Message {
+    fn rand<R: rand::Rng>(rng: &mut R) -> Self {
+        Message(vec![rng.gen()])
+    }
+}
+
+/// A threshold signing algorithm instance. On input, broadcasts our threshold signature share.
+/// Upon receiving at least `threshold + 1` shares, attempts to combine them into a signature. If
+/// that signature is valid, the instance outputs it and terminates; otherwise the instance aborts.
+#[derive(Debug)]
+pub struct ThresholdSign<N, T> {
+    netinfo: Arc<NetworkInfo<N>>,
+    /// The document to be signed.
+    doc: T,
+    /// All received threshold signature shares, by sender.
+    received_shares: BTreeMap<N, Vec<SignatureShare>>,
+    /// Whether we provided input to the algorithm.
+    had_input: bool,
+    /// Termination flag.
+    terminated: bool,
+}
+
+pub type Step<N, T> = messaging::Step<ThresholdSign<N, T>>;
+
+impl<N, T> DistAlgorithm for ThresholdSign<N, T>
+where
+    N: NodeIdT,
+    T: Clone + AsRef<[u8]> + Send + Sync,
+{
+    type NodeId = N;
+    type Input = ();
+    type Output = Signature;
+    type Message = Message;
+    type Error = Error;
+
+    /// Sends our threshold signature share if not yet sent.
+    fn handle_input(&mut self, _input: Self::Input) -> Result<Step<N, T>> {
+        if !self.had_input {
+            self.had_input = true;
+            self.sign()
+        } else {
+            Ok(Step::default())
+        }
+    }
+
+    /// Receives input from a remote node.
+    fn handle_message(
+        &mut self,
+        sender_id: &Self::NodeId,
+        message: Self::Message,
+    ) -> Result<Step<N, T>> {
+        if !self.terminated {
+            let Message(shares) = message;
+            self.handle_shares(sender_id, shares)
+        } else {
+            Ok(Step::default())
+        }
+    }
+
+    /// Whether the algorithm has terminated.
+    fn terminated(&self) -> bool {
+        self.terminated
+    }
+
+    fn our_id(&self) -> &Self::NodeId {
+        self.netinfo.our_id()
+    }
+}
+
+impl<N, T> ThresholdSign<N, T>
+where
+    N: NodeIdT,
+    T: Clone + AsRef<[u8]> + Send + Sync,
+{
+    pub fn new(netinfo: Arc<NetworkInfo<N>>, doc: T) -> Self {
+        ThresholdSign {
+            netinfo,
+            doc,
+            received_shares: BTreeMap::new(),
+            had_input: false,
+            terminated: false,
+        }
+    }
+
+    /// Returns the document to be signed.
+    pub fn doc(&self) -> &T {
+        &self.doc
+    }
+
+    fn sign(&mut self) -> Result<Step<N, T>> {
+        if !self.netinfo.is_validator() {
+            return self.try_output();
+        }
+        let shares: Vec<SignatureShare> = {
+            let sign = |sk_share: &SecretKeyShare| sk_share.sign(&self.doc);
+            self.netinfo.secret_key_shares().iter().map(sign).collect()
+        };
+        let mut step: Step<_, _> = Target::All.message(Message(shares.clone())).into();
+        let id = self.netinfo.our_id().clone();
+        step.extend(self.handle_shares(&id, shares)?);
+        Ok(step)
+    }
+
+    fn handle_shares(&mut self, sender_id: &N, shares: Vec<SignatureShare>) -> Result<Step<N, T>> {
+        let indices = self
+            .netinfo
+            .share_indices(sender_id)
+            .ok_or(Error::UnknownSender)?;
+        let is_valid = {
+            let pk_set = self.netinfo.public_key_set();
+            let doc = &self.doc;
+            let verify = |(i, share): (usize, &SignatureShare)| {
+                pk_set.public_key_share(i).verify(share, doc)
+            };
+            indices.len() == shares.len() && indices.zip(&shares).all(verify)
+        };
+        if !is_valid {
+            // Log the faulty node and ignore the invalid shares.
+            let fault_kind = FaultKind::UnverifiedSignatureShareSender;
+            return Ok(Fault::new(sender_id.clone(), fault_kind).into());
+        }
+        self.received_shares.insert(sender_id.clone(), shares);
+        self.try_output()
+    }
+
+    /// Returns the number of valid shares we have received, i.e. the total weight of their senders.
+    fn num_received_shares(&self) -> usize {
+        self.netinfo.weight_of(self.received_shares.keys()) as usize
+    }
+
+    fn try_output(&mut self) -> Result<Step<N, T>> {
+        debug!(
+            "{:?} received {} shares, had_input = {}",
+            self.netinfo.our_id(),
+            self.num_received_shares(),
+            self.had_input
+        );
+        if self.had_input && self.num_received_shares() > self.netinfo.threshold() {
+            let sig = self.combine_and_verify_sig()?;
+            debug!("{:?} output {:?}", self.netinfo.our_id(), sig);
+            self.terminated = true;
+            let step = self.handle_input(())?; // Before terminating, make sure we sent our share.
+            Ok(step.with_output(sig))
+        } else {
+            Ok(Step::default())
+        }
+    }
+
+    fn combine_and_verify_sig(&self) -> Result<Signature> {
+        // Pass the indices of the shares to `combine_signatures`.
+        let netinfo = &self.netinfo;
+        let to_indexed = |(id, shares): (&N, &Vec<SignatureShare>)| {
+            let indices = netinfo
+                .share_indices(id)
+                .expect("shares are from validators");
+            indices.zip(shares)
+        };
+        let shares = self.received_shares.iter().flat_map(to_indexed);
+        let sig = self
+            .netinfo
+            .public_key_set()
+            .combine_signatures(shares)
+            .map_err(Error::CombineAndVerifySigCrypto)?;
+        if !self
+            .netinfo
+            .public_key_set()
+            .public_key()
+            .verify(&sig, &self.doc)
+        {
+            // Abort
+            error!(
+                "{:?} main public key verification failed",
+                self.netinfo.our_id()
+            );
+            Err(Error::VerificationFailed)
+        } else {
+            Ok(sig)
+        }
+    }
+}
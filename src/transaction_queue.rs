@@ -1,35 +1,408 @@
-use std::cmp;
-use std::collections::{HashSet, VecDeque};
+//! # Transaction queues
+//!
+//! `QueueingHoneyBadger` keeps the pending transactions in a queue that implements the
+//! `TransactionQueue` trait. It only adds transactions, picks the ones to propose, and removes the
+//! ones that were output in a batch, so the queue can use any ordering or storage: e.g. a mempool
+//! sorted by fees, a queue ordered by per-sender nonces, or one backed by a database.
+//!
+//! The default implementation for `VecDeque` is a FIFO queue that randomly chooses its proposals
+//! from the oldest transactions. A `PriorityQueue` instead chooses them from the transactions with
+//! the highest priority, e.g. the highest fees, as defined by a `TransactionOrdering`.
+//!
+//! Besides the number of transactions, a proposal can be limited in size: If a maximum size is
+//! given, the serialized list of transactions must not be larger than that many bytes. Queues can
+//! use `limit_size` to drop the chosen transactions that don't fit.
+//!
+//! ## Migrating from the `TransactionQueue` struct
+//!
+//! `TransactionQueue` used to be a struct wrapping a `VecDeque`. It is now a trait, which is a
+//! breaking change: Instead of `TransactionQueue(queue)`, use the `VecDeque` itself, and replace
+//! `queue.0` with `queue`. To call `choose` and `remove_all`, import the trait; `choose` takes an
+//! additional `max_size` argument, which can be `None`.
 
+use std::cmp::{self, Reverse};
+use std::collections::{BTreeMap, BTreeSet, HashSet, VecDeque};
+use std::fmt::Debug;
+
+use bincode;
 use rand;
+use serde::Serialize;
 
 use traits::Contribution;
 
-/// A wrapper providing a few convenience methods for a queue of pending transactions.
-#[derive(Debug)]
-pub struct TransactionQueue<T>(pub VecDeque<T>);
+/// A queue of pending transactions, from which contributions are chosen.
+pub trait TransactionQueue<T>: Debug + Default + Extend<T> + Send + Sync {
+    /// Returns the number of transactions in the queue.
+    fn len(&self) -> usize;
 
-impl<T: Clone> TransactionQueue<T> {
-    /// Removes the given transactions from the queue.
-    pub fn remove_all<'a, I>(&mut self, txs: I)
+    /// Returns `true` if the queue contains no transactions.
+    fn is_empty(&self) -> bool;
+
+    /// Returns a new set of at most `amount` transactions to propose. `batch_size` is the target
+    /// number of transactions in a batch, i.e. roughly `amount` times the number of validators.
+    /// If `max_size` is given, the serialized set must not be larger than that many bytes.
+    /// No transactions are removed from the queue.
+    fn choose(&self, amount: usize, batch_size: usize, max_size: Option<u64>) -> Vec<T>;
+
+    /// Adds a transaction to the queue, and returns the transaction that was dropped as a result,
+    /// if any, e.g. because it is a duplicate or the queue is full.
+    fn push(&mut self, tx: T) -> Option<T>;
+
+    /// Removes the given transactions from the queue, together with any queued duplicates of them,
+    /// and returns the removed ones.
+    fn remove_all<'a, I>(&mut self, txs: I) -> Vec<T>
     where
         I: IntoIterator<Item = &'a T>,
-        T: 'a + Contribution,
-    {
-        let tx_set: HashSet<_> = txs.into_iter().collect();
-        self.0.retain(|tx| !tx_set.contains(tx));
+        T: 'a;
+
+    /// Removes the transactions that satisfy the predicate from the queue, and returns them.
+    fn remove_where<F>(&mut self, f: F) -> Vec<T>
+    where
+        F: FnMut(&T) -> bool;
+}
+
+/// The number of bytes in which a serialized contribution encodes its number of transactions.
+const CONTRIBUTION_LEN_SIZE: u64 = 8;
+
+/// Returns the serialized size of a list of the given transactions, in bytes. This is only the
+/// user payload: The contribution `DynamicHoneyBadger` proposes also contains key generation
+/// messages and votes, and `HoneyBadger` encrypts it, so the messages that are sent are larger.
+pub fn contribution_size<'a, T, I>(txs: I) -> bincode::Result<u64>
+where
+    T: Serialize + 'a,
+    I: IntoIterator<Item = &'a T>,
+{
+    let mut size = CONTRIBUTION_LEN_SIZE;
+    for tx in txs {
+        size += bincode::serialized_size(tx)?;
     }
+    Ok(size)
+}
 
-    /// Returns a new set of `amount` transactions, randomly chosen from the first `batch_size`.
-    /// No transactions are removed from the queue.
+/// Returns the given transactions in the same order, except for the ones that would make the
+/// serialized contribution larger than `max_size` bytes, if it is given. Each transaction is
+/// included if it still fits, even if an earlier one didn't. Transactions that cannot be
+/// serialized are dropped.
+pub fn limit_size<T, I>(txs: I, max_size: Option<u64>) -> Vec<T>
+where
+    T: Serialize,
+    I: IntoIterator<Item = T>,
+{
+    let max_size = match max_size {
+        None => return txs.into_iter().collect(),
+        Some(max_size) => max_size,
+    };
+    let mut size = CONTRIBUTION_LEN_SIZE;
+    txs.into_iter()
+        .filter(|tx| match bincode::serialized_size(tx) {
+            Ok(tx_size) if size + tx_size <= max_size => {
+                size += tx_size;
+                true
+            }
+            _ => false,
+        }).collect()
+}
+
+impl<T> TransactionQueue<T> for VecDeque<T>
+where
+    T: Contribution + Serialize + Clone,
+{
+    fn len(&self) -> usize {
+        VecDeque::len(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        VecDeque::is_empty(self)
+    }
+
+    /// Returns a new set of `amount` transactions, randomly chosen from the first `batch_size`,
+    /// without the ones that exceed the size limit. No transactions are removed from the queue.
     // TODO: Return references, once the `HoneyBadger` API accepts them. Remove `Clone` bound.
-    pub fn choose(&self, amount: usize, batch_size: usize) -> Vec<T> {
+    fn choose(&self, amount: usize, batch_size: usize, max_size: Option<u64>) -> Vec<T> {
         let mut rng = rand::thread_rng();
-        let limit = cmp::min(batch_size, self.0.len());
-        let sample = match rand::seq::sample_iter(&mut rng, self.0.iter().take(limit), amount) {
+        let limit = cmp::min(batch_size, self.len());
+        let sample = match rand::seq::sample_iter(&mut rng, self.iter().take(limit), amount) {
             Ok(choice) => choice,
             Err(choice) => choice, // Fewer than `amount` were available, which is fine.
         };
-        sample.into_iter().cloned().collect()
+        limit_size(sample, max_size).into_iter().cloned().collect()
+    }
+
+    fn push(&mut self, tx: T) -> Option<T> {
+        self.push_back(tx);
+        None
+    }
+
+    fn remove_all<'a, I>(&mut self, txs: I) -> Vec<T>
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: 'a,
+    {
+        let tx_set: HashSet<_> = txs.into_iter().collect();
+        self.remove_where(|tx| tx_set.contains(tx))
+    }
+
+    fn remove_where<F>(&mut self, mut f: F) -> Vec<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let (removed, kept): (VecDeque<T>, VecDeque<T>) = self.drain(..).partition(|tx| f(tx));
+        *self = kept;
+        removed.into_iter().collect()
+    }
+}
+
+/// Determines the priority of the transactions in a `PriorityQueue`, and which of them are
+/// duplicates.
+pub trait TransactionOrdering<T>: Debug + Default + Send + Sync {
+    /// A key identifying a transaction: Two transactions with the same key are duplicates, and
+    /// only one of them can be committed, e.g. because they have the same sender and nonce.
+    type Key: Ord + Clone + Debug + Send + Sync;
+    /// The priority of a transaction, e.g. its fee. Higher values are proposed first.
+    type Priority: Ord + Clone + Debug + Send + Sync;
+
+    /// Returns the transaction's key.
+    fn key(&self, tx: &T) -> Self::Key;
+
+    /// Returns the transaction's priority.
+    fn priority(&self, tx: &T) -> Self::Priority;
+}
+
+/// A transaction queue that proposes the transactions with the highest priority first.
+///
+/// Like the default queue, it chooses each proposal randomly from the first `batch_size`
+/// candidates, so that different validators are unlikely to propose the same transactions.
+///
+/// Of several transactions with the same key, only the one with the highest priority is kept. If a
+/// capacity is set, the transactions with the lowest priority are evicted when it is exceeded.
+#[derive(Debug)]
+pub struct PriorityQueue<T, O: TransactionOrdering<T>> {
+    /// The rule that determines the transactions' keys and priorities.
+    ordering: O,
+    /// The maximum number of queued transactions, if any.
+    capacity: Option<usize>,
+    /// The queued transactions with their priorities, by key.
+    txs: BTreeMap<O::Key, (O::Priority, T)>,
+    /// The priorities and keys of all queued transactions, in ascending order.
+    ranking: BTreeSet<(O::Priority, O::Key)>,
+}
+
+impl<T, O: TransactionOrdering<T>> Default for PriorityQueue<T, O> {
+    fn default() -> Self {
+        PriorityQueue::new(O::default())
+    }
+}
+
+impl<T, O: TransactionOrdering<T>> PriorityQueue<T, O> {
+    /// Returns a new, empty queue without a capacity limit.
+    pub fn new(ordering: O) -> Self {
+        PriorityQueue {
+            ordering,
+            capacity: None,
+            txs: BTreeMap::new(),
+            ranking: BTreeSet::new(),
+        }
+    }
+
+    /// Sets the maximum number of queued transactions.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// Returns the number of queued transactions.
+    pub fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    /// Returns `true` if the queue contains no transactions.
+    pub fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    /// Returns an iterator over the queued transactions, with the highest priority first.
+    pub fn iter<'a>(&'a self) -> impl Iterator<Item = &'a T> {
+        self.ranking
+            .iter()
+            .rev()
+            .map(move |&(_, ref key)| &self.txs[key].1)
+    }
+
+    /// Inserts a transaction into the queue, and returns the transaction that was dropped as a
+    /// result, if any: the given one, if it is a duplicate with a lower priority than the queued
+    /// one or has the lowest priority in a full queue, or else the queued duplicate it replaces or
+    /// the evicted one with the lowest priority.
+    pub fn insert(&mut self, tx: T) -> Option<T> {
+        let key = self.ordering.key(&tx);
+        let priority = self.ordering.priority(&tx);
+        if let Some(&(ref queued_priority, _)) = self.txs.get(&key) {
+            if *queued_priority >= priority {
+                return Some(tx);
+            }
+        }
+        let replaced = self.remove_key(&key);
+        self.ranking.insert((priority.clone(), key.clone()));
+        self.txs.insert(key, (priority, tx));
+        if replaced.is_some() {
+            return replaced;
+        }
+        if self
+            .capacity
+            .map_or(false, |capacity| self.txs.len() > capacity)
+        {
+            let lowest_key = self
+                .ranking
+                .iter()
+                .next()
+                .map(|&(_, ref key)| key.clone())?;
+            return self.remove_key(&lowest_key);
+        }
+        None
+    }
+
+    /// Removes the transaction with the given key, and returns it.
+    fn remove_key(&mut self, key: &O::Key) -> Option<T> {
+        let (priority, tx) = self.txs.remove(key)?;
+        self.ranking.remove(&(priority, key.clone()));
+        Some(tx)
+    }
+}
+
+impl<T, O: TransactionOrdering<T>> Extend<T> for PriorityQueue<T, O> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, txs: I) {
+        for tx in txs {
+            self.insert(tx);
+        }
+    }
+}
+
+impl<T, O> TransactionQueue<T> for PriorityQueue<T, O>
+where
+    T: Contribution + Serialize + Clone,
+    O: TransactionOrdering<T>,
+{
+    fn len(&self) -> usize {
+        self.txs.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.txs.is_empty()
+    }
+
+    /// Returns a new set of `amount` transactions, randomly chosen from the `batch_size` ones with
+    /// the highest priority, and ordered by priority. If the size limit is exceeded, the ones with
+    /// the lowest priority are dropped. No transactions are removed from the queue.
+    fn choose(&self, amount: usize, batch_size: usize, max_size: Option<u64>) -> Vec<T> {
+        let mut rng = rand::thread_rng();
+        let mut sample =
+            match rand::seq::sample_iter(&mut rng, self.iter().take(batch_size), amount) {
+                Ok(choice) => choice,
+                Err(choice) => choice, // Fewer than `amount` were available, which is fine.
+            };
+        sample.sort_by_key(|tx| Reverse(self.ordering.priority(tx)));
+        limit_size(sample, max_size).into_iter().cloned().collect()
+    }
+
+    fn push(&mut self, tx: T) -> Option<T> {
+        self.insert(tx)
+    }
+
+    /// Removes the given transactions, and any queued duplicates of them, i.e. transactions with
+    /// the same key.
+    fn remove_all<'a, I>(&mut self, txs: I) -> Vec<T>
+    where
+        I: IntoIterator<Item = &'a T>,
+        T: 'a,
+    {
+        let keys: Vec<O::Key> = txs.into_iter().map(|tx| self.ordering.key(tx)).collect();
+        keys.iter().filter_map(|key| self.remove_key(key)).collect()
+    }
+
+    fn remove_where<F>(&mut self, mut f: F) -> Vec<T>
+    where
+        F: FnMut(&T) -> bool,
+    {
+        let keys: Vec<O::Key> = self
+            .txs
+            .iter()
+            .filter(|&(_, &(_, ref tx))| f(tx))
+            .map(|(key, _)| key.clone())
+            .collect();
+        keys.iter().filter_map(|key| self.remove_key(key)).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{contribution_size, PriorityQueue, TransactionOrdering, TransactionQueue};
+
+    /// A transaction with a fee. Only one transaction per sender and nonce can be committed.
+    #[derive(Clone, Debug, Eq, PartialEq, Hash, Serialize)]
+    struct Tx {
+        sender: u8,
+        nonce: u8,
+        fee: u64,
+    }
+
+    fn tx(sender: u8, nonce: u8, fee: u64) -> Tx {
+        Tx { sender, nonce, fee }
+    }
+
+    /// Orders transactions by fee, and identifies them by sender and nonce.
+    #[derive(Debug, Default)]
+    struct ByFee;
+
+    impl TransactionOrdering<Tx> for ByFee {
+        type Key = (u8, u8);
+        type Priority = u64;
+
+        fn key(&self, tx: &Tx) -> (u8, u8) {
+            (tx.sender, tx.nonce)
+        }
+
+        fn priority(&self, tx: &Tx) -> u64 {
+            tx.fee
+        }
+    }
+
+    #[test]
+    fn test_priority_queue() {
+        let mut queue = PriorityQueue::new(ByFee).capacity(3);
+        assert_eq!(None, queue.insert(tx(0, 0, 5)));
+        assert_eq!(None, queue.insert(tx(1, 0, 7)));
+        assert_eq!(None, queue.insert(tx(2, 0, 1)));
+
+        // A duplicate with a lower fee is rejected, and one with a higher fee replaces the old one.
+        assert_eq!(Some(tx(0, 0, 4)), queue.insert(tx(0, 0, 4)));
+        assert_eq!(Some(tx(0, 0, 5)), queue.insert(tx(0, 0, 6)));
+
+        // If the capacity is exceeded, the transaction with the lowest fee is evicted.
+        assert_eq!(Some(tx(2, 0, 1)), queue.insert(tx(3, 0, 3)));
+        assert_eq!(Some(tx(4, 0, 2)), queue.insert(tx(4, 0, 2)));
+        let expected = vec![&tx(1, 0, 7), &tx(0, 0, 6), &tx(3, 0, 3)];
+        assert_eq!(expected, queue.iter().collect::<Vec<_>>());
+
+        // Only the first `batch_size` transactions are candidates. Proposals are ordered by fee.
+        assert_eq!(vec![tx(1, 0, 7), tx(0, 0, 6)], queue.choose(3, 2, None));
+        let chosen = queue.choose(1, 2, None);
+        assert!(chosen == vec![tx(1, 0, 7)] || chosen == vec![tx(0, 0, 6)]);
+
+        // If there is a size limit, the transactions with the lowest fees are dropped.
+        let max_size = contribution_size(&[tx(1, 0, 7), tx(0, 0, 6)]).expect("size");
+        assert_eq!(
+            vec![tx(1, 0, 7), tx(0, 0, 6)],
+            queue.choose(3, 3, Some(max_size))
+        );
+        assert_eq!(vec![tx(1, 0, 7)], queue.choose(3, 3, Some(max_size - 1)));
+
+        // Committed transactions are removed by key, even if their fee differs.
+        assert_eq!(vec![tx(1, 0, 7)], queue.remove_all(&[tx(1, 0, 8)]));
+        assert_eq!(
+            vec![&tx(0, 0, 6), &tx(3, 0, 3)],
+            queue.iter().collect::<Vec<_>>()
+        );
+
+        assert_eq!(vec![tx(3, 0, 3)], queue.remove_where(|tx| tx.fee < 5));
+        assert_eq!(vec![&tx(0, 0, 6)], queue.iter().collect::<Vec<_>>());
     }
 }
@@ -0,0 +1,139 @@
+#![deny(unused_must_use)]
+//! Integration tests of asynchronous key generation.
+
+extern crate env_logger;
+extern crate hbbft;
+#[macro_use]
+extern crate log;
+extern crate rand;
+#[macro_use]
+extern crate serde_derive;
+#[macro_use]
+extern crate rand_derive;
+extern crate threshold_crypto as crypto;
+
+mod network;
+
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crypto::SecretKey;
+use hbbft::async_key_gen::{AsyncKeyGen, Error};
+use hbbft::messaging::NetworkInfo;
+use rand::Rng;
+
+use network::{Adversary, MessageScheduler, NodeId, SilentAdversary, TestNetwork, TestNode};
+
+fn test_async_key_gen<A: Adversary<AsyncKeyGen<NodeId>>>(
+    mut network: TestNetwork<A, AsyncKeyGen<NodeId>>,
+    threshold: usize,
+) {
+    network.input_all(());
+
+    // Terminate when all good nodes do.
+    while !network.nodes.values().all(TestNode::terminated) {
+        network.step();
+    }
+
+    // All nodes and the observer output the same public key set.
+    let (pk_set, observer_sks) = network.observer.outputs()[0].clone();
+    assert!(observer_sks.is_none());
+    let msg = "Totally real news";
+    let mut sig_shares = BTreeMap::new();
+    for node in network.nodes.values() {
+        assert_eq!(1, node.outputs().len());
+        let (ref node_pk_set, ref opt_sks) = node.outputs()[0];
+        assert_eq!(pk_set, *node_pk_set);
+        let sks = opt_sks.as_ref().expect("validator secret key share");
+        let idx = node.id.0;
+        let sig_share = sks.sign(msg);
+        assert!(pk_set.public_key_share(idx).verify(&sig_share, msg));
+        sig_shares.insert(idx, sig_share);
+    }
+
+    // Any `threshold + 1` shares combine to a valid signature.
+    let sig = pk_set
+        .combine_signatures(sig_shares.iter().take(threshold + 1))
+        .expect("combine signature shares");
+    assert!(pk_set.public_key().verify(&sig, msg));
+}
+
+fn new_network<A, F>(
+    good_num: usize,
+    bad_num: usize,
+    threshold: usize,
+    max_faulty: usize,
+    adversary: F,
+) -> TestNetwork<A, AsyncKeyGen<NodeId>>
+where
+    A: Adversary<AsyncKeyGen<NodeId>>,
+    F: Fn(BTreeMap<NodeId, Arc<NetworkInfo<NodeId>>>) -> A,
+{
+    // This returns an error in all but the first test.
+    let _ = env_logger::try_init();
+
+    let new_async_key_gen = |netinfo: Arc<NetworkInfo<NodeId>>| {
+        AsyncKeyGen::new(
+            &mut rand::thread_rng(),
+            *netinfo.our_id(),
+            netinfo.secret_key().clone(),
+            netinfo.public_key_map().clone(),
+            threshold,
+            max_faulty,
+        ).expect("new AsyncKeyGen instance")
+    };
+    TestNetwork::new(good_num, bad_num, adversary, new_async_key_gen)
+}
+
+#[test]
+fn test_async_key_gen_5_out_of_6_nodes() {
+    let adversary = |_| SilentAdversary::new(MessageScheduler::First);
+    let network = new_network(5, 1, 1, 1, adversary);
+    test_async_key_gen(network, 1);
+}
+
+#[test]
+fn test_async_key_gen_9_out_of_11_nodes_random_order() {
+    let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+    let network = new_network(9, 2, 2, 2, adversary);
+    test_async_key_gen(network, 2);
+}
+
+#[test]
+fn test_async_key_gen_honey_badger_threshold() {
+    // Two faulty nodes are tolerated, but the keys have Honey Badger's threshold `(N - 1) / 3`.
+    let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+    let network = new_network(9, 2, 3, 2, adversary);
+    test_async_key_gen(network, 3);
+}
+
+#[test]
+fn test_async_key_gen_invalid_threshold() {
+    let mut rng = rand::thread_rng();
+    let sec_keys: Vec<SecretKey> = (0..6).map(|_| rng.gen()).collect();
+    let pub_keys: BTreeMap<_, _> = sec_keys
+        .iter()
+        .enumerate()
+        .map(|(i, sk)| (NodeId(i), sk.public_key()))
+        .collect();
+    let new_async_key_gen = |threshold, max_faulty| {
+        let (sk, pks) = (sec_keys[0].clone(), pub_keys.clone());
+        let mut rng = rand::thread_rng();
+        let result = AsyncKeyGen::new(&mut rng, NodeId(0), sk, pks, threshold, max_faulty);
+        result.err()
+    };
+    // The local coin agreement requires more than five times as many nodes as faulty ones.
+    assert_eq!(Some(Error::InvalidMaxFaulty), new_async_key_gen(2, 2));
+    // The faulty nodes must not be able to sign or decrypt on their own.
+    assert_eq!(Some(Error::InvalidThreshold), new_async_key_gen(0, 1));
+    // Every correct node must be able to reconstruct its share.
+    assert_eq!(Some(Error::InvalidThreshold), new_async_key_gen(4, 1));
+    assert_eq!(None, new_async_key_gen(3, 1));
+}
+
+#[test]
+fn test_async_key_gen_1_node() {
+    let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+    let network = new_network(1, 0, 0, 0, adversary);
+    test_async_key_gen(network, 0);
+}
@@ -32,7 +32,7 @@ use std::sync::Arc;
 
 use rand::Rng;
 
-use hbbft::binary_agreement::BinaryAgreement;
+use hbbft::binary_agreement::{BinaryAgreement, CoinSchedule};
 use hbbft::messaging::NetworkInfo;
 
 use network::{Adversary, MessageScheduler, NodeId, SilentAdversary, TestNetwork, TestNode};
@@ -63,7 +63,7 @@ fn test_binary_agreement<A: Adversary<BinaryAgreement<NodeId>>>(
     assert!(expected.iter().eq(network.observer.outputs()));
 }
 
-fn test_binary_agreement_different_sizes<A, F>(new_adversary: F)
+fn test_binary_agreement_different_sizes<A, F>(new_adversary: F, coin_schedule: CoinSchedule)
 where
     A: Adversary<BinaryAgreement<NodeId>>,
     F: Fn(usize, usize) -> A,
@@ -80,12 +80,14 @@ where
         let num_good_nodes = size - num_faulty_nodes;
         for &input in &[None, Some(false), Some(true)] {
             info!(
-                "Test start: {} good nodes and {} faulty nodes, input: {:?}",
-                num_good_nodes, num_faulty_nodes, input
+                "Test start: {} good nodes and {} faulty nodes, input: {:?}, {:?}",
+                num_good_nodes, num_faulty_nodes, input, coin_schedule
             );
             let adversary = |_| new_adversary(num_good_nodes, num_faulty_nodes);
             let new_ba = |netinfo: Arc<NetworkInfo<NodeId>>| {
-                BinaryAgreement::new(netinfo, 0, NodeId(0)).expect("Binary Agreement instance")
+                BinaryAgreement::new(netinfo, 0, NodeId(0))
+                    .expect("Binary Agreement instance")
+                    .with_coin_schedule(coin_schedule)
             };
             let network = TestNetwork::new(num_good_nodes, num_faulty_nodes, adversary, new_ba);
             test_binary_agreement(network, input);
@@ -100,11 +102,23 @@ where
 #[test]
 fn test_binary_agreement_random_silent() {
     let new_adversary = |_: usize, _: usize| SilentAdversary::new(MessageScheduler::Random);
-    test_binary_agreement_different_sizes(new_adversary);
+    test_binary_agreement_different_sizes(new_adversary, CoinSchedule::TrueFalseCoin);
 }
 
 #[test]
 fn test_binary_agreement_first_silent() {
     let new_adversary = |_: usize, _: usize| SilentAdversary::new(MessageScheduler::First);
-    test_binary_agreement_different_sizes(new_adversary);
+    test_binary_agreement_different_sizes(new_adversary, CoinSchedule::TrueFalseCoin);
+}
+
+#[test]
+fn test_binary_agreement_always_coin_random_silent() {
+    let new_adversary = |_: usize, _: usize| SilentAdversary::new(MessageScheduler::Random);
+    test_binary_agreement_different_sizes(new_adversary, CoinSchedule::AlwaysCoin);
+}
+
+#[test]
+fn test_binary_agreement_always_coin_first_silent() {
+    let new_adversary = |_: usize, _: usize| SilentAdversary::new(MessageScheduler::First);
+    test_binary_agreement_different_sizes(new_adversary, CoinSchedule::AlwaysCoin);
 }
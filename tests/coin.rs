@@ -129,3 +129,16 @@ fn test_coin_first_silent_50_samples() {
     let new_adversary = |_: usize, _: usize| SilentAdversary::new(MessageScheduler::First);
     test_coin_different_sizes(new_adversary, 50);
 }
+
+#[test]
+fn test_coin_weighted() {
+    let _ = env_logger::try_init();
+    // The total weight is 8, so the faulty node's weight of 2 is the maximum that is tolerated.
+    for i in 0..10 {
+        let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+        let nonce = format!("My weighted nonce {}", i);
+        let new_coin = |netinfo: _| Coin::new(netinfo, nonce.clone());
+        let network = TestNetwork::new_weighted(&[1, 3, 2], &[2], adversary, new_coin);
+        test_coin(network);
+    }
+}
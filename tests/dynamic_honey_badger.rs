@@ -1,6 +1,7 @@
 #![deny(unused_must_use)]
 //! Network tests for Dynamic Honey Badger.
 
+extern crate bincode;
 extern crate hbbft;
 extern crate itertools;
 #[macro_use]
@@ -16,42 +17,71 @@ extern crate threshold_crypto as crypto;
 mod network;
 
 use std::cmp;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::sync::Arc;
 
+use crypto::{PublicKey, SecretKey, SecretKeySet};
 use itertools::Itertools;
 use rand::Rng;
 
-use hbbft::dynamic_honey_badger::{Batch, Change, ChangeState, DynamicHoneyBadger, Input};
-use hbbft::messaging::NetworkInfo;
+use hbbft::dynamic_honey_badger::{
+    Batch, Change, ChangeState, DynamicHoneyBadger, ErrorKind, Input, Params, SignedJoinPlan,
+};
+use hbbft::honey_badger::{EncryptionSchedule, SubsetHandlingStrategy};
+use hbbft::messaging::{NetworkInfo, Threshold};
 use hbbft::transaction_queue::TransactionQueue;
 
 use network::{Adversary, MessageScheduler, NodeId, SilentAdversary, TestNetwork, TestNode};
 
 type UsizeDhb = DynamicHoneyBadger<Vec<usize>, NodeId>;
 
-/// Proposes `num_txs` values and expects nodes to output and order them.
-fn test_dynamic_honey_badger<A>(mut network: TestNetwork<A, UsizeDhb>, num_txs: usize)
-where
+/// Proposes `num_txs` values and expects nodes to output and order them. Node 0 is removed and
+/// then added again, either with `Remove` and `Add` or, if `node_change` is `true`, with
+/// `NodeChange` votes.
+fn test_dynamic_honey_badger<A>(
+    mut network: TestNetwork<A, UsizeDhb>,
+    num_txs: usize,
+    node_change: bool,
+) where
     A: Adversary<UsizeDhb>,
 {
-    let new_queue = |id: &NodeId| (*id, TransactionQueue((0..num_txs).collect()));
+    let new_queue = |id: &NodeId| (*id, (0..num_txs).collect::<VecDeque<_>>());
     let mut queues: BTreeMap<_, _> = network.nodes.keys().map(new_queue).collect();
     for (id, queue) in &queues {
-        network.input(*id, Input::User(queue.choose(3, 10)));
+        network.input(*id, Input::User(queue.choose(3, 10, None)));
     }
 
-    network.input_all(Input::Change(Change::Remove(NodeId(0))));
+    let all_pub_keys = network.nodes[&NodeId(0)]
+        .instance()
+        .netinfo()
+        .public_key_map()
+        .clone();
+    let initial_pk = network.nodes[&NodeId(0)]
+        .instance()
+        .netinfo()
+        .public_key_set()
+        .public_key();
+    let remove = if node_change {
+        let mut pub_keys = all_pub_keys.clone();
+        pub_keys.remove(&NodeId(0));
+        Change::NodeChange(pub_keys)
+    } else {
+        Change::Remove(NodeId(0))
+    };
+    network.input_all(Input::Change(remove.clone()));
 
-    fn has_remove(node: &TestNode<UsizeDhb>) -> bool {
+    let has_remove = |node: &TestNode<UsizeDhb>| {
         node.outputs()
             .iter()
-            .any(|batch| *batch.change() == ChangeState::Complete(Change::Remove(NodeId(0))))
-    }
+            .any(|batch| *batch.change() == ChangeState::Complete(remove.clone()))
+    };
 
     fn has_add(node: &TestNode<UsizeDhb>) -> bool {
         node.outputs().iter().any(|batch| match *batch.change() {
             ChangeState::Complete(Change::Add(ref id, _)) => *id == NodeId(0),
+            ChangeState::Complete(Change::NodeChange(ref pub_keys)) => {
+                pub_keys.contains_key(&NodeId(0))
+            }
             _ => false,
         })
     }
@@ -87,7 +117,7 @@ where
         if let Some(id) = rng.choose(&input_ids) {
             let queue = queues.get_mut(id).unwrap();
             queue.remove_all(network.nodes[id].outputs().iter().flat_map(Batch::iter));
-            network.input(*id, Input::User(queue.choose(3, 10)));
+            network.input(*id, Input::User(queue.choose(3, 10, None)));
         }
         network.step();
         // Once all nodes have processed the removal of node 0, add it again.
@@ -97,36 +127,99 @@ where
                 .netinfo()
                 .secret_key()
                 .public_key();
-            network.input_all(Input::Change(Change::Add(NodeId(0), pk)));
+            let add = if node_change {
+                let mut pub_keys = all_pub_keys.clone();
+                pub_keys.insert(NodeId(0), pk);
+                Change::NodeChange(pub_keys)
+            } else {
+                Change::Add(NodeId(0), pk)
+            };
+            network.input_all(Input::Change(add));
             input_add = true;
         }
     }
     verify_output_sequence(&network);
+
+    // Deliver the remaining messages, so that all join plan signing rounds complete.
+    while network.nodes.values().any(|node| !node.queue.is_empty()) {
+        network.step();
+    }
+    verify_signed_join_plans(&network, &initial_pk);
+
+    // The keys created by key generation have the threshold determined by the configured rule.
+    for node in network.nodes.values() {
+        let netinfo = node.instance().netinfo();
+        let threshold = node.instance().threshold().for_num_nodes(netinfo.num_nodes());
+        assert_eq!(Some(netinfo.threshold()), threshold);
+    }
 }
 
 /// Verifies that all instances output the same sequence of batches. We already know that all of
 /// them have output all transactions and events, but some may have advanced a few empty batches
-/// more than others, so we ignore those.
+/// more than others, so we ignore those. Also verifies that the timestamps never decrease, even
+/// when Honey Badger is restarted.
 fn verify_output_sequence<A>(network: &TestNetwork<A, UsizeDhb>)
 where
     A: Adversary<UsizeDhb>,
 {
     let expected = network.nodes[&NodeId(0)].outputs().to_vec();
     assert!(!expected.is_empty());
+    assert!(expected
+        .windows(2)
+        .all(|pair| pair[0].timestamp() <= pair[1].timestamp()));
     for node in network.nodes.values() {
         let len = cmp::min(expected.len(), node.outputs().len());
         assert_eq!(&expected[..len], &node.outputs()[..len]);
     }
 }
 
-// Allow passing `netinfo` by value. `TestNetwork` expects this function signature.
+/// Verifies that all instances have era certificates for both key changes, and that a new node
+/// that only knows the initial public master key can join using any of their signed join plans.
+fn verify_signed_join_plans<A>(network: &TestNetwork<A, UsizeDhb>, initial_pk: &PublicKey)
+where
+    A: Adversary<UsizeDhb>,
+{
+    let mut rng = rand::thread_rng();
+    let new_id = NodeId(100); // Not used by any node in the network.
+    for node in network.nodes.values() {
+        let dhb = node.instance();
+        assert_eq!(2, dhb.era_certificates().len());
+        let certs = dhb.era_certificates().to_vec();
+        let signed_plan = dhb.signed_join_plan().expect("signed join plan").clone();
+        // A plan with a modified epoch doesn't match the signature.
+        let mut ser_plan = bincode::serialize(&signed_plan).expect("serialize signed join plan");
+        ser_plan[0] = ser_plan[0].wrapping_add(1);
+        let tampered_plan: SignedJoinPlan<NodeId> =
+            bincode::deserialize(&ser_plan).expect("deserialize signed join plan");
+        let mut builder = UsizeDhb::builder();
+        let (sk, certs0) = (rng.gen(), certs.clone());
+        let result = builder.build_joining_signed(new_id, sk, initial_pk, certs0, tampered_plan);
+        assert!(result.is_err());
+        // Without the first certificate, the chain doesn't start at the trusted key.
+        let (sk, certs1, plan) = (rng.gen(), certs[1..].to_vec(), signed_plan.clone());
+        let result = builder.build_joining_signed(new_id, sk, initial_pk, certs1, plan);
+        assert!(result.is_err());
+        UsizeDhb::builder()
+            .build_joining_signed(new_id, rng.gen(), initial_pk, certs, signed_plan)
+            .expect("verify signed join plan");
+    }
+}
+
+// Allow passing `netinfo` by value, as `TestNetwork` provides it.
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
-fn new_dynamic_hb(netinfo: Arc<NetworkInfo<NodeId>>) -> UsizeDhb {
-    DynamicHoneyBadger::builder().build((*netinfo).clone())
+fn new_dynamic_hb(netinfo: Arc<NetworkInfo<NodeId>>, threshold: Threshold) -> UsizeDhb {
+    DynamicHoneyBadger::builder()
+        .threshold(threshold)
+        .build((*netinfo).clone())
+        .expect("instantiate DynamicHoneyBadger")
 }
 
-fn test_dynamic_honey_badger_different_sizes<A, F>(new_adversary: F, num_txs: usize)
-where
+fn test_dynamic_honey_badger_different_sizes<A, F>(
+    new_adversary: F,
+    num_txs: usize,
+    threshold: Threshold,
+    node_change: bool,
+) where
     A: Adversary<UsizeDhb>,
     F: Fn(usize, usize, BTreeMap<NodeId, Arc<NetworkInfo<NodeId>>>) -> A,
 {
@@ -144,19 +237,171 @@ where
             num_good_nodes, num_adv_nodes
         );
         let adversary = |adv_nodes| new_adversary(num_good_nodes, num_adv_nodes, adv_nodes);
-        let network = TestNetwork::new(num_good_nodes, num_adv_nodes, adversary, new_dynamic_hb);
-        test_dynamic_honey_badger(network, num_txs);
+        let new_dhb = |netinfo| new_dynamic_hb(netinfo, threshold);
+        let network = TestNetwork::new(num_good_nodes, num_adv_nodes, adversary, new_dhb);
+        test_dynamic_honey_badger(network, num_txs, node_change);
     }
 }
 
 #[test]
 fn test_dynamic_honey_badger_random_delivery_silent() {
     let new_adversary = |_: usize, _: usize, _| SilentAdversary::new(MessageScheduler::Random);
-    test_dynamic_honey_badger_different_sizes(new_adversary, 10);
+    test_dynamic_honey_badger_different_sizes(new_adversary, 10, Threshold::Faulty, false);
 }
 
 #[test]
 fn test_dynamic_honey_badger_first_delivery_silent() {
     let new_adversary = |_: usize, _: usize, _| SilentAdversary::new(MessageScheduler::First);
-    test_dynamic_honey_badger_different_sizes(new_adversary, 10);
+    test_dynamic_honey_badger_different_sizes(new_adversary, 10, Threshold::Faulty, false);
+}
+
+#[test]
+fn test_dynamic_honey_badger_max_threshold() {
+    let new_adversary = |_: usize, _: usize, _| SilentAdversary::new(MessageScheduler::Random);
+    test_dynamic_honey_badger_different_sizes(new_adversary, 10, Threshold::Max, false);
+}
+
+#[test]
+fn test_dynamic_honey_badger_node_change() {
+    let new_adversary = |_: usize, _: usize, _| SilentAdversary::new(MessageScheduler::Random);
+    test_dynamic_honey_badger_different_sizes(new_adversary, 10, Threshold::Faulty, true);
+}
+
+#[test]
+fn test_dynamic_honey_badger_parameters() {
+    // This returns an error in all but the first test.
+    let _ = env_logger::try_init();
+
+    let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+    let new_dhb = |netinfo| new_dynamic_hb(netinfo, Threshold::Faulty);
+    let mut network = TestNetwork::new(4, 1, adversary, new_dhb);
+    let params = Params {
+        max_future_epochs: 5,
+        subset_handling_strategy: SubsetHandlingStrategy::AllAtEnd,
+        batch_size: 50,
+        vote_expiry: None,
+        encryption_schedule: EncryptionSchedule::TickTock(1, 1),
+    };
+    network.input_all(Input::Change(Change::Parameters(params.clone())));
+
+    let has_params = |node: &TestNode<UsizeDhb>| {
+        let change = ChangeState::Complete(Change::Parameters(params.clone()));
+        node.outputs().iter().any(|batch| *batch.change() == change)
+    };
+
+    // Propose empty contributions until all nodes have switched to the new parameters.
+    while !network.nodes.values().all(has_params) {
+        let input_ids: Vec<_> = network
+            .nodes
+            .iter()
+            .filter(|(_, node)| !node.instance().has_input())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in input_ids {
+            network.input(id, Input::User(Vec::new()));
+        }
+        network.step();
+    }
+    verify_output_sequence(&network);
+
+    for node in network.nodes.values() {
+        assert_eq!(params, *node.instance().params());
+    }
+}
+
+#[test]
+fn test_dynamic_honey_badger_parameters_during_key_gen() {
+    // This returns an error in all but the first test.
+    let _ = env_logger::try_init();
+
+    let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+    let new_dhb = |netinfo| new_dynamic_hb(netinfo, Threshold::Faulty);
+    let mut network = TestNetwork::new(4, 1, adversary, new_dhb);
+    let remove = Change::Remove(NodeId(0));
+    let params = Params {
+        max_future_epochs: 2,
+        encryption_schedule: EncryptionSchedule::Never,
+        ..Params::default()
+    };
+    network.input_all(Input::Change(remove.clone()));
+
+    let has_change = |node: &TestNode<UsizeDhb>, change: &ChangeState<NodeId>| {
+        node.outputs().iter().any(|batch| batch.change() == change)
+    };
+    let in_progress = ChangeState::InProgress(remove.clone());
+    let complete = ChangeState::Complete(remove.clone());
+    let params_complete = ChangeState::Complete(Change::Parameters(params.clone()));
+    // How many times the validators have voted for the new parameters.
+    let mut params_votes = 0;
+
+    // Propose empty contributions until all nodes have switched to the new parameters and removed
+    // node 0. The parameter change must not cancel the key generation for the removal.
+    while !network
+        .nodes
+        .values()
+        .all(|node| has_change(node, &complete) && has_change(node, &params_complete))
+    {
+        let input_ids: Vec<_> = network
+            .nodes
+            .iter()
+            .filter(|(_, node)| !node.instance().has_input())
+            .map(|(id, _)| *id)
+            .collect();
+        for id in input_ids {
+            network.input(id, Input::User(Vec::new()));
+        }
+        network.step();
+        // Once key generation has started everywhere, vote for the new parameters. If it completes
+        // before that vote passes, the new era discards the votes: In that case, vote again.
+        let should_vote = {
+            let all_have = |change: &ChangeState<NodeId>| {
+                network.nodes.values().all(|node| has_change(node, change))
+            };
+            let any_has_params = network
+                .nodes
+                .values()
+                .any(|node| has_change(node, &params_complete));
+            (params_votes == 0 && all_have(&in_progress))
+                || (params_votes == 1 && all_have(&complete) && !any_has_params)
+        };
+        if should_vote {
+            let validator_ids: Vec<_> = network
+                .nodes
+                .iter()
+                .filter(|(_, node)| node.instance().netinfo().is_validator())
+                .map(|(id, _)| *id)
+                .collect();
+            for id in validator_ids {
+                network.input(id, Input::Change(Change::Parameters(params.clone())));
+            }
+            params_votes += 1;
+        }
+    }
+    verify_output_sequence(&network);
+
+    for node in network.nodes.values() {
+        assert_eq!(params, *node.instance().params());
+        assert!(!node.instance().netinfo().is_node_validator(&NodeId(0)));
+    }
+}
+
+#[test]
+fn test_dynamic_honey_badger_weighted() {
+    // Key generation doesn't support weights, so `DynamicHoneyBadger` rejects them.
+    let mut rng = rand::thread_rng();
+    let sk_set = SecretKeySet::random(1, &mut rng).expect("random key set");
+    let shares = (0..2)
+        .map(|idx| sk_set.secret_key_share(idx).expect("secret key share"))
+        .collect();
+    let (sk0, sk1): (SecretKey, SecretKey) = (rng.gen(), rng.gen());
+    let pub_keys = vec![(NodeId(0), sk0.public_key()), (NodeId(1), sk1.public_key())];
+    let weights = vec![(NodeId(0), 2), (NodeId(1), 1)].into_iter().collect();
+    let pk_set = sk_set.public_keys();
+    let pub_keys = pub_keys.into_iter().collect();
+    let netinfo = NetworkInfo::new_weighted(NodeId(0), shares, pk_set, sk0, pub_keys, weights);
+    let err = UsizeDhb::builder().build(netinfo).err().expect("weighted validators");
+    match *err.kind() {
+        ErrorKind::WeightedValidators => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
 }
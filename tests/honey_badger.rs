@@ -16,13 +16,16 @@ extern crate threshold_crypto as crypto;
 
 mod network;
 
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, VecDeque};
 use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
 
 use itertools::Itertools;
 use rand::Rng;
 
-use hbbft::honey_badger::{self, Batch, HoneyBadger, MessageContent};
+use hbbft::honey_badger::{
+    self, Batch, EncryptionSchedule, HoneyBadger, MessageContent, SubsetAlgorithm,
+};
 use hbbft::messaging::{NetworkInfo, Target, TargetedMessage};
 use hbbft::threshold_decryption;
 use hbbft::transaction_queue::TransactionQueue;
@@ -111,7 +114,7 @@ impl Adversary<UsizeHoneyBadger> for FaultyShareAdversary {
                             Target::All.message(
                                 MessageContent::DecryptionShare {
                                     proposer_id: NodeId(proposer_id),
-                                    share: threshold_decryption::Message(share.clone()),
+                                    share: threshold_decryption::Message(vec![share.clone()]),
                                 }.with_epoch(*epoch),
                             ),
                         ))
@@ -124,11 +127,14 @@ impl Adversary<UsizeHoneyBadger> for FaultyShareAdversary {
 }
 
 /// Proposes `num_txs` values and expects nodes to output and order them.
-fn test_honey_badger<A>(mut network: TestNetwork<A, UsizeHoneyBadger>, num_txs: usize)
+fn test_honey_badger<A>(
+    mut network: TestNetwork<A, UsizeHoneyBadger>,
+    num_txs: usize,
+) -> TestNetwork<A, UsizeHoneyBadger>
 where
     A: Adversary<UsizeHoneyBadger>,
 {
-    let new_queue = |id: &NodeId| (*id, TransactionQueue((0..num_txs).collect()));
+    let new_queue = |id: &NodeId| (*id, (0..num_txs).collect::<VecDeque<_>>());
     let mut queues: BTreeMap<_, _> = network.nodes.keys().map(new_queue).collect();
 
     // Returns `true` if the node has not output all transactions yet.
@@ -151,30 +157,40 @@ where
         if let Some(id) = rng.choose(&input_ids) {
             let queue = queues.get_mut(id).unwrap();
             queue.remove_all(network.nodes[id].outputs().iter().flat_map(Batch::iter));
-            network.input(*id, queue.choose(3, 10));
+            network.input(*id, queue.choose(3, 10, None));
         } else {
             network.step();
         }
     }
     verify_output_sequence(&network);
+    network
 }
 
-/// Verifies that all instances output the same sequence of batches.
+/// Verifies that all instances output the same sequence of batches, and that their timestamps are
+/// in the past and never decrease.
 fn verify_output_sequence<A>(network: &TestNetwork<A, UsizeHoneyBadger>)
 where
     A: Adversary<UsizeHoneyBadger>,
 {
-    let mut expected: Option<BTreeMap<&_, &_>> = None;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("current time")
+        .as_secs();
+    let mut expected: Option<BTreeMap<&_, _>> = None;
     for node in network.nodes.values() {
         assert!(!node.outputs().is_empty());
-        let outputs: BTreeMap<&u64, &BTreeMap<NodeId, Vec<usize>>> = node
+        let timestamps: Vec<u64> = node.outputs().iter().map(|batch| batch.timestamp).collect();
+        assert!(timestamps.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(timestamps.iter().all(|&timestamp| timestamp <= now));
+        let outputs: BTreeMap<&u64, (&u64, &BTreeMap<NodeId, Vec<usize>>)> = node
             .outputs()
             .iter()
             .map(
                 |Batch {
                      epoch,
                      contributions,
-                 }| (epoch, contributions),
+                     timestamp,
+                 }| (epoch, (timestamp, contributions)),
             ).collect();
         if expected.is_none() {
             expected = Some(outputs);
@@ -242,3 +258,70 @@ fn test_honey_badger_random_adversary() {
     };
     test_honey_badger_different_sizes(new_adversary, 8);
 }
+
+#[test]
+fn test_honey_badger_validity_predicate() {
+    let _ = env_logger::try_init();
+    // Every node proposes every transaction eventually, so we can discard node 0's contributions.
+    let new_hb = |netinfo: Arc<NetworkInfo<NodeId>>| {
+        HoneyBadger::builder(netinfo)
+            .validity_predicate(|proposer_id: &NodeId, _: &Vec<usize>| *proposer_id != NodeId(0))
+            .build()
+    };
+    let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+    let network = TestNetwork::new(4, 0, adversary, new_hb);
+    let network = test_honey_badger(network, 10);
+    for node in network.nodes.values() {
+        for batch in node.outputs() {
+            assert!(!batch.contributions.contains_key(&NodeId(0)));
+        }
+    }
+}
+
+#[test]
+fn test_honey_badger_mvba_subset() {
+    let _ = env_logger::try_init();
+    let new_hb = |netinfo: Arc<NetworkInfo<NodeId>>| {
+        HoneyBadger::builder(netinfo)
+            .subset_algorithm(SubsetAlgorithm::Mvba)
+            .build()
+    };
+    let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+    let network = TestNetwork::new(5, 2, adversary, new_hb);
+    test_honey_badger(network, 10);
+}
+
+#[test]
+fn test_honey_badger_encryption_schedule() {
+    let _ = env_logger::try_init();
+    for &schedule in &[
+        EncryptionSchedule::Never,
+        EncryptionSchedule::EveryNthEpoch(3),
+        EncryptionSchedule::TickTock(2, 1),
+    ] {
+        let new_hb = |netinfo: Arc<NetworkInfo<NodeId>>| {
+            HoneyBadger::builder(netinfo)
+                .encryption_schedule(schedule)
+                .build()
+        };
+        let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+        let network = TestNetwork::new(4, 1, adversary, new_hb);
+        test_honey_badger(network, 10);
+    }
+}
+
+#[test]
+fn test_honey_badger_weighted() {
+    let _ = env_logger::try_init();
+    // The total weight is 9, so the faulty node's weight of 2 is the maximum that is tolerated.
+    let adversary = |adv_nodes: BTreeMap<NodeId, Arc<NetworkInfo<NodeId>>>| {
+        let netinfo = &adv_nodes[&NodeId(4)];
+        assert!(netinfo.is_weighted());
+        assert_eq!((9, 2), (netinfo.total_weight(), netinfo.faulty_weight()));
+        assert_eq!(Some(7..9), netinfo.share_indices(&NodeId(4)));
+        assert_eq!(2, netinfo.secret_key_shares().len());
+        SilentAdversary::new(MessageScheduler::Random)
+    };
+    let network = TestNetwork::new_weighted(&[1, 1, 3, 2], &[2], adversary, new_honey_badger);
+    test_honey_badger(network, 10);
+}
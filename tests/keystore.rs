@@ -0,0 +1,141 @@
+#![deny(unused_must_use)]
+//! Tests for the encrypted key store.
+
+extern crate env_logger;
+extern crate hbbft;
+extern crate rand;
+extern crate threshold_crypto as crypto;
+
+use std::collections::BTreeMap;
+
+use crypto::PublicKey;
+use rand::Rng;
+
+use hbbft::dynamic_honey_badger::DynamicHoneyBadger;
+use hbbft::keystore::{Error, KeyStore, SecretValue};
+use hbbft::sync_key_gen::{PartOutcome, SyncKeyGen};
+
+/// Runs a key generation with four nodes and returns their key stores.
+fn generate_key_stores() -> Vec<KeyStore<usize>> {
+    let mut rng = rand::thread_rng();
+    let sec_vals: Vec<SecretValue> = (0..4).map(|_| rng.gen()).collect();
+    let pub_keys: BTreeMap<usize, PublicKey> = sec_vals
+        .iter()
+        .map(|val| val.secret_key().expect("secret key").public_key())
+        .enumerate()
+        .collect();
+    let (mut nodes, parts): (Vec<_>, Vec<_>) = sec_vals
+        .iter()
+        .enumerate()
+        .map(|(id, val)| {
+            let sk = val.secret_key().expect("secret key");
+            SyncKeyGen::new(&mut rng, id, sk, pub_keys.clone(), 1).expect("new SyncKeyGen")
+        }).unzip();
+    let mut acks = Vec::new();
+    for (sender_id, part) in parts.into_iter().enumerate() {
+        let part = part.expect("validators create parts");
+        for (node_id, node) in nodes.iter_mut().enumerate() {
+            match node.handle_part(&mut rng, &sender_id, part.clone()) {
+                Some(PartOutcome::Valid(ack)) => acks.push((node_id, ack)),
+                _ => panic!("invalid part"),
+            }
+        }
+    }
+    for (sender_id, ack) in acks {
+        for node in &mut nodes {
+            assert!(node.handle_ack(&sender_id, ack.clone()).is_empty());
+        }
+    }
+    nodes
+        .into_iter()
+        .zip(sec_vals)
+        .enumerate()
+        .map(|(id, (node, sec_val))| {
+            let (pk_set, opt_share) = node.generate_share().expect("generate keys");
+            KeyStore::new(id, sec_val, opt_share, pk_set, pub_keys.clone())
+        }).collect()
+}
+
+#[test]
+fn test_keystore_round_trip() {
+    // This returns an error in all but the first test.
+    let _ = env_logger::try_init();
+
+    let msg = "Keep it secret, keep it safe";
+    let mut sig_shares = BTreeMap::new();
+    let mut pk_set = None;
+    for (id, store) in generate_key_stores().into_iter().enumerate() {
+        let mut bytes = Vec::new();
+        store
+            .save(&mut rand::thread_rng(), b"correct horse", &mut bytes)
+            .expect("save key store");
+        let loaded = KeyStore::<usize>::load(&bytes[..], b"correct horse").expect("load");
+        let netinfo = loaded.network_info().expect("network info");
+        assert_eq!(id, *netinfo.our_id());
+        assert_eq!(store.public_keys(), netinfo.public_key_map());
+        assert_eq!(store.public_key_set(), netinfo.public_key_set());
+        assert_eq!(
+            *netinfo.public_key(&id).expect("our public key"),
+            netinfo.secret_key().public_key()
+        );
+        sig_shares.insert(id, netinfo.secret_key_share().sign(msg));
+        pk_set = Some(netinfo.public_key_set().clone());
+
+        // The share value can be passed on to a restarted `DynamicHoneyBadger` instance.
+        let dhb = DynamicHoneyBadger::<Vec<usize>, usize>::builder()
+            .share_value(loaded.share_value().cloned())
+            .build(netinfo)
+            .expect("instantiate DynamicHoneyBadger");
+        assert!(dhb.share_value().is_some());
+    }
+
+    // The loaded key shares combine to a valid signature.
+    let pk_set = pk_set.expect("public key set");
+    let sig = pk_set
+        .combine_signatures(sig_shares.iter().take(2))
+        .expect("combine signature shares");
+    assert!(pk_set.public_key().verify(&sig, msg));
+}
+
+#[test]
+fn test_keystore_invalid() {
+    let store = generate_key_stores().swap_remove(0);
+    let mut bytes = Vec::new();
+    store
+        .save(&mut rand::thread_rng(), b"correct horse", &mut bytes)
+        .expect("save key store");
+
+    // A wrong passphrase is detected.
+    match KeyStore::<usize>::load(&bytes[..], b"battery staple") {
+        Err(Error::Authentication) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // So is any modification of the contents.
+    let mut modified = bytes.clone();
+    let last = modified.len() - 40;
+    modified[last] ^= 1;
+    match KeyStore::<usize>::load(&modified[..], b"correct horse") {
+        Err(Error::Authentication) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // An excessive number of key derivation iterations is rejected before deriving the key. It
+    // is serialized after the 6 header bytes and the 32 bytes of salt.
+    let mut modified = bytes.clone();
+    for byte in &mut modified[38..42] {
+        *byte = 0xff;
+    }
+    match KeyStore::<usize>::load(&modified[..], b"correct horse") {
+        Err(Error::UnsupportedIterations(iterations)) => assert_eq!(u32::max_value(), iterations),
+        result => panic!("unexpected result {:?}", result),
+    }
+
+    // An unknown version is rejected.
+    let mut modified = bytes.clone();
+    modified[5] = 42;
+    match KeyStore::<usize>::load(&modified[..], b"correct horse") {
+        Err(Error::UnsupportedVersion(42)) => (),
+        result => panic!("unexpected result {:?}", result),
+    }
+}
@@ -106,6 +106,7 @@ fn do_drop_and_readd(cfg: TestConfig) {
             DynamicHoneyBadger::builder()
                 .rng(node.rng)
                 .build(node.netinfo)
+                .expect("instantiate DynamicHoneyBadger")
         }).build()
         .expect("could not construct test network");
 
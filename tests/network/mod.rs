@@ -3,7 +3,6 @@ use std::fmt::{self, Debug};
 use std::mem;
 use std::sync::Arc;
 
-use crypto::SecretKeyShare;
 use rand::{self, Rng};
 
 use hbbft::messaging::{DistAlgorithm, NetworkInfo, Step, Target, TargetedMessage};
@@ -399,16 +398,56 @@ where
     {
         let mut rng = rand::thread_rng();
         let node_ids = (0..(good_num + adv_num)).map(NodeId);
-        let mut netinfos = NetworkInfo::generate_map(node_ids, &mut rng)
+        let netinfos = NetworkInfo::generate_map(node_ids, &mut rng)
             .expect("Failed to generate `NetworkInfo` map");
+        Self::from_netinfos(netinfos, good_num, adversary, new_algo)
+    }
+
+    /// Creates a new network with weighted validators: good nodes with the weights `good_weights`,
+    /// and nodes with the weights `adv_weights`, controlled by the given `adversary`.
+    #[allow(unused)] // Not used in all tests.
+    pub fn new_weighted<F, G>(
+        good_weights: &[u64],
+        adv_weights: &[u64],
+        adversary: G,
+        new_algo: F,
+    ) -> TestNetwork<A, D>
+    where
+        F: Fn(Arc<NetworkInfo<NodeId>>) -> D,
+        G: Fn(BTreeMap<D::NodeId, Arc<NetworkInfo<D::NodeId>>>) -> A,
+    {
+        let mut rng = rand::thread_rng();
+        let weights = good_weights.iter().chain(adv_weights).cloned();
+        let node_weights = weights.enumerate().map(|(i, w)| (NodeId(i), w));
+        let netinfos = NetworkInfo::generate_map_weighted(node_weights, &mut rng)
+            .expect("Failed to generate `NetworkInfo` map");
+        Self::from_netinfos(netinfos, good_weights.len(), adversary, |netinfo| {
+            (new_algo(netinfo), Step::default())
+        })
+    }
+
+    /// Creates a new network from the given `NetworkInfo`s: The first `good_num` nodes are good,
+    /// the others are controlled by the given `adversary`.
+    fn from_netinfos<F, G>(
+        mut netinfos: BTreeMap<NodeId, NetworkInfo<NodeId>>,
+        good_num: usize,
+        adversary: G,
+        new_algo: F,
+    ) -> TestNetwork<A, D>
+    where
+        F: Fn(Arc<NetworkInfo<NodeId>>) -> (D, Step<D>),
+        G: Fn(BTreeMap<D::NodeId, Arc<NetworkInfo<D::NodeId>>>) -> A,
+    {
+        let mut rng = rand::thread_rng();
         let obs_netinfo = {
             let node_ni = netinfos.values().next().unwrap();
-            NetworkInfo::new(
-                NodeId(good_num + adv_num),
-                SecretKeyShare::default(),
+            NetworkInfo::new_weighted(
+                NodeId(netinfos.len()),
+                Vec::new(),
                 node_ni.public_key_set().clone(),
                 rng.gen(),
                 node_ni.public_key_map().clone(),
+                node_ni.weight_map().clone(),
             )
         };
         let adv_netinfos = netinfos.split_off(&NodeId(good_num));
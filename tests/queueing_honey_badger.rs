@@ -1,6 +1,7 @@
 #![deny(unused_must_use)]
 //! Network tests for Queueing Honey Badger.
 
+extern crate bincode;
 extern crate env_logger;
 extern crate hbbft;
 extern crate itertools;
@@ -16,23 +17,153 @@ extern crate threshold_crypto as crypto;
 mod network;
 
 use std::cmp;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::sync::Arc;
+use std::time::Duration;
 
+use crypto::Ciphertext;
 use hbbft::dynamic_honey_badger::DynamicHoneyBadger;
-use hbbft::messaging::NetworkInfo;
-use hbbft::queueing_honey_badger::{Batch, Change, ChangeState, Input, QueueingHoneyBadger, Step};
+use hbbft::fault_log::{Fault, FaultKind};
+use hbbft::messaging::{DistAlgorithm, NetworkInfo};
+use hbbft::queueing_honey_badger::{
+    AcceptAll, AdaptiveBatchSize, Batch, Change, ChangeState, ErrorKind, Gossip, HashId, Input,
+    Message, QueueingHoneyBadger, Step, TransactionEncryption, TransactionIdentifier,
+    TransactionValidator, TxEvent,
+};
+use hbbft::transaction_queue::{limit_size, PriorityQueue, TransactionOrdering, TransactionQueue};
 use itertools::Itertools;
 use rand::Rng;
 
 use network::{Adversary, MessageScheduler, NodeId, SilentAdversary, TestNetwork, TestNode};
 
+type UsizeQhb<Q> = QueueingHoneyBadger<usize, NodeId, Q>;
+type BelowLimitQhb = QueueingHoneyBadger<usize, NodeId, VecDeque<usize>, BelowLimit>;
+type OncePerKeyQhb = QueueingHoneyBadger<usize, NodeId, VecDeque<usize>, OncePerKey>;
+type EncQhb =
+    QueueingHoneyBadger<EncTx, NodeId, VecDeque<EncTx>, AcceptAll, HashId, EncTxEncryption>;
+
+/// A transaction queue that always proposes the lowest transactions.
+#[derive(Debug, Default)]
+struct LowestFirstQueue(BTreeSet<usize>);
+
+impl Extend<usize> for LowestFirstQueue {
+    fn extend<I: IntoIterator<Item = usize>>(&mut self, txs: I) {
+        self.0.extend(txs)
+    }
+}
+
+impl TransactionQueue<usize> for LowestFirstQueue {
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn choose(&self, amount: usize, _batch_size: usize, max_size: Option<u64>) -> Vec<usize> {
+        limit_size(self.0.iter().take(amount).cloned(), max_size)
+    }
+
+    fn push(&mut self, tx: usize) -> Option<usize> {
+        self.0.insert(tx);
+        None
+    }
+
+    fn remove_all<'a, I>(&mut self, txs: I) -> Vec<usize>
+    where
+        I: IntoIterator<Item = &'a usize>,
+    {
+        txs.into_iter()
+            .filter(|tx| self.0.remove(*tx))
+            .cloned()
+            .collect()
+    }
+
+    fn remove_where<F>(&mut self, mut f: F) -> Vec<usize>
+    where
+        F: FnMut(&usize) -> bool,
+    {
+        let removed: Vec<usize> = self.0.iter().cloned().filter(|tx| f(tx)).collect();
+        for tx in &removed {
+            self.0.remove(tx);
+        }
+        removed
+    }
+}
+
+/// Prioritizes higher transactions, and considers the ones with equal last digits duplicates.
+#[derive(Debug, Default)]
+struct ByValue;
+
+impl TransactionOrdering<usize> for ByValue {
+    type Key = usize;
+    type Priority = usize;
+
+    fn key(&self, tx: &usize) -> usize {
+        tx % 10
+    }
+
+    fn priority(&self, tx: &usize) -> usize {
+        *tx
+    }
+}
+
+/// A validator that only accepts transactions below the limit, if there is one.
+#[derive(Debug, Default)]
+struct BelowLimit(Option<usize>);
+
+impl TransactionValidator<usize> for BelowLimit {
+    fn validate(&self, tx: &usize) -> Result<(), String> {
+        match self.0 {
+            Some(limit) if *tx >= limit => Err(format!("{} is not below {}", tx, limit)),
+            _ => Ok(()),
+        }
+    }
+}
+
+/// A validator that accepts only one transaction per key, i.e. per remainder modulo 5.
+#[derive(Debug, Default)]
+struct OncePerKey(BTreeSet<usize>);
+
+impl TransactionValidator<usize> for OncePerKey {
+    fn validate(&self, tx: &usize) -> Result<(), String> {
+        if self.0.contains(&(tx % 5)) {
+            return Err(format!("key {} was already used", tx % 5));
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, tx: &usize) {
+        self.0.insert(tx % 5);
+    }
+}
+
+/// A transaction that is either plaintext, or encrypted to the network's master public key.
+#[derive(Clone, Debug, Eq, Hash, PartialEq, Serialize, Deserialize)]
+enum EncTx {
+    Plain(usize),
+    Encrypted(Ciphertext),
+}
+
+/// Recognizes the `EncTx::Encrypted` transactions.
+#[derive(Debug, Default)]
+struct EncTxEncryption;
+
+impl TransactionEncryption<EncTx> for EncTxEncryption {
+    fn ciphertext<'a>(&self, tx: &'a EncTx) -> Option<&'a Ciphertext> {
+        match *tx {
+            EncTx::Plain(_) => None,
+            EncTx::Encrypted(ref ct) => Some(ct),
+        }
+    }
+}
+
 /// Proposes `num_txs` values and expects nodes to output and order them.
-fn test_queueing_honey_badger<A>(
-    mut network: TestNetwork<A, QueueingHoneyBadger<usize, NodeId>>,
-    num_txs: usize,
-) where
-    A: Adversary<QueueingHoneyBadger<usize, NodeId>>,
+fn test_queueing_honey_badger<A, Q>(mut network: TestNetwork<A, UsizeQhb<Q>>, num_txs: usize)
+where
+    A: Adversary<UsizeQhb<Q>>,
+    Q: TransactionQueue<usize>,
 {
     // The second half of the transactions will be input only after a node has been removed.
     network.input_all(Input::Change(Change::Remove(NodeId(0))));
@@ -40,13 +171,13 @@ fn test_queueing_honey_badger<A>(
         network.input_all(Input::User(tx));
     }
 
-    fn has_remove(node: &TestNode<QueueingHoneyBadger<usize, NodeId>>) -> bool {
+    fn has_remove<Q: TransactionQueue<usize>>(node: &TestNode<UsizeQhb<Q>>) -> bool {
         node.outputs()
             .iter()
             .any(|batch| *batch.change() == ChangeState::Complete(Change::Remove(NodeId(0))))
     }
 
-    fn has_add(node: &TestNode<QueueingHoneyBadger<usize, NodeId>>) -> bool {
+    fn has_add<Q: TransactionQueue<usize>>(node: &TestNode<UsizeQhb<Q>>) -> bool {
         node.outputs().iter().any(|batch| match *batch.change() {
             ChangeState::Complete(Change::Add(ref id, _)) => *id == NodeId(0),
             _ => false,
@@ -55,7 +186,7 @@ fn test_queueing_honey_badger<A>(
 
     // Returns `true` if the node has not output all transactions yet.
     // If it has, and has advanced another epoch, it clears all messages for later epochs.
-    let node_busy = |node: &mut TestNode<QueueingHoneyBadger<usize, NodeId>>| {
+    let node_busy = |node: &mut TestNode<UsizeQhb<Q>>| {
         if !has_remove(node) || !has_add(node) {
             return true;
         }
@@ -88,31 +219,54 @@ fn test_queueing_honey_badger<A>(
 
 /// Verifies that all instances output the same sequence of batches. We already know that all of
 /// them have output all transactions and events, but some may have advanced a few empty batches
-/// more than others, so we ignore those.
-fn verify_output_sequence<A>(network: &TestNetwork<A, QueueingHoneyBadger<usize, NodeId>>)
+/// more than others, so we ignore those. Also verifies that the batches' canonical orderings
+/// contain each transaction exactly once.
+fn verify_output_sequence<A, Q>(network: &TestNetwork<A, UsizeQhb<Q>>)
 where
-    A: Adversary<QueueingHoneyBadger<usize, NodeId>>,
+    A: Adversary<UsizeQhb<Q>>,
+    Q: TransactionQueue<usize>,
 {
     let expected = network.nodes[&NodeId(0)].outputs().to_vec();
     assert!(!expected.is_empty());
+    for batch in &expected {
+        let canonical = batch.canonical_txs().expect("canonical transactions");
+        let canonical_set: BTreeSet<&usize> = canonical.iter().cloned().collect();
+        assert_eq!(canonical.len(), canonical_set.len());
+        assert_eq!(batch.iter().collect::<BTreeSet<_>>(), canonical_set);
+    }
     for node in network.nodes.values() {
         let len = cmp::min(expected.len(), node.outputs().len());
         assert_eq!(&expected[..len], &node.outputs()[..len]);
     }
 }
 
+/// Returns a new `DynamicHoneyBadger` instance in a network with a single node.
+fn new_single_dyn_hb() -> DynamicHoneyBadger<Vec<usize>, NodeId> {
+    let mut rng = rand::thread_rng();
+    let netinfo = NetworkInfo::generate_map(vec![NodeId(0)], &mut rng)
+        .expect("Failed to generate `NetworkInfo` map")
+        .remove(&NodeId(0))
+        .expect("missing `NetworkInfo`");
+    DynamicHoneyBadger::builder()
+        .build(netinfo)
+        .expect("instantiate DynamicHoneyBadger")
+}
+
 // Allow passing `netinfo` by value. `TestNetwork` expects this function signature.
 #[cfg_attr(feature = "cargo-clippy", allow(needless_pass_by_value))]
-fn new_queueing_hb(
+fn new_queueing_hb<Q: TransactionQueue<usize>>(
     netinfo: Arc<NetworkInfo<NodeId>>,
-) -> (QueueingHoneyBadger<usize, NodeId>, Step<usize, NodeId>) {
-    let dyn_hb = DynamicHoneyBadger::builder().build((*netinfo).clone());
+) -> (UsizeQhb<Q>, Step<usize, NodeId, Q>) {
+    let dyn_hb = DynamicHoneyBadger::builder()
+        .build((*netinfo).clone())
+        .expect("instantiate DynamicHoneyBadger");
     QueueingHoneyBadger::builder(dyn_hb).batch_size(3).build()
 }
 
-fn test_queueing_honey_badger_different_sizes<A, F>(new_adversary: F, num_txs: usize)
+fn test_queueing_honey_badger_different_sizes<A, Q, F>(new_adversary: F, num_txs: usize)
 where
-    A: Adversary<QueueingHoneyBadger<usize, NodeId>>,
+    A: Adversary<UsizeQhb<Q>>,
+    Q: TransactionQueue<usize>,
     F: Fn(usize, usize, BTreeMap<NodeId, Arc<NetworkInfo<NodeId>>>) -> A,
 {
     // This returns an error in all but the first test.
@@ -129,8 +283,8 @@ where
             num_good_nodes, num_adv_nodes
         );
         let adversary = |adv_nodes| new_adversary(num_good_nodes, num_adv_nodes, adv_nodes);
-        let network =
-            TestNetwork::new_with_step(num_good_nodes, num_adv_nodes, adversary, new_queueing_hb);
+        let new_qhb = new_queueing_hb::<Q>;
+        let network = TestNetwork::new_with_step(num_good_nodes, num_adv_nodes, adversary, new_qhb);
         test_queueing_honey_badger(network, num_txs);
     }
 }
@@ -138,11 +292,420 @@ where
 #[test]
 fn test_queueing_honey_badger_random_delivery_silent() {
     let new_adversary = |_: usize, _: usize, _| SilentAdversary::new(MessageScheduler::Random);
-    test_queueing_honey_badger_different_sizes(new_adversary, 30);
+    test_queueing_honey_badger_different_sizes::<_, VecDeque<_>, _>(new_adversary, 30);
 }
 
 #[test]
 fn test_queueing_honey_badger_first_delivery_silent() {
     let new_adversary = |_: usize, _: usize, _| SilentAdversary::new(MessageScheduler::First);
-    test_queueing_honey_badger_different_sizes(new_adversary, 30);
+    test_queueing_honey_badger_different_sizes::<_, VecDeque<_>, _>(new_adversary, 30);
+}
+
+#[test]
+fn test_queueing_honey_badger_custom_queue() {
+    let new_adversary = |_: usize, _: usize, _| SilentAdversary::new(MessageScheduler::Random);
+    test_queueing_honey_badger_different_sizes::<_, LowestFirstQueue, _>(new_adversary, 30);
+}
+
+#[test]
+fn test_queueing_honey_badger_validator() {
+    let _ = env_logger::try_init();
+
+    // Invalid transactions are rejected on input.
+    let (mut qhb, _): (BelowLimitQhb, _) = QueueingHoneyBadger::builder(new_single_dyn_hb())
+        .validator(BelowLimit(Some(10)))
+        .build();
+    assert!(qhb.handle_input(Input::User(9)).is_ok());
+    let err = qhb
+        .handle_input(Input::User(10))
+        .err()
+        .expect("invalid transaction was accepted");
+    match *err.kind() {
+        ErrorKind::InvalidTransaction(ref msg) => assert_eq!("10 is not below 10", msg),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+
+    // Node 0 accepts all transactions, so it proposes invalid ones. The other nodes must remove
+    // them from the batches.
+    let new_qhb = |netinfo: Arc<NetworkInfo<NodeId>>| {
+        let limit = if *netinfo.our_id() == NodeId(0) {
+            None
+        } else {
+            Some(10)
+        };
+        let dyn_hb = DynamicHoneyBadger::builder()
+            .build((*netinfo).clone())
+            .expect("instantiate DynamicHoneyBadger");
+        QueueingHoneyBadger::builder(dyn_hb)
+            .batch_size(3)
+            .validator(BelowLimit(limit))
+            .build()
+    };
+    let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+    let mut network: TestNetwork<_, BelowLimitQhb> =
+        TestNetwork::new_with_step(4, 0, adversary, new_qhb);
+    for tx in 0..20 {
+        network.input(NodeId(0), Input::User(tx));
+    }
+
+    let has_valid_txs = |node: &TestNode<BelowLimitQhb>| {
+        node.id == NodeId(0) || node.outputs().iter().flat_map(Batch::iter).unique().count() >= 10
+    };
+    while !network.nodes.values().all(has_valid_txs) {
+        network.step();
+    }
+    for node in network.nodes.values().filter(|node| node.id != NodeId(0)) {
+        assert!(node
+            .outputs()
+            .iter()
+            .flat_map(Batch::iter)
+            .all(|tx| *tx < 10));
+    }
+}
+
+#[test]
+fn test_queueing_honey_badger_conflicting_transactions() {
+    let _ = env_logger::try_init();
+
+    // The single node proposes 0 and 5, which have the same key, in one contribution. Only the
+    // first one is committed, but since both were valid before the batch, that isn't a fault.
+    let (mut qhb, step): (OncePerKeyQhb, _) = QueueingHoneyBadger::builder(new_single_dyn_hb())
+        .batch_size(2)
+        .build_with_transactions(vec![0, 5])
+        .expect("instantiate QueueingHoneyBadger");
+    assert!(step.fault_log.is_empty());
+    let batch_txs: Vec<usize> = step.output.iter().flat_map(Batch::iter).cloned().collect();
+    assert_eq!(1, batch_txs.len());
+    let rejected = if batch_txs[0] == 0 { 5 } else { 0 };
+    let events = qhb.take_tx_events();
+    let invalid = TxEvent::Invalid {
+        id: HashId.id(&rejected).expect("transaction ID"),
+        epoch: 0,
+    };
+    assert!(events.contains(&invalid));
+
+    // Every node inputs transactions with all keys, so that the batches contain conflicting ones.
+    let new_qhb = |netinfo: Arc<NetworkInfo<NodeId>>| {
+        let dyn_hb = DynamicHoneyBadger::builder()
+            .build((*netinfo).clone())
+            .expect("instantiate DynamicHoneyBadger");
+        let NodeId(id) = *netinfo.our_id();
+        let txs = (0..5).map(|key| 5 * id + key);
+        QueueingHoneyBadger::builder(dyn_hb)
+            .batch_size(20)
+            .build_with_transactions(txs)
+            .expect("build QueueingHoneyBadger")
+    };
+    let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+    let mut network: TestNetwork<_, OncePerKeyQhb> =
+        TestNetwork::new_with_step(4, 0, adversary, new_qhb);
+
+    fn committed(node: &TestNode<OncePerKeyQhb>) -> BTreeSet<usize> {
+        node.outputs()
+            .iter()
+            .flat_map(Batch::iter)
+            .cloned()
+            .collect()
+    }
+
+    let has_all_keys = |node: &TestNode<OncePerKeyQhb>| {
+        committed(node).iter().map(|tx| tx % 5).unique().count() == 5
+    };
+    while !network.nodes.values().all(has_all_keys) {
+        network.step();
+    }
+    // All nodes accepted the same transaction for each key, and no other ones.
+    let first = committed(&network.nodes[&NodeId(0)]);
+    assert_eq!(5, first.len());
+    for node in network.nodes.values() {
+        assert_eq!(first, committed(node));
+    }
+}
+
+#[test]
+fn test_queueing_honey_badger_max_contribution_size() {
+    // A contribution with a single `usize` transaction has 8 bytes for the length and 8 for the
+    // transaction.
+    let (mut qhb, _): (UsizeQhb<VecDeque<usize>>, _) =
+        QueueingHoneyBadger::builder(new_single_dyn_hb())
+            .max_contribution_size(16)
+            .build();
+    assert!(qhb.handle_input(Input::User(0)).is_ok());
+
+    let (mut qhb, _): (UsizeQhb<VecDeque<usize>>, _) =
+        QueueingHoneyBadger::builder(new_single_dyn_hb())
+            .max_contribution_size(15)
+            .build();
+    let err = qhb
+        .handle_input(Input::User(0))
+        .err()
+        .expect("too large transaction was accepted");
+    match *err.kind() {
+        ErrorKind::TransactionTooLarge(16, 15) => (),
+        ref kind => panic!("unexpected error: {}", kind),
+    }
+
+    // Transactions in the configured queue that are too large are removed.
+    let (qhb, _): (UsizeQhb<VecDeque<usize>>, _) =
+        QueueingHoneyBadger::builder(new_single_dyn_hb())
+            .queue((0..3).collect())
+            .max_contribution_size(15)
+            .build();
+    assert!(qhb.queue().is_empty());
+}
+
+#[test]
+fn test_adaptive_batch_size() {
+    let ms = Duration::from_millis;
+    let mut abs = AdaptiveBatchSize::new(2, 20, ms(100));
+    assert_eq!(2, abs.amount());
+
+    // Fast, full epochs with many pending transactions increase the proposal size.
+    abs.update(ms(50), 100, 8, 4);
+    assert_eq!(3, abs.amount());
+    for _ in 0..20 {
+        let num_txs = 4 * abs.amount();
+        abs.update(ms(50), 100, num_txs, 4);
+    }
+    assert_eq!(20, abs.amount());
+    assert_eq!(Some(ms(50)), abs.average_latency());
+
+    // Slow epochs reduce it, but not below the minimum.
+    abs.update(ms(450), 100, 80, 4);
+    assert_eq!(Some(ms(150)), abs.average_latency());
+    assert_eq!(10, abs.amount());
+    abs.update(ms(450), 100, 40, 4);
+    assert_eq!(5, abs.amount());
+    abs.update(ms(450), 100, 20, 4);
+    abs.update(ms(450), 100, 8, 4);
+    assert_eq!(2, abs.amount());
+
+    // It doesn't grow if there are few pending transactions, or the batch was mostly empty.
+    let mut abs = AdaptiveBatchSize::new(8, 20, ms(100));
+    abs.update(ms(50), 8, 32, 4);
+    abs.update(ms(50), 100, 15, 4);
+    assert_eq!(8, abs.amount());
+}
+
+#[test]
+fn test_queueing_honey_badger_tx_events() {
+    // With a batch size of 1, the single node commits one transaction per epoch, in order.
+    let (mut qhb, _): (UsizeQhb<VecDeque<usize>>, _) =
+        QueueingHoneyBadger::builder(new_single_dyn_hb())
+            .batch_size(1)
+            .tx_expiry(2)
+            .build_with_transactions(vec![1, 2, 3])
+            .expect("instantiate QueueingHoneyBadger");
+    let id = |tx: usize| HashId.id(&tx).expect("transaction ID");
+    let committed = |tx: usize, epoch: u64| TxEvent::Committed {
+        id: id(tx),
+        epoch,
+        proposer_id: NodeId(0),
+    };
+
+    // All transactions were queued before epoch 0, so the third one expires after epoch 1.
+    let expected = vec![
+        committed(1, 0),
+        committed(2, 1),
+        TxEvent::Expired {
+            id: id(3),
+            epoch: 1,
+        },
+    ];
+    assert_eq!(expected, qhb.take_tx_events());
+    assert!(qhb.queue().is_empty());
+
+    let _ = qhb.handle_input(Input::User(4)).expect("input");
+    assert_eq!(vec![committed(4, 2)], qhb.take_tx_events());
+    assert!(qhb.take_tx_events().is_empty());
+
+    // Transactions dropped by the queue are reported as evicted: 1 is replaced by its duplicate
+    // 11, and 2 has the lowest priority when 3 exceeds the capacity.
+    let (mut qhb, _): (UsizeQhb<PriorityQueue<usize, ByValue>>, _) =
+        QueueingHoneyBadger::builder(new_single_dyn_hb())
+            .batch_size(1)
+            .queue(PriorityQueue::new(ByValue).capacity(2))
+            .build_with_transactions(vec![1, 11, 2, 3])
+            .expect("instantiate QueueingHoneyBadger");
+    let evicted = |tx: usize| TxEvent::Evicted {
+        id: id(tx),
+        epoch: 0,
+    };
+    let expected = vec![evicted(1), evicted(2), committed(11, 0), committed(3, 1)];
+    assert_eq!(expected, qhb.take_tx_events());
+}
+
+#[test]
+fn test_queueing_honey_badger_encrypted() {
+    let _ = env_logger::try_init();
+
+    let new_qhb = |netinfo: Arc<NetworkInfo<NodeId>>| {
+        let dyn_hb = DynamicHoneyBadger::builder()
+            .build((*netinfo).clone())
+            .expect("instantiate DynamicHoneyBadger");
+        QueueingHoneyBadger::builder(dyn_hb)
+            .batch_size(3)
+            .encryption(EncTxEncryption)
+            .build()
+    };
+    let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+    let mut network: TestNetwork<_, EncQhb> = TestNetwork::new_with_step(4, 0, adversary, new_qhb);
+    let pk = network.nodes[&NodeId(0)]
+        .instance()
+        .dyn_hb()
+        .netinfo()
+        .public_key_set()
+        .public_key();
+    let mut rng = rand::thread_rng();
+    for tx in 0..10 {
+        let bytes = bincode::serialize(&EncTx::Plain(tx)).expect("serialize transaction");
+        let ct = pk.encrypt_with_rng(&mut rng, &bytes);
+        network.input_all(Input::User(EncTx::Encrypted(ct)));
+    }
+    // A ciphertext that doesn't decrypt to a transaction is dropped.
+    let ct = pk.encrypt_with_rng(&mut rng, b"garbage");
+    network.input_all(Input::User(EncTx::Encrypted(ct)));
+
+    // Returns the set of transactions the node has output. They must all be decrypted.
+    let output_txs = |node: &TestNode<EncQhb>| -> BTreeSet<usize> {
+        node.outputs()
+            .iter()
+            .flat_map(Batch::iter)
+            .map(|tx| match *tx {
+                EncTx::Plain(tx) => tx,
+                EncTx::Encrypted(_) => panic!("encrypted transaction in output"),
+            }).collect()
+    };
+    let expected: BTreeSet<usize> = (0..10).collect();
+    while network
+        .nodes
+        .values()
+        .any(|node| output_txs(node) != expected)
+    {
+        network.step();
+    }
+
+    let expected_batches = network.nodes[&NodeId(0)].outputs().to_vec();
+    for node in network.nodes.values() {
+        let len = cmp::min(expected_batches.len(), node.outputs().len());
+        assert_eq!(&expected_batches[..len], &node.outputs()[..len]);
+    }
+}
+
+#[test]
+fn test_queueing_honey_badger_encrypted_invalid() {
+    let mut rng = rand::thread_rng();
+    let netinfo = NetworkInfo::generate_map(vec![NodeId(0)], &mut rng)
+        .expect("Failed to generate `NetworkInfo` map")
+        .remove(&NodeId(0))
+        .expect("missing `NetworkInfo`");
+    let pk = netinfo.public_key_set().public_key();
+    let dyn_hb = DynamicHoneyBadger::builder()
+        .build(netinfo)
+        .expect("instantiate DynamicHoneyBadger");
+    let (mut qhb, _): (EncQhb, _) = QueueingHoneyBadger::builder(dyn_hb)
+        .encryption(EncTxEncryption)
+        .build();
+
+    // A single node commits and decrypts the transaction right away. Since it doesn't decrypt to
+    // a transaction, it is removed from the batch, and reported as invalid. The proposer couldn't
+    // know that, so it isn't reported in the fault log.
+    let tx = EncTx::Encrypted(pk.encrypt_with_rng(&mut rng, b"garbage"));
+    let step = qhb.handle_input(Input::User(tx.clone())).expect("input");
+    assert!(step.fault_log.is_empty());
+    assert_eq!(1, step.output.len());
+    assert_eq!(None, step.output[0].iter().next());
+    let invalid = TxEvent::Invalid {
+        id: HashId.id(&tx).expect("transaction ID"),
+        epoch: 0,
+    };
+    assert_eq!(vec![invalid], qhb.take_tx_events());
+}
+
+#[test]
+fn test_queueing_honey_badger_gossip_limits() {
+    // Node 0 is one of four validators. The others never send it any consensus messages, so it
+    // stays in epoch 0. At most two transactions are accepted from each peer per epoch.
+    let mut rng = rand::thread_rng();
+    let netinfo = NetworkInfo::generate_map((0..4).map(NodeId), &mut rng)
+        .expect("Failed to generate `NetworkInfo` map")
+        .remove(&NodeId(0))
+        .expect("missing `NetworkInfo`");
+    let dyn_hb = DynamicHoneyBadger::builder()
+        .build(netinfo)
+        .expect("instantiate DynamicHoneyBadger");
+    let (mut qhb, _): (UsizeQhb<VecDeque<usize>>, _) = QueueingHoneyBadger::builder(dyn_hb)
+        .gossip(Gossip::new(1, 2, 10))
+        .build();
+    let queued = |qhb: &UsizeQhb<VecDeque<usize>>| qhb.queue().iter().cloned().collect::<Vec<_>>();
+    let step = qhb
+        .handle_message(&NodeId(1), Message::Transactions(0, vec![1, 2, 3]))
+        .expect("handle gossip");
+    let fault = || Fault::new(NodeId(1), FaultKind::GossipLimitExceeded);
+    assert_eq!(vec![fault()], step.fault_log.0);
+    assert_eq!(vec![1, 2], queued(&qhb));
+
+    // The limit is counted against our epoch, not the one the sender claims. A peer that is ahead
+    // of us isn't reported, though.
+    let step = qhb
+        .handle_message(&NodeId(1), Message::Transactions(1, vec![4]))
+        .expect("handle gossip");
+    assert!(step.fault_log.is_empty());
+    assert_eq!(vec![1, 2], queued(&qhb));
+    let step = qhb
+        .handle_message(&NodeId(1), Message::Transactions(0, vec![4]))
+        .expect("handle gossip");
+    assert_eq!(vec![fault()], step.fault_log.0);
+
+    // Another peer has its own limit, and the duplicate is ignored.
+    let step = qhb
+        .handle_message(&NodeId(2), Message::Transactions(0, vec![1, 3]))
+        .expect("handle gossip");
+    assert!(step.fault_log.is_empty());
+    assert_eq!(vec![1, 2, 3], queued(&qhb));
+
+    // Transactions from nodes that aren't validators are ignored.
+    let step = qhb
+        .handle_message(&NodeId(4), Message::Transactions(0, vec![5]))
+        .expect("handle gossip");
+    assert!(step.fault_log.is_empty());
+    assert_eq!(vec![1, 2, 3], queued(&qhb));
+}
+
+#[test]
+fn test_queueing_honey_badger_gossip() {
+    let _ = env_logger::try_init();
+
+    let new_qhb = |netinfo: Arc<NetworkInfo<NodeId>>| {
+        let dyn_hb = DynamicHoneyBadger::builder()
+            .build((*netinfo).clone())
+            .expect("instantiate DynamicHoneyBadger");
+        QueueingHoneyBadger::builder(dyn_hb)
+            .batch_size(3)
+            .gossip(Gossip::new(3, 100, 10))
+            .build()
+    };
+    let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+    let mut network: TestNetwork<_, UsizeQhb<VecDeque<usize>>> =
+        TestNetwork::new_with_step(4, 0, adversary, new_qhb);
+    // All transactions are input into node 0 only.
+    for tx in 0..20 {
+        network.input(NodeId(0), Input::User(tx));
+    }
+
+    let has_all_txs = |node: &TestNode<UsizeQhb<VecDeque<usize>>>| {
+        node.outputs().iter().flat_map(Batch::iter).unique().count() >= 20
+    };
+    while !network.nodes.values().all(has_all_txs) {
+        network.step();
+    }
+    verify_output_sequence(&network);
+
+    // The other nodes received the transactions, so they proposed some of them, too.
+    let other_proposed = network.nodes[&NodeId(1)]
+        .outputs()
+        .iter()
+        .flat_map(Batch::contributions)
+        .any(|(id, txs)| *id != NodeId(0) && !txs.is_empty());
+    assert!(other_proposed);
 }
@@ -75,6 +75,7 @@ fn new_network<A, F>(
     good_num: usize,
     bad_num: usize,
     adversary: F,
+    shared_coin: bool,
 ) -> TestNetwork<A, Subset<NodeId>>
 where
     A: Adversary<Subset<NodeId>>,
@@ -83,8 +84,14 @@ where
     // This returns an error in all but the first test.
     let _ = env_logger::try_init();
 
-    let new_subset =
-        |netinfo: Arc<NetworkInfo<NodeId>>| Subset::new(netinfo, 0).expect("new Subset instance");
+    let new_subset = |netinfo: Arc<NetworkInfo<NodeId>>| {
+        let subset = Subset::new(netinfo, 0).expect("new Subset instance");
+        if shared_coin {
+            subset.with_shared_coin()
+        } else {
+            subset
+        }
+    };
     TestNetwork::new(good_num, bad_num, adversary, new_subset)
 }
 
@@ -97,7 +104,7 @@ fn test_subset_3_out_of_4_nodes_propose() {
         .map(|id| (*id, proposed_value.clone()))
         .collect();
     let adversary = |_| SilentAdversary::new(MessageScheduler::First);
-    let network = new_network(3, 1, adversary);
+    let network = new_network(3, 1, adversary, false);
     test_subset(network, &proposals);
 }
 
@@ -116,7 +123,7 @@ fn test_subset_5_nodes_different_proposed_values() {
         .zip(proposed_values)
         .collect();
     let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
-    let network = new_network(5, 0, adversary);
+    let network = new_network(5, 0, adversary, false);
     test_subset(network, &proposals);
 }
 
@@ -125,6 +132,49 @@ fn test_subset_1_node() {
     let proposals: BTreeMap<NodeId, ProposedValue> =
         once((NodeId(0), Vec::from("Node 0 is the greatest!"))).collect();
     let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
-    let network = new_network(1, 0, adversary);
+    let network = new_network(1, 0, adversary, false);
+    test_subset(network, &proposals);
+}
+
+#[test]
+fn test_subset_shared_coin() {
+    let proposals: BTreeMap<NodeId, ProposedValue> = (0..7)
+        .map(|i| (NodeId(i), Vec::from(format!("Proposal {}", i))))
+        .collect();
+    let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+    let network = new_network(7, 0, adversary, true);
+    test_subset(network, &proposals);
+}
+
+#[test]
+fn test_subset_shared_coin_2_out_of_7_silent() {
+    let proposals: BTreeMap<NodeId, ProposedValue> = (0..5)
+        .map(|i| (NodeId(i), Vec::from(format!("Proposal {}", i))))
+        .collect();
+    let adversary = |_| SilentAdversary::new(MessageScheduler::First);
+    let network = new_network(5, 2, adversary, true);
+    test_subset(network, &proposals);
+}
+
+#[test]
+fn test_subset_consistent_broadcast_for_small_values() {
+    // Even nodes propose short values, which are sent via consistent broadcast.
+    let proposals: BTreeMap<NodeId, ProposedValue> = (0..7)
+        .map(|i| {
+            let value = if i % 2 == 0 {
+                format!("P{}", i)
+            } else {
+                format!("A longer proposal from node {}", i)
+            };
+            (NodeId(i), Vec::from(value))
+        }).collect();
+    let _ = env_logger::try_init();
+    let new_subset = |netinfo: Arc<NetworkInfo<NodeId>>| {
+        Subset::new(netinfo, 0)
+            .expect("new Subset instance")
+            .with_consistent_broadcast_limit(8)
+    };
+    let adversary = |_| SilentAdversary::new(MessageScheduler::Random);
+    let network = TestNetwork::new(5, 2, adversary, new_subset);
     test_subset(network, &proposals);
 }
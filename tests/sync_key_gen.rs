@@ -10,7 +10,7 @@ use std::collections::BTreeMap;
 
 use crypto::{PublicKey, SecretKey};
 
-use hbbft::sync_key_gen::{PartOutcome, SyncKeyGen};
+use hbbft::sync_key_gen::{Part, PartOutcome, SyncKeyGen};
 
 fn test_sync_key_gen_with(threshold: usize, node_num: usize) {
     // Generate individual key pairs for encryption. These are not suitable for threshold schemes.
@@ -99,3 +99,108 @@ fn test_sync_key_gen() {
         test_sync_key_gen_with(threshold, node_num);
     }
 }
+
+/// Handles all parts and then all acks in all nodes, and returns the key generation instances.
+fn run_key_gen(key_gens: Vec<(SyncKeyGen<usize>, Option<Part>)>) -> Vec<SyncKeyGen<usize>> {
+    let (mut nodes, parts): (Vec<_>, Vec<_>) = key_gens.into_iter().unzip();
+    let mut acks = Vec::new();
+    for (sender_id, part) in parts.into_iter().enumerate() {
+        let part = match part {
+            Some(part) => part,
+            None => continue, // Not a dealer.
+        };
+        for (node_id, node) in nodes.iter_mut().enumerate() {
+            match node.handle_part(&mut rand::thread_rng(), &sender_id, part.clone()) {
+                Some(PartOutcome::Valid(ack)) => acks.push((node_id, ack)),
+                _ => panic!("invalid part"),
+            }
+        }
+    }
+    for (sender_id, ack) in acks {
+        for node in &mut nodes {
+            assert!(node.handle_ack(&sender_id, ack.clone()).is_empty());
+        }
+    }
+    nodes
+}
+
+#[test]
+fn test_sync_key_gen_reshare() {
+    // This returns an error in all but the first test.
+    let _ = env_logger::try_init();
+
+    // Four nodes run a regular key generation.
+    let threshold = 1;
+    let sec_keys: Vec<SecretKey> = (0..6).map(|_| SecretKey::random()).collect();
+    let pub_keys: BTreeMap<usize, PublicKey> = sec_keys
+        .iter()
+        .take(4)
+        .map(SecretKey::public_key)
+        .enumerate()
+        .collect();
+    let key_gens = sec_keys
+        .iter()
+        .take(4)
+        .enumerate()
+        .map(|(id, sk)| {
+            SyncKeyGen::new(
+                &mut rand::thread_rng(),
+                id,
+                sk.clone(),
+                pub_keys.clone(),
+                threshold,
+            ).expect("new SyncKeyGen instance")
+        }).collect();
+    let old: Vec<_> = run_key_gen(key_gens)
+        .into_iter()
+        .map(|node| {
+            assert!(node.is_ready());
+            node.into_network_info_with_share().expect("generate keys")
+        }).collect();
+    let old_pk_set = old[0].0.public_key_set().clone();
+
+    // Reshare the keys to six nodes. Node 3 has lost its key share, and nodes 4 and 5 are new.
+    let pub_keys: BTreeMap<usize, PublicKey> = sec_keys
+        .iter()
+        .map(SecretKey::public_key)
+        .enumerate()
+        .collect();
+    let key_gens = sec_keys
+        .into_iter()
+        .enumerate()
+        .map(|(id, sk)| {
+            let (ref old_netinfo, ref opt_share) = old[id.min(3)];
+            let our_share = if id < 3 { opt_share.as_ref() } else { None };
+            SyncKeyGen::new_reshare(
+                &mut rand::thread_rng(),
+                id,
+                sk,
+                pub_keys.clone(),
+                threshold,
+                old_netinfo,
+                our_share,
+            ).expect("new resharing SyncKeyGen instance")
+        }).collect();
+    let nodes = run_key_gen(key_gens);
+
+    // The master key is the same, but the key shares have changed.
+    let msg = "Same key, new shares";
+    let mut sig_shares = BTreeMap::new();
+    let mut pub_key_set = None;
+    for (idx, node) in nodes.iter().enumerate() {
+        assert!(node.is_ready());
+        let (pks, opt_sk) = node.generate().expect("generate reshared keys");
+        assert_eq!(old_pk_set.public_key(), pks.public_key());
+        assert_ne!(old_pk_set.public_key_share(0), pks.public_key_share(0));
+        let sk = opt_sk.expect("new secret key");
+        let sig = sk.sign(msg);
+        assert!(pks.public_key_share(idx).verify(&sig, msg));
+        sig_shares.insert(idx, sig);
+        pub_key_set = Some(pks);
+    }
+    let pub_key_set = pub_key_set.expect("public key set");
+    let sig = pub_key_set
+        .combine_signatures(sig_shares.iter().skip(3).take(threshold + 1))
+        .expect("signature shares match");
+    assert!(old_pk_set.public_key().verify(&sig, msg));
+}